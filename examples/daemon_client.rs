@@ -0,0 +1,40 @@
+//! Talks to a running `hymod` over its control socket directly, the way a
+//! manager app would, without going through the `meta-hybrid` binary.
+//!
+//! ```text
+//! cargo run --example daemon_client                     # daemon status
+//! cargo run --example daemon_client -- add_module foo   # inject module `foo`
+//! cargo run --example daemon_client -- remove_module foo
+//! cargo run --example daemon_client -- reload
+//! ```
+//!
+//! Connects to the socket `HYMOD_SOCKET` points at, or the default one,
+//! so it needs root on a device where `hymod` is running.
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use anyhow::{Context, Result, bail};
+use serde_json::{Value, json};
+
+const DEFAULT_SOCKET: &str = "/data/adb/meta-hybrid/run/hymod.sock";
+
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let request = match (args.first().map(String::as_str), args.get(1)) {
+        (None | Some("status"), _) => json!({ "cmd": "status" }),
+        (Some("reload"), _) => json!({ "cmd": "reload" }),
+        (Some(cmd @ ("add_module" | "remove_module")), Some(id)) => json!({ "cmd": cmd, "id": id }),
+        _ => bail!("usage: daemon_client [status | reload | add_module <id> | remove_module <id>]"),
+    };
+    let socket = std::env::var("HYMOD_SOCKET").unwrap_or_else(|_| DEFAULT_SOCKET.to_string());
+    let mut stream = UnixStream::connect(&socket).with_context(|| format!("failed to connect to {}", socket))?;
+    writeln!(stream, "{}", request)?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    let response: Value = serde_json::from_str(&line).context("malformed response")?;
+    if response["ok"] != Value::Bool(true) {
+        bail!("hymod: {}", response["error"].as_str().unwrap_or("request failed"));
+    }
+    println!("{}", serde_json::to_string_pretty(&response["result"])?);
+    Ok(())
+}
@@ -0,0 +1,35 @@
+//! Injects a directory over a partition path through whichever backend
+//! meta-hybrid selects for it, then shows what each backend now serves.
+//!
+//! ```text
+//! cargo run --example inject_module -- /data/local/tmp/etc /system/etc [hymofs|overlay|magic]
+//! ```
+//!
+//! Uses the `hymo` library with the device's config, so it needs root on a
+//! device with the module installed.
+
+use std::path::Path;
+use anyhow::{Result, anyhow, bail};
+use clap::ValueEnum;
+use hymo::prelude::*;
+
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (Some(source), Some(target)) = (args.first(), args.get(1)) else {
+        bail!("usage: inject_module <source dir> <target dir> [backend]");
+    };
+    let backend = match args.get(2) {
+        Some(name) => Backend::Only(BackendKind::from_str(name, true).map_err(|e| anyhow!(e))?),
+        None => Auto,
+    };
+    let hymo = Hymo::builder().backend(backend).build()?;
+    let target = Path::new(target);
+    let name = hymo.inject(Path::new(source), target)?;
+    println!("Injected {} over {} through {}", source, target.display(), name);
+
+    for backend in hymo.backends().all().into_iter().filter(|b| b.is_usable()) {
+        let below = backend.list()?.into_iter().filter(|p| p.starts_with(target)).count();
+        println!("{}: {} entries at or below {}", backend.name(), below, target.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,39 @@
+//! Prints the HymoFS rules in the kernel, or the ones a namespace holds.
+//!
+//! ```text
+//! cargo run --example list_rules            # every live rule
+//! cargo run --example list_rules -- lsposed # rules of namespace `lsposed`
+//! ```
+//!
+//! Uses the `hymo` library with the device's config, so it needs root on a
+//! device with the HymoFS kernel module loaded.
+
+use anyhow::{Context, Result};
+use hymo::core::{namespace, rules::RuleLedger};
+use hymo::prelude::*;
+
+fn print(rule: &RuleOp) {
+    match rule {
+        RuleOp::Add { src, target, .. } => println!("add  {} -> {}", src, target),
+        RuleOp::Delete { src } => println!("del  {}", src),
+        RuleOp::Hide { path } => println!("hide {}", path),
+    }
+}
+
+fn main() -> Result<()> {
+    let hymo = Hymo::builder().backend(BackendKind::HymoFs).build()?;
+    let hymofs = hymo.backends().hymofs.as_ref().context("HymoFS is not available")?;
+    let live = hymofs.list_ops()?;
+    match std::env::args().nth(1) {
+        Some(owner) => {
+            let rules = namespace::list(&RuleLedger::load(), &owner, Some(&live));
+            rules.iter().for_each(print);
+            println!("{} live rules in namespace {}", rules.len(), owner);
+        }
+        None => {
+            live.iter().for_each(print);
+            println!("{} live HymoFS rules", live.len());
+        }
+    }
+    Ok(())
+}
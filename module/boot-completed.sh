@@ -0,0 +1,10 @@
+MODDIR="${0%/*}"
+BINARY="$MODDIR/meta-hybrid"
+if [ ! -f "$BINARY" ]; then
+    exit 0
+fi
+"$BINARY" boot-completed >> "/data/adb/meta-hybrid/daemon.log" 2>&1
+"$BINARY" resume-deferred >> "/data/adb/meta-hybrid/daemon.log" 2>&1 &
+"$BINARY" watch-packages >> "/data/adb/meta-hybrid/daemon.log" 2>&1 &
+"$BINARY" hymod >> "/data/adb/meta-hybrid/daemon.log" 2>&1 &
+exit 0
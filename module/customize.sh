@@ -27,6 +27,8 @@ fi
 ui_print "- Installing binary for $ABI..."
 cp -f "$BIN_SOURCE" "$BIN_TARGET"
 set_perm "$BIN_TARGET" 0 0 0755
+ln -sf meta-hybrid "$MODPATH/hymo"
+ln -sf meta-hybrid "$MODPATH/hymod"
 rm -rf "$MODPATH/binaries"
 rm -rf "$MODPATH/system"
 BASE_DIR="/data/adb/meta-hybrid"
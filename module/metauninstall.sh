@@ -1,8 +1,13 @@
-
+MODDIR="${0%/*}"
 MNT_DIR="/data/adb/meta-hybrid/mnt"
 if [ -z "$MODULE_ID" ]; then
     exit 0
 fi
+BINARY="$MODDIR/meta-hybrid"
+if [ -x "$BINARY" ]; then
+    # ksud runs uninstall.sh and deletes the module dir itself
+    "$BINARY" uninstall-module "$MODULE_ID" --skip-script --keep-files >> /data/adb/meta-hybrid/daemon.log 2>&1 && exit 0
+fi
 if ! mountpoint -q "$MNT_DIR" 2>/dev/null; then
     exit 0
 fi
@@ -10,4 +15,4 @@ MOD_IMG_DIR="$MNT_DIR/$MODULE_ID"
 if [ -d "$MOD_IMG_DIR" ]; then
     rm -rf "$MOD_IMG_DIR"
 fi
-exit 0
\ No newline at end of file
+exit 0
@@ -0,0 +1,7 @@
+MODDIR="${0%/*}"
+BINARY="$MODDIR/meta-hybrid"
+if [ ! -f "$BINARY" ]; then
+    exit 0
+fi
+"$BINARY" service >> "/data/adb/meta-hybrid/daemon.log" 2>&1
+exit 0
@@ -0,0 +1,184 @@
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result, bail};
+use tracing_appender::non_blocking::WorkerGuard;
+use crate::{
+    conf::config::Config,
+    core::{manager::{self, InjectOutcome, InstalledModule, ReloadReport}, state::RuntimeState},
+    mount::{self, BackendKind, Backends, MountBackend},
+    utils,
+};
+
+/// Which backend a [`Hymo`] injects through.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// The one the config and the device's backend health prefer for each
+    /// target, as the boot path picks.
+    #[default]
+    Auto,
+    Only(BackendKind),
+}
+
+impl From<BackendKind> for Backend {
+    fn from(kind: BackendKind) -> Self {
+        Backend::Only(kind)
+    }
+}
+
+/// Sets up a [`Hymo`]: where the config comes from, which backend to use
+/// and whether to log to a file.
+#[derive(Debug, Default)]
+pub struct HymoBuilder {
+    config_path: Option<PathBuf>,
+    config: Option<Config>,
+    backend: Backend,
+    log_file: Option<PathBuf>,
+    verbose: bool,
+}
+
+impl HymoBuilder {
+    /// Reads the config from `path` instead of the default location.
+    pub fn config<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.config_path = Some(path.into());
+        self
+    }
+
+    /// Uses `config` as is, without reading a file.
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    pub fn backend<B: Into<Backend>>(mut self, backend: B) -> Self {
+        self.backend = backend.into();
+        self
+    }
+
+    /// Logs to `path` the way the daemon does.
+    pub fn log_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.log_file = Some(path.into());
+        self
+    }
+
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Loads the config and runtime state and opens the backends. A config
+    /// file named with [`HymoBuilder::config`] must load; the default one
+    /// falls back to the defaults, as the command line does.
+    pub fn build(self) -> Result<Hymo> {
+        let config = match (self.config, &self.config_path) {
+            (Some(config), _) => config,
+            (None, Some(path)) => Config::from_file(path).with_context(|| format!("Failed to load {}", path.display()))?,
+            (None, None) => Config::load_default().unwrap_or_default(),
+        };
+        let log_guard = match &self.log_file {
+            Some(path) => Some(utils::init_logging(self.verbose || config.verbose, path)?),
+            None => None,
+        };
+        let backends = Backends::new(&config);
+        if let Backend::Only(kind) = self.backend {
+            if !backends.get(kind).is_some_and(|b| b.is_usable()) {
+                bail!("Backend {:?} is not usable on this device", kind);
+            }
+        }
+        Ok(Hymo {
+            state: RuntimeState::load().unwrap_or_default(),
+            config,
+            backends,
+            backend: self.backend,
+            _log_guard: log_guard,
+        })
+    }
+}
+
+/// Config, runtime state and backends wired together for embedders.
+pub struct Hymo {
+    config: Config,
+    state: RuntimeState,
+    backends: Backends,
+    backend: Backend,
+    _log_guard: Option<WorkerGuard>,
+}
+
+impl Hymo {
+    pub fn builder() -> HymoBuilder {
+        HymoBuilder::default()
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// What the last boot mounted.
+    pub fn state(&self) -> &RuntimeState {
+        &self.state
+    }
+
+    pub fn backends(&self) -> &Backends {
+        &self.backends
+    }
+
+    /// The backend that serves `target`.
+    pub fn backend_for(&self, target: &Path) -> Result<&dyn MountBackend> {
+        let kind = match self.backend {
+            Backend::Auto => None,
+            Backend::Only(kind) => Some(kind),
+        };
+        mount::pick_backend(&self.backends, &self.config, kind, target)
+    }
+
+    /// Merges the tree at `source` over `target` and returns the name of
+    /// the backend that did it.
+    pub fn inject(&self, source: &Path, target: &Path) -> Result<&'static str> {
+        let backend = self.backend_for(target)?;
+        backend.inject(source, target)?;
+        Ok(backend.name())
+    }
+
+    /// Takes back what was injected at or below `target`.
+    pub fn remove(&self, target: &Path) -> Result<usize> {
+        self.backend_for(target)?.remove(target)
+    }
+
+    /// Every installed module, enabled or not.
+    pub fn modules(&self) -> Result<Vec<InstalledModule>> {
+        manager::discover(&self.config)
+    }
+
+    /// Injects every partition of every enabled module, ordered so the
+    /// config's conflict policy decides paths several of them ship.
+    pub fn mount_all(&self) -> Result<Vec<InjectOutcome>> {
+        let modules = self.modules()?;
+        let order = manager::resolve(&modules, self.config.conflict_policy, &self.config.module_priority)?;
+        Ok(manager::mount_all(&self.config, &order, |_, content, target| self.inject(content, target)))
+    }
+
+    /// Applies modules enabled or disabled since boot to the live HymoFS
+    /// rules.
+    pub fn reload(&self) -> Result<ReloadReport> {
+        let hymofs = self.backends.hymofs.as_ref().filter(|h| h.is_available()).context("HymoFS is not available")?;
+        manager::reload(&self.config, hymofs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_from_an_explicit_config() {
+        let moduledir = std::env::temp_dir().join(format!("builder-{}", std::process::id()));
+        std::fs::create_dir_all(moduledir.join("demo/system")).unwrap();
+        let hymo = Hymo::builder()
+            .with_config(Config { moduledir: moduledir.clone(), ..Config::default() })
+            .backend(Backend::Auto)
+            .build()
+            .unwrap();
+        assert_eq!(hymo.config().moduledir, moduledir);
+        assert_eq!(hymo.modules().unwrap().iter().map(|m| m.id.as_str()).collect::<Vec<_>>(), ["demo"]);
+        assert!(Hymo::builder().config(moduledir.join("missing.toml")).build().is_err());
+        let _ = std::fs::remove_dir_all(&moduledir);
+    }
+}
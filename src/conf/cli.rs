@@ -1,6 +1,7 @@
 use std::path::PathBuf;
 use clap::{Parser, Subcommand};
 use super::config::CONFIG_FILE_DEFAULT;
+use crate::{defs, mount::BackendKind};
 
 #[derive(Parser, Debug)]
 #[command(name = "meta-hybrid", version, about = "Hybrid Mount Metamodule")]
@@ -19,6 +20,10 @@ pub struct Cli {
     pub partitions: Vec<String>,
     #[arg(long = "dry-run")]
     pub dry_run: bool,
+    #[arg(long = "deterministic")]
+    pub deterministic: bool,
+    #[arg(long = "report-file")]
+    pub report_file: Option<PathBuf>,
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
@@ -30,6 +35,8 @@ pub enum Commands {
         output: PathBuf,
     },
     ShowConfig,
+    #[command(name = "check-config")]
+    CheckConfig,
     #[command(name = "save-config")]
     SaveConfig {
         #[arg(long)]
@@ -46,4 +53,312 @@ pub enum Commands {
     Modules,
     Conflicts,
     Diagnostics,
+    Service,
+    #[command(name = "boot-completed")]
+    BootCompleted,
+    Batch,
+    #[command(name = "resume-deferred")]
+    ResumeDeferred {
+        #[arg(long, default_value_t = 60)]
+        interval: u64,
+    },
+    Certify {
+        #[arg(long, default_value_t = defs::DEFAULT_CERTIFY_RULES)]
+        rules: usize,
+        #[arg(long)]
+        json: bool,
+    },
+    Namespace {
+        owner: String,
+        #[command(subcommand)]
+        action: NamespaceAction,
+    },
+    Mntns {
+        #[command(subcommand)]
+        action: MntnsAction,
+    },
+    #[command(name = "boot-guard")]
+    BootGuard {
+        #[command(subcommand)]
+        action: BootGuardAction,
+    },
+    Hymo {
+        #[arg(long, global = true)]
+        json: bool,
+        #[command(subcommand)]
+        action: HymoAction,
+    },
+    Hymod {
+        #[command(subcommand)]
+        action: Option<HymodAction>,
+    },
+    Module {
+        #[command(subcommand)]
+        action: ModuleAction,
+    },
+    Backend {
+        #[command(subcommand)]
+        action: BackendAction,
+    },
+    #[command(name = "crash-scan")]
+    CrashScan,
+    #[command(name = "watch-packages")]
+    WatchPackages {
+        #[arg(long, default_value_t = 5)]
+        interval: u64,
+    },
+    #[command(name = "uninstall-module")]
+    UninstallModule {
+        #[arg(required = true)]
+        modules: Vec<String>,
+        #[arg(long = "skip-script")]
+        skip_script: bool,
+        #[arg(long = "keep-files")]
+        keep_files: bool,
+        #[arg(short = 'y', long)]
+        yes: bool,
+    },
+    Adopt {
+        #[arg(long)]
+        release: Vec<String>,
+    },
+    Migrate {
+        #[arg(long = "state-version")]
+        state_version: Option<u32>,
+    },
+    History {
+        module: Option<String>,
+        #[arg(long)]
+        json: bool,
+    },
+    Explain {
+        path: PathBuf,
+        #[arg(long)]
+        json: bool,
+    },
+    ApproveDirect {
+        module: String,
+        #[arg(long)]
+        revoke: bool,
+    },
+    Preview {
+        module: String,
+        #[arg(last = true)]
+        command: Vec<String>,
+    },
+    Compact,
+    #[command(name = "selinux-check")]
+    SelinuxCheck {
+        #[arg(long)]
+        json: bool,
+    },
+    Metrics {
+        #[arg(short = 'o', long)]
+        output: Option<PathBuf>,
+    },
+    Profiles,
+    Profile {
+        name: String,
+        #[arg(long, conflicts_with = "disable")]
+        enable: bool,
+        #[arg(long)]
+        disable: bool,
+    },
+    Wait {
+        #[arg(long, conflicts_with = "module", required_unless_present = "module")]
+        rule: Option<String>,
+        #[arg(long)]
+        module: Option<String>,
+        #[arg(long, default_value = "mounted", requires = "module")]
+        state: String,
+        #[arg(long, default_value_t = 10)]
+        timeout: u64,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum NamespaceAction {
+    Add {
+        src: String,
+        target: PathBuf,
+        #[arg(long)]
+        package: Option<String>,
+    },
+    Hide {
+        path: String,
+        #[arg(long)]
+        package: Option<String>,
+    },
+    Remove {
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
+    List {
+        #[arg(long)]
+        json: bool,
+    },
+    Clear,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum BootGuardAction {
+    Status {
+        #[arg(long)]
+        json: bool,
+    },
+    Reset,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum MntnsAction {
+    List {
+        #[arg(long)]
+        json: bool,
+    },
+    Verify {
+        #[arg(long)]
+        pid: u32,
+        #[arg(long)]
+        module: Option<String>,
+        #[arg(long)]
+        json: bool,
+    },
+    Inject {
+        #[arg(long)]
+        pid: u32,
+        source: PathBuf,
+        target: PathBuf,
+        #[arg(long)]
+        backend: Option<BackendKind>,
+    },
+    Isolate,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum HymoAction {
+    Add {
+        src: String,
+        target: PathBuf,
+        #[arg(long, default_value_t = 0)]
+        priority: i32,
+        #[arg(long)]
+        package: Option<String>,
+    },
+    Del {
+        #[arg(required_unless_present = "module")]
+        paths: Vec<String>,
+        #[arg(long, conflicts_with = "paths")]
+        module: Option<String>,
+    },
+    Hide {
+        #[arg(required = true)]
+        paths: Vec<String>,
+        #[arg(long)]
+        package: Option<String>,
+    },
+    InjectDir {
+        source: PathBuf,
+        target: PathBuf,
+        #[arg(long = "dry-run")]
+        dry_run: bool,
+        #[arg(long)]
+        package: Option<String>,
+    },
+    Verify {
+        source: PathBuf,
+        target: PathBuf,
+    },
+    List,
+    Clear,
+    Status,
+    Version,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum HymodAction {
+    #[command(name = "add-module")]
+    AddModule {
+        id: String,
+    },
+    #[command(name = "remove-module")]
+    RemoveModule {
+        id: String,
+    },
+    Reload,
+    #[command(name = "reload-modules")]
+    ReloadModules,
+    Status,
+    Install {
+        source: PathBuf,
+    },
+    #[command(name = "cancel-install")]
+    CancelInstall {
+        id: String,
+    },
+    Installs,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ModuleAction {
+    Scan {
+        #[arg(long)]
+        json: bool,
+    },
+    Info {
+        id: String,
+        key: Option<String>,
+        #[arg(long)]
+        json: bool,
+    },
+    #[command(name = "mount-all")]
+    MountAll {
+        #[arg(long)]
+        backend: Option<BackendKind>,
+        #[arg(long)]
+        json: bool,
+    },
+    Install {
+        source: PathBuf,
+    },
+    Conflicts {
+        #[arg(long)]
+        json: bool,
+    },
+    Reload {
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum BackendAction {
+    Status {
+        #[arg(long)]
+        json: bool,
+    },
+    Inject {
+        source: PathBuf,
+        target: PathBuf,
+        #[arg(long)]
+        backend: Option<BackendKind>,
+    },
+    Remove {
+        target: PathBuf,
+        #[arg(long)]
+        backend: Option<BackendKind>,
+    },
+    List {
+        #[arg(long)]
+        backend: Option<BackendKind>,
+        #[arg(long)]
+        json: bool,
+    },
+    Clear {
+        #[arg(long)]
+        backend: BackendKind,
+    },
+    Rules {
+        #[arg(long)]
+        json: bool,
+    },
 }
@@ -1,17 +1,23 @@
 use std::{
+    collections::BTreeMap,
     fs,
     path::{Path, PathBuf},
 };
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use serde::{Deserialize, Serialize};
+use crate::mount::{BackendKind, default_backend_order, propagation::PropagationConfig};
+use crate::core::{apps, bootguard::BootGuard, bootstage::BootStages, bootreason::BootReasonSkip, budget::BudgetPolicy, direct::WritablePolicy, planner::HymoFallback, integrity::HashAlgorithm, manager::ConflictPolicy, migrate::{self, Document}, mntns::NamespaceScope, partitions::PartitionMount, protect, remote::RemoteControl, rewrite::PathRewrite, selinux::SelinuxLabels, sepolicy, stealth::Stealth, symlinks::SymlinkPolicy, wal::PartialApplyPolicy, watcher::ModuleWatch};
 pub const CONFIG_FILE_DEFAULT: &str = "/data/adb/meta-hybrid/config.toml";
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Config {
     #[serde(default = "default_moduledir")]
     pub moduledir: PathBuf,
+    #[serde(default)]
+    pub module_roots: Vec<PathBuf>,
     pub tempdir: Option<PathBuf>,
     #[serde(default = "default_mountsource")]
     pub mountsource: String,
+    #[serde(default)]
     pub verbose: bool,
     #[serde(default, deserialize_with = "deserialize_partitions_flexible")]
     pub partitions: Vec<String>,
@@ -25,6 +31,101 @@ pub struct Config {
     pub allow_umount_coexistence: bool,
     #[serde(default)]
     pub dry_run: bool,
+    #[serde(default = "default_hymofs_device")]
+    pub hymofs_device: PathBuf,
+    #[serde(default)]
+    pub hymofs_clear_foreign: bool,
+    #[serde(default = "default_hymofs_rule_budget")]
+    pub hymofs_rule_budget: usize,
+    #[serde(default)]
+    pub partial_apply_policy: PartialApplyPolicy,
+    #[serde(default)]
+    pub writable_partition_policy: WritablePolicy,
+    #[serde(default)]
+    pub disable_arch_filter: bool,
+    #[serde(default)]
+    pub hide_stale_oat: bool,
+    #[serde(default = "default_crash_window_secs")]
+    pub crash_window_secs: u64,
+    #[serde(default)]
+    pub crash_disable_threshold: u32,
+    #[serde(default)]
+    pub restart_early_readers: Vec<String>,
+    #[serde(default = "default_uninstall_timeout_secs")]
+    pub uninstall_timeout_secs: u64,
+    #[serde(default)]
+    pub staging_root: Option<PathBuf>,
+    #[serde(default)]
+    pub staging_tmpfs_mb: u64,
+    #[serde(default = "default_staging_image_mb")]
+    pub staging_image_mb: u64,
+    #[serde(default)]
+    pub integrity_hash: HashAlgorithm,
+    #[serde(default)]
+    pub verify_staging: bool,
+    #[serde(default)]
+    pub verify_selinux: bool,
+    #[serde(default = "sepolicy::default_consumers")]
+    pub selinux_consumers: Vec<String>,
+    #[serde(default)]
+    pub metrics_file: Option<PathBuf>,
+    #[serde(default)]
+    pub low_battery_threshold: u8,
+    #[serde(default)]
+    pub low_memory: bool,
+    #[serde(default)]
+    pub memory_ceiling_mb: u64,
+    #[serde(default)]
+    pub path_rewrites: Vec<PathRewrite>,
+    #[serde(default)]
+    pub boot_reason_skips: Vec<BootReasonSkip>,
+    #[serde(default)]
+    pub staging_budget_module_mb: u64,
+    #[serde(default)]
+    pub staging_budget_total_mb: u64,
+    #[serde(default)]
+    pub staging_budget_policy: BudgetPolicy,
+    #[serde(default)]
+    pub hymofs_fallback: HymoFallback,
+    #[serde(default)]
+    pub mount_propagation: PropagationConfig,
+    #[serde(default = "default_backend_order")]
+    pub backend_order: Vec<BackendKind>,
+    #[serde(default)]
+    pub partition_backends: BTreeMap<String, BackendKind>,
+    #[serde(default)]
+    pub partition_mounts: BTreeMap<String, PartitionMount>,
+    #[serde(default)]
+    pub pinned_backend: Option<BackendKind>,
+    #[serde(default)]
+    pub disable_backend_learning: bool,
+    #[serde(default)]
+    pub deterministic: bool,
+    #[serde(default)]
+    pub remote_control: RemoteControl,
+    #[serde(default)]
+    pub conflict_policy: ConflictPolicy,
+    /// Modules whose files win when several ship the same path, highest
+    /// priority first.
+    #[serde(default)]
+    pub module_priority: Vec<String>,
+    #[serde(default)]
+    pub selinux_labels: SelinuxLabels,
+    #[serde(default)]
+    pub module_watch: ModuleWatch,
+    #[serde(default)]
+    pub namespace_scope: NamespaceScope,
+    #[serde(default)]
+    pub boot_guard: BootGuard,
+    #[serde(default)]
+    pub stealth: Stealth,
+    #[serde(default)]
+    pub boot_stages: BootStages,
+    /// Paths no module may replace or hide.
+    #[serde(default = "protect::default_paths")]
+    pub protected_paths: Vec<PathBuf>,
+    #[serde(default)]
+    pub symlink_policy: SymlinkPolicy,
 }
 fn default_moduledir() -> PathBuf {
     PathBuf::from("/data/adb/modules/")
@@ -32,6 +133,22 @@ fn default_moduledir() -> PathBuf {
 fn default_mountsource() -> String {
     String::from("KSU")
 }
+fn default_hymofs_device() -> PathBuf {
+    PathBuf::from("/dev/hymo_ctl")
+}
+fn default_staging_image_mb() -> u64 {
+    2048
+}
+
+fn default_hymofs_rule_budget() -> usize {
+    crate::defs::DEFAULT_HYMOFS_RULE_BUDGET
+}
+fn default_crash_window_secs() -> u64 {
+    3600
+}
+fn default_uninstall_timeout_secs() -> u64 {
+    30
+}
 fn deserialize_partitions_flexible<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
 where
     D: serde::Deserializer<'de>,
@@ -55,6 +172,7 @@ impl Default for Config {
     fn default() -> Self {
         Self {
             moduledir: default_moduledir(),
+            module_roots: Vec::new(),
             tempdir: None,
             mountsource: default_mountsource(),
             verbose: false,
@@ -64,20 +182,191 @@ impl Default for Config {
             disable_umount: false,
             allow_umount_coexistence: false,
             dry_run: false,
+            hymofs_device: default_hymofs_device(),
+            hymofs_clear_foreign: false,
+            hymofs_rule_budget: default_hymofs_rule_budget(),
+            partial_apply_policy: PartialApplyPolicy::default(),
+            writable_partition_policy: WritablePolicy::default(),
+            disable_arch_filter: false,
+            hide_stale_oat: false,
+            crash_window_secs: default_crash_window_secs(),
+            crash_disable_threshold: 0,
+            restart_early_readers: Vec::new(),
+            uninstall_timeout_secs: default_uninstall_timeout_secs(),
+            staging_root: None,
+            staging_tmpfs_mb: 0,
+            staging_image_mb: default_staging_image_mb(),
+            integrity_hash: HashAlgorithm::default(),
+            verify_staging: false,
+            verify_selinux: false,
+            selinux_consumers: sepolicy::default_consumers(),
+            metrics_file: None,
+            low_battery_threshold: 0,
+            low_memory: false,
+            memory_ceiling_mb: 0,
+            path_rewrites: Vec::new(),
+            boot_reason_skips: Vec::new(),
+            staging_budget_module_mb: 0,
+            staging_budget_total_mb: 0,
+            staging_budget_policy: BudgetPolicy::default(),
+            hymofs_fallback: HymoFallback::default(),
+            mount_propagation: PropagationConfig::default(),
+            backend_order: default_backend_order(),
+            partition_backends: BTreeMap::new(),
+            partition_mounts: BTreeMap::new(),
+            pinned_backend: None,
+            disable_backend_learning: false,
+            // Tests compare whole plans, which needs them built the same way
+            // on every run.
+            deterministic: cfg!(test),
+            remote_control: RemoteControl::default(),
+            conflict_policy: ConflictPolicy::default(),
+            module_priority: Vec::new(),
+            selinux_labels: SelinuxLabels::default(),
+            module_watch: ModuleWatch::default(),
+            namespace_scope: NamespaceScope::default(),
+            boot_guard: BootGuard::default(),
+            stealth: Stealth::default(),
+            boot_stages: BootStages::default(),
+            protected_paths: protect::default_paths(),
+            symlink_policy: SymlinkPolicy::default(),
         }
     }
 }
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "json")
+}
 impl Config {
+    /// Loads the TOML, or JSON by its extension, config at `path`; keys it
+    /// does not know are logged and ignored.
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let content = fs::read_to_string(path.as_ref()).context("failed to read config file")?;
-        let config: Config = toml::from_str(&content).context("failed to parse config file")?;
+        let path = path.as_ref();
+        let (config, unknown) = Self::check_file(path)?;
+        for key in unknown {
+            log::warn!("Unknown config key `{}` in {}, ignored", key, path.display());
+        }
         Ok(config)
     }
+    /// Loads and validates the config at `path`; returns it with the keys it
+    /// does not know.
+    pub fn check_file(path: &Path) -> Result<(Self, Vec<String>)> {
+        if !is_json(path) {
+            if let Err(e) = migrate::migrate_config_file(path) {
+                log::warn!("Config migration failed: {:#}", e);
+            }
+        }
+        let content = fs::read_to_string(path).with_context(|| format!("failed to read config file {}", path.display()))?;
+        let (config, unknown) = Self::parse(&content, is_json(path)).with_context(|| format!("failed to parse config file {}", path.display()))?;
+        config.validate().with_context(|| format!("invalid config file {}", path.display()))?;
+        Ok((config, unknown))
+    }
+    fn parse(content: &str, json: bool) -> Result<(Self, Vec<String>)> {
+        let mut unknown = Vec::new();
+        let note = |key: serde_ignored::Path| {
+            let key = key.to_string();
+            if key != migrate::VERSION_KEY {
+                unknown.push(key);
+            }
+        };
+        let config = if json {
+            serde_ignored::deserialize(&mut serde_json::Deserializer::from_str(content), note)?
+        } else {
+            serde_ignored::deserialize(toml::Deserializer::parse(content)?, note)?
+        };
+        Ok((config, unknown))
+    }
+    /// Every value that cannot work, as `key: problem`.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut absolute = |key: &str, path: &Path| {
+            if !path.is_absolute() {
+                problems.push(format!("{}: {} is not an absolute path", key, path.display()));
+            }
+        };
+        absolute("moduledir", &self.moduledir);
+        self.module_roots.iter().for_each(|root| absolute("module_roots", root));
+        self.tempdir.iter().for_each(|dir| absolute("tempdir", dir));
+        self.staging_root.iter().for_each(|dir| absolute("staging_root", dir));
+        absolute("hymofs_device", &self.hymofs_device);
+        self.protected_paths.iter().for_each(|path| absolute("protected_paths", path));
+        self.stealth.hide_paths.iter().for_each(|path| absolute("stealth.hide_paths", path));
+        for rewrite in &self.path_rewrites {
+            absolute("path_rewrites", Path::new(&rewrite.from));
+            absolute("path_rewrites", Path::new(&rewrite.to));
+        }
+
+        if self.mountsource.trim().is_empty() {
+            problems.push("mountsource: must not be empty".to_string());
+        }
+        let partitions = self.partitions.iter()
+            .chain(self.partition_backends.keys())
+            .chain(self.partition_mounts.keys());
+        for name in partitions {
+            if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+                problems.push(format!("partitions: {:?} is not a partition name", name));
+            }
+        }
+        if self.hymofs_rule_budget == 0 {
+            problems.push("hymofs_rule_budget: must be at least 1".to_string());
+        }
+        if self.staging_image_mb == 0 {
+            problems.push("staging_image_mb: must be at least 1".to_string());
+        }
+        if self.staging_tmpfs_mb > 0 && self.staging_budget_total_mb > self.staging_tmpfs_mb {
+            problems.push(format!("staging_budget_total_mb: {} exceeds staging_tmpfs_mb {}", self.staging_budget_total_mb, self.staging_tmpfs_mb));
+        }
+        if self.low_battery_threshold > 100 {
+            problems.push(format!("low_battery_threshold: {} is not a percentage", self.low_battery_threshold));
+        }
+        if self.crash_disable_threshold > 0 && self.crash_window_secs == 0 {
+            problems.push("crash_window_secs: must be at least 1 while crash_disable_threshold is set".to_string());
+        }
+        if self.backend_order.is_empty() {
+            problems.push("backend_order: must name at least one backend".to_string());
+        }
+        if let Some(backend) = duplicate(&self.backend_order) {
+            problems.push(format!("backend_order: {:?} is listed twice", backend));
+        }
+        if let Some(module) = duplicate(&self.module_priority) {
+            problems.push(format!("module_priority: {} is listed twice", module));
+        }
+        if self.module_watch.debounce_ms > self.module_watch.max_delay_ms {
+            problems.push("module_watch: debounce_ms exceeds max_delay_ms".to_string());
+        }
+        if self.boot_guard.max_failed_boots == 0 {
+            problems.push("boot_guard.max_failed_boots: must be at least 1".to_string());
+        }
+        for name in &self.boot_stages.early_partitions {
+            if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+                problems.push(format!("boot_stages.early_partitions: {:?} is not a partition name", name));
+            }
+        }
+        for package in &self.stealth.packages {
+            if let Err(e) = apps::validate_package(package) {
+                problems.push(format!("stealth.packages: {}", e));
+            }
+        }
+        if self.remote_control.enabled && self.remote_control.port == 0 {
+            problems.push("remote_control.port: must be set while remote_control is enabled".to_string());
+        }
+        problems
+    }
+    pub fn validate(&self) -> Result<()> {
+        let problems = self.problems();
+        if !problems.is_empty() {
+            bail!("{}", problems.join("; "));
+        }
+        Ok(())
+    }
     pub fn load_default() -> Result<Self> {
         Self::from_file(CONFIG_FILE_DEFAULT)
     }
     pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
-        let content = toml::to_string_pretty(self).context("failed to serialize config")?;
+        let mut doc = toml::Value::try_from(self).context("failed to serialize config")?;
+        if let Some(table) = doc.as_table_mut() {
+            table.insert(migrate::VERSION_KEY.to_string(), toml::Value::Integer(Document::Config.current() as i64));
+        }
+        let content = toml::to_string_pretty(&doc).context("failed to serialize config")?;
         if let Some(parent) = path.as_ref().parent() {
             fs::create_dir_all(parent).context("failed to create config directory")?;
         }
@@ -101,3 +390,54 @@ impl Config {
         if dry_run { self.dry_run = true; }
     }
 }
+fn duplicate<T: PartialEq>(items: &[T]) -> Option<&T> {
+    items.iter().enumerate().find(|(i, item)| items[..*i].contains(item)).map(|(_, item)| item)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_either_format_and_reports_unknown_keys() {
+        let (config, unknown) = Config::parse("verbose = true\nformat_version = 3\nverbos = false\n[boot_guard]\nmax_faild_boots = 1\n", false).unwrap();
+        assert!(config.verbose);
+        assert_eq!(unknown, ["boot_guard.max_faild_boots", "verbos"]);
+
+        let (config, unknown) = Config::parse(r#"{"partitions": "vendor, odm", "module_priority": ["a"]}"#, true).unwrap();
+        assert_eq!(config.partitions, ["vendor", "odm"]);
+        assert_eq!(config.module_priority, ["a"]);
+        assert!(unknown.is_empty());
+
+        let err = Config::parse("verbose = \"yes\"\n", false).unwrap_err();
+        assert!(format!("{:#}", err).contains("line 1"), "{:#}", err);
+    }
+
+    #[test]
+    fn lists_every_invalid_value() {
+        assert!(Config::default().problems().is_empty());
+        let config = Config {
+            moduledir: PathBuf::from("modules"),
+            partitions: vec!["vendor".into(), "my/part".into()],
+            staging_tmpfs_mb: 64,
+            staging_budget_total_mb: 128,
+            low_battery_threshold: 120,
+            backend_order: vec![BackendKind::Magic, BackendKind::Overlay, BackendKind::Magic],
+            boot_guard: BootGuard { max_failed_boots: 0, ..BootGuard::default() },
+            stealth: Stealth { packages: vec!["bank".into()], ..Stealth::default() },
+            boot_stages: BootStages { early_partitions: vec!["..".into()], ..BootStages::default() },
+            ..Config::default()
+        };
+        assert_eq!(config.problems(), [
+            "moduledir: modules is not an absolute path",
+            "partitions: \"my/part\" is not a partition name",
+            "staging_budget_total_mb: 128 exceeds staging_tmpfs_mb 64",
+            "low_battery_threshold: 120 is not a percentage",
+            "backend_order: Magic is listed twice",
+            "boot_guard.max_failed_boots: must be at least 1",
+            "boot_stages.early_partitions: \"..\" is not a partition name",
+            "stealth.packages: Invalid package name \"bank\"",
+        ]);
+        assert!(config.validate().is_err());
+    }
+}
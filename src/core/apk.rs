@@ -0,0 +1,281 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use anyhow::{Result, bail};
+use rustix::fs::{AtFlags, StatxAttributes, StatxFlags, CWD};
+use crate::mount::hymofs::RuleOp;
+
+const OAT_EXTENSIONS: [&str; 3] = ["odex", "vdex", "art"];
+const EOCD_SIGNATURE: u32 = 0x0605_4b50;
+const CENTRAL_SIGNATURE: u32 = 0x0201_4b50;
+const LOCAL_SIGNATURE: u32 = 0x0403_4b50;
+const EOCD_MIN_LEN: u64 = 22;
+const EOCD_SEARCH_LEN: u64 = EOCD_MIN_LEN + u16::MAX as u64;
+
+#[derive(Debug)]
+pub struct ApkFinding {
+    pub target: String,
+    pub message: String,
+}
+
+#[derive(Debug, Default)]
+pub struct ApkInspection {
+    pub findings: Vec<ApkFinding>,
+    pub companion_ops: Vec<RuleOp>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct MisalignedEntry {
+    pub name: String,
+    pub offset: u64,
+    pub required: u64,
+}
+
+pub fn is_apk(path: &str) -> bool {
+    path.ends_with(".apk")
+}
+
+pub fn is_fsverity_protected(path: &Path) -> bool {
+    match rustix::fs::statx(CWD, path, AtFlags::empty(), StatxFlags::BASIC_STATS) {
+        Ok(stx) => stx.stx_attributes_mask.contains(StatxAttributes::VERITY)
+            && stx.stx_attributes.contains(StatxAttributes::VERITY),
+        Err(_) => false,
+    }
+}
+
+pub fn oat_artifact_paths(apk: &Path, isa: &str) -> Vec<PathBuf> {
+    let (Some(dir), Some(stem)) = (apk.parent(), apk.file_stem()) else {
+        return Vec::new();
+    };
+    let stem = stem.to_string_lossy();
+    OAT_EXTENSIONS.iter()
+        .map(|ext| dir.join("oat").join(isa).join(format!("{}.{}", stem, ext)))
+        .collect()
+}
+
+fn existing_oat_artifacts(apk: &Path) -> Vec<PathBuf> {
+    let Some(oat_dir) = apk.parent().map(|d| d.join("oat")) else {
+        return Vec::new();
+    };
+    let Ok(entries) = fs::read_dir(oat_dir) else {
+        return Vec::new();
+    };
+    entries.flatten()
+        .filter(|e| e.path().is_dir())
+        .flat_map(|e| oat_artifact_paths(apk, &e.file_name().to_string_lossy()))
+        .filter(|p| p.exists())
+        .collect()
+}
+
+fn required_alignment(name: &str) -> u64 {
+    if name.ends_with(".so") { 4096 } else { 4 }
+}
+
+/// Out-of-range reads yield zero, which never matches a zip signature, so a
+/// truncated archive is reported as corrupt instead of panicking.
+fn read_u16(buf: &[u8], at: usize) -> u16 {
+    buf.get(at..at + 2).and_then(|b| b.try_into().ok()).map_or(0, u16::from_le_bytes)
+}
+
+fn read_u32(buf: &[u8], at: usize) -> u32 {
+    buf.get(at..at + 4).and_then(|b| b.try_into().ok()).map_or(0, u32::from_le_bytes)
+}
+
+pub fn misaligned_entries<R: Read + Seek>(reader: &mut R) -> Result<Vec<MisalignedEntry>> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    if len < EOCD_MIN_LEN {
+        bail!("file too short to be a zip archive");
+    }
+    let tail_len = len.min(EOCD_SEARCH_LEN);
+    reader.seek(SeekFrom::Start(len - tail_len))?;
+    let mut tail = vec![0u8; tail_len as usize];
+    reader.read_exact(&mut tail)?;
+    let Some(eocd) = (0..=tail.len() - EOCD_MIN_LEN as usize)
+        .rev()
+        .find(|&i| read_u32(&tail, i) == EOCD_SIGNATURE)
+    else {
+        bail!("end of central directory not found");
+    };
+    let entries = read_u16(&tail, eocd + 10) as usize;
+    let cd_size = read_u32(&tail, eocd + 12) as usize;
+    let cd_offset = read_u32(&tail, eocd + 16) as u64;
+
+    if cd_offset.saturating_add(cd_size as u64) > len {
+        bail!("central directory extends past the end of the archive");
+    }
+    reader.seek(SeekFrom::Start(cd_offset))?;
+    let mut cd = vec![0u8; cd_size];
+    reader.read_exact(&mut cd)?;
+
+    let mut misaligned = Vec::new();
+    let mut pos = 0;
+    for _ in 0..entries {
+        if pos + 46 > cd.len() || read_u32(&cd, pos) != CENTRAL_SIGNATURE {
+            bail!("corrupt central directory entry at {}", pos);
+        }
+        let method = read_u16(&cd, pos + 10);
+        let name_len = read_u16(&cd, pos + 28) as usize;
+        let extra_len = read_u16(&cd, pos + 30) as usize;
+        let comment_len = read_u16(&cd, pos + 32) as usize;
+        let local_offset = read_u32(&cd, pos + 42) as u64;
+        let name_end = (pos + 46 + name_len).min(cd.len());
+        let name = String::from_utf8_lossy(cd.get(pos + 46..name_end).unwrap_or_default()).into_owned();
+        pos += 46 + name_len + extra_len + comment_len;
+
+        if method != 0 || name.ends_with('/') {
+            continue;
+        }
+        let mut local = [0u8; 30];
+        reader.seek(SeekFrom::Start(local_offset))?;
+        reader.read_exact(&mut local)?;
+        if read_u32(&local, 0) != LOCAL_SIGNATURE {
+            bail!("corrupt local header for {}", name);
+        }
+        let offset = local_offset + 30 + read_u16(&local, 26) as u64 + read_u16(&local, 28) as u64;
+        let required = required_alignment(&name);
+        if !offset.is_multiple_of(required) {
+            misaligned.push(MisalignedEntry { name, offset, required });
+        }
+    }
+    Ok(misaligned)
+}
+
+pub fn inspect(ops: &[RuleOp], hide_stale_oat: bool) -> ApkInspection {
+    let mut inspection = ApkInspection::default();
+    let shipped: HashSet<&str> = ops.iter()
+        .filter_map(|op| match op {
+            RuleOp::Add { src, .. } => Some(src.as_str()),
+            _ => None,
+        })
+        .collect();
+
+    for op in ops {
+        let RuleOp::Add { src, target, .. } = op else { continue };
+        if !is_apk(src) {
+            continue;
+        }
+        let mut note = |message: String| inspection.findings.push(ApkFinding {
+            target: src.clone(),
+            message,
+        });
+
+        match File::open(target).map_err(anyhow::Error::from).and_then(|mut f| misaligned_entries(&mut f)) {
+            Ok(entries) => {
+                if let Some(first) = entries.first() {
+                    note(format!("{} stored entries are not zipaligned (e.g. {} at offset {}, needs {})",
+                        entries.len(), first.name, first.offset, first.required));
+                }
+            }
+            Err(e) => note(format!("Replacement APK could not be read as a zip archive: {}", e)),
+        }
+
+        let original = Path::new(src);
+        if !original.exists() {
+            continue;
+        }
+        if is_fsverity_protected(original) {
+            note("Original APK is fs-verity protected; PackageManager may reject the replacement".to_string());
+        }
+        let stale = existing_oat_artifacts(original)
+            .into_iter()
+            .filter(|p| !shipped.contains(p.to_string_lossy().as_ref()))
+            .collect::<Vec<_>>();
+        if stale.is_empty() {
+            continue;
+        }
+        note(format!("{} preopt artifacts of the original APK would be stale ({})",
+            stale.len(), stale.iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join(", ")));
+        if hide_stale_oat {
+            inspection.companion_ops.extend(stale.into_iter().map(|p| RuleOp::Hide {
+                path: p.to_string_lossy().into_owned(),
+            }));
+        }
+    }
+    inspection
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn zip_with_stored_entry(name: &str, pad: usize) -> Vec<u8> {
+        let mut out = vec![0u8; pad];
+        let local_offset = out.len() as u32;
+        out.extend_from_slice(&LOCAL_SIGNATURE.to_le_bytes());
+        out.extend_from_slice(&[0u8; 22]);
+        out.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(b"data");
+
+        let cd_offset = out.len() as u32;
+        let mut central = Vec::new();
+        central.extend_from_slice(&CENTRAL_SIGNATURE.to_le_bytes());
+        central.extend_from_slice(&[0u8; 24]);
+        central.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        central.extend_from_slice(&[0u8; 12]);
+        central.extend_from_slice(&local_offset.to_le_bytes());
+        central.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(&central);
+
+        out.extend_from_slice(&EOCD_SIGNATURE.to_le_bytes());
+        out.extend_from_slice(&[0u8; 4]);
+        out.extend_from_slice(&1u16.to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes());
+        out.extend_from_slice(&(central.len() as u32).to_le_bytes());
+        out.extend_from_slice(&cd_offset.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out
+    }
+
+    #[test]
+    fn detects_misaligned_stored_entries() {
+        let zip = zip_with_stored_entry("classes.dex", 1);
+        let found = misaligned_entries(&mut Cursor::new(zip)).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].name, "classes.dex");
+        assert_eq!(found[0].required, 4);
+    }
+
+    #[test]
+    fn accepts_aligned_entries() {
+        let zip = zip_with_stored_entry("classes.dex", 3);
+        assert!(misaligned_entries(&mut Cursor::new(zip)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn native_libs_need_page_alignment() {
+        let zip = zip_with_stored_entry("lib/arm64-v8a/libfoo.so", 10);
+        let found = misaligned_entries(&mut Cursor::new(zip)).unwrap();
+        assert_eq!(found[0].required, 4096);
+    }
+
+    #[test]
+    fn rejects_non_zip() {
+        assert!(misaligned_entries(&mut Cursor::new(vec![0u8; 64])).is_err());
+    }
+
+    #[test]
+    fn corrupt_directory_sizes_are_errors() {
+        let mut zip = zip_with_stored_entry("classes.dex", 1);
+        let eocd = zip.len() - 22;
+        zip[eocd + 12..eocd + 16].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(misaligned_entries(&mut Cursor::new(zip)).is_err());
+        let mut zip = zip_with_stored_entry("classes.dex", 1);
+        let eocd = zip.len() - 22;
+        zip[eocd + 10..eocd + 12].copy_from_slice(&9u16.to_le_bytes());
+        assert!(misaligned_entries(&mut Cursor::new(zip)).is_err());
+    }
+
+    #[test]
+    fn builds_oat_artifact_paths() {
+        let paths = oat_artifact_paths(Path::new("/system/priv-app/Foo/Foo.apk"), "arm64");
+        assert_eq!(paths, vec![
+            PathBuf::from("/system/priv-app/Foo/oat/arm64/Foo.odex"),
+            PathBuf::from("/system/priv-app/Foo/oat/arm64/Foo.vdex"),
+            PathBuf::from("/system/priv-app/Foo/oat/arm64/Foo.art"),
+        ]);
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use anyhow::{Context, Result, bail};
+use crate::{core::{mntns, namespace, rules::RuleLedger}, mount::hymofs::HymoFs};
+
+const PACKAGES_LIST_FILE: &str = "/data/system/packages.list";
+
+/// Android user ids are `user * 100000 + app id`; apps take the range below.
+const PER_USER_RANGE: u32 = 100_000;
+const FIRST_APP_ID: u32 = 10_000;
+const LAST_APP_ID: u32 = 19_999;
+
+/// Package names are dot-separated Java identifiers, e.g. `com.example.app`.
+pub fn validate_package(package: &str) -> Result<()> {
+    let valid = package.contains('.')
+        && package.split('.').all(|part| {
+            part.chars().next().is_some_and(|c| c.is_ascii_alphabetic())
+                && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        });
+    if !valid {
+        bail!("Invalid package name {:?}", package);
+    }
+    Ok(())
+}
+
+/// Ties the rules `owner` holds on `paths` to `package`; paths it does not
+/// hold are skipped. Returns how many were tied.
+pub fn bind(ledger: &mut RuleLedger, owner: &str, paths: &[String], package: &str) -> Result<usize> {
+    validate_package(package)?;
+    let held: Vec<&String> = paths.iter().filter(|p| ledger.namespace_of(p) == Some(owner)).collect();
+    for path in &held {
+        ledger.packages.insert(path.to_string(), package.to_string());
+    }
+    Ok(held.len())
+}
+
+/// Installed package names from a `packages.list` listing, whose first
+/// column is the package.
+pub fn installed(listing: &str) -> HashSet<&str> {
+    listing.lines().filter_map(|line| line.split_whitespace().next()).collect()
+}
+
+/// The uid `package` runs as in a `packages.list` listing, its second
+/// column.
+pub fn uid_in(listing: &str, package: &str) -> Option<u32> {
+    listing.lines()
+        .map(|line| line.split_whitespace())
+        .find_map(|mut fields| (fields.next() == Some(package)).then(|| fields.next()?.parse().ok()).flatten())
+}
+
+pub fn uid_of_package(package: &str) -> Result<u32> {
+    validate_package(package)?;
+    let listing = fs::read_to_string(PACKAGES_LIST_FILE).with_context(|| format!("Cannot read {}", PACKAGES_LIST_FILE))?;
+    uid_in(&listing, package).with_context(|| format!("{} is not installed", package))
+}
+
+/// The real uid in a `/proc/<pid>/status` listing.
+fn uid_of(status: &str) -> Option<u32> {
+    status.lines()
+        .find_map(|line| line.strip_prefix("Uid:"))
+        .and_then(|ids| ids.split_whitespace().next())
+        .and_then(|uid| uid.parse().ok())
+}
+
+pub fn is_app(uid: u32) -> bool {
+    (FIRST_APP_ID..=LAST_APP_ID).contains(&(uid % PER_USER_RANGE))
+}
+
+/// The package a `/proc/<pid>/cmdline` belongs to, without the process
+/// name suffix of secondary processes such as `com.example.app:remote`.
+fn package_of(cmdline: &[u8]) -> &str {
+    let name = cmdline.split(|b| *b == 0).next().unwrap_or_default();
+    let name = std::str::from_utf8(name).unwrap_or_default();
+    name.split(':').next().unwrap_or_default()
+}
+
+/// A running app process outside init's mount namespace.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppProcess {
+    pub pid: u32,
+    pub namespace: u64,
+    pub uid: u32,
+    pub package: String,
+}
+
+/// Every app process in a mount namespace of its own, by ascending pid.
+pub fn processes() -> Result<Vec<AppProcess>> {
+    let global = mntns::namespace_of(1)?;
+    let mut found = Vec::new();
+    for entry in fs::read_dir("/proc").context("Cannot list /proc")?.flatten() {
+        let Some(pid) = entry.file_name().to_str().and_then(|name| name.parse::<u32>().ok()) else { continue };
+        let Some(uid) = fs::read_to_string(entry.path().join("status")).ok().as_deref().and_then(uid_of) else { continue };
+        if !is_app(uid) {
+            continue;
+        }
+        let Ok(namespace) = mntns::namespace_of(pid) else { continue };
+        if namespace != global {
+            let cmdline = fs::read(entry.path().join("cmdline")).unwrap_or_default();
+            found.push(AppProcess { pid, namespace, uid, package: package_of(&cmdline).to_string() });
+        }
+    }
+    found.sort_by_key(|process| process.pid);
+    Ok(found)
+}
+
+/// Rules tied to packages that are no longer installed, grouped by the
+/// namespace that holds them.
+pub fn orphaned(ledger: &RuleLedger, installed: &HashSet<&str>) -> BTreeMap<String, Vec<String>> {
+    let mut found: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (path, package) in &ledger.packages {
+        if installed.contains(package.as_str()) {
+            continue;
+        }
+        if let Some(owner) = ledger.namespace_of(path) {
+            found.entry(owner.to_string()).or_default().push(path.clone());
+        }
+    }
+    found
+}
+
+/// Removes the rules of uninstalled packages. Nothing is removed when the
+/// package list cannot be read, since every package would look gone.
+pub fn sweep(handle: &HymoFs, ledger: &mut RuleLedger) -> Result<usize> {
+    if ledger.packages.is_empty() {
+        return Ok(0);
+    }
+    let listing = fs::read_to_string(PACKAGES_LIST_FILE)?;
+    let mut removed = 0;
+    for (owner, paths) in orphaned(ledger, &installed(&listing)) {
+        log::info!(">> Package gone: removing {} rules of namespace {}", paths.len(), owner);
+        removed += namespace::remove(handle, ledger, &owner, &paths);
+    }
+    Ok(removed)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Sweeps once, then again every time the package manager rewrites its
+/// package list, checking every `interval`. Never returns.
+pub fn watch(handle: &HymoFs, interval: Duration) -> ! {
+    let mut seen = None;
+    loop {
+        let stamp = modified(Path::new(PACKAGES_LIST_FILE));
+        if stamp != seen {
+            seen = stamp;
+            let mut ledger = RuleLedger::load();
+            match sweep(handle, &mut ledger) {
+                Ok(0) => {}
+                Ok(removed) => {
+                    log::info!(">> Removed {} rules of uninstalled packages", removed);
+                    if let Err(e) = ledger.save() {
+                        log::warn!("Failed to save HymoFS rule ledger: {}", e);
+                    }
+                }
+                Err(e) => log::warn!("!! Package sweep failed: {:#}", e),
+            }
+        }
+        std::thread::sleep(interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mount::hymofs::RuleOp;
+
+    #[test]
+    fn validates_package_names() {
+        assert!(validate_package("com.example.app_2").is_ok());
+        assert!(validate_package("example").is_err());
+        assert!(validate_package("com..app").is_err());
+        assert!(validate_package("com.1app").is_err());
+        assert!(validate_package("com.app/../x").is_err());
+    }
+
+    #[test]
+    fn finds_rules_of_uninstalled_packages() {
+        let mut ledger = RuleLedger::default();
+        let hide = |path: &str| RuleOp::Hide { path: path.into() };
+        namespace::record(&mut ledger, "tool", &[hide("/system/a"), hide("/system/b")]).unwrap();
+        namespace::record(&mut ledger, "other", &[hide("/system/c")]).unwrap();
+        assert_eq!(bind(&mut ledger, "tool", &["/system/a".into(), "/system/c".into()], "com.example.gone").unwrap(), 1);
+        bind(&mut ledger, "other", &["/system/c".into()], "com.example.kept").unwrap();
+
+        let listing = "com.example.kept 10123 0 /data/user/0/com.example.kept default:targetSdkVersion=34 3003\n";
+        let found = orphaned(&ledger, &installed(listing));
+        assert_eq!(found, BTreeMap::from([("tool".to_string(), vec!["/system/a".to_string()])]));
+        assert_eq!(uid_in(listing, "com.example.kept"), Some(10123));
+        assert_eq!(uid_in(listing, "com.example.gone"), None);
+    }
+
+    #[test]
+    fn reads_app_processes() {
+        let status = "Name:\tapp\nUmask:\t0077\nUid:\t1010123\t1010123\t1010123\t1010123\n";
+        assert_eq!(uid_of(status), Some(1_010_123));
+        assert_eq!(uid_of("Name:\tinit\n"), None);
+        assert!(is_app(10_123) && is_app(1_010_123));
+        assert!(!is_app(0) && !is_app(1000) && !is_app(99_000));
+        assert_eq!(package_of(b"com.example.app:remote\0--flag\0"), "com.example.app");
+        assert_eq!(package_of(b""), "");
+    }
+}
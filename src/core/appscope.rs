@@ -0,0 +1,213 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use anyhow::{Context, Result, bail};
+use rustix::mount::mount_bind;
+use serde::{Deserialize, Serialize};
+use crate::{core::{apps, mntns}, defs, mount::hymofs::{HymoError, HymoFileType, HymoFs, RuleOp}};
+
+/// A rule only the processes of one app see.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScopedRule {
+    pub package: String,
+    pub op: RuleOp,
+}
+
+/// Per-app rules, kept across reboots like the modules they stand in for.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AppScopes {
+    #[serde(default)]
+    pub rules: Vec<ScopedRule>,
+}
+
+impl AppScopes {
+    pub fn load() -> Self {
+        Self::load_from(Path::new(defs::APP_SCOPES_FILE))
+    }
+
+    fn load_from(path: &Path) -> Self {
+        fs::read_to_string(path).ok().and_then(|s| serde_json::from_str(&s).ok()).unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Path::new(defs::APP_SCOPES_FILE);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Records `op` for `package`, replacing its earlier rule on the path.
+    fn record(&mut self, package: &str, op: &RuleOp) {
+        self.rules.retain(|rule| rule.package != package || rule.op.path() != op.path());
+        self.rules.push(ScopedRule { package: package.to_string(), op: op.clone() });
+    }
+
+    /// Forgets the rules of every app on `path`; returns how many went.
+    pub fn remove(&mut self, path: &str) -> usize {
+        let before = self.rules.len();
+        self.rules.retain(|rule| rule.op.path() != path);
+        before - self.rules.len()
+    }
+}
+
+/// How a per-app rule took effect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scoped {
+    /// The kernel limits it to the uid of the app.
+    Kernel,
+    /// hymod mounts it into the mount namespace of the app as it starts.
+    Emulated,
+}
+
+/// Applies `op` for `package` alone and records it. Kernels that cannot
+/// scope rules to a uid get it emulated, which only works for adds.
+pub fn add(handle: &HymoFs, scopes: &mut AppScopes, package: &str, op: &RuleOp) -> Result<Scoped> {
+    let uid = apps::uid_of_package(package)?;
+    let scoped = match handle.apply_scoped(op, uid) {
+        Ok(()) => Scoped::Kernel,
+        Err(HymoError::Unsupported(_)) if matches!(op, RuleOp::Add { .. }) => Scoped::Emulated,
+        Err(HymoError::Unsupported(reason)) => bail!("Cannot {} for {} alone: HymoFS {}", op.verb(), package, reason),
+        Err(e) => return Err(e.into()),
+    };
+    scopes.record(package, op);
+    Ok(scoped)
+}
+
+/// Adds `ops` for `package` alone and saves the record; returns how many
+/// took effect and how many failed.
+pub fn add_all(handle: &HymoFs, package: &str, ops: &[RuleOp]) -> Result<(usize, usize)> {
+    let mut scopes = AppScopes::load();
+    let (mut kernel, mut emulated, mut failed) = (0, 0, 0);
+    for op in ops {
+        match add(handle, &mut scopes, package, op) {
+            Ok(Scoped::Kernel) => kernel += 1,
+            Ok(Scoped::Emulated) => emulated += 1,
+            Err(e) => {
+                log::warn!("!! {:#}", e);
+                failed += 1;
+            }
+        }
+    }
+    scopes.save()?;
+    if emulated > 0 {
+        log::info!(">> HymoFS cannot scope rules to a uid: hymod mounts {} rules into {} as it starts", emulated, package);
+    }
+    Ok((kernel + emulated, failed))
+}
+
+/// Puts the recorded rules back into a kernel that scopes rules to a uid,
+/// as after a reboot; returns how many it took. Elsewhere hymod emulates
+/// them and nothing is done here.
+pub fn restore(handle: &HymoFs, scopes: &AppScopes) -> usize {
+    if !handle.caps().is_ok_and(|caps| caps.supports_uid_scope) {
+        return 0;
+    }
+    scopes.rules.iter()
+        .filter(|rule| {
+            let applied = apps::uid_of_package(&rule.package).and_then(|uid| Ok(handle.apply_scoped(&rule.op, uid)?));
+            if let Err(e) = &applied {
+                log::warn!("!! Per-app rule {} of {}: {:#}", rule.op.path(), rule.package, e);
+            }
+            applied.is_ok()
+        })
+        .count()
+}
+
+/// The adds of `rules` for `package` not yet mounted in `namespace`. A
+/// directory add is skipped, as a mount over it would shadow its entries.
+fn pending<'a>(rules: &'a [ScopedRule], package: &str, namespace: u64, done: &HashSet<(u64, String)>) -> Vec<&'a RuleOp> {
+    rules.iter()
+        .filter(|rule| rule.package == package && matches!(rule.op, RuleOp::Add { file_type, .. } if file_type != HymoFileType::Dir))
+        .filter(|rule| !done.contains(&(namespace, rule.op.path().to_string())))
+        .map(|rule| &rule.op)
+        .collect()
+}
+
+/// Bind-mounts the recorded adds into the mount namespace of every running
+/// process of their app that lacks them; returns how many were mounted.
+/// `done` remembers what each namespace already got, and tried rules are
+/// not retried there.
+pub fn inject(scopes: &AppScopes, done: &mut HashSet<(u64, String)>) -> Result<usize> {
+    if scopes.rules.is_empty() {
+        return Ok(0);
+    }
+    let processes = apps::processes()?;
+    let live: HashSet<u64> = processes.iter().map(|process| process.namespace).collect();
+    done.retain(|(namespace, _)| live.contains(namespace));
+    let mut mounted = 0;
+    for process in &processes {
+        let ops = pending(&scopes.rules, &process.package, process.namespace, done);
+        if ops.is_empty() {
+            continue;
+        }
+        let bound = mntns::enter(process.pid, || {
+            Ok(ops.iter()
+                .filter(|op| match op {
+                    RuleOp::Add { src, target, .. } => mount_bind(target.as_str(), src.as_str())
+                        .map_err(|e| log::debug!("Cannot mount {} over {} for {}: {}", target, src, process.package, e))
+                        .is_ok(),
+                    _ => false,
+                })
+                .count())
+        });
+        match bound {
+            Ok(count) => mounted += count,
+            Err(e) => log::debug!("Cannot enter the mount namespace of {} ({}): {:#}", process.package, process.pid, e),
+        }
+        done.extend(ops.iter().map(|op| (process.namespace, op.path().to_string())));
+    }
+    Ok(mounted)
+}
+
+/// Emulates per-app rules for apps as they start, checking every
+/// `interval`, on kernels that cannot scope rules themselves. Returns right
+/// away on kernels that can.
+pub fn keep_injected(handle: &HymoFs, interval: Duration) {
+    if handle.caps().is_ok_and(|caps| caps.supports_uid_scope) {
+        return;
+    }
+    let mut done = HashSet::new();
+    loop {
+        match inject(&AppScopes::load(), &mut done) {
+            Ok(0) => {}
+            Ok(count) => log::info!(">> Mounted {} per-app rules into app mount namespaces", count),
+            Err(e) => log::warn!("!! Per-app rule scan failed: {:#}", e),
+        }
+        std::thread::sleep(interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add_op(src: &str) -> RuleOp {
+        RuleOp::Add { src: src.into(), target: format!("/data/adb/modules/m{}", src), file_type: HymoFileType::Reg }
+    }
+
+    #[test]
+    fn records_one_rule_per_app_and_path() {
+        let mut scopes = AppScopes::default();
+        scopes.record("com.bank.app", &add_op("/system/etc/hosts"));
+        scopes.record("com.bank.app", &add_op("/system/etc/hosts"));
+        scopes.record("com.game.app", &add_op("/system/etc/hosts"));
+        scopes.record("com.bank.app", &RuleOp::Hide { path: "/system/app/Ads".into() });
+        scopes.record("com.bank.app", &RuleOp::Add { src: "/system/etc/x".into(), target: "/m/x".into(), file_type: HymoFileType::Dir });
+        assert_eq!(scopes.rules.len(), 4);
+
+        let mut done = HashSet::new();
+        assert_eq!(pending(&scopes.rules, "com.bank.app", 7, &done), [&add_op("/system/etc/hosts")]);
+        done.insert((7, "/system/etc/hosts".to_string()));
+        assert!(pending(&scopes.rules, "com.bank.app", 7, &done).is_empty());
+        assert_eq!(pending(&scopes.rules, "com.bank.app", 8, &done).len(), 1);
+        assert!(pending(&scopes.rules, "com.other.app", 8, &done).is_empty());
+
+        assert_eq!(scopes.remove("/system/etc/hosts"), 2);
+        let path = std::env::temp_dir().join(format!("appscope-{}.json", std::process::id()));
+        fs::write(&path, serde_json::to_string(&scopes).unwrap()).unwrap();
+        assert_eq!(AppScopes::load_from(&path).rules, scopes.rules);
+        let _ = fs::remove_file(&path);
+    }
+}
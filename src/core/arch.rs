@@ -0,0 +1,176 @@
+use std::ffi::OsStr;
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
+use crate::utils;
+
+const ABI_ISA_MAP: [(&str, &str); 6] = [
+    ("arm64-v8a", "arm64"),
+    ("armeabi-v7a", "arm"),
+    ("armeabi", "arm"),
+    ("x86_64", "x86_64"),
+    ("x86", "x86"),
+    ("riscv64", "riscv64"),
+];
+
+static FILTER: OnceLock<ArchFilter> = OnceLock::new();
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArchDecision {
+    Keep,
+    Skip,
+    Rename(&'static str),
+}
+
+#[derive(Debug, Default)]
+pub struct ArchFilter {
+    supported: Vec<String>,
+}
+
+impl ArchFilter {
+    pub fn detect() -> Self {
+        let abilist = utils::get_prop("ro.product.cpu.abilist")
+            .or_else(|| utils::get_prop("ro.product.cpu.abi"))
+            .unwrap_or_default();
+        Self::from_abilist(&abilist)
+    }
+
+    pub fn from_abilist(abilist: &str) -> Self {
+        let supported = abilist
+            .split(',')
+            .map(|abi| abi.trim().to_string())
+            .filter(|abi| !abi.is_empty())
+            .collect::<Vec<_>>();
+        if !supported.is_empty() {
+            log::debug!("Device ABIs: {}", supported.join(","));
+        }
+        Self { supported }
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.supported.is_empty()
+    }
+
+    fn supports_isa(&self, isa: &str) -> bool {
+        ABI_ISA_MAP.iter()
+            .filter(|(_, i)| *i == isa)
+            .any(|(abi, _)| self.supported.iter().any(|s| s == abi))
+    }
+
+    pub fn decide(&self, name: &str) -> ArchDecision {
+        if !self.is_active() {
+            return ArchDecision::Keep;
+        }
+        if let Some((abi, isa)) = ABI_ISA_MAP.iter().find(|(abi, _)| *abi == name) {
+            if !self.supported.iter().any(|s| s == abi) {
+                return ArchDecision::Skip;
+            }
+            if abi != isa {
+                return ArchDecision::Rename(isa);
+            }
+            return ArchDecision::Keep;
+        }
+        if ABI_ISA_MAP.iter().any(|(_, isa)| *isa == name) && !self.supports_isa(name) {
+            return ArchDecision::Skip;
+        }
+        ArchDecision::Keep
+    }
+
+    /// Decision for a direct child of a `lib` directory; an ABI dir whose ISA
+    /// sibling already exists is skipped so the explicit ISA dir wins.
+    pub fn resolve<F: Fn(&'static str) -> bool>(&self, name: &str, sibling_exists: F) -> ArchDecision {
+        match self.decide(name) {
+            ArchDecision::Rename(isa) if sibling_exists(isa) => ArchDecision::Skip,
+            decision => decision,
+        }
+    }
+
+    pub fn map_relative<F: Fn(&Path) -> bool>(&self, relative: &Path, exists: F) -> Option<PathBuf> {
+        if !self.is_active() {
+            return Some(relative.to_path_buf());
+        }
+        let mut original = PathBuf::new();
+        let mut mapped = PathBuf::new();
+        let mut parent_is_lib = false;
+        for component in relative.components() {
+            let Component::Normal(name) = component else {
+                original.push(component);
+                mapped.push(component);
+                parent_is_lib = false;
+                continue;
+            };
+            if parent_is_lib {
+                match self.resolve(&name.to_string_lossy(), |isa| exists(&original.join(isa))) {
+                    ArchDecision::Skip => return None,
+                    ArchDecision::Rename(isa) => mapped.push(isa),
+                    ArchDecision::Keep => mapped.push(name),
+                }
+            } else {
+                mapped.push(name);
+            }
+            original.push(name);
+            parent_is_lib = name == OsStr::new("lib");
+        }
+        Some(mapped)
+    }
+}
+
+pub fn init(enabled: bool) {
+    let _ = FILTER.set(if enabled { ArchFilter::detect() } else { ArchFilter::default() });
+}
+
+pub fn filter() -> &'static ArchFilter {
+    FILTER.get_or_init(ArchFilter::default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arm64_device() -> ArchFilter {
+        ArchFilter::from_abilist("arm64-v8a, armeabi-v7a,armeabi,")
+    }
+
+    #[test]
+    fn decides_by_device_abis() {
+        let filter = arm64_device();
+        assert_eq!(filter.decide("arm64-v8a"), ArchDecision::Rename("arm64"));
+        assert_eq!(filter.decide("armeabi-v7a"), ArchDecision::Rename("arm"));
+        assert_eq!(filter.decide("arm64"), ArchDecision::Keep);
+        assert_eq!(filter.decide("x86_64"), ArchDecision::Skip);
+        assert_eq!(filter.decide("x86"), ArchDecision::Skip);
+        assert_eq!(filter.decide("libfoo.so"), ArchDecision::Keep);
+    }
+
+    #[test]
+    fn inactive_filter_keeps_everything() {
+        let filter = ArchFilter::default();
+        assert_eq!(filter.decide("x86_64"), ArchDecision::Keep);
+        let path = Path::new("app/Foo/lib/x86_64/libfoo.so");
+        assert_eq!(filter.map_relative(path, |_| false), Some(path.to_path_buf()));
+    }
+
+    #[test]
+    fn maps_abi_dirs_under_lib_only() {
+        let filter = arm64_device();
+        assert_eq!(
+            filter.map_relative(Path::new("app/Foo/lib/arm64-v8a/libfoo.so"), |_| false),
+            Some(PathBuf::from("app/Foo/lib/arm64/libfoo.so"))
+        );
+        assert_eq!(filter.map_relative(Path::new("app/Foo/lib/x86_64/libfoo.so"), |_| false), None);
+        assert_eq!(
+            filter.map_relative(Path::new("etc/x86_64/foo.conf"), |_| false),
+            Some(PathBuf::from("etc/x86_64/foo.conf"))
+        );
+    }
+
+    #[test]
+    fn existing_isa_dir_wins_over_abi_dir() {
+        let filter = arm64_device();
+        let exists = |p: &Path| p == Path::new("app/Foo/lib/arm64");
+        assert_eq!(filter.map_relative(Path::new("app/Foo/lib/arm64-v8a/libfoo.so"), exists), None);
+        assert_eq!(
+            filter.map_relative(Path::new("app/Foo/lib/arm64/libfoo.so"), exists),
+            Some(PathBuf::from("app/Foo/lib/arm64/libfoo.so"))
+        );
+    }
+}
@@ -0,0 +1,99 @@
+use std::io::BufRead;
+use std::fs;
+use anyhow::{Result, bail};
+use crate::mount::hymofs::{FlushStats, HymoFileType, HymoFs, RuleOp, SharedHymoFs};
+
+const BATCH_TAG: &str = "batch";
+
+fn file_type_of(target: &str) -> HymoFileType {
+    fs::symlink_metadata(target)
+        .map(|m| HymoFileType::from(m.file_type()))
+        .unwrap_or(HymoFileType::Reg)
+}
+
+pub fn parse_line(line: &str) -> Result<Option<RuleOp>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    let fields = line.split_whitespace().collect::<Vec<_>>();
+    let op = match fields.as_slice() {
+        ["add", src, target] => RuleOp::Add {
+            src: src.to_string(),
+            target: target.to_string(),
+            file_type: file_type_of(target),
+        },
+        ["del" | "delete", src] => RuleOp::Delete { src: src.to_string() },
+        ["hide", path] => RuleOp::Hide { path: path.to_string() },
+        [cmd, ..] => bail!("unknown command or wrong arguments: '{}' ({} arguments)", cmd, fields.len() - 1),
+        [] => return Ok(None),
+    };
+    if !op.path().starts_with('/') {
+        bail!("path must be absolute: '{}'", op.path());
+    }
+    Ok(Some(op))
+}
+
+pub fn parse_script<R: BufRead>(reader: R) -> Result<Vec<RuleOp>> {
+    let mut ops = Vec::new();
+    let mut errors = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        match parse_line(&line?) {
+            Ok(Some(op)) => ops.push(op),
+            Ok(None) => {}
+            Err(e) => errors.push(format!("line {}: {}", index + 1, e)),
+        }
+    }
+    if !errors.is_empty() {
+        bail!("rejected batch, nothing applied:\n{}", errors.join("\n"));
+    }
+    Ok(ops)
+}
+
+pub fn run<R: BufRead>(hymofs: &HymoFs, reader: R) -> Result<FlushStats> {
+    let ops = parse_script(reader)?;
+    let shared = SharedHymoFs::new(hymofs.clone());
+    shared.enqueue(BATCH_TAG, ops);
+    shared.flush();
+    Ok(shared.take_stats().remove(BATCH_TAG).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(parse_line("del /system/bin/foo").unwrap(), Some(RuleOp::Delete { src: "/system/bin/foo".into() }));
+        assert_eq!(parse_line("  hide /system/app/Bloat  ").unwrap(), Some(RuleOp::Hide { path: "/system/app/Bloat".into() }));
+        match parse_line("add /system/bin/foo /data/adb/modules/m/system/bin/foo").unwrap() {
+            Some(RuleOp::Add { src, target, .. }) => {
+                assert_eq!(src, "/system/bin/foo");
+                assert_eq!(target, "/data/adb/modules/m/system/bin/foo");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn skips_blank_and_comment_lines() {
+        assert_eq!(parse_line("").unwrap(), None);
+        assert_eq!(parse_line("   # comment").unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_bad_lines() {
+        assert!(parse_line("add /only-one").is_err());
+        assert!(parse_line("remove /a").is_err());
+        assert!(parse_line("hide relative/path").is_err());
+    }
+
+    #[test]
+    fn script_errors_report_line_numbers() {
+        let script = "hide /a\nbogus\ndel /b\n";
+        let err = parse_script(script.as_bytes()).unwrap_err().to_string();
+        assert!(err.contains("line 2"));
+        let ok = parse_script("hide /a\n# note\ndel /b\n".as_bytes()).unwrap();
+        assert_eq!(ok.len(), 2);
+    }
+}
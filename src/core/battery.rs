@@ -0,0 +1,155 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use serde::Serialize;
+use crate::{conf::config::Config, core::{inventory::{BootWindow, Module}, partitions, planner::HymoOperation}};
+
+pub const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct BatteryStatus {
+    /// Charge in percent, when the kernel exposes a battery.
+    pub capacity: Option<u8>,
+    /// Whether the battery is charging or any charger is online.
+    pub charging: bool,
+}
+
+impl BatteryStatus {
+    /// Low enough that a long apply should wait. Devices without a readable
+    /// battery, and a threshold of 0, never are.
+    pub fn is_critical(&self, threshold: u8) -> bool {
+        threshold > 0 && !self.charging && self.capacity.is_some_and(|c| c < threshold)
+    }
+}
+
+fn read_attr(supply: &Path, name: &str) -> Option<String> {
+    fs::read_to_string(supply.join(name)).ok().map(|v| v.trim().to_string())
+}
+
+/// Reads the `power_supply` class under `dir`: capacity and status of the
+/// first battery, and whether any mains, USB or wireless supply is online.
+pub fn read(dir: &Path) -> BatteryStatus {
+    let mut status = BatteryStatus::default();
+    let Ok(entries) = fs::read_dir(dir) else {
+        return status;
+    };
+    let mut supplies: Vec<PathBuf> = entries.flatten().map(|e| e.path()).collect();
+    supplies.sort();
+    for supply in supplies {
+        match read_attr(&supply, "type").as_deref() {
+            Some("Battery") if status.capacity.is_none() => {
+                status.capacity = read_attr(&supply, "capacity").and_then(|c| c.parse().ok());
+                if matches!(read_attr(&supply, "status").as_deref(), Some("Charging") | Some("Full")) {
+                    status.charging = true;
+                }
+            }
+            Some("Mains") | Some("USB") | Some("Wireless") if read_attr(&supply, "online").as_deref() == Some("1") => {
+                status.charging = true;
+            }
+            _ => {}
+        }
+    }
+    status
+}
+
+/// Blocks until the battery is no longer critical.
+pub fn wait_until_safe(threshold: u8, interval: Duration) -> BatteryStatus {
+    loop {
+        let status = read(Path::new(POWER_SUPPLY_DIR));
+        if !status.is_critical(threshold) {
+            return status;
+        }
+        std::thread::sleep(interval);
+    }
+}
+
+/// Splits `modules` into the ones to mount now and the ids of the ones held
+/// back until the battery recovers; modules marked critical always mount.
+pub fn split_deferred(modules: Vec<Module>) -> (Vec<Module>, Vec<String>) {
+    let (now, later): (Vec<_>, Vec<_>) = modules.into_iter().partition(|m| m.rules.is_critical());
+    (now, later.into_iter().map(|m| m.id).collect())
+}
+
+/// Redirects that inject a deferred module through HymoFS once the battery
+/// allows it, from its staged copy when there is one.
+pub fn resume_ops(config: &Config, modules: &[Module], storage_root: &Path) -> Vec<HymoOperation> {
+    let mut ops = Vec::new();
+    for module in modules {
+        let staged = storage_root.join(&module.id);
+        let content = if staged.is_dir() { staged } else { module.source_path.clone() };
+        for partition in partitions::enabled(config) {
+            let source = content.join(partition);
+            if source.is_dir() {
+                ops.push(HymoOperation {
+                    module_id: module.id.clone(),
+                    source,
+                    target: partitions::target(config, partition),
+                    window: BootWindow::AfterBoot,
+                });
+            }
+        }
+    }
+    ops
+}
+
+/// Puts the `resume` operations where their modules rank among the
+/// HymoFS operations of the whole boot, so they get the priorities they
+/// would have had at boot. The operations of `ranked` not being resumed
+/// stay in place with no window, which leaves them alone.
+pub fn rank_resume_ops(ranked: &[HymoOperation], mut resume: Vec<HymoOperation>) -> Vec<HymoOperation> {
+    let mut ops: Vec<HymoOperation> = ranked.iter()
+        .map(|op| match resume.iter().position(|r| r.module_id == op.module_id && r.target == op.target) {
+            Some(i) => resume.remove(i),
+            None => HymoOperation { window: BootWindow::Always, ..op.clone() },
+        })
+        .collect();
+    ops.extend(resume);
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn supply(root: &Path, name: &str, attrs: &[(&str, &str)]) {
+        let dir = root.join(name);
+        fs::create_dir_all(&dir).unwrap();
+        for (attr, value) in attrs {
+            fs::write(dir.join(attr), format!("{}\n", value)).unwrap();
+        }
+    }
+
+    #[test]
+    fn resumed_ops_keep_their_rank() {
+        let op = |id: &str, source: &str, window| HymoOperation {
+            module_id: id.into(), source: PathBuf::from(source), target: PathBuf::from("/system"), window,
+        };
+        let ranked = [op("a", "/mnt/a/system", BootWindow::BootOnly), op("late", "/mnt/late/system", BootWindow::Always), op("b", "/mnt/b/system", BootWindow::Always)];
+        let resume = vec![op("late", "/data/adb/modules/late/system", BootWindow::AfterBoot), op("extra", "/mnt/extra/system", BootWindow::AfterBoot)];
+        let plan = crate::core::planner::MountPlan { hymo_ops: rank_resume_ops(&ranked, resume), ..Default::default() };
+        let windows: Vec<BootWindow> = plan.hymo_ops.iter().map(|op| op.window).collect();
+        assert_eq!(windows, [BootWindow::Always, BootWindow::AfterBoot, BootWindow::Always, BootWindow::AfterBoot]);
+        let priorities = plan.hymo_priorities();
+        assert_eq!(priorities.of("/data/adb/modules/late/system/bin/sh"), 2);
+        assert_eq!(priorities.of("/mnt/extra/system/bin/sh"), 4);
+    }
+
+    #[test]
+    fn reads_battery_and_chargers() {
+        let root = std::env::temp_dir().join(format!("battery-read-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        supply(&root, "battery", &[("type", "Battery"), ("capacity", "4"), ("status", "Discharging")]);
+        supply(&root, "usb", &[("type", "USB"), ("online", "0")]);
+        let status = read(&root);
+        assert_eq!(status, BatteryStatus { capacity: Some(4), charging: false });
+        assert!(status.is_critical(10));
+        assert!(!status.is_critical(0));
+        assert!(!status.is_critical(4));
+
+        supply(&root, "usb", &[("online", "1")]);
+        assert!(read(&root).charging);
+        assert!(!read(&root).is_critical(10));
+        assert_eq!(read(&root.join("missing")), BatteryStatus::default());
+        let _ = fs::remove_dir_all(&root);
+    }
+}
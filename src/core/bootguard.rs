@@ -0,0 +1,126 @@
+use std::fs;
+use std::path::Path;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use crate::utils;
+
+/// Set by init when the user holds the safe mode key at boot.
+pub const SAFEMODE_PROP: &str = "persist.sys.safemode";
+
+/// What a tripped guard does at boot.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GuardAction {
+    /// Injects nothing.
+    #[default]
+    Skip,
+    /// Also clears every HymoFS rule, including other consumers' ones.
+    Clear,
+}
+
+/// Protection against a module that keeps the device from booting.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BootGuard {
+    pub enabled: bool,
+    /// Boots in a row that may end before boot completion before the guard
+    /// trips.
+    pub max_failed_boots: u32,
+    pub action: GuardAction,
+    /// Also inject nothing when Android is booting into safe mode.
+    pub safe_mode: bool,
+}
+
+impl Default for BootGuard {
+    fn default() -> Self {
+        Self { enabled: true, max_failed_boots: 3, action: GuardAction::Skip, safe_mode: true }
+    }
+}
+
+/// Boot attempts as recorded across reboots.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BootAttempts {
+    /// An attempt is under way and has not reached boot completion.
+    pub pending: bool,
+    /// Attempts in a row that never reached it.
+    pub failed: u32,
+    /// The guard tripped; injection stays off until [`reset`].
+    pub tripped: bool,
+}
+
+impl BootAttempts {
+    pub fn load(path: &Path) -> Self {
+        fs::read_to_string(path).ok().and_then(|s| serde_json::from_str(&s).ok()).unwrap_or_default()
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string(self)?).with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+/// Records the start of a boot attempt, counting the previous one as failed
+/// if it never completed, and returns the record as it stands now.
+pub fn begin(guard: &BootGuard, path: &Path) -> Result<BootAttempts> {
+    let mut attempts = BootAttempts::load(path);
+    if attempts.pending {
+        attempts.failed += 1;
+    }
+    attempts.pending = true;
+    if guard.enabled && attempts.failed >= guard.max_failed_boots.max(1) {
+        attempts.tripped = true;
+    }
+    attempts.save(path)?;
+    Ok(attempts)
+}
+
+/// Marks the current attempt as having reached boot completion.
+pub fn complete(path: &Path) -> Result<()> {
+    let mut attempts = BootAttempts::load(path);
+    if !attempts.pending && attempts.failed == 0 {
+        return Ok(());
+    }
+    attempts.pending = false;
+    attempts.failed = 0;
+    attempts.save(path)
+}
+
+/// Re-arms a tripped guard so the next boot injects again.
+pub fn reset(path: &Path) -> Result<()> {
+    BootAttempts::default().save(path)
+}
+
+pub fn in_safe_mode() -> bool {
+    utils::get_prop(SAFEMODE_PROP).is_some_and(|value| value.trim() == "1")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trips_after_consecutive_unfinished_boots() {
+        let path = std::env::temp_dir().join(format!("bootguard-{}/attempts.json", std::process::id()));
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+        let guard = BootGuard { max_failed_boots: 2, ..BootGuard::default() };
+
+        assert_eq!(begin(&guard, &path).unwrap(), BootAttempts { pending: true, failed: 0, tripped: false });
+        complete(&path).unwrap();
+        assert!(!begin(&guard, &path).unwrap().tripped);
+        assert_eq!(begin(&guard, &path).unwrap().failed, 1);
+        let attempts = begin(&guard, &path).unwrap();
+        assert!(attempts.tripped && attempts.failed == 2);
+
+        complete(&path).unwrap();
+        assert!(begin(&guard, &path).unwrap().tripped, "a completed boot keeps the guard tripped");
+        reset(&path).unwrap();
+        assert_eq!(BootAttempts::load(&path), BootAttempts::default());
+
+        let disabled = BootGuard { enabled: false, max_failed_boots: 1, ..BootGuard::default() };
+        begin(&disabled, &path).unwrap();
+        assert!(!begin(&disabled, &path).unwrap().tripped);
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+}
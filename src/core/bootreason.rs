@@ -0,0 +1,127 @@
+use serde::{Deserialize, Serialize};
+use crate::{core::inventory::Module, utils};
+
+pub const BOOTREASON_PROP: &str = "ro.boot.bootreason";
+/// Pattern matching every reason that is not an ordinary reboot, shutdown
+/// or power-on.
+pub const UNEXPECTED: &str = "unexpected";
+
+/// Reasons the device boots with when nothing went wrong.
+const EXPECTED_REASONS: [&str; 7] = ["reboot", "shutdown", "cold", "warm", "bootloader", "recovery", "usb"];
+
+/// Why the device booted, as the bootloader reports it: a reason and
+/// optional comma-separated details, e.g. `kernel_panic,sysrq` or
+/// `reboot,userrequested`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BootReason {
+    raw: String,
+}
+
+impl BootReason {
+    pub fn parse(raw: &str) -> Self {
+        Self { raw: raw.trim().to_ascii_lowercase() }
+    }
+
+    pub fn read() -> Option<Self> {
+        utils::get_prop(BOOTREASON_PROP).map(|raw| Self::parse(&raw))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.raw
+    }
+
+    pub fn reason(&self) -> &str {
+        self.raw.split(',').next().unwrap_or_default()
+    }
+
+    pub fn is_unexpected(&self) -> bool {
+        !EXPECTED_REASONS.contains(&self.reason())
+    }
+
+    /// Whether `pattern` names this boot: the whole reason or its leading
+    /// components (`reboot` matches `reboot,ota`), a prefix ending in `*`
+    /// (`watchdog*` matches `watchdog_bark`), or [`UNEXPECTED`].
+    pub fn matches(&self, pattern: &str) -> bool {
+        let pattern = pattern.trim().to_ascii_lowercase();
+        if pattern == UNEXPECTED {
+            return self.is_unexpected();
+        }
+        match pattern.strip_suffix('*') {
+            Some(prefix) => self.raw.starts_with(prefix),
+            None => self.raw == pattern || self.raw.strip_prefix(&pattern).is_some_and(|rest| rest.starts_with(',')),
+        }
+    }
+}
+
+/// Modules to leave unmounted when the device booted for one of `reasons`,
+/// e.g. all of them after a watchdog reset so it comes up clean. An empty
+/// `modules` list means every module.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BootReasonSkip {
+    pub reasons: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub modules: Vec<String>,
+}
+
+impl BootReasonSkip {
+    fn applies(&self, boot: &BootReason) -> bool {
+        self.reasons.iter().any(|pattern| boot.matches(pattern))
+    }
+
+    fn skips(&self, module_id: &str) -> bool {
+        self.modules.is_empty() || self.modules.iter().any(|id| id == module_id)
+    }
+}
+
+/// Splits `modules` into the ones to mount and the ids of the ones the
+/// rules that apply to `boot` skip.
+pub fn split_skipped(modules: Vec<Module>, rules: &[BootReasonSkip], boot: &BootReason) -> (Vec<Module>, Vec<String>) {
+    let applying: Vec<&BootReasonSkip> = rules.iter().filter(|rule| rule.applies(boot)).collect();
+    let (skipped, kept): (Vec<_>, Vec<_>) = modules.into_iter()
+        .partition(|m| applying.iter().any(|rule| rule.skips(&m.id)));
+    (kept, skipped.into_iter().map(|m| m.id).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::core::inventory::ModuleRules;
+
+    fn module(id: &str) -> Module {
+        Module { id: id.to_string(), source_path: PathBuf::from("/data/adb/modules").join(id), rules: ModuleRules::default() }
+    }
+
+    #[test]
+    fn matches_reasons_and_their_details() {
+        let boot = BootReason::parse("Kernel_Panic,sysrq");
+        assert_eq!(boot.reason(), "kernel_panic");
+        assert!(boot.matches("kernel_panic"));
+        assert!(boot.matches("kernel_panic,sysrq"));
+        assert!(boot.matches("kernel*"));
+        assert!(!boot.matches("kernel"));
+        assert!(boot.matches(UNEXPECTED));
+
+        let boot = BootReason::parse("reboot,userrequested");
+        assert!(boot.matches("reboot") && !boot.matches(UNEXPECTED));
+        assert!(BootReason::parse("watchdog_bark").matches("watchdog*"));
+        assert!(BootReason::parse("recovery").matches("recovery"));
+    }
+
+    #[test]
+    fn skips_modules_of_applying_rules_only() {
+        let rules = vec![
+            BootReasonSkip { reasons: vec!["recovery".into(), "watchdog*".into()], modules: Vec::new() },
+            BootReasonSkip { reasons: vec![UNEXPECTED.into()], modules: vec!["risky".into()] },
+        ];
+        let modules = || vec![module("risky"), module("safe")];
+        let ids = |kept: Vec<Module>| kept.into_iter().map(|m| m.id).collect::<Vec<_>>();
+
+        let (kept, skipped) = split_skipped(modules(), &rules, &BootReason::parse("reboot"));
+        assert_eq!((ids(kept), skipped), (vec!["risky".to_string(), "safe".to_string()], vec![]));
+        let (kept, skipped) = split_skipped(modules(), &rules, &BootReason::parse("hard,hw_reset"));
+        assert_eq!((ids(kept), skipped), (vec!["safe".to_string()], vec!["risky".to_string()]));
+        let (kept, skipped) = split_skipped(modules(), &rules, &BootReason::parse("watchdog"));
+        assert_eq!((ids(kept), skipped), (vec![], vec!["risky".to_string(), "safe".to_string()]));
+    }
+}
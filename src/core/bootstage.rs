@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+use crate::{core::{inventory::{BootWindow, ModuleRules}, planner::MountPlan}, mount::hymofs::{HymoFs, Unresolved}};
+
+/// Spreading injection over the boot stages instead of doing it all at
+/// post-fs-data.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BootStages {
+    /// Defers the HymoFS content of modules for partitions outside
+    /// `early_partitions` to the service stage. A module's `window` rule
+    /// overrides this either way.
+    pub enabled: bool,
+    /// Partitions injected at post-fs-data, before anything that runs early
+    /// could see them unmodified.
+    pub early_partitions: Vec<String>,
+    /// Checks at boot completion that every rule injected shows where it
+    /// should.
+    pub verify: bool,
+}
+
+impl Default for BootStages {
+    fn default() -> Self {
+        Self { enabled: false, early_partitions: default_early_partitions(), verify: false }
+    }
+}
+
+fn default_early_partitions() -> Vec<String> {
+    ["system", "vendor", "product", "system_ext", "odm"].into_iter().map(str::to_string).collect()
+}
+
+/// When the rules of a module for `partition` are injected: as its `window`
+/// rule says, else by `stages`.
+pub fn window(stages: &BootStages, rules: &ModuleRules, partition: &str) -> BootWindow {
+    match rules.window {
+        Some(window) => window,
+        None if stages.enabled && !stages.early_partitions.iter().any(|p| p == partition) => BootWindow::AfterService,
+        None => BootWindow::Always,
+    }
+}
+
+/// The rules of `modules` in `plan` that do not resolve, by module. Rules
+/// that are still deferred or removed again are not looked at.
+pub fn verify(handle: &HymoFs, plan: &MountPlan, modules: &[String]) -> Vec<(String, Unresolved)> {
+    let mut unresolved = Vec::new();
+    for op in plan.hymo_ops.iter().filter(|op| modules.contains(&op.module_id) && op.window != BootWindow::BootOnly) {
+        match handle.verify(&op.target, &op.source) {
+            Ok(found) => unresolved.extend(found.into_iter().map(|miss| (op.module_id.clone(), miss))),
+            Err(e) => log::warn!("!! Cannot verify {} of {}: {:#}", op.target.display(), op.module_id, e),
+        }
+    }
+    unresolved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defers_late_partitions_unless_the_module_says_otherwise() {
+        let stages = BootStages { enabled: true, ..BootStages::default() };
+        let rules = ModuleRules::default();
+        assert_eq!(window(&stages, &rules, "system"), BootWindow::Always);
+        assert_eq!(window(&stages, &rules, "my_product"), BootWindow::AfterService);
+        assert_eq!(window(&BootStages::default(), &rules, "my_product"), BootWindow::Always);
+
+        let early = ModuleRules { window: Some(BootWindow::Always), ..ModuleRules::default() };
+        assert_eq!(window(&stages, &early, "my_product"), BootWindow::Always);
+        let late = ModuleRules { window: Some(BootWindow::AfterService), ..ModuleRules::default() };
+        assert_eq!(window(&BootStages::default(), &late, "system"), BootWindow::AfterService);
+    }
+}
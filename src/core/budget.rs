@@ -0,0 +1,137 @@
+use std::fmt;
+use std::path::Path;
+use anyhow::{Result, bail};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+use crate::{conf::config::Config, core::inventory::{Module, MountMode}};
+
+const MIB: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BudgetPolicy {
+    /// Stage anyway and report the overrun.
+    #[default]
+    Warn,
+    /// Abort the mount before anything is staged.
+    Fail,
+}
+
+/// A module, or with `module_id` unset all of them, needing more staging
+/// space than its budget.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Overrun {
+    pub module_id: Option<String>,
+    pub bytes: u64,
+    pub budget: u64,
+    /// The largest staged modules, biggest first, when the total overran.
+    pub largest: Vec<String>,
+}
+
+impl fmt::Display for Overrun {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mib = |bytes: u64| bytes as f64 / MIB as f64;
+        match &self.module_id {
+            Some(id) => write!(f, "{} would stage {:.1} MiB, over its {:.0} MiB budget; set default_mode = \"magic\" in its rules to mount it without staging",
+                id, mib(self.bytes), mib(self.budget)),
+            None => write!(f, "modules would stage {:.1} MiB, over the {:.0} MiB total budget; consider Magic Mount for {}",
+                mib(self.bytes), mib(self.budget), self.largest.join(", ")),
+        }
+    }
+}
+
+/// Bytes sync would copy into staging for `module`: its whole source tree,
+/// or nothing for a Magic Mount module.
+pub fn staged_bytes(module: &Module) -> u64 {
+    if matches!(module.rules.default_mode, MountMode::Magic) {
+        return 0;
+    }
+    tree_bytes(&module.source_path)
+}
+
+fn tree_bytes(root: &Path) -> u64 {
+    WalkDir::new(root).into_iter()
+        .flatten()
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| e.metadata().ok())
+        .map(|m| m.len())
+        .sum()
+}
+
+/// Compares per-module estimates against the budgets in MiB; a budget of 0
+/// is unlimited.
+pub fn overruns(estimates: &[(String, u64)], per_module_mb: u64, total_mb: u64) -> Vec<Overrun> {
+    let mut found = Vec::new();
+    let per_module = per_module_mb.saturating_mul(MIB);
+    if per_module > 0 {
+        found.extend(estimates.iter()
+            .filter(|(_, bytes)| *bytes > per_module)
+            .map(|(id, bytes)| Overrun { module_id: Some(id.clone()), bytes: *bytes, budget: per_module, largest: Vec::new() }));
+    }
+    let total = total_mb.saturating_mul(MIB);
+    let staged: u64 = estimates.iter().map(|(_, bytes)| bytes).sum();
+    if total > 0 && staged > total {
+        let mut by_size: Vec<&(String, u64)> = estimates.iter().filter(|(_, bytes)| *bytes > 0).collect();
+        by_size.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        let largest = by_size.into_iter().take(3).map(|(id, _)| id.clone()).collect();
+        found.push(Overrun { module_id: None, bytes: staged, budget: total, largest });
+    }
+    found
+}
+
+/// Estimates what tmpfs staging will hold before anything is copied and
+/// returns the overruns as warnings, or fails under [`BudgetPolicy::Fail`].
+pub fn check_staging(config: &Config, modules: &[Module]) -> Result<Vec<String>> {
+    if config.staging_budget_module_mb == 0 && config.staging_budget_total_mb == 0 {
+        return Ok(Vec::new());
+    }
+    let estimates: Vec<(String, u64)> = modules.par_iter()
+        .map(|m| (m.id.clone(), staged_bytes(m)))
+        .collect();
+    let found: Vec<String> = overruns(&estimates, config.staging_budget_module_mb, config.staging_budget_total_mb)
+        .iter()
+        .map(Overrun::to_string)
+        .collect();
+    if config.staging_budget_policy == BudgetPolicy::Fail && !found.is_empty() {
+        bail!("Staging budget exceeded: {}", found.join("; "));
+    }
+    for overrun in &found {
+        log::warn!("!! Staging budget: {}", overrun);
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn estimates(sizes: &[(&str, u64)]) -> Vec<(String, u64)> {
+        sizes.iter().map(|(id, mb)| (id.to_string(), mb * MIB)).collect()
+    }
+
+    #[test]
+    fn reports_module_and_total_overruns() {
+        let sizes = estimates(&[("small", 1), ("big", 40), ("mid", 10), ("magic", 0)]);
+        assert!(overruns(&sizes, 0, 0).is_empty());
+        assert!(overruns(&sizes, 40, 51).is_empty());
+
+        let found = overruns(&sizes, 32, 50);
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].module_id.as_deref(), Some("big"));
+        assert!(found[0].to_string().contains("default_mode = \"magic\""));
+        assert_eq!((found[1].module_id.as_deref(), found[1].bytes), (None, 51 * MIB));
+        assert_eq!(found[1].largest, vec!["big", "mid", "small"]);
+    }
+
+    #[test]
+    fn sums_regular_files_of_the_tree() {
+        let root = std::env::temp_dir().join(format!("budget-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("system/bin")).unwrap();
+        std::fs::write(root.join("module.prop"), "id=a\n").unwrap();
+        std::fs::write(root.join("system/bin/tool"), vec![0u8; 4096]).unwrap();
+        assert_eq!(tree_bytes(&root), 4096 + 5);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
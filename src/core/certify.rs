@@ -0,0 +1,272 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+use crate::{
+    defs,
+    core::selfcheck::{self, Check},
+    mount::hymofs::{self, HymoFileType, HymoFs, RuleOp, SharedHymoFs},
+};
+
+/// Real directory every probe path is placed in; no ROM ships anything below
+/// the certify prefix.
+const PROBE_PARENT: &str = "/system/etc";
+const PROBE_PREFIX: &str = ".meta_hybrid_certify";
+/// A real file in the probe directory on every Android build, hidden for a
+/// moment.
+const HIDE_NAME: &str = "hosts";
+const UNICODE_NAME: &str = "ünïcødé-测试-😀.txt";
+const BATCH_RULES: usize = 1000;
+
+/// Pass/fail matrix of one run against the loaded HymoFS.
+#[derive(Debug, Clone, Serialize)]
+pub struct Certification {
+    pub fingerprint: Option<String>,
+    pub checks: Vec<Check>,
+}
+
+impl Certification {
+    pub fn passed(&self) -> bool {
+        !self.checks.is_empty() && self.checks.iter().all(|c| c.passed)
+    }
+
+    pub fn failed(&self) -> usize {
+        self.checks.iter().filter(|c| !c.passed).count()
+    }
+
+    pub fn render(&self) -> String {
+        let width = self.checks.iter().map(|c| c.name.chars().count()).max().unwrap_or(0);
+        let mut out = format!("HymoFS: {}\n", self.fingerprint.as_deref().unwrap_or("unknown build"));
+        for check in &self.checks {
+            let verdict = if check.passed { "PASS" } else { "FAIL" };
+            out.push_str(&format!("  {}  {:<width$}  {}\n", verdict, check.name, check.detail, width = width));
+        }
+        out.push_str(&format!("{} of {} checks passed\n", self.checks.len() - self.failed(), self.checks.len()));
+        out
+    }
+}
+
+fn probe_path(name: &str) -> String {
+    format!("{}/{}{}", PROBE_PARENT, PROBE_PREFIX, name)
+}
+
+/// Whether `name` is listed in `dir` as seen through the VFS.
+fn listed_in(dir: &Path, name: &str) -> Result<bool> {
+    let entries = fs::read_dir(dir).with_context(|| format!("cannot list {}", dir.display()))?;
+    Ok(entries.flatten().any(|e| e.file_name().to_str() == Some(name)))
+}
+
+fn expect_content(path: &str, expected: &[u8]) -> Result<()> {
+    let seen = fs::read(path).with_context(|| format!("{} is not readable through the rule", path))?;
+    if seen != expected {
+        bail!("{} resolved to unexpected content", path);
+    }
+    Ok(())
+}
+
+/// Applies `rules`, runs `check` and removes the rules again whatever it
+/// returns.
+fn with_rules<T>(handle: &HymoFs, rules: &[RuleOp], check: impl FnOnce() -> Result<T>) -> Result<T> {
+    let result = rules.iter().try_for_each(|rule| handle.apply_op(rule)).map_err(anyhow::Error::from).and_then(|()| check());
+    for rule in rules.iter().rev() {
+        let _ = handle.delete_rule(rule.path());
+    }
+    result
+}
+
+fn protocol(handle: &HymoFs) -> Result<String> {
+    let version = handle.get_version()?;
+    let abi = handle.abi()?;
+    let caps = handle.caps()?.describe();
+    if !abi.is_current() {
+        return Ok(format!("protocol {}, older than {}: driven through the {} shim, capabilities {}", version, defs::HYMO_PROTOCOL_VERSION, abi.describe(), caps));
+    }
+    Ok(format!("protocol {}, capabilities {}", version, caps))
+}
+
+fn add_list_delete(handle: &HymoFs, scratch: &Path) -> Result<String> {
+    let source = scratch.join("add");
+    fs::write(&source, "certify add").context("Failed to write probe source")?;
+    let src = probe_path("-add");
+    let rule = RuleOp::Add { src: src.clone(), target: source.to_string_lossy().into_owned(), file_type: HymoFileType::Reg };
+    with_rules(handle, std::slice::from_ref(&rule), || {
+        if !handle.list_ops()?.contains(&rule) {
+            bail!("{} is missing from the rule list", src);
+        }
+        expect_content(&src, b"certify add")
+    })?;
+    if handle.list_ops()?.iter().any(|op| op.path() == src) {
+        bail!("{} is still listed after delete", src);
+    }
+    if Path::new(&src).symlink_metadata().is_ok() {
+        bail!("{} is still visible after delete", src);
+    }
+    Ok("rule added, listed, resolved and deleted".to_string())
+}
+
+fn hide(handle: &HymoFs) -> Result<String> {
+    let path = format!("{}/{}", PROBE_PARENT, HIDE_NAME);
+    let visible = || Path::new(&path).symlink_metadata().is_ok();
+    if !visible() {
+        bail!("{} does not exist on this device", path);
+    }
+    let rule = RuleOp::Hide { path: path.clone() };
+    with_rules(handle, std::slice::from_ref(&rule), || {
+        if visible() {
+            bail!("{} is still visible to stat", path);
+        }
+        if listed_in(Path::new(PROBE_PARENT), HIDE_NAME)? {
+            bail!("{} is still listed in {}", path, PROBE_PARENT);
+        }
+        Ok(())
+    })?;
+    if !visible() {
+        bail!("{} did not come back after the hide rule was deleted", path);
+    }
+    Ok("hidden from stat and readdir, restored on delete".to_string())
+}
+
+/// Lays out a module tree with a nested directory, as a module would ship it,
+/// and injects it the way the executor does.
+fn directory_injection(handle: &HymoFs, scratch: &Path) -> Result<String> {
+    let module = scratch.join("module");
+    let content = module.join(PROBE_PARENT.trim_start_matches('/')).join(format!("{}-dir", PROBE_PREFIX));
+    fs::create_dir_all(content.join("sub")).context("Failed to lay out probe module")?;
+    fs::write(content.join("top.txt"), "certify top").context("Failed to write probe module")?;
+    fs::write(content.join("sub/nested.txt"), "certify nested").context("Failed to write probe module")?;
+    let rules = hymofs::collect_directory_ops(Path::new("/system"), &module.join("system"));
+    if rules.len() != 2 {
+        bail!("walk produced {} rules for 2 files", rules.len());
+    }
+    let injected = PathBuf::from(probe_path("-dir"));
+    with_rules(handle, &rules, || {
+        expect_content(&injected.join("top.txt").to_string_lossy(), b"certify top")?;
+        expect_content(&injected.join("sub/nested.txt").to_string_lossy(), b"certify nested")?;
+        if !listed_in(&injected, "top.txt")? || !listed_in(&injected.join("sub"), "nested.txt")? {
+            bail!("injected files are not listed in their directories");
+        }
+        Ok(format!("{} rules from a nested module tree resolved and listed", rules.len()))
+    })
+}
+
+fn unicode(handle: &HymoFs, scratch: &Path) -> Result<String> {
+    let source = scratch.join(UNICODE_NAME);
+    fs::write(&source, UNICODE_NAME).context("Failed to write probe source")?;
+    let src = probe_path(&format!("-{}", UNICODE_NAME));
+    let rule = RuleOp::Add { src: src.clone(), target: source.to_string_lossy().into_owned(), file_type: HymoFileType::Reg };
+    with_rules(handle, std::slice::from_ref(&rule), || {
+        if !handle.list_ops()?.contains(&rule) {
+            bail!("rule list does not round-trip the unicode path");
+        }
+        expect_content(&src, UNICODE_NAME.as_bytes())?;
+        Ok("unicode source and target resolved and listed back unchanged".to_string())
+    })
+}
+
+/// Registers a module-sized tree through the batch API and expects far fewer
+/// ioctls than rules on kernels that support batching.
+fn batched_add(handle: &HymoFs, scratch: &Path) -> Result<String> {
+    let source = scratch.join("batch");
+    fs::write(&source, "certify batch").context("Failed to write probe source")?;
+    let target = source.to_string_lossy().into_owned();
+    let paths: Vec<String> = (0..BATCH_RULES).map(|i| probe_path(&format!("-batch-{}", i))).collect();
+    let rules: Vec<(&str, &str, HymoFileType)> = paths.iter().map(|p| (p.as_str(), target.as_str(), HymoFileType::Reg)).collect();
+    let outcome = handle.add_rules_batch(&rules);
+    let result = match outcome.failed.first() {
+        Some(&first) => Err(anyhow::anyhow!("{} of {} rules rejected, e.g. {}", outcome.failed.len(), paths.len(), paths[first])),
+        None => expect_content(&paths[BATCH_RULES - 1], b"certify batch").map(|()| {
+            let mode = if outcome.ioctls < paths.len() { "batched" } else { "per-rule fallback" };
+            format!("{} rules in {} ioctls ({})", paths.len(), outcome.ioctls, mode)
+        }),
+    };
+    for path in &paths {
+        let _ = handle.delete_rule(path);
+    }
+    result
+}
+
+/// Pushes `count` rules through the same queue the executor uses and reads
+/// them all back from the rule list.
+fn large_rule_count(handle: &HymoFs, scratch: &Path, count: usize) -> Result<String> {
+    let source = scratch.join("bulk");
+    fs::write(&source, "certify bulk").context("Failed to write probe source")?;
+    let target = source.to_string_lossy().into_owned();
+    let rules: Vec<RuleOp> = (0..count)
+        .map(|i| RuleOp::Add { src: probe_path(&format!("-bulk-{}", i)), target: target.clone(), file_type: HymoFileType::Reg })
+        .collect();
+    let shared = SharedHymoFs::new(handle.clone());
+    let started = Instant::now();
+    shared.enqueue("certify", rules.clone());
+    shared.flush();
+    let elapsed = started.elapsed();
+    let applied = shared.take_stats().remove("certify").map_or(0, |s| s.applied);
+    let result = handle.list_ops().map_err(anyhow::Error::from).and_then(|listed| {
+        let listed = listed.iter().filter(|op| op.path().starts_with(&probe_path("-bulk-"))).count();
+        if applied != count || listed != count {
+            bail!("{} of {} rules applied, {} listed", applied, count, listed);
+        }
+        expect_content(&probe_path(&format!("-bulk-{}", count.saturating_sub(1))), b"certify bulk")?;
+        Ok(format!("{} rules applied and listed in {} ms", count, elapsed.as_millis()))
+    });
+    shared.delete_rules("certify", rules);
+    shared.flush();
+    result
+}
+
+/// Exercises the loaded HymoFS end to end: add/list/delete, hide semantics,
+/// directory injection, unicode paths, batched adds and `rules` rules at
+/// once. Every rule is removed again before the next check starts.
+pub fn run(handle: &HymoFs, rules: usize) -> Certification {
+    let fingerprint = selfcheck::fingerprint(handle);
+    let scratch = Path::new(defs::RUN_DIR).join("certify");
+    let _ = fs::remove_dir_all(&scratch);
+    if let Err(e) = fs::create_dir_all(&scratch) {
+        let detail = format!("cannot create {}: {}", scratch.display(), e);
+        let checks = vec![Check { name: "scratch".to_string(), passed: false, detail }];
+        return Certification { fingerprint, checks };
+    }
+    let checks = vec![
+        selfcheck::check("protocol", protocol(handle)),
+        selfcheck::check("canary", handle.self_test(&scratch)),
+        selfcheck::check("add/list/delete", add_list_delete(handle, &scratch)),
+        selfcheck::check("hide", hide(handle)),
+        selfcheck::check("directory injection", directory_injection(handle, &scratch)),
+        selfcheck::check("unicode paths", unicode(handle, &scratch)),
+        selfcheck::check("batched add", batched_add(handle, &scratch)),
+        selfcheck::check("large rule count", large_rule_count(handle, &scratch, rules)),
+    ];
+    let _ = fs::remove_dir_all(&scratch);
+    Certification { fingerprint, checks }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(name: &str, passed: bool, detail: &str) -> Check {
+        Check { name: name.into(), passed, detail: detail.into() }
+    }
+
+    #[test]
+    fn renders_an_aligned_matrix() {
+        let result = Certification {
+            fingerprint: Some("protocol=5 kernel=5.10".into()),
+            checks: vec![check("hide", true, "ok"), check("unicode paths", false, "not listed")],
+        };
+        assert!(!result.passed());
+        assert_eq!(result.render(), concat!(
+            "HymoFS: protocol=5 kernel=5.10\n",
+            "  PASS  hide           ok\n",
+            "  FAIL  unicode paths  not listed\n",
+            "1 of 2 checks passed\n",
+        ));
+        assert!(!Certification { fingerprint: None, checks: Vec::new() }.passed());
+    }
+
+    #[test]
+    fn probe_paths_stay_below_the_prefix() {
+        assert_eq!(probe_path("-add"), "/system/etc/.meta_hybrid_certify-add");
+        assert!(probe_path(&format!("-{}", UNICODE_NAME)).starts_with("/system/etc/.meta_hybrid_certify"));
+    }
+}
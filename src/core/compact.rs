@@ -0,0 +1,166 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use anyhow::{Result, bail};
+use serde::Serialize;
+use crate::{conf::config::Config, utils, core::{rules::{self, RuleLedger}, state::{self, RuntimeState}}, mount::hymofs::{HymoFs, RuleOp}};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Redundancy {
+    /// The same rule is listed more than once.
+    Duplicate,
+    /// A hide on a parent directory means the rule can never match.
+    Shadowed,
+    /// The rule redirects to a file that is gone, or belongs to a module
+    /// that is no longer mounted.
+    Stale,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Finding {
+    pub rule: RuleOp,
+    pub reason: Redundancy,
+    pub detail: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct CompactReport {
+    pub scanned: usize,
+    pub findings: Vec<Finding>,
+    pub reclaimed: usize,
+    pub errors: Vec<String>,
+}
+
+fn is_below(path: &str, dir: &str) -> bool {
+    path.strip_prefix(dir).is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Finds redundant rules among the live ones we own; adopted and foreign
+/// rules are left alone. `active` holds the modules the committed state says
+/// are mounted, when that state is current enough to trust.
+pub fn analyze(
+    live: &[RuleOp],
+    ledger: &RuleLedger,
+    roots: &[PathBuf],
+    active: Option<&HashSet<String>>,
+    exists: impl Fn(&Path) -> bool,
+) -> Vec<Finding> {
+    let hidden: Vec<&str> = live.iter()
+        .filter_map(|op| match op {
+            RuleOp::Hide { path } => Some(path.as_str()),
+            _ => None,
+        })
+        .collect();
+    let mut seen: Vec<&RuleOp> = Vec::new();
+    let mut findings = Vec::new();
+    for op in live.iter().filter(|op| ledger.is_owned(op, roots)) {
+        let finding = |reason, detail: String| Finding { rule: op.clone(), reason, detail };
+        if seen.contains(&op) {
+            findings.push(finding(Redundancy::Duplicate, format!("{} is listed more than once", op.path())));
+            continue;
+        }
+        seen.push(op);
+        let missing_source = match op {
+            RuleOp::Add { target, .. } if !exists(Path::new(target)) => Some(target),
+            _ => None,
+        };
+        if let Some(dir) = hidden.iter().find(|dir| is_below(op.path(), dir)) {
+            findings.push(finding(Redundancy::Shadowed, format!("{} is hidden", dir)));
+        } else if let Some(target) = missing_source {
+            findings.push(finding(Redundancy::Stale, format!("source {} is gone", target)));
+        } else if let Some(owner) = ledger.owned.owner(op.path()).filter(|owner| active.is_some_and(|a| !a.contains(*owner))) {
+            findings.push(finding(Redundancy::Stale, format!("{} is no longer mounted", owner)));
+        }
+    }
+    findings
+}
+
+/// Removes what [`analyze`] found. Shadowed and stale rules are deleted;
+/// duplicated ones are deleted and written back once.
+pub fn compact(handle: &HymoFs, ledger: &mut RuleLedger, scanned: usize, findings: Vec<Finding>) -> CompactReport {
+    let mut report = CompactReport { scanned, ..Default::default() };
+    let mut rewritten = HashSet::new();
+    for finding in &findings {
+        let path = finding.rule.path();
+        let result = match finding.reason {
+            Redundancy::Duplicate if !rewritten.insert(path.to_string()) => Ok(()),
+            Redundancy::Duplicate => handle.delete_rule(path).and_then(|()| handle.apply_op(&finding.rule)),
+            Redundancy::Shadowed | Redundancy::Stale => handle.delete_rule(path).map(|()| {
+                ledger.owned.remove(path);
+            }),
+        };
+        match result {
+            Ok(()) => report.reclaimed += 1,
+            Err(e) => report.errors.push(format!("{}: {:#}", path, e)),
+        }
+    }
+    report.findings = findings;
+    report
+}
+
+/// Compacts the live table against the ledger and committed state, or only
+/// reports what would go with `dry_run`.
+pub fn run(config: &Config, dry_run: bool) -> Result<CompactReport> {
+    let handle = HymoFs::open_at(&config.hymofs_device)?;
+    if !handle.is_available() {
+        bail!("HymoFS is not available at {}", config.hymofs_device.display());
+    }
+    let live = handle.list_ops()?;
+    let mut ledger = RuleLedger::load();
+    let state = RuntimeState::load().unwrap_or_default();
+    let roots = rules::owned_roots(config, Some(&state.mount_point));
+    // Mid-apply or last-boot state says nothing about what is mounted now.
+    let current = state::apply_in_progress().is_none() && utils::same_boot(&state.session_id, utils::session_id());
+    let active: Option<HashSet<String>> = current.then(|| state.hymo_modules.iter().cloned().collect());
+    let findings = analyze(&live, &ledger, &roots, active.as_ref(), Path::exists);
+    if dry_run {
+        return Ok(CompactReport { scanned: live.len(), findings, ..Default::default() });
+    }
+    let report = compact(&handle, &mut ledger, live.len(), findings);
+    if report.reclaimed > 0 {
+        ledger.save()?;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mount::hymofs::HymoFileType;
+
+    fn add(src: &str, target: &str) -> RuleOp {
+        RuleOp::Add { src: src.into(), target: target.into(), file_type: HymoFileType::Reg }
+    }
+
+    fn reasons(findings: &[Finding]) -> Vec<(&str, Redundancy)> {
+        findings.iter().map(|f| (f.rule.path(), f.reason)).collect()
+    }
+
+    #[test]
+    fn finds_duplicate_shadowed_and_stale_rules() {
+        let mut ledger = RuleLedger::default();
+        let live = vec![
+            add("/system/bin/a", "/mnt/a/system/bin/a"),
+            add("/system/bin/a", "/mnt/a/system/bin/a"),
+            RuleOp::Hide { path: "/system/app/Foo".into() },
+            add("/system/app/Foo/Foo.apk", "/mnt/a/system/app/Foo/Foo.apk"),
+            add("/system/bin/gone", "/mnt/a/system/bin/gone"),
+            add("/system/bin/old", "/mnt/old/system/bin/old"),
+            add("/system/bin/foreign", "/somewhere/else"),
+        ];
+        ledger.record_owned(live.iter().take(6).map(|op| (if op.path().ends_with("old") { "old" } else { "a" }, op)));
+        let active: HashSet<String> = ["a".to_string()].into();
+        let exists = |p: &Path| !p.ends_with("gone");
+
+        let findings = analyze(&live, &ledger, &[PathBuf::from("/mnt")], Some(&active), exists);
+        assert_eq!(reasons(&findings), vec![
+            ("/system/bin/a", Redundancy::Duplicate),
+            ("/system/app/Foo/Foo.apk", Redundancy::Shadowed),
+            ("/system/bin/gone", Redundancy::Stale),
+            ("/system/bin/old", Redundancy::Stale),
+        ]);
+
+        let without_state = analyze(&live, &ledger, &[PathBuf::from("/mnt")], None, exists);
+        assert!(!reasons(&without_state).contains(&("/system/bin/old", Redundancy::Stale)));
+    }
+}
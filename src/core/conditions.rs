@@ -0,0 +1,156 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock, PoisonError};
+use anyhow::{Result, bail};
+use crate::utils;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CompareOp {
+    Eq,
+    Ne,
+    Ge,
+    Le,
+    Gt,
+    Lt,
+}
+
+const OPERATORS: [(&str, CompareOp); 7] = [
+    (">=", CompareOp::Ge),
+    ("<=", CompareOp::Le),
+    ("!=", CompareOp::Ne),
+    ("==", CompareOp::Eq),
+    ("=", CompareOp::Eq),
+    (">", CompareOp::Gt),
+    ("<", CompareOp::Lt),
+];
+
+static PROP_CACHE: OnceLock<Mutex<HashMap<String, Option<String>>>> = OnceLock::new();
+
+fn cached_prop(key: &str) -> Option<String> {
+    let cache = PROP_CACHE.get_or_init(|| Mutex::new(HashMap::new()));
+    let mut cache = cache.lock().unwrap_or_else(PoisonError::into_inner);
+    cache.entry(key.to_string())
+        .or_insert_with(|| utils::get_prop(key))
+        .clone()
+}
+
+fn compare_values(actual: &str, expected: &str) -> Ordering {
+    let segments = |s: &str| s.split('.').map(|p| p.parse::<i64>()).collect::<Result<Vec<_>, _>>();
+    match (segments(actual), segments(expected)) {
+        (Ok(a), Ok(b)) => {
+            let len = a.len().max(b.len());
+            (0..len)
+                .map(|i| a.get(i).unwrap_or(&0).cmp(b.get(i).unwrap_or(&0)))
+                .find(|o| *o != Ordering::Equal)
+                .unwrap_or(Ordering::Equal)
+        }
+        _ => actual.cmp(expected),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PropCondition {
+    key: String,
+    op: CompareOp,
+    value: String,
+}
+
+impl PropCondition {
+    pub fn parse(expr: &str) -> Result<Self> {
+        let expr = expr.trim();
+        let found = expr.char_indices().find_map(|(pos, _)| {
+            OPERATORS.iter()
+                .find(|(token, _)| expr[pos..].starts_with(token))
+                .map(|(token, op)| (pos, *token, *op))
+        });
+        let Some((pos, token, op)) = found else {
+            bail!("no comparison operator in condition '{}'", expr)
+        };
+        let key = expr[..pos].trim();
+        if key.is_empty() {
+            bail!("missing property name in condition '{}'", expr);
+        }
+        Ok(Self {
+            key: key.to_string(),
+            op,
+            value: expr[pos + token.len()..].trim().to_string(),
+        })
+    }
+
+    pub fn matches(&self, actual: Option<&str>) -> bool {
+        let Some(actual) = actual else {
+            return self.op == CompareOp::Ne;
+        };
+        let ordering = compare_values(actual, &self.value);
+        match self.op {
+            CompareOp::Eq => ordering == Ordering::Equal,
+            CompareOp::Ne => ordering != Ordering::Equal,
+            CompareOp::Ge => ordering != Ordering::Less,
+            CompareOp::Le => ordering != Ordering::Greater,
+            CompareOp::Gt => ordering == Ordering::Greater,
+            CompareOp::Lt => ordering == Ordering::Less,
+        }
+    }
+
+    pub fn evaluate(&self) -> bool {
+        self.matches(cached_prop(&self.key).as_deref())
+    }
+}
+
+pub fn evaluate(expr: &str) -> bool {
+    expr.split("&&").all(|part| match PropCondition::parse(part) {
+        Ok(cond) => cond.evaluate(),
+        Err(e) => {
+            log::warn!("Ignoring rule with invalid condition: {}", e);
+            false
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(expr: &str, actual: Option<&str>) -> bool {
+        PropCondition::parse(expr).unwrap().matches(actual)
+    }
+
+    #[test]
+    fn parses_first_operator_position() {
+        let cond = PropCondition::parse("ro.build.version.sdk >= 34").unwrap();
+        assert_eq!(cond.key, "ro.build.version.sdk");
+        assert_eq!(cond.op, CompareOp::Ge);
+        assert_eq!(cond.value, "34");
+
+        let cond = PropCondition::parse("ro.custom=a<=b").unwrap();
+        assert_eq!(cond.key, "ro.custom");
+        assert_eq!(cond.op, CompareOp::Eq);
+        assert_eq!(cond.value, "a<=b");
+    }
+
+    #[test]
+    fn rejects_malformed_conditions() {
+        assert!(PropCondition::parse("ro.build.version.sdk").is_err());
+        assert!(PropCondition::parse(">= 34").is_err());
+    }
+
+    #[test]
+    fn compares_numbers_and_versions() {
+        assert!(check("sdk>=34", Some("34")));
+        assert!(!check("sdk<34", Some("34")));
+        assert!(check("sdk>9", Some("10")));
+        assert!(check("release>=9", Some("14.1")));
+        assert!(check("release<14.10", Some("14.9")));
+        assert!(check("release==14", Some("14.0")));
+        assert!(check("brand==google", Some("google")));
+        assert!(check("brand!=google", Some("samsung")));
+    }
+
+    #[test]
+    fn missing_property_fails_except_not_equal() {
+        assert!(!check("sdk<34", None));
+        assert!(!check("sdk<=34", None));
+        assert!(!check("sdk==34", None));
+        assert!(check("sdk!=34", None));
+    }
+}
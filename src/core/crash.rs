@@ -0,0 +1,168 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use crate::{conf::config::Config, core::{disable::{self, DisableReason, DisableSource}, inventory::Module, roots}, defs};
+
+const CRASH_TAGS: [&str; 4] = ["_crash", "_native_crash", "SYSTEM_TOMBSTONE", "_anr"];
+const MAX_HEADER_BYTES: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrashEvent {
+    pub process: String,
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ModuleStats {
+    pub enabled_at: u64,
+    #[serde(default)]
+    pub crash_correlations: BTreeMap<String, u32>,
+    #[serde(default)]
+    pub auto_disabled: bool,
+}
+
+pub fn load_stats() -> HashMap<String, ModuleStats> {
+    fs::read_to_string(defs::MODULE_STATS_FILE)
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+fn save_stats(stats: &HashMap<String, ModuleStats>) -> Result<()> {
+    fs::write(defs::MODULE_STATS_FILE, serde_json::to_string_pretty(stats)?)?;
+    Ok(())
+}
+
+pub fn parse_dropbox_name(name: &str) -> Option<(&str, u64)> {
+    let (tag, rest) = name.split_once('@')?;
+    let millis = rest.split('.').next()?.parse::<u64>().ok()?;
+    let is_crash = CRASH_TAGS.iter().any(|t| tag.ends_with(t) || tag == t.trim_start_matches('_'));
+    is_crash.then_some((tag, millis / 1000))
+}
+
+pub fn parse_process(content: &str) -> Option<String> {
+    for line in content.lines() {
+        if let Some(process) = line.strip_prefix("Process: ") {
+            return Some(process.trim().to_string());
+        }
+        if let (Some(start), Some(end)) = (line.find(">>> "), line.find(" <<<")) {
+            if start + 4 <= end {
+                return Some(line[start + 4..end].trim().to_string());
+            }
+        }
+    }
+    None
+}
+
+fn collect_events(dropbox: &Path) -> Vec<CrashEvent> {
+    let Ok(entries) = fs::read_dir(dropbox) else {
+        return Vec::new();
+    };
+    entries.flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let (_, timestamp) = parse_dropbox_name(&name)?;
+            if name.ends_with(".gz") {
+                return None;
+            }
+            let bytes = fs::read(entry.path()).ok()?;
+            let header = String::from_utf8_lossy(&bytes[..bytes.len().min(MAX_HEADER_BYTES)]);
+            parse_process(&header).map(|process| CrashEvent { process, timestamp })
+        })
+        .collect()
+}
+
+pub fn correlate(events: &[CrashEvent], enabled_at: u64, window: u64) -> BTreeMap<String, u32> {
+    let before = enabled_at.saturating_sub(window)..enabled_at;
+    let after = enabled_at..enabled_at.saturating_add(window);
+    let mut counts = BTreeMap::new();
+    for event in events.iter().filter(|e| after.contains(&e.timestamp)) {
+        *counts.entry(event.process.clone()).or_insert(0) += 1;
+    }
+    counts.retain(|process, _| {
+        !events.iter().any(|e| &e.process == process && before.contains(&e.timestamp))
+    });
+    counts
+}
+
+pub fn scan(config: &Config, modules: &[Module]) -> Result<HashMap<String, ModuleStats>> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let mut stats = load_stats();
+    stats.retain(|id, _| modules.iter().any(|m| &m.id == id));
+    for module in modules {
+        stats.entry(module.id.clone()).or_insert_with(|| ModuleStats {
+            enabled_at: now,
+            ..Default::default()
+        });
+    }
+
+    let events = collect_events(Path::new(defs::DROPBOX_DIR));
+    for module in modules {
+        let Some(entry) = stats.get_mut(&module.id) else { continue };
+        entry.crash_correlations = correlate(&events, entry.enabled_at, config.crash_window_secs);
+        for (process, count) in &entry.crash_correlations {
+            log::warn!("!! {} crashed {} times after {} was enabled", process, count, module.id);
+        }
+        let threshold = config.crash_disable_threshold;
+        let worst = entry.crash_correlations.values().copied().max().unwrap_or(0);
+        if threshold > 0 && worst >= threshold && !entry.auto_disabled {
+            log::error!("!! Auto-disabling {}: correlated crashes reached {}", module.id, worst);
+            let reason = entry.crash_correlations.iter().fold(
+                DisableReason::new(DisableSource::CrashCorrelation,
+                    format!("{} crashes correlated with enabling the module (threshold {})", worst, threshold)),
+                |reason, (process, count)| reason.detail(process, count),
+            );
+            let reasons_file = Path::new(defs::DISABLE_REASONS_FILE);
+            if let Err(e) = disable::disable(reasons_file, &roots::active(config), &module.id, &module.source_path, reason) {
+                log::warn!("Failed to disable {}: {:#}", module.id, e);
+            } else {
+                entry.auto_disabled = true;
+            }
+        }
+    }
+    save_stats(&stats)?;
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(process: &str, timestamp: u64) -> CrashEvent {
+        CrashEvent { process: process.to_string(), timestamp }
+    }
+
+    #[test]
+    fn parses_dropbox_names() {
+        assert_eq!(parse_dropbox_name("system_app_crash@1700000000123.txt"), Some(("system_app_crash", 1_700_000_000)));
+        assert_eq!(parse_dropbox_name("SYSTEM_TOMBSTONE@1700000000000.txt.gz"), Some(("SYSTEM_TOMBSTONE", 1_700_000_000)));
+        assert_eq!(parse_dropbox_name("data_app_anr@1700000000000.txt"), Some(("data_app_anr", 1_700_000_000)));
+        assert_eq!(parse_dropbox_name("SYSTEM_BOOT@1700000000000.txt"), None);
+        assert_eq!(parse_dropbox_name("garbage"), None);
+    }
+
+    #[test]
+    fn parses_process_names() {
+        assert_eq!(parse_process("Process: com.android.systemui\nPID: 1").as_deref(), Some("com.android.systemui"));
+        assert_eq!(parse_process("pid: 1, tid: 1, name: x  >>> /system/bin/audioserver <<<").as_deref(), Some("/system/bin/audioserver"));
+        assert_eq!(parse_process("nothing here"), None);
+    }
+
+    #[test]
+    fn correlates_only_new_crashes_after_enable() {
+        let events = vec![
+            event("com.a", 1010),
+            event("com.a", 1020),
+            event("com.b", 1030),
+            event("com.b", 990),
+            event("com.c", 5000),
+        ];
+        let counts = correlate(&events, 1000, 100);
+        assert_eq!(counts.get("com.a"), Some(&2));
+        assert!(!counts.contains_key("com.b"));
+        assert!(!counts.contains_key("com.c"));
+    }
+}
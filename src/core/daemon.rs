@@ -0,0 +1,407 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use crate::{
+    conf::config::Config,
+    core::{install::{self, Cancelled, Progress}, manager, partitions, rewrite, roots, rules::RuleLedger, state::RuntimeState, transaction::Transaction, uninstall},
+    mount::hymofs::{self, HymoFs},
+};
+
+/// One line of the control protocol: a JSON object tagged by `cmd`, e.g.
+/// `{"cmd":"add_module","id":"foo"}`. Every request is answered by one
+/// [`Response`] line.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum Request {
+    AddModule { id: String },
+    RemoveModule { id: String },
+    Reload,
+    /// Applies the rules of modules enabled or disabled since the last
+    /// boot or reload.
+    ReloadModules,
+    Status,
+    /// Starts installing the unpacked module at `source` in the background;
+    /// `installs` follows it and `cancel_install` stops it.
+    Install { source: PathBuf },
+    CancelInstall { id: String },
+    Installs,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Response {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub result: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Response {
+    fn from_result(result: Result<Value>) -> Self {
+        match result {
+            Ok(result) => Self { ok: true, result, error: None },
+            Err(e) => Self { ok: false, result: Value::Null, error: Some(format!("{:#}", e)) },
+        }
+    }
+}
+
+/// The answer line to one request line.
+pub fn respond(line: &str, handle: impl FnOnce(Request) -> Result<Value>) -> String {
+    let response = serde_json::from_str::<Request>(line)
+        .context("Malformed request")
+        .and_then(handle);
+    serde_json::to_string(&Response::from_result(response)).unwrap_or_default()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InstallState {
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+/// An install the daemon runs or ran, as `installs` reports it.
+#[derive(Debug, Clone, Serialize)]
+pub struct InstallJob {
+    pub source: PathBuf,
+    pub state: InstallState,
+    pub progress: Progress,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip)]
+    cancel: Arc<AtomicBool>,
+}
+
+type InstallJobs = Arc<Mutex<BTreeMap<String, InstallJob>>>;
+
+fn update_job(jobs: &InstallJobs, id: &str, update: impl FnOnce(&mut InstallJob)) {
+    if let Some(job) = jobs.lock().unwrap_or_else(PoisonError::into_inner).get_mut(id) {
+        update(job);
+    }
+}
+
+/// Long-running owner of the HymoFS device: callers talk to it over the
+/// control socket instead of each opening the device, and their requests
+/// run one at a time.
+pub struct Daemon {
+    config: Config,
+    config_path: Option<PathBuf>,
+    hymofs: HymoFs,
+    started: Instant,
+    served: u64,
+    installs: InstallJobs,
+}
+
+impl Daemon {
+    pub fn new(config: Config, config_path: Option<PathBuf>) -> Result<Self> {
+        let hymofs = HymoFs::open_at(&config.hymofs_device)?.with_labels(&config.selinux_labels);
+        Ok(Self { config, config_path, hymofs, started: Instant::now(), served: 0, installs: InstallJobs::default() })
+    }
+
+    pub fn handle(&mut self, request: Request) -> Result<Value> {
+        self.served += 1;
+        match request {
+            Request::AddModule { id } => self.add_module(&id),
+            Request::RemoveModule { id } => self.remove_module(&id),
+            Request::Reload => self.reload(),
+            Request::ReloadModules => Ok(json!(manager::reload(&self.config, self.available()?)?)),
+            Request::Status => Ok(self.status()),
+            Request::Install { source } => self.install(source),
+            Request::CancelInstall { id } => self.cancel_install(&id),
+            Request::Installs => Ok(json!(*self.installs.lock().unwrap_or_else(PoisonError::into_inner))),
+        }
+    }
+
+    /// Runs the install on its own thread, so requests keep being answered
+    /// while a slow source is copied.
+    fn install(&self, source: PathBuf) -> Result<Value> {
+        let id = install::module_id(&source)?;
+        let cancel = Arc::new(AtomicBool::new(false));
+        {
+            let mut jobs = self.installs.lock().unwrap_or_else(PoisonError::into_inner);
+            if jobs.get(&id).is_some_and(|job| job.state == InstallState::Running) {
+                bail!("{} is already being installed", id);
+            }
+            jobs.insert(id.clone(), InstallJob {
+                source: source.clone(),
+                state: InstallState::Running,
+                progress: Progress::default(),
+                error: None,
+                cancel: Arc::clone(&cancel),
+            });
+        }
+        let (config, jobs, job_id) = (self.config.clone(), Arc::clone(&self.installs), id.clone());
+        std::thread::spawn(move || {
+            log::info!(">> hymod: installing {} from {}", job_id, source.display());
+            let result = install::install(&config, &source, &cancel, |progress| {
+                update_job(&jobs, &job_id, |job| job.progress = *progress);
+            });
+            let (state, error) = match result {
+                Ok(path) => {
+                    log::info!(">> hymod: installed {} to {}", job_id, path.display());
+                    (InstallState::Done, None)
+                }
+                Err(e) if e.is::<Cancelled>() => {
+                    log::info!(">> hymod: install of {} cancelled", job_id);
+                    (InstallState::Cancelled, None)
+                }
+                Err(e) => {
+                    log::warn!("!! hymod: install of {} failed: {:#}", job_id, e);
+                    (InstallState::Failed, Some(format!("{:#}", e)))
+                }
+            };
+            update_job(&jobs, &job_id, |job| {
+                job.state = state;
+                job.error = error;
+            });
+        });
+        Ok(json!({ "id": id, "state": InstallState::Running }))
+    }
+
+    /// Stops a running install after its current chunk; installing the same
+    /// source again resumes it.
+    fn cancel_install(&self, id: &str) -> Result<Value> {
+        let jobs = self.installs.lock().unwrap_or_else(PoisonError::into_inner);
+        match jobs.get(id) {
+            Some(job) if job.state == InstallState::Running => {
+                job.cancel.store(true, Ordering::Relaxed);
+                Ok(json!({ "id": id, "cancelling": true }))
+            }
+            Some(_) => bail!("{} is not being installed", id),
+            None => bail!("No install of {} was started", id),
+        }
+    }
+
+    fn available(&self) -> Result<&HymoFs> {
+        if !self.hymofs.is_available() {
+            bail!("HymoFS is not available at {}", self.hymofs.device().display());
+        }
+        Ok(&self.hymofs)
+    }
+
+    /// Injects every partition of the module through HymoFS, from its
+    /// staged copy when there is one, and records the rules as its own.
+    fn add_module(&self, module_id: &str) -> Result<Value> {
+        uninstall::validate_id(module_id)?;
+        let hymofs = self.available()?;
+        let module_dir = roots::locate(&self.config, module_id);
+        if !module_dir.is_dir() {
+            bail!("Module {} not found", module_id);
+        }
+        let state = RuntimeState::load().unwrap_or_default();
+        let storage_root = state.storage_root();
+        let staged = storage_root.join(module_id);
+        let content = if staged.is_dir() { staged } else { module_dir };
+        let rewrites = rewrite::active(&self.config.path_rewrites);
+        let mut ops = Vec::new();
+        for partition in partitions::enabled(&self.config) {
+            let source = content.join(partition);
+            if source.is_dir() {
+                ops.extend(hymofs::collect_directory_ops(&partitions::target(&self.config, partition), &source));
+            }
+        }
+        rewrite::apply(&mut ops, &rewrites);
+        let mut transaction = Transaction::begin(hymofs);
+        transaction.apply_all(&ops).with_context(|| format!("Failed to add {}", module_id))?;
+        let mut ledger = RuleLedger::load();
+        ledger.claim(ops.iter().map(|op| (module_id, op)));
+        ledger.save()?;
+        let applied = transaction.commit();
+        log::info!(">> hymod: added {} ({} rules)", module_id, applied);
+        Ok(json!({ "applied": applied }))
+    }
+
+    fn remove_module(&self, module_id: &str) -> Result<Value> {
+        uninstall::validate_id(module_id)?;
+        let hymofs = self.available()?;
+        let mut ledger = RuleLedger::load();
+        let (removed, _) = ledger.remove_module(hymofs, module_id);
+        ledger.save()?;
+        log::info!(">> hymod: removed {} ({} rules)", module_id, removed);
+        Ok(json!({ "removed": removed }))
+    }
+
+    /// Reads the config again and reopens the device it names, e.g. after
+    /// the kernel module was reloaded.
+    fn reload(&mut self) -> Result<Value> {
+        let config = match &self.config_path {
+            Some(path) => Config::from_file(path)?,
+            None => Config::load_default().unwrap_or_default(),
+        };
+        self.hymofs = HymoFs::open_at(&config.hymofs_device)?.with_labels(&config.selinux_labels);
+        self.config = config;
+        log::info!(">> hymod: reloaded config, device {}", self.hymofs.device().display());
+        Ok(json!({ "device": self.hymofs.device() }))
+    }
+
+    fn status(&self) -> Value {
+        let status = self.hymofs.status();
+        json!({
+            "device": self.hymofs.device(),
+            "state": status.label(),
+            "detail": status.describe(),
+            "protocol": self.hymofs.get_version().ok(),
+            "caps": self.available().ok().and_then(|h| h.caps().ok()),
+            "rules": self.available().ok().and_then(|h| h.list_rules().ok()).map(|rules| rules.len()),
+            "uptime_secs": self.started.elapsed().as_secs(),
+            "requests": self.served,
+        })
+    }
+}
+
+/// Longest request line a client may send; a longer one ends its
+/// connection.
+const MAX_REQUEST_LEN: u64 = 64 * 1024;
+
+/// Binds the control socket, replacing one a previous daemon left behind;
+/// only root may connect. The socket is bound in a directory only root can
+/// enter and renamed into place once it is 0600, so it is never open to
+/// others.
+pub fn bind(socket: &Path) -> Result<UnixListener> {
+    let parent = socket.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    fs::create_dir_all(parent)?;
+    if UnixStream::connect(socket).is_ok() {
+        bail!("Another hymod is already listening on {}", socket.display());
+    }
+    let private = parent.join(format!(".hymod-bind-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&private);
+    fs::DirBuilder::new().mode(0o700).create(&private).with_context(|| format!("Failed to create {}", private.display()))?;
+    let staged = private.join("socket");
+    let bound = UnixListener::bind(&staged)
+        .with_context(|| format!("Failed to bind {}", socket.display()))
+        .and_then(|listener| {
+            fs::set_permissions(&staged, fs::Permissions::from_mode(0o600))?;
+            let _ = fs::remove_file(socket);
+            fs::rename(&staged, socket).with_context(|| format!("Failed to move the socket to {}", socket.display()))?;
+            Ok(listener)
+        });
+    let _ = fs::remove_dir_all(&private);
+    bound
+}
+
+/// Reads one request line of at most [`MAX_REQUEST_LEN`] bytes; `None` at
+/// the end of the stream.
+fn read_request<R: BufRead>(reader: &mut R) -> std::io::Result<Option<String>> {
+    let mut line = String::new();
+    let read = Read::take(reader, MAX_REQUEST_LEN + 1).read_line(&mut line)?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if read as u64 > MAX_REQUEST_LEN {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Request longer than {} bytes", MAX_REQUEST_LEN)));
+    }
+    Ok(Some(line))
+}
+
+/// Answers connections, each on its own thread, while `handle` runs one
+/// request at a time. Never returns.
+pub fn serve<H>(listener: UnixListener, handle: H) -> !
+where
+    H: FnMut(Request) -> Result<Value> + Send + 'static,
+{
+    let handle = Arc::new(Mutex::new(handle));
+    loop {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                log::warn!("!! hymod: accept failed: {}", e);
+                continue;
+            }
+        };
+        let handle = Arc::clone(&handle);
+        std::thread::spawn(move || {
+            let Ok(mut writer) = stream.try_clone() else { return };
+            let mut reader = BufReader::new(stream);
+            loop {
+                let line = match read_request(&mut reader) {
+                    Ok(Some(line)) => line,
+                    Ok(None) => break,
+                    Err(e) => {
+                        let _ = writeln!(writer, "{}", serde_json::to_string(&Response::from_result(Err(e.into()))).unwrap_or_default());
+                        break;
+                    }
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let answer = respond(&line, |request| {
+                    let mut handle = handle.lock().unwrap_or_else(PoisonError::into_inner);
+                    handle(request)
+                });
+                if writeln!(writer, "{}", answer).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+/// Sends `request` to the daemon on `socket` and waits for its answer.
+pub fn request(socket: &Path, request: &Request) -> Result<Value> {
+    let mut stream = UnixStream::connect(socket)
+        .with_context(|| format!("hymod is not running ({} cannot be reached)", socket.display()))?;
+    writeln!(stream, "{}", serde_json::to_string(request)?)?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    let response: Response = serde_json::from_str(&line).context("Malformed response from hymod")?;
+    match response.error {
+        Some(error) => bail!("hymod: {}", error),
+        None => Ok(response.result),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn answers_every_line() {
+        assert_eq!(respond(r#"{"cmd":"add_module","id":"foo"}"#, |request| {
+            assert_eq!(request, Request::AddModule { id: "foo".into() });
+            Ok(json!({ "applied": 2 }))
+        }), r#"{"ok":true,"result":{"applied":2}}"#);
+        let answer: Response = serde_json::from_str(&respond(r#"{"cmd":"format"}"#, |_| Ok(Value::Null))).unwrap();
+        assert!(!answer.ok && answer.error.unwrap().starts_with("Malformed request"));
+        assert_eq!(respond(r#"{"cmd":"reload"}"#, |_| bail!("no config")), r#"{"ok":false,"error":"no config"}"#);
+    }
+
+    #[test]
+    fn cuts_off_overlong_requests() {
+        let mut reader = std::io::Cursor::new(format!("{{\"cmd\":\"status\"}}\n{}", "x".repeat(MAX_REQUEST_LEN as usize + 1)));
+        assert_eq!(read_request(&mut reader).unwrap().as_deref(), Some("{\"cmd\":\"status\"}\n"));
+        assert!(read_request(&mut reader).is_err());
+        assert_eq!(read_request(&mut std::io::Cursor::new("")).unwrap(), None);
+    }
+
+    #[test]
+    fn serializes_requests_of_concurrent_clients() {
+        let socket = std::env::temp_dir().join(format!("hymod-{}.sock", std::process::id()));
+        let listener = bind(&socket).unwrap();
+        assert_eq!(fs::metadata(&socket).unwrap().permissions().mode() & 0o777, 0o600);
+        assert!(bind(&socket).is_err());
+        let mut served = 0;
+        std::thread::spawn(move || serve(listener, move |_| {
+            served += 1;
+            Ok(json!(served))
+        }));
+        let clients: Vec<_> = (0..4).map(|_| {
+            let socket = socket.clone();
+            std::thread::spawn(move || request(&socket, &Request::Status).unwrap())
+        }).collect();
+        let mut answers: Vec<u64> = clients.into_iter().map(|c| c.join().unwrap().as_u64().unwrap()).collect();
+        answers.sort();
+        assert_eq!(answers, vec![1, 2, 3, 4]);
+        let _ = fs::remove_file(&socket);
+    }
+}
@@ -0,0 +1,325 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use crate::{
+    core::staging,
+    defs,
+    mount::hymofs::{self, RuleOp},
+    utils,
+};
+
+const MANIFEST_NAME: &str = "manifest.json";
+const BACKUP_FILES_DIR: &str = "files";
+
+/// What the planner does with a module partition whose target is mounted
+/// read-write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WritablePolicy {
+    /// Always go through rules and mounts, never touch the partition.
+    #[default]
+    AlwaysRules,
+    /// Copy module files into the partition, backing up what they replace.
+    PreferDirect,
+    /// Modify directly only modules approved with `approve-direct`.
+    Ask,
+}
+
+pub fn uses_direct(policy: WritablePolicy, writable: bool, approved: bool) -> bool {
+    writable && match policy {
+        WritablePolicy::AlwaysRules => false,
+        WritablePolicy::PreferDirect => true,
+        WritablePolicy::Ask => approved,
+    }
+}
+
+/// Partitions among `partitions` whose mount point is currently read-write.
+pub fn writable_partitions(mountinfo: &str, partitions: &[&str]) -> HashSet<String> {
+    partitions.iter()
+        .filter(|part| {
+            let target = Path::new("/").join(part);
+            let resolved = target.canonicalize().unwrap_or(target);
+            resolved.is_dir() && staging::mount_flags(mountinfo, &resolved).is_some_and(|flags| !flags.read_only)
+        })
+        .map(|part| part.to_string())
+        .collect()
+}
+
+pub fn load_approvals() -> HashSet<String> {
+    fs::read_to_string(defs::DIRECT_APPROVALS_FILE)
+        .map(|content| content.lines().map(str::trim).filter(|l| !l.is_empty()).map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+pub fn set_approval(module_id: &str, approved: bool) -> Result<()> {
+    let mut approvals = load_approvals();
+    if approved {
+        approvals.insert(module_id.to_string());
+    } else {
+        approvals.remove(module_id);
+    }
+    let mut ids: Vec<String> = approvals.into_iter().collect();
+    ids.sort();
+    utils::ensure_dir_exists(defs::BASE_DIR)?;
+    fs::write(defs::DIRECT_APPROVALS_FILE, ids.iter().map(|id| format!("{}\n", id)).collect::<String>())?;
+    Ok(())
+}
+
+/// One path in a partition that a module wrote or removed. `backup` holds
+/// the original when there was one, `context` its SELinux label.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub path: PathBuf,
+    pub backup: Option<PathBuf>,
+    pub context: Option<String>,
+}
+
+/// Everything needed to undo a module's direct modifications. Entries are
+/// recorded the first time a path is touched, so re-applying on later boots
+/// never mistakes the module's own copy for the original.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub entries: Vec<Entry>,
+    pub created_dirs: Vec<PathBuf>,
+}
+
+fn module_dir(backup_root: &Path, module_id: &str) -> PathBuf {
+    backup_root.join(module_id)
+}
+
+impl Manifest {
+    pub fn load(backup_root: &Path, module_id: &str) -> Self {
+        fs::read_to_string(module_dir(backup_root, module_id).join(MANIFEST_NAME))
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, backup_root: &Path, module_id: &str) -> Result<()> {
+        let dir = module_dir(backup_root, module_id);
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join(MANIFEST_NAME), serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+fn remove_path(path: &Path) -> Result<()> {
+    match path.symlink_metadata() {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(path)?,
+        Ok(_) => fs::remove_file(path)?,
+        Err(_) => {}
+    }
+    Ok(())
+}
+
+/// Copies a regular file or symlink, keeping symlinks as links.
+fn copy_entry(from: &Path, to: &Path) -> Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    remove_path(to)?;
+    if from.symlink_metadata()?.file_type().is_symlink() {
+        std::os::unix::fs::symlink(fs::read_link(from)?, to)?;
+    } else {
+        fs::copy(from, to)?;
+    }
+    Ok(())
+}
+
+fn create_parents(path: &Path, manifest: &mut Manifest) -> Result<()> {
+    let Some(parent) = path.parent() else { return Ok(()) };
+    let missing: Vec<&Path> = parent.ancestors().take_while(|dir| !dir.exists()).collect();
+    for dir in missing.into_iter().rev() {
+        fs::create_dir(dir)?;
+        if let Some(context) = dir.parent().and_then(|p| utils::lgetfilecon(p).ok()) {
+            utils::lsetfilecon(dir, &context)?;
+        }
+        manifest.created_dirs.push(dir.to_path_buf());
+    }
+    Ok(())
+}
+
+fn record(path: &Path, backup_root: &Path, module_id: &str, manifest: &mut Manifest) -> Result<()> {
+    if manifest.entries.iter().any(|entry| entry.path == path) {
+        return Ok(());
+    }
+    let original = path.symlink_metadata().is_ok_and(|meta| !meta.is_dir());
+    let backup = if original {
+        let relative = path.strip_prefix("/").unwrap_or(path);
+        let backup = module_dir(backup_root, module_id).join(BACKUP_FILES_DIR).join(relative);
+        copy_entry(path, &backup).with_context(|| format!("Failed to back up {}", path.display()))?;
+        Some(backup)
+    } else {
+        None
+    };
+    manifest.entries.push(Entry {
+        path: path.to_path_buf(),
+        backup,
+        context: original.then(|| utils::lgetfilecon(path).ok()).flatten(),
+    });
+    Ok(())
+}
+
+fn restore_entry(entry: &Entry) -> Result<()> {
+    match &entry.backup {
+        Some(backup) => {
+            copy_entry(backup, &entry.path)?;
+            if let Some(context) = &entry.context {
+                utils::lsetfilecon(&entry.path, context)?;
+            }
+        }
+        None => remove_path(&entry.path)?,
+    }
+    Ok(())
+}
+
+fn apply_ops(ops: &[RuleOp], backup_root: &Path, module_id: &str, manifest: &mut Manifest) -> Result<usize> {
+    let mut produced = HashSet::new();
+    for op in ops {
+        let path = Path::new(op.path());
+        match op {
+            RuleOp::Add { target: source, .. } => {
+                if path.symlink_metadata().is_ok_and(|meta| meta.is_dir()) {
+                    log::warn!("!! {}: {} is a directory in the partition, skipping", module_id, path.display());
+                    continue;
+                }
+                create_parents(path, manifest)?;
+                record(path, backup_root, module_id, manifest)?;
+                copy_entry(Path::new(source), path).with_context(|| format!("Failed to write {}", path.display()))?;
+                match manifest.entries.iter().find(|e| e.path == path).and_then(|e| e.context.as_deref()) {
+                    Some(context) => utils::lsetfilecon(path, context)?,
+                    None => utils::copy_path_context(source, path)?,
+                }
+            }
+            RuleOp::Hide { .. } => {
+                if path.symlink_metadata().is_ok_and(|meta| meta.is_dir()) {
+                    log::warn!("!! {}: cannot remove directory {} directly, skipping", module_id, path.display());
+                    continue;
+                }
+                record(path, backup_root, module_id, manifest)?;
+                remove_path(path)?;
+            }
+            RuleOp::Delete { .. } => continue,
+        }
+        produced.insert(path.to_path_buf());
+    }
+    // Files the module no longer ships go back to their originals.
+    let (current, stale): (Vec<Entry>, Vec<Entry>) = std::mem::take(&mut manifest.entries)
+        .into_iter()
+        .partition(|entry| produced.contains(&entry.path));
+    manifest.entries = current;
+    for entry in stale.iter().rev() {
+        restore_entry(entry)?;
+    }
+    Ok(produced.len())
+}
+
+/// Writes the module partition at `source` into the live partition at
+/// `target`, walking it the same way rule injection does. The manifest is
+/// saved even when a write fails, so whatever landed can still be undone.
+pub fn apply(module_id: &str, source: &Path, target: &Path, backup_root: &Path) -> Result<usize> {
+    let ops = hymofs::collect_directory_ops(target, source);
+    let mut manifest = Manifest::load(backup_root, module_id);
+    let applied = apply_ops(&ops, backup_root, module_id, &mut manifest);
+    manifest.save(backup_root, module_id)?;
+    applied
+}
+
+/// Undoes every direct modification of `module_id`; returns how many paths
+/// were restored.
+pub fn restore(module_id: &str, backup_root: &Path) -> Result<usize> {
+    let dir = module_dir(backup_root, module_id);
+    if !dir.exists() {
+        return Ok(0);
+    }
+    let manifest = Manifest::load(backup_root, module_id);
+    for entry in manifest.entries.iter().rev() {
+        restore_entry(entry).with_context(|| format!("Failed to restore {}", entry.path.display()))?;
+    }
+    for created in manifest.created_dirs.iter().rev() {
+        let _ = fs::remove_dir(created);
+    }
+    fs::remove_dir_all(&dir)?;
+    Ok(manifest.entries.len())
+}
+
+/// Modules with recorded direct modifications that are not in `active`.
+pub fn stale_modules(backup_root: &Path, active: &[String]) -> Vec<String> {
+    let mut stale: Vec<String> = fs::read_dir(backup_root).into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.path().join(MANIFEST_NAME).exists())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|id| !active.contains(id))
+        .collect();
+    stale.sort();
+    stale
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("direct-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        root
+    }
+
+    #[test]
+    fn policy_only_applies_to_writable_targets() {
+        assert!(!uses_direct(WritablePolicy::PreferDirect, false, true));
+        assert!(uses_direct(WritablePolicy::PreferDirect, true, false));
+        assert!(!uses_direct(WritablePolicy::AlwaysRules, true, true));
+        assert!(!uses_direct(WritablePolicy::Ask, true, false));
+        assert!(uses_direct(WritablePolicy::Ask, true, true));
+    }
+
+    #[test]
+    fn finds_partitions_mounted_read_write() {
+        let mountinfo = "22 1 253:0 / / rw,relatime - ext4 /dev/block/dm-0 rw\n\
+                         23 22 0:5 / /proc ro,relatime - proc proc ro\n";
+        let writable = writable_partitions(mountinfo, &["proc", "does-not-exist"]);
+        assert!(writable.is_empty());
+        let writable = writable_partitions("22 1 253:0 / / rw,relatime - ext4 /dev/block/dm-0 rw\n", &["proc"]);
+        assert!(writable.contains("proc"));
+    }
+
+    #[test]
+    fn applies_and_restores_with_backups() {
+        let root = scratch("apply");
+        let (source, target, backups) = (root.join("module/system"), root.join("system"), root.join("backup"));
+        fs::create_dir_all(source.join("bin")).unwrap();
+        fs::create_dir_all(target.join("bin")).unwrap();
+        fs::write(source.join("bin/tool"), "module").unwrap();
+        fs::write(source.join("bin/new"), "added").unwrap();
+        fs::create_dir_all(source.join("etc/extra")).unwrap();
+        fs::write(source.join("etc/extra/conf"), "conf").unwrap();
+        fs::write(target.join("bin/tool"), "stock").unwrap();
+
+        assert_eq!(apply("m", &source, &target, &backups).unwrap(), 3);
+        assert_eq!(fs::read_to_string(target.join("bin/tool")).unwrap(), "module");
+        assert_eq!(fs::read_to_string(target.join("etc/extra/conf")).unwrap(), "conf");
+        // A second boot re-applies without backing up the module's own copy.
+        apply("m", &source, &target, &backups).unwrap();
+        let manifest = Manifest::load(&backups, "m");
+        assert_eq!(manifest.entries.len(), 3);
+        assert_eq!(manifest.created_dirs, vec![target.join("etc"), target.join("etc/extra")]);
+
+        fs::remove_file(source.join("bin/new")).unwrap();
+        apply("m", &source, &target, &backups).unwrap();
+        assert!(!target.join("bin/new").exists(), "dropped files are undone on the next apply");
+
+        assert_eq!(stale_modules(&backups, &[]), vec!["m".to_string()]);
+        assert!(stale_modules(&backups, &["m".to_string()]).is_empty());
+        assert_eq!(restore("m", &backups).unwrap(), 2);
+        assert_eq!(fs::read_to_string(target.join("bin/tool")).unwrap(), "stock");
+        assert!(!target.join("etc").exists());
+        assert!(!backups.join("m").exists());
+        assert_eq!(restore("m", &backups).unwrap(), 0);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
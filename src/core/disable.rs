@@ -0,0 +1,99 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use crate::{defs, utils};
+
+/// Why meta-hybrid turned a module off on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DisableSource {
+    /// Crashes in some process started right after the module was enabled.
+    CrashCorrelation,
+    /// The staged copy kept failing content verification.
+    Integrity,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DisableReason {
+    pub source: DisableSource,
+    pub message: String,
+    pub disabled_at: u64,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub details: BTreeMap<String, String>,
+}
+
+impl DisableReason {
+    pub fn new(source: DisableSource, message: impl Into<String>) -> Self {
+        Self {
+            source,
+            message: message.into(),
+            disabled_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+            details: BTreeMap::new(),
+        }
+    }
+
+    pub fn detail(mut self, key: &str, value: impl ToString) -> Self {
+        self.details.insert(key.to_string(), value.to_string());
+        self
+    }
+}
+
+fn load_all(path: &Path) -> BTreeMap<String, DisableReason> {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+/// Reasons for modules that are still disabled. Once the user re-enables a
+/// module (the manager deletes its `disable` file) the reason no longer
+/// applies and is dropped. `roots` are the module roots to look in.
+pub fn current(reasons_file: &Path, roots: &[PathBuf]) -> BTreeMap<String, DisableReason> {
+    let mut reasons = load_all(reasons_file);
+    reasons.retain(|id, _| roots.iter().any(|root| root.join(id).join(defs::DISABLE_FILE_NAME).exists()));
+    reasons
+}
+
+/// Turns the module off the same way a manager would and records why.
+pub fn disable(reasons_file: &Path, roots: &[PathBuf], module_id: &str, module_dir: &Path, reason: DisableReason) -> Result<()> {
+    fs::write(module_dir.join(defs::DISABLE_FILE_NAME), "")
+        .with_context(|| format!("failed to disable {}", module_id))?;
+    let mut reasons = current(reasons_file, roots);
+    reasons.insert(module_id.to_string(), reason);
+    utils::atomic_write(reasons_file, serde_json::to_string_pretty(&reasons)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_reasons_only_while_disabled() {
+        let root = std::env::temp_dir().join(format!("disable-reasons-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let moduledir = root.join("modules");
+        let roots = [root.join("other"), moduledir.clone()];
+        fs::create_dir_all(moduledir.join("a")).unwrap();
+        fs::create_dir_all(moduledir.join("b")).unwrap();
+        let file = root.join("reasons.json");
+
+        let reason = DisableReason::new(DisableSource::CrashCorrelation, "too many crashes")
+            .detail("com.android.systemui", 3);
+        disable(&file, &roots, "a", &moduledir.join("a"), reason.clone()).unwrap();
+        disable(&file, &roots, "b", &moduledir.join("b"), DisableReason::new(DisableSource::Integrity, "bad copy")).unwrap();
+        assert!(moduledir.join("a").join(defs::DISABLE_FILE_NAME).exists());
+        assert_eq!(current(&file, &roots).get("a"), Some(&reason));
+
+        fs::remove_file(moduledir.join("b").join(defs::DISABLE_FILE_NAME)).unwrap();
+        let reasons = current(&file, &roots);
+        assert_eq!(reasons.keys().collect::<Vec<_>>(), vec!["a"]);
+
+        let json = serde_json::to_value(&reasons["a"]).unwrap();
+        assert_eq!(json["source"], "crash-correlation");
+        let _ = fs::remove_dir_all(&root);
+    }
+}
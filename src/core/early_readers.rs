@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+use crate::{core::planner::MountPlan, mount::hymofs::RuleOp};
+
+const CRITICAL_PROCESSES: [&str; 6] = ["init", "ueventd", "zygote", "zygote64", "system_server", "servicemanager"];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EarlyReader {
+    pub module_id: String,
+    pub path: String,
+    pub pid: i32,
+    pub process: String,
+}
+
+pub fn parse_maps_path(line: &str) -> Option<&str> {
+    let mut rest = line;
+    for _ in 0..5 {
+        rest = rest.trim_start();
+        let end = rest.find(char::is_whitespace)?;
+        rest = &rest[end..];
+    }
+    let path = rest.trim();
+    let path = path.strip_suffix(" (deleted)").unwrap_or(path);
+    path.starts_with('/').then_some(path)
+}
+
+fn collect_relative_files(root: &Path, target_base: &Path, module_id: &str, out: &mut HashMap<String, String>) {
+    for entry in WalkDir::new(root).min_depth(1).into_iter().flatten() {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        if let Ok(rel) = entry.path().strip_prefix(root) {
+            out.insert(target_base.join(rel).to_string_lossy().into_owned(), module_id.to_string());
+        }
+    }
+}
+
+fn module_id_of(layer: &Path) -> String {
+    layer.parent()
+        .and_then(|p| p.file_name())
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| "unknown".into())
+}
+
+pub fn redirected_targets(plan: &MountPlan) -> HashMap<String, String> {
+    let mut targets = HashMap::new();
+    for op in &plan.hymo_ops {
+        for rule in plan.rules_of(op) {
+            if let RuleOp::Add { src, .. } = rule {
+                targets.insert(src, op.module_id.clone());
+            }
+        }
+    }
+    for op in &plan.overlay_ops {
+        for layer in &op.lowerdirs {
+            collect_relative_files(layer, Path::new(&op.target), &module_id_of(layer), &mut targets);
+        }
+    }
+    for root in &plan.magic_module_paths {
+        let id = root.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        let Ok(entries) = fs::read_dir(root) else { continue };
+        for entry in entries.flatten().filter(|e| e.path().is_dir()) {
+            let target = PathBuf::from("/").join(entry.file_name());
+            collect_relative_files(&entry.path(), &target, &id, &mut targets);
+        }
+    }
+    targets
+}
+
+pub fn find(targets: &HashMap<String, String>) -> Vec<EarlyReader> {
+    let mut readers = Vec::new();
+    if targets.is_empty() {
+        return readers;
+    }
+    let Ok(procs) = fs::read_dir("/proc") else {
+        return readers;
+    };
+    let own_pid = std::process::id() as i32;
+    for proc_entry in procs.flatten() {
+        let Ok(pid) = proc_entry.file_name().to_string_lossy().parse::<i32>() else { continue };
+        if pid == own_pid {
+            continue;
+        }
+        let Ok(maps) = fs::read_to_string(proc_entry.path().join("maps")) else { continue };
+        let process = fs::read_to_string(proc_entry.path().join("comm"))
+            .map(|s| s.trim().to_string())
+            .unwrap_or_default();
+        let mut seen = Vec::new();
+        for path in maps.lines().filter_map(parse_maps_path) {
+            if seen.contains(&path) {
+                continue;
+            }
+            seen.push(path);
+            if let Some(module_id) = targets.get(path) {
+                readers.push(EarlyReader {
+                    module_id: module_id.clone(),
+                    path: path.to_string(),
+                    pid,
+                    process: process.clone(),
+                });
+            }
+        }
+    }
+    readers.sort_by(|a, b| a.module_id.cmp(&b.module_id).then(a.pid.cmp(&b.pid)));
+    readers
+}
+
+pub fn is_restartable(process: &str, allowlist: &[String]) -> bool {
+    !CRITICAL_PROCESSES.contains(&process) && allowlist.iter().any(|p| p == process)
+}
+
+pub fn restart(readers: &[EarlyReader], allowlist: &[String]) -> Vec<i32> {
+    let mut restarted = Vec::new();
+    for reader in readers {
+        if restarted.contains(&reader.pid) || reader.pid <= 1 || !is_restartable(&reader.process, allowlist) {
+            continue;
+        }
+        log::info!(">> Restarting {} (pid {}) so it picks up {}", reader.process, reader.pid, reader.path);
+        if unsafe { libc::kill(reader.pid, libc::SIGTERM) } == 0 {
+            restarted.push(reader.pid);
+        } else {
+            log::warn!("Failed to signal {}: {}", reader.pid, std::io::Error::last_os_error());
+        }
+    }
+    restarted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_maps_paths() {
+        let line = "7f1c2a000000-7f1c2a021000 r--p 00000000 fd:00 1234   /system/lib64/libfoo.so";
+        assert_eq!(parse_maps_path(line), Some("/system/lib64/libfoo.so"));
+        let deleted = "7f1c2a000000-7f1c2a021000 r--p 00000000 fd:00 1234 /system/lib64/libbar.so (deleted)";
+        assert_eq!(parse_maps_path(deleted), Some("/system/lib64/libbar.so"));
+        assert_eq!(parse_maps_path("7f1c2a000000-7f1c2a021000 rw-p 00000000 00:00 0 [heap]"), None);
+        assert_eq!(parse_maps_path("7f1c2a000000-7f1c2a021000 rw-p 00000000 00:00 0"), None);
+    }
+
+    #[test]
+    fn never_restarts_critical_processes() {
+        let allow = vec!["audioserver".to_string(), "zygote64".to_string()];
+        assert!(is_restartable("audioserver", &allow));
+        assert!(!is_restartable("zygote64", &allow));
+        assert!(!is_restartable("cameraserver", &allow));
+    }
+}
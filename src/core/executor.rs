@@ -1,5 +1,6 @@
 use std::path::{Path, PathBuf};
 use std::collections::{HashMap, HashSet};
+use std::time::Instant;
 use anyhow::Result;
 use rayon::prelude::*;
 use walkdir::WalkDir;
@@ -7,19 +8,30 @@ use rustix::mount::UnmountFlags;
 
 use crate::{
     conf::config, 
-    mount::{magic, overlay, hymofs::{HymoFs, HymoFsStatus}}, 
+    mount::{magic, overlay, propagation, BackendKind, hymofs::{self, FlushStats, HymoFs, HymoFsStatus, HymoResult, RuleOp, SharedHymoFs}}, 
     utils,
-    core::planner::MountPlan
+    core::{apk, direct, guards, health::BackendRun, inventory::BootWindow, journal, memory, partitions, planner::{HymoOperation, MountPlan}, verity::{self, VerityMode, VerityReport}, rules::{self, RuleLedger, EXTERNAL_GROUP}, state::RuntimeState, wal},
 };
 
 pub struct ExecutionResult {
     pub overlay_module_ids: Vec<String>,
     pub hymo_module_ids: Vec<String>,
     pub magic_module_ids: Vec<String>,
+    pub direct_module_ids: Vec<String>,
+    pub rules_applied: usize,
+    pub rules_failed: usize,
+    pub warnings: Vec<String>,
+    pub failures: Vec<String>,
+    /// How each backend fared, for its health record.
+    pub backend_runs: Vec<BackendRun>,
+}
+
+pub struct BootTransition {
+    pub injected_module_ids: Vec<String>,
+    pub removed_module_ids: Vec<String>,
 }
 
 pub enum DiagnosticLevel {
-    #[allow(dead_code)]
     Info,
     Warning,
     Critical,
@@ -37,6 +49,8 @@ fn extract_id(path: &Path) -> Option<String> {
         .map(|s| s.to_string_lossy().to_string())
 }
 
+const RESET_GROUP: &str = "reset";
+
 fn extract_module_root(partition_path: &Path) -> Option<PathBuf> {
     partition_path.parent().map(|p| p.to_path_buf())
 }
@@ -45,6 +59,8 @@ struct OverlayResult {
     magic_roots: Vec<PathBuf>,
     fallback_ids: Vec<String>,
     success_records: Vec<(PathBuf, String)>,
+    warning: Option<String>,
+    failed: bool,
 }
 
 pub fn diagnose_plan(plan: &MountPlan) -> Vec<DiagnosticIssue> {
@@ -69,72 +85,316 @@ pub fn diagnose_plan(plan: &MountPlan) -> Vec<DiagnosticIssue> {
         .collect();
     for (mod_id, layer_path) in all_layers {
         if !layer_path.exists() { continue; }
-        for entry in WalkDir::new(layer_path) {
-            if let Ok(entry) = entry {
-                if entry.path_is_symlink() {
-                    if let Ok(target) = std::fs::read_link(entry.path()) {
-                        if target.is_absolute() {
-                            if !target.exists() {
-                                issues.push(DiagnosticIssue {
-                                    level: DiagnosticLevel::Warning,
-                                    context: mod_id.clone(),
-                                    message: format!("Dead absolute symlink: {} -> {}", 
-                                        entry.path().display(), target.display()),
-                                });
-                            }
-                        }
+        for entry in WalkDir::new(layer_path).into_iter().flatten() {
+            if entry.path_is_symlink() {
+                if let Ok(target) = std::fs::read_link(entry.path()) {
+                    if target.is_absolute() && !target.exists() {
+                        issues.push(DiagnosticIssue {
+                            level: DiagnosticLevel::Warning,
+                            context: mod_id.clone(),
+                            message: format!("Dead absolute symlink: {} -> {}", 
+                                entry.path().display(), target.display()),
+                        });
                     }
                 }
             }
         }
     }
+    for reason in &plan.rejected {
+        issues.push(DiagnosticIssue {
+            level: DiagnosticLevel::Warning,
+            context: "planner".to_string(),
+            message: format!("Rejected {}", reason),
+        });
+    }
+    for op in &plan.hymo_ops {
+        let ops = hymofs::collect_directory_ops(&op.target, &op.source);
+        for finding in apk::inspect(&ops, false).findings {
+            issues.push(DiagnosticIssue {
+                level: DiagnosticLevel::Warning,
+                context: op.module_id.clone(),
+                message: format!("{}: {}", finding.target, finding.message),
+            });
+        }
+    }
+    let partitions = planned_partitions(plan);
+    let partitions: Vec<&str> = partitions.iter().map(String::as_str).collect();
+    issues.extend(diagnose_verity(plan, &verity::probe(&partitions)));
+    issues
+}
+
+fn planned_partitions(plan: &MountPlan) -> Vec<String> {
+    let mut partitions: Vec<String> = plan.overlay_ops.iter().map(|op| op.partition_name.clone())
+        .chain(plan.hymo_ops.iter().filter_map(|op| op.target.file_name()).map(|n| n.to_string_lossy().into_owned()))
+        .chain(plan.direct_ops.iter().filter_map(|op| op.target.file_name()).map(|n| n.to_string_lossy().into_owned()))
+        .chain(plan.magic_module_paths.iter()
+            .flat_map(|root| crate::defs::BUILTIN_PARTITIONS.iter().filter(|part| root.join(part).is_dir()))
+            .map(|part| part.to_string()))
+        .collect();
+    partitions.sort();
+    partitions.dedup();
+    partitions
+}
+
+/// Reports the verity state of the targeted partitions and flags backends
+/// that cannot take effect on them. Rules and mounts act in the VFS above
+/// the block device, so only direct writes collide with the hash tree.
+pub fn diagnose_verity(plan: &MountPlan, report: &VerityReport) -> Vec<DiagnosticIssue> {
+    let mut issues = vec![DiagnosticIssue {
+        level: DiagnosticLevel::Info,
+        context: "verity".to_string(),
+        message: format!("Verity mode {:?}, verified boot state {}, device {}", report.mode,
+            report.verified_boot_state.as_deref().unwrap_or("unknown"), report.device_state.as_deref().unwrap_or("unknown")),
+    }];
+    for part in &report.partitions {
+        issues.push(DiagnosticIssue {
+            level: DiagnosticLevel::Info,
+            context: part.partition.clone(),
+            message: format!("{} on {}{}: {}", if part.read_only { "ro" } else { "rw" }, part.device,
+                part.dm_name.as_ref().map(|n| format!(" ({})", n)).unwrap_or_default(),
+                if part.locked { "verity-protected" } else { "not verity-protected" }),
+        });
+        if part.locked && report.mode == VerityMode::Eio {
+            issues.push(DiagnosticIssue {
+                level: DiagnosticLevel::Warning,
+                context: part.partition.clone(),
+                message: "dm-verity already hit a corrupted block; unredirected reads of damaged files fail with EIO".to_string(),
+            });
+        }
+    }
+    for op in &plan.direct_ops {
+        let part_name = op.target.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        if report.partition(&part_name).is_some_and(|p| p.locked) {
+            issues.push(DiagnosticIssue {
+                level: DiagnosticLevel::Critical,
+                context: op.module_id.clone(),
+                message: format!("direct writes to {} land on verity-protected blocks and fail verification on the next read", op.target.display()),
+            });
+        }
+    }
+    issues
+}
+
+pub fn diagnose_state(state: &RuntimeState) -> Vec<DiagnosticIssue> {
+    let mut issues = Vec::new();
+    if let Some(test) = &state.hymofs_selftest {
+        issues.push(DiagnosticIssue {
+            level: if test.passed { DiagnosticLevel::Info } else { DiagnosticLevel::Critical },
+            context: "hymofs".to_string(),
+            message: if test.passed {
+                format!("Kernel honored the canary rule on this boot ({})", test.detail)
+            } else {
+                format!("Kernel did not honor the canary rule on this boot: {}", test.detail)
+            },
+        });
+    }
     issues
 }
 
-pub fn execute(plan: &MountPlan, config: &config::Config) -> Result<ExecutionResult> {
+pub fn execute(plan: &MountPlan, config: &config::Config, hymofs: Option<&HymoFs>) -> Result<ExecutionResult> {
     let mut magic_queue = plan.magic_module_paths.clone();
     let mut global_success_map: HashMap<PathBuf, HashSet<String>> = HashMap::new();
     let mut final_overlay_ids = HashSet::new();
     let mut final_hymo_ids = HashSet::new();
+    let mut rules_applied = 0;
+    let mut rules_failed = 0;
+    let mut warnings = Vec::new();
+    let mut failures = Vec::new();
+    let mut backend_runs = Vec::new();
     
     plan.overlay_module_ids.iter().for_each(|id| { final_overlay_ids.insert(id.clone()); });
     plan.hymo_module_ids.iter().for_each(|id| { final_hymo_ids.insert(id.clone()); });
 
+    let final_direct_ids = apply_direct(plan, &mut magic_queue, &mut global_success_map, &mut warnings);
+
     if !plan.hymo_ops.is_empty() {
-        match HymoFs::check_status() {
-            HymoFsStatus::Available => {
-                log::info!(">> Phase 1: HymoFS Injection (Protocol v{})...", crate::defs::HYMO_PROTOCOL_VERSION);
-                if let Err(e) = HymoFs::clear() {
-                    log::warn!("Failed to reset HymoFS rules: {}", e);
+        let device = &config.hymofs_device;
+        let controller = match hymofs {
+            Some(handle) => match handle.status() {
+                HymoFsStatus::Available => Ok(handle),
+                status => Err(status),
+            },
+            None => Err(HymoFsStatus::NotPresent),
+        };
+        match controller {
+            Ok(ctl) => {
+                let phase_started = Instant::now();
+                log::info!(">> Phase 1: HymoFS Injection via {} (Protocol v{})...", ctl.device().display(), ctl.get_version().unwrap_or(crate::defs::HYMO_PROTOCOL_VERSION));
+                let low_memory = memory::low_memory();
+                let mut shared = SharedHymoFs::new(ctl.clone().with_priorities(plan.hymo_priorities()));
+                if low_memory {
+                    shared = shared.with_flush_threshold(memory::LOW_MEMORY_FLUSH_THRESHOLD);
                 }
-                for op in &plan.hymo_ops {
+                // Without a rule listing the ledger is the only record of
+                // which rules a previous run left.
+                let mut ledger = if low_memory && ctl.caps().is_ok_and(|caps| caps.supports_list) {
+                    RuleLedger::load_foreign()
+                } else {
+                    RuleLedger::load()
+                };
+                match journal::recover(ctl, &mut ledger, Path::new(crate::defs::RULE_JOURNAL_DIR)) {
+                    Ok(found) if !found.rolled_back.is_empty() || !found.adopted.is_empty() => {
+                        warnings.push(format!("Recovered interrupted rule journals: {} rules rolled back, {} recorded again", found.rolled_back.len(), found.adopted.len()));
+                    }
+                    Ok(_) => {}
+                    Err(e) => log::warn!("Failed to recover rule journals: {:#}", e),
+                }
+                let roots: Vec<PathBuf> = plan.hymo_ops.iter()
+                    .filter_map(|op| extract_module_root(&op.source)?.parent().map(Path::to_path_buf))
+                    .chain(rules::owned_roots(config, None))
+                    .collect();
+                let live = ctl.list_ops();
+                let partial = wal::recover(Path::new(crate::defs::APPLY_WAL_FILE));
+                let recovery = partial.as_ref()
+                    .map(|p| {
+                        let verified = live.as_deref().ok().filter(|_| !config.hymofs_clear_foreign);
+                        p.plan(verified, config.partial_apply_policy)
+                    })
+                    .unwrap_or_default();
+                if let Some(p) = &partial {
+                    log::warn!("!! Previous apply (session {}) was interrupted after {}/{} rule groups; {} verified and kept, {} rules rolled back",
+                        p.session, p.checkpoints.len(), p.planned.len(), recovery.resume.len(), recovery.rollback.len());
+                    warnings.push(format!("Recovered interrupted HymoFS apply of session {}", p.session));
+                }
+                let kept = partial.as_ref().map(|p| recovery.kept(&p.checkpoints)).unwrap_or_default();
+                let cleared_all = if config.hymofs_clear_foreign {
+                    if let Err(e) = ctl.clear() {
+                        log::warn!("Failed to reset HymoFS rules: {}", e);
+                    }
+                    true
+                } else {
+                    reset_owned_rules(&shared, &ledger, &roots, &live, &kept, &recovery.rollback);
+                    false
+                };
+                let foreign = ledger.foreign_rules();
+                if cleared_all && !foreign.is_empty() {
+                    log::info!(">> Restoring {} adopted and namespaced HymoFS rules", foreign.len());
+                    shared.enqueue(EXTERNAL_GROUP, foreign);
+                }
+                let (deferred, active): (Vec<_>, Vec<_>) = plan.hymo_ops.iter()
+                    .partition(|op| matches!(op.window, BootWindow::AfterService | BootWindow::AfterBoot));
+                for op in deferred {
+                    let stage = if op.window == BootWindow::AfterService { "service start" } else { "boot completed" };
+                    log::info!("Deferring {} until {}", op.module_id, stage);
+                    final_hymo_ids.remove(&op.module_id);
+                }
+                // Low-memory mode only counts the rules of each group here and
+                // builds them one group at a time below, so no more than one
+                // group's rules are held at once.
+                let sized: Vec<(&HymoOperation, String, usize, Option<BuiltGroup>)> = if low_memory {
+                    active.iter().map(|op| {
+                        let tag = op.source.to_string_lossy().into_owned();
+                        let size = if recovery.resume.contains(&tag) { 0 } else { hymofs::count_directory_ops(&op.target, &op.source) };
+                        (*op, tag, size, None)
+                    }).collect()
+                } else {
+                    active.par_iter().map(|op| {
+                        let tag = op.source.to_string_lossy().into_owned();
+                        let built = if recovery.resume.contains(&tag) { BuiltGroup::default() } else { build_group(op, plan, config) };
+                        (*op, tag, built.ops.len(), Some(built))
+                    }).collect()
+                };
+                let (collected, rejected) = admit_within_budget(sized, |(_, _, size, _)| *size, config.hymofs_rule_budget);
+                let over_budget: HashSet<String> = rejected.into_iter()
+                    .map(|(op, tag, size, _)| {
+                        log::warn!("!! {}: {} rules would exceed the HymoFS budget of {}", op.module_id, size, config.hymofs_rule_budget);
+                        tag
+                    })
+                    .collect();
+                let planned = collected.iter().map(|(_, tag, _, _)| tag.clone()).collect();
+                let mut journal = wal::ApplyWal::begin(Path::new(crate::defs::APPLY_WAL_FILE), utils::session_id(), planned)
+                    .map_err(|e| log::warn!("Failed to open apply journal: {}", e))
+                    .ok();
+                // Only the kept live rules outlive the reset, and the ledger
+                // is rebuilt from the groups as each one is flushed.
+                let live_by_path: HashMap<String, RuleOp> = live.into_iter().flatten()
+                    .filter(|op| kept.contains(op.path()))
+                    .map(|op| (op.path().to_string(), op))
+                    .collect();
+                ledger.owned.clear();
+                let mut resumed: HashMap<String, FlushStats> = HashMap::new();
+                for (op, tag, _, built) in collected {
+                    let module_id = op.module_id.as_str();
+                    let checkpoint = partial.iter().flat_map(|p| &p.checkpoints).find(|cp| cp.tag == tag);
+                    let ops = if let Some(cp) = checkpoint.filter(|cp| recovery.resume.contains(&cp.tag)) {
+                        log::info!("Resuming {}: {} rules already live", module_id, cp.rules.len());
+                        let ops: Vec<RuleOp> = cp.rules.iter()
+                            .filter_map(|rule| live_by_path.get(rule.as_str()).cloned())
+                            .collect();
+                        resumed.insert(tag.clone(), FlushStats { applied: ops.len(), failed: 0 });
+                        ops
+                    } else {
+                        log::debug!("Injecting {} -> {}", op.source.display(), op.target.display());
+                        let built = built.unwrap_or_else(|| build_group(op, plan, config));
+                        for finding in &built.findings {
+                            log::warn!("!! {}: {}", module_id, finding);
+                        }
+                        for skipped in &built.skipped {
+                            log::warn!("!! {}: {}", module_id, skipped);
+                            warnings.push(format!("{}: {}", module_id, skipped));
+                        }
+                        let relabeled = ctl.relabel(&built.ops);
+                        if relabeled > 0 {
+                            log::debug!("{}: relabeled {} files", module_id, relabeled);
+                        }
+                        shared.enqueue(&tag, built.ops.clone());
+                        shared.flush();
+                        built.ops
+                    };
+                    if let Some(Err(e)) = journal.as_mut().map(|j| j.checkpoint(&tag, module_id, &ops)) {
+                        log::warn!("Failed to checkpoint {}: {}", module_id, e);
+                        journal = None;
+                    }
+                    ledger.claim(ops.iter().map(|rule| (module_id, rule)));
+                }
+                shared.flush();
+                if let Err(e) = ledger.save() {
+                    log::warn!("Failed to save HymoFS rule ledger: {}", e);
+                }
+                if let Some(Err(e)) = journal.map(wal::ApplyWal::commit) {
+                    log::warn!("Failed to commit apply journal: {}", e);
+                }
+                let mut module_stats = shared.take_stats();
+                module_stats.extend(resumed);
+                let mut run = BackendRun { backend: BackendKind::HymoFs, attempts: 0, failures: 0, elapsed: phase_started.elapsed() };
+                for op in active {
                     let part_name = op.target.file_name()
                         .map(|s| s.to_string_lossy().to_string())
                         .unwrap_or_else(|| "unknown".to_string());
-                    log::debug!("Injecting {} -> {}", op.source.display(), op.target.display());
-                    match HymoFs::inject_directory(&op.target, &op.source) {
-                        Ok(_) => {
-                            if let Some(root) = extract_module_root(&op.source) {
-                                global_success_map.entry(root).or_default().insert(part_name);
-                            }
-                        },
-                        Err(e) => {
-                            log::error!("HymoFS failed for {}: {}. Fallback to Magic Mount.", op.module_id, e);
-                            if let Some(root) = extract_module_root(&op.source) {
-                                magic_queue.push(root);
-                            }
-                            final_hymo_ids.remove(&op.module_id);
+                    if over_budget.contains(op.source.to_string_lossy().as_ref()) {
+                        warnings.push(format!("{}: {} exceeds the HymoFS rule budget, fell back to Magic Mount", op.module_id, part_name));
+                        if let Some(root) = extract_module_root(&op.source) {
+                            magic_queue.push(root);
                         }
+                        final_hymo_ids.remove(&op.module_id);
+                        continue;
+                    }
+                    let stats = module_stats.get(op.source.to_string_lossy().as_ref()).copied().unwrap_or_default();
+                    log::debug!("{}: {} rules applied, {} failed", op.module_id, stats.applied, stats.failed);
+                    run.attempts += 1;
+                    run.failures += usize::from(stats.failed > 0);
+                    rules_applied += stats.applied;
+                    rules_failed += stats.failed;
+                    if stats.failed > 0 {
+                        warnings.push(format!("{}: {} HymoFS rules failed for {}", op.module_id, stats.failed, part_name));
+                    }
+                    if stats.applied == 0 && stats.failed > 0 {
+                        log::error!("HymoFS rejected every rule for {}. Fallback to Magic Mount.", op.module_id);
+                        warnings.push(format!("{}: HymoFS rejected every rule, fell back to Magic Mount", op.module_id));
+                        if let Some(root) = extract_module_root(&op.source) {
+                            magic_queue.push(root);
+                        }
+                        final_hymo_ids.remove(&op.module_id);
+                    } else if let Some(root) = extract_module_root(&op.source) {
+                        global_success_map.entry(root).or_default().insert(part_name);
                     }
                 }
+                backend_runs.push(run);
             },
-            status => {
-                let reason = match status {
-                    HymoFsStatus::NotPresent => "Kernel module not loaded",
-                    HymoFsStatus::ProtocolMismatch => "Protocol version mismatch",
-                    _ => "Unavailable",
-                };
-                log::warn!("!! HymoFS requested but unavailable: {}. Falling back to Magic Mount.", reason);
+            Err(status) => {
+                let reason = status.describe();
+                log::warn!("!! HymoFS requested but unavailable at {}: {}. Falling back to Magic Mount.", device.display(), reason);
+                warnings.push(format!("HymoFS unavailable at {}: {}", device.display(), reason));
                 for op in &plan.hymo_ops {
                     if let Some(root) = extract_module_root(&op.source) {
                         magic_queue.push(root);
@@ -146,6 +406,7 @@ pub fn execute(plan: &MountPlan, config: &config::Config) -> Result<ExecutionRes
     }
 
     log::info!(">> Phase 2: OverlayFS Execution...");
+    let phase_started = Instant::now();
     let overlay_results: Vec<OverlayResult> = plan.overlay_ops.par_iter()
         .map(|op| {
             let lowerdir_strings: Vec<String> = op.lowerdirs.iter()
@@ -168,8 +429,14 @@ pub fn execute(plan: &MountPlan, config: &config::Config) -> Result<ExecutionRes
                     magic_roots: local_magic,
                     fallback_ids: local_fallback_ids,
                     success_records: Vec::new(),
+                    warning: Some(format!("OverlayFS failed for {}: {}", op.target, e)),
+                    failed: true,
                 };
             }
+            let wanted = config.mount_propagation.for_mount(Path::new(&op.target), config.mount_propagation.overlay);
+            let warning = propagation::apply(Path::new(&op.target), wanted)
+                .err()
+                .map(|e| format!("OverlayFS mount {} kept its propagation: {:#}", op.target, e));
             let mut successes = Vec::new();
             for layer_path in &op.lowerdirs {
                  if let Some(root) = extract_module_root(layer_path) {
@@ -180,12 +447,21 @@ pub fn execute(plan: &MountPlan, config: &config::Config) -> Result<ExecutionRes
                 magic_roots: Vec::new(),
                 fallback_ids: Vec::new(),
                 success_records: successes,
+                warning,
+                failed: false,
             }
         })
         .collect();
+    backend_runs.push(BackendRun {
+        backend: BackendKind::Overlay,
+        attempts: overlay_results.len(),
+        failures: overlay_results.iter().filter(|res| res.failed).count(),
+        elapsed: phase_started.elapsed(),
+    });
 
     for res in overlay_results {
         magic_queue.extend(res.magic_roots);
+        warnings.extend(res.warning);
         for id in res.fallback_ids {
             final_overlay_ids.remove(&id); 
         }
@@ -213,6 +489,7 @@ pub fn execute(plan: &MountPlan, config: &config::Config) -> Result<ExecutionRes
             }
         }
         
+        let phase_started = Instant::now();
         log::info!(">> Phase 3: Magic Mount (Fallback) using {} (tmpfs_overlay={})", tempdir.display(), needs_mount);
         
         if needs_mount {
@@ -224,15 +501,31 @@ pub fn execute(plan: &MountPlan, config: &config::Config) -> Result<ExecutionRes
             utils::ensure_temp_dir(&tempdir)?;
         }
 
-        if let Err(e) = magic::mount_partitions(
+        let excluded = partitions::magic_exclusions(config);
+        if !excluded.is_empty() {
+            for path in &magic_queue {
+                global_success_map.entry(path.clone()).or_default().extend(excluded.iter().cloned());
+            }
+        }
+        let known: Vec<String> = partitions::known(config).into_iter().map(str::to_string).collect();
+        let mounted = magic::mount_partitions(
             &tempdir, 
             &magic_queue, 
             &config.mountsource, 
-            &config.partitions, 
+            &known, 
             global_success_map, 
+            &config.mount_propagation,
             config.disable_umount
-        ) {
+        );
+        backend_runs.push(BackendRun {
+            backend: BackendKind::Magic,
+            attempts: magic_queue.len(),
+            failures: if mounted.is_err() { magic_queue.len() } else { 0 },
+            elapsed: phase_started.elapsed(),
+        });
+        if let Err(e) = mounted {
             log::error!("Magic Mount critical failure: {:#}", e);
+            failures.push(format!("Magic Mount failed for {}: {:#}", final_magic_ids.join(", "), e));
             final_magic_ids.clear();
         }
 
@@ -256,5 +549,240 @@ pub fn execute(plan: &MountPlan, config: &config::Config) -> Result<ExecutionRes
         overlay_module_ids: result_overlay,
         hymo_module_ids: result_hymo,
         magic_module_ids: result_magic,
+        direct_module_ids: final_direct_ids,
+        rules_applied,
+        rules_failed,
+        warnings,
+        failures,
+        backend_runs,
+    })
+}
+
+/// Writes the direct operations into their partitions after undoing the
+/// modifications of modules that no longer qualify. A module whose write
+/// fails is restored and falls back to Magic Mount.
+fn apply_direct(plan: &MountPlan, magic_queue: &mut Vec<PathBuf>, success_map: &mut HashMap<PathBuf, HashSet<String>>, warnings: &mut Vec<String>) -> Vec<String> {
+    let backup_root = Path::new(crate::defs::DIRECT_BACKUP_DIR);
+    for module_id in direct::stale_modules(backup_root, &plan.direct_module_ids) {
+        match direct::restore(&module_id, backup_root) {
+            Ok(restored) => log::info!(">> Restored {} paths modified directly by {}", restored, module_id),
+            Err(e) => {
+                log::warn!("!! Failed to restore direct modifications of {}: {:#}", module_id, e);
+                warnings.push(format!("{}: direct modifications could not be restored: {:#}", module_id, e));
+            }
+        }
+    }
+    if plan.direct_ops.is_empty() {
+        return Vec::new();
+    }
+    log::info!(">> Phase 0: Direct modification of writable partitions...");
+    let partitions: Vec<&str> = plan.direct_ops.iter().filter_map(|op| op.target.file_name()?.to_str()).collect();
+    let verity = verity::probe(&partitions);
+    let mut failed = HashSet::new();
+    for op in &plan.direct_ops {
+        if failed.contains(&op.module_id) {
+            continue;
+        }
+        let locked = op.target.file_name()
+            .and_then(|n| verity.partition(&n.to_string_lossy()))
+            .is_some_and(|p| p.locked);
+        if locked {
+            log::warn!("!! {}: {} is verity-protected, direct writes would fail verification. Fallback to Magic Mount.", op.module_id, op.target.display());
+            warnings.push(format!("{}: {} is verity-protected, fell back to Magic Mount", op.module_id, op.target.display()));
+            failed.insert(op.module_id.clone());
+            continue;
+        }
+        match direct::apply(&op.module_id, &op.source, &op.target, backup_root) {
+            Ok(written) => log::info!("Wrote {} paths of {} into {}", written, op.module_id, op.target.display()),
+            Err(e) => {
+                log::error!("Direct modification of {} by {} failed: {:#}. Fallback to Magic Mount.", op.target.display(), op.module_id, e);
+                warnings.push(format!("{}: direct modification failed, fell back to Magic Mount: {:#}", op.module_id, e));
+                if let Err(e) = direct::restore(&op.module_id, backup_root) {
+                    log::warn!("!! Failed to restore {}: {:#}", op.module_id, e);
+                }
+                failed.insert(op.module_id.clone());
+            }
+        }
+    }
+    for op in &plan.direct_ops {
+        let Some(root) = extract_module_root(&op.source) else { continue };
+        if failed.contains(&op.module_id) {
+            magic_queue.push(root);
+        } else if let Some(part_name) = op.target.file_name() {
+            success_map.entry(root).or_default().insert(part_name.to_string_lossy().into_owned());
+        }
+    }
+    plan.direct_module_ids.iter().filter(|id| !failed.contains(*id)).cloned().collect()
+}
+
+#[derive(Default)]
+struct BuiltGroup {
+    ops: Vec<RuleOp>,
+    /// APK problems worth a warning in the log, as `target: message`.
+    findings: Vec<String>,
+    skipped: Vec<guards::Skipped>,
+}
+
+/// Rules for one HymoFS operation: its rewritten directory tree plus APK
+/// companions, minus hash-guarded rules whose original changed.
+fn build_group(op: &HymoOperation, plan: &MountPlan, config: &config::Config) -> BuiltGroup {
+    let mut ops = plan.rules_of(op);
+    let inspection = apk::inspect(&ops, config.hide_stale_oat);
+    let findings = inspection.findings.iter().map(|f| format!("{}: {}", f.target, f.message)).collect();
+    ops.extend(inspection.companion_ops);
+    let skipped = plan.hash_guards.get(&op.module_id)
+        .map(|module_guards| guards::retain_matching(&mut ops, module_guards))
+        .unwrap_or_default();
+    BuiltGroup { ops, findings, skipped }
+}
+
+/// Keeps groups in order while their running total fits `budget`; a group
+/// that would overflow it is rejected and later, smaller groups still fit.
+fn admit_within_budget<T>(groups: Vec<T>, size: impl Fn(&T) -> usize, budget: usize) -> (Vec<T>, Vec<T>) {
+    let mut used = 0usize;
+    groups.into_iter().partition(|group| match used.checked_add(size(group)) {
+        Some(total) if total <= budget => {
+            used = total;
+            true
+        }
+        _ => false,
     })
 }
+
+/// Drops the rules this crate owns from a previous run, except the `kept`
+/// ones a resumed apply verified, plus the rules a rollback asked for.
+fn reset_owned_rules(shared: &SharedHymoFs, ledger: &RuleLedger, roots: &[PathBuf], live: &HymoResult<Vec<RuleOp>>, kept: &HashSet<&str>, rollback: &[String]) {
+    let mut stale: Vec<String> = match live {
+        Ok(live) => ledger.owned_live(live, roots)
+            .into_iter()
+            .map(|op| op.path().to_string())
+            .collect(),
+        Err(_) if ledger.owned.is_empty() => Vec::new(),
+        Err(e) => {
+            log::debug!("HymoFS rule listing unavailable ({}), resetting {} rules from the ledger", e, ledger.owned.len());
+            ledger.owned.iter().map(|(path, _)| path).collect()
+        }
+    };
+    stale.retain(|path| !kept.contains(path.as_str()));
+    let mut seen: HashSet<String> = stale.iter().cloned().collect();
+    stale.extend(rollback.iter().filter(|path| seen.insert(path.to_string())).cloned());
+    if !stale.is_empty() {
+        log::info!(">> Removing {} HymoFS rules left by a previous run", stale.len());
+        shared.enqueue(RESET_GROUP, stale.into_iter().map(|src| RuleOp::Delete { src }).collect());
+        shared.flush();
+        shared.take_stats();
+    }
+}
+
+/// Injects the rules `op` deferred; returns whether they took.
+fn inject_deferred(shared: &SharedHymoFs, ledger: &mut RuleLedger, plan: &MountPlan, op: &HymoOperation) -> bool {
+    let tag = op.source.to_string_lossy();
+    let mut ops = plan.rules_of(op);
+    if let Some(module_guards) = plan.hash_guards.get(&op.module_id) {
+        for skipped in guards::retain_matching(&mut ops, module_guards) {
+            log::warn!("!! {}: {}", op.module_id, skipped);
+        }
+    }
+    shared.enqueue(&tag, ops.clone());
+    shared.flush();
+    let stats = shared.take_stats().remove(tag.as_ref()).unwrap_or_default();
+    if stats.applied > 0 || stats.failed == 0 {
+        ledger.claim(ops.iter().map(|rule| (op.module_id.as_str(), rule)));
+        true
+    } else {
+        log::error!("HymoFS rejected every deferred rule for {}", op.module_id);
+        false
+    }
+}
+
+fn finish_transition(ledger: &RuleLedger, injected: HashSet<String>, removed: HashSet<String>) -> BootTransition {
+    if let Err(e) = ledger.save() {
+        log::warn!("Failed to save HymoFS rule ledger: {}", e);
+    }
+    let mut injected_module_ids = injected.into_iter().collect::<Vec<_>>();
+    let mut removed_module_ids = removed.into_iter().collect::<Vec<_>>();
+    injected_module_ids.sort();
+    removed_module_ids.sort();
+    BootTransition {
+        injected_module_ids,
+        removed_module_ids,
+    }
+}
+
+/// Injects the rules deferred to the service stage, once /data is up.
+pub fn apply_service(plan: &MountPlan, hymofs: &HymoFs) -> Result<BootTransition> {
+    let shared = SharedHymoFs::new(hymofs.clone().with_priorities(plan.hymo_priorities()));
+    let mut ledger = RuleLedger::load();
+    let mut injected = HashSet::new();
+    for op in plan.hymo_ops.iter().filter(|op| op.window == BootWindow::AfterService) {
+        log::info!("Service started, injecting deferred module {}", op.module_id);
+        if inject_deferred(&shared, &mut ledger, plan, op) {
+            injected.insert(op.module_id.clone());
+        }
+    }
+    Ok(finish_transition(&ledger, injected, HashSet::new()))
+}
+
+/// Applies the boot windows once boot has completed. Rules of the service
+/// stage are injected too for modules not in `serviced`, as when that stage
+/// never ran.
+pub fn apply_boot_completed(plan: &MountPlan, hymofs: &HymoFs, serviced: &[String]) -> Result<BootTransition> {
+    let shared = SharedHymoFs::new(hymofs.clone().with_priorities(plan.hymo_priorities()));
+    let mut ledger = RuleLedger::load();
+    let mut injected = HashSet::new();
+    let mut removed = HashSet::new();
+    for op in &plan.hymo_ops {
+        match op.window {
+            BootWindow::Always => {}
+            BootWindow::BootOnly => {
+                log::info!("Boot window closed, removing rules of {}", op.module_id);
+                shared.delete_rules(&op.source.to_string_lossy(), plan.rules_of(op));
+                shared.flush();
+                ledger.disown(&op.module_id);
+                removed.insert(op.module_id.clone());
+            }
+            BootWindow::AfterService if serviced.contains(&op.module_id) => {}
+            BootWindow::AfterService | BootWindow::AfterBoot => {
+                log::info!("Boot completed, injecting deferred module {}", op.module_id);
+                if inject_deferred(&shared, &mut ledger, plan, op) {
+                    injected.insert(op.module_id.clone());
+                }
+            }
+        }
+    }
+    Ok(finish_transition(&ledger, injected, removed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn budget_admits_groups_in_order_and_skips_overflowing_ones() {
+        let (admitted, rejected) = admit_within_budget(vec![4, 3, 5, 2, 1], |n| *n, 10);
+        assert_eq!(admitted, vec![4, 3, 2, 1]);
+        assert_eq!(rejected, vec![5]);
+        let (admitted, rejected) = admit_within_budget(vec![usize::MAX, 1], |n| *n, usize::MAX);
+        assert_eq!((admitted, rejected), (vec![usize::MAX], vec![1]));
+    }
+
+    #[test]
+    fn direct_writes_to_verity_partitions_are_critical() {
+        use crate::core::{planner::DirectOperation, verity::PartitionVerity};
+        let plan = MountPlan {
+            direct_ops: vec![DirectOperation { module_id: "m".into(), source: "/data/adb/modules/m/system".into(), target: "/system".into() }],
+            ..Default::default()
+        };
+        let part = |locked| PartitionVerity {
+            partition: "system".into(), device: "/dev/block/dm-0".into(), dm_name: Some("system-verity".into()), read_only: false, locked,
+        };
+        let report = |mode, locked| VerityReport { mode, verified_boot_state: Some("green".into()), device_state: None, partitions: vec![part(locked)] };
+        let critical = |issues: &[DiagnosticIssue]| issues.iter().filter(|i| matches!(i.level, DiagnosticLevel::Critical)).count();
+        let issues = diagnose_verity(&plan, &report(VerityMode::Enforcing, true));
+        assert_eq!(critical(&issues), 1);
+        assert!(issues[1].message.contains("(system-verity): verity-protected"));
+        assert_eq!(critical(&diagnose_verity(&plan, &report(VerityMode::Disabled, false))), 0);
+        let eio = diagnose_verity(&MountPlan::default(), &report(VerityMode::Eio, true));
+        assert!(eio.iter().any(|i| matches!(i.level, DiagnosticLevel::Warning) && i.message.contains("EIO")));
+    }
+}
@@ -0,0 +1,427 @@
+use std::fmt::Write as _;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+use crate::{
+    core::{guards, planner::MountPlan, rules::RuleLedger, state::RuntimeState},
+    mount::hymofs::RuleOp,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    HymoFs,
+    Magic,
+    Overlay,
+    Direct,
+    Stock,
+}
+
+impl Backend {
+    fn label(self) -> &'static str {
+        match self {
+            Backend::HymoFs => "HymoFS",
+            Backend::Magic => "Magic Mount",
+            Backend::Overlay => "OverlayFS",
+            Backend::Direct => "direct write",
+            Backend::Stock => "stock",
+        }
+    }
+}
+
+/// One thing that could decide how the path resolves. Steps are listed in
+/// precedence order; `active` says whether it is in effect this session.
+#[derive(Debug, Serialize)]
+pub struct Step {
+    pub backend: Backend,
+    pub module_id: String,
+    pub detail: String,
+    pub provides: bool,
+    pub active: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LiveRule {
+    pub rule: String,
+    pub owner: String,
+}
+
+/// A module whose copy of the path loses to the one being served.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Shadowed {
+    pub module_id: String,
+    pub backend: Backend,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Explanation {
+    pub path: String,
+    pub serving: Backend,
+    pub served_by: Option<String>,
+    /// The other active providers, in precedence order.
+    pub shadowed: Vec<Shadowed>,
+    pub steps: Vec<Step>,
+    pub contending_modules: Vec<String>,
+    pub live_rules: Vec<LiveRule>,
+    pub live_rules_below: usize,
+    pub rejected: Vec<String>,
+}
+
+fn is_whiteout(path: &Path) -> bool {
+    path.symlink_metadata().is_ok_and(|m| m.file_type().is_char_device() && m.rdev() == 0)
+}
+
+pub fn describe_rule(op: &RuleOp) -> String {
+    match op {
+        RuleOp::Add { src, target, .. } => format!("add {} -> {}", src, target),
+        RuleOp::Delete { src } => format!("delete {}", src),
+        RuleOp::Hide { path } => format!("hide {}", path),
+    }
+}
+
+/// Whether `rule_path` is `path` itself or one of its ancestors.
+fn covers(rule_path: &str, path: &Path) -> bool {
+    path.starts_with(rule_path)
+}
+
+fn module_of_layer(layer: &Path) -> String {
+    layer.parent()
+        .and_then(|p| p.file_name())
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "unknown".into())
+}
+
+fn hymofs_steps(path: &Path, plan: &MountPlan, state: &RuntimeState, live: Option<&[RuleOp]>) -> Vec<Step> {
+    let mut steps = Vec::new();
+    // Later rules for the same path replace earlier ones, so the last
+    // operation in plan order wins and is listed first.
+    for op in plan.hymo_ops.iter().rev() {
+        if !path.starts_with(&op.target) && !op.target.starts_with(path) {
+            continue;
+        }
+        let fell_back = state.magic_modules.contains(&op.module_id);
+        let is_live = |rule: &RuleOp| match live {
+            Some(live) => live.contains(rule),
+            None => state.hymo_modules.contains(&op.module_id),
+        };
+        let mut planned = plan.rules_of(op);
+        if let Some(module_guards) = plan.hash_guards.get(&op.module_id) {
+            for skipped in guards::retain_matching(&mut planned, module_guards).into_iter().filter(|s| covers(&s.path, path)) {
+                steps.push(Step { backend: Backend::HymoFs, module_id: op.module_id.clone(), detail: skipped.to_string(), provides: false, active: false });
+            }
+        }
+        let mut below = 0;
+        let mut live_below = 0;
+        for rule in &planned {
+            let rule_path = Path::new(rule.path());
+            if covers(rule.path(), path) {
+                let (backend, detail, active) = match (rule, fell_back) {
+                    (RuleOp::Add { target, .. }, false) => (Backend::HymoFs, format!("redirects to {}", target), is_live(rule)),
+                    (RuleOp::Hide { path: hidden }, false) => (Backend::HymoFs, format!("hides {}", hidden), is_live(rule)),
+                    (RuleOp::Add { target, .. }, true) => (Backend::Magic, format!("binds {} (HymoFS fallback)", target), true),
+                    (_, true) => (Backend::Magic, format!("whiteout at {} (HymoFS fallback)", rule.path()), true),
+                    (RuleOp::Delete { .. }, false) => continue,
+                };
+                steps.push(Step { backend, module_id: op.module_id.clone(), detail, provides: true, active });
+            } else if rule_path.starts_with(path) {
+                below += 1;
+                live_below += usize::from(is_live(rule));
+            }
+        }
+        if below > 0 {
+            let backend = if fell_back { Backend::Magic } else { Backend::HymoFs };
+            steps.push(Step {
+                backend,
+                module_id: op.module_id.clone(),
+                detail: format!("injects {} entries into this directory ({} live)", below, if fell_back { below } else { live_below }),
+                provides: false,
+                active: fell_back || live_below > 0,
+            });
+        }
+    }
+    steps
+}
+
+fn magic_steps(path: &Path, plan: &MountPlan, state: &RuntimeState) -> Vec<Step> {
+    let Ok(relative) = path.strip_prefix("/") else { return Vec::new() };
+    plan.magic_module_paths.iter()
+        .filter_map(|module_root| {
+            let candidate = module_root.join(relative);
+            candidate.symlink_metadata().ok()?;
+            let module_id = module_root.file_name()?.to_string_lossy().into_owned();
+            let detail = if is_whiteout(&candidate) {
+                "whiteout removes it".to_string()
+            } else {
+                format!("binds {}", candidate.display())
+            };
+            Some(Step { active: state.magic_modules.contains(&module_id), module_id, detail, provides: !candidate.is_dir(), backend: Backend::Magic })
+        })
+        .collect()
+}
+
+fn overlay_steps(path: &Path, plan: &MountPlan, state: &RuntimeState) -> Vec<Step> {
+    let mut steps = Vec::new();
+    for op in &plan.overlay_ops {
+        let Ok(relative) = path.strip_prefix(Path::new("/").join(&op.partition_name)) else { continue };
+        let mounted = state.active_mounts.contains(&op.partition_name);
+        let mut shadowed = false;
+        for layer in &op.lowerdirs {
+            let candidate = layer.join(relative);
+            if candidate.symlink_metadata().is_err() {
+                continue;
+            }
+            let module_id = module_of_layer(layer);
+            let is_dir = candidate.is_dir();
+            let detail = match (is_whiteout(&candidate), is_dir, shadowed) {
+                (_, _, true) => format!("layer {} is shadowed by a higher layer", candidate.display()),
+                (true, _, false) => "whiteout removes it".to_string(),
+                (false, true, false) => format!("merges directory {}", candidate.display()),
+                (false, false, false) => format!("top layer {}", candidate.display()),
+            };
+            let active = mounted && state.overlay_modules.contains(&module_id);
+            shadowed |= active && !is_dir;
+            steps.push(Step { backend: Backend::Overlay, active, module_id, detail, provides: !is_dir });
+        }
+    }
+    steps
+}
+
+fn direct_steps(path: &Path, plan: &MountPlan, state: &RuntimeState) -> Vec<Step> {
+    plan.direct_ops.iter()
+        .filter_map(|op| {
+            let candidate = op.source.join(path.strip_prefix(&op.target).ok()?);
+            candidate.symlink_metadata().ok()?;
+            let detail = if is_whiteout(&candidate) {
+                "deleted from the partition".to_string()
+            } else {
+                format!("copied from {}", candidate.display())
+            };
+            Some(Step {
+                backend: Backend::Direct,
+                module_id: op.module_id.clone(),
+                detail,
+                provides: !candidate.is_dir(),
+                active: state.direct_modules.contains(&op.module_id),
+            })
+        })
+        .collect()
+}
+
+/// Builds the decision chain for `path` from the plan, the recorded runtime
+/// state and, when available, the live HymoFS rule table. Precedence follows
+/// the mount order: HymoFS redirects at lookup time, magic binds are mounted
+/// after the overlays, and overlays sit on the partition, whose files may
+/// have been written directly.
+pub fn explain(path: &Path, plan: &MountPlan, state: &RuntimeState, live: Option<&[RuleOp]>, ledger: &RuleLedger) -> Explanation {
+    let mut steps = hymofs_steps(path, plan, state, live);
+    steps.extend(magic_steps(path, plan, state));
+    steps.extend(overlay_steps(path, plan, state));
+    steps.extend(direct_steps(path, plan, state));
+    steps.sort_by_key(|step| match step.backend {
+        Backend::HymoFs => 0,
+        Backend::Magic => 1,
+        Backend::Overlay => 2,
+        Backend::Direct => 3,
+        Backend::Stock => 4,
+    });
+
+    let serving_step = steps.iter().find(|step| step.active && step.provides);
+    let merged_by = steps.iter().find(|step| step.active).map(|step| step.backend);
+    let shadowed = serving_step
+        .map(|serving| steps.iter()
+            .filter(|step| step.active && step.provides && !std::ptr::eq(*step, serving))
+            .map(|step| Shadowed { module_id: step.module_id.clone(), backend: step.backend })
+            .collect())
+        .unwrap_or_default();
+    let mut contending: Vec<String> = steps.iter().filter(|s| s.provides).map(|s| s.module_id.clone()).collect();
+    contending.sort();
+    contending.dedup();
+    if contending.len() < 2 {
+        contending.clear();
+    }
+
+    let live_rules = live.unwrap_or_default().iter()
+        .filter(|op| covers(op.path(), path))
+        .map(|op| {
+            let owner = ledger.owned.under(op.path()).into_iter()
+                .find(|(p, _)| p == op.path())
+                .map(|(_, owner)| owner.to_string())
+                .or_else(|| ledger.namespace_of(op.path()).map(|ns| format!("namespace {}", ns)))
+                .unwrap_or_else(|| if ledger.is_external(op.path()) { "external".into() } else { "unmanaged".into() });
+            LiveRule { rule: describe_rule(op), owner }
+        })
+        .collect();
+    let live_rules_below = live.unwrap_or_default().iter()
+        .filter(|op| Path::new(op.path()).starts_with(path) && op.path() != path.as_os_str())
+        .count();
+
+    let module_ids: Vec<&str> = steps.iter().map(|s| s.module_id.as_str()).collect();
+    let rejected = plan.rejected.iter()
+        .filter(|reason| module_ids.iter().any(|id| reason.starts_with(&format!("{}:", id))))
+        .cloned()
+        .collect();
+
+    Explanation {
+        path: path.display().to_string(),
+        serving: serving_step.map(|s| s.backend).or(merged_by).unwrap_or(Backend::Stock),
+        served_by: serving_step.map(|s| s.module_id.clone()),
+        shadowed,
+        contending_modules: contending,
+        steps,
+        live_rules,
+        live_rules_below,
+        rejected,
+    }
+}
+
+impl Explanation {
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let _ = match &self.served_by {
+            Some(id) => writeln!(out, "{}: served by {} ({})", self.path, self.serving.label(), id),
+            None if self.serving == Backend::Stock => writeln!(out, "{}: served by the stock partition", self.path),
+            None => writeln!(out, "{}: directory merged by {}", self.path, self.serving.label()),
+        };
+        for step in &self.steps {
+            let marker = if step.active { "*" } else { "-" };
+            let _ = writeln!(out, "  {} [{}] {}: {}", marker, step.backend.label(), step.module_id, step.detail);
+        }
+        if !self.shadowed.is_empty() {
+            let stack: Vec<String> = self.shadowed.iter().map(|s| format!("{} ({})", s.module_id, s.backend.label())).collect();
+            let _ = writeln!(out, "  shadowed: {}", stack.join(", "));
+        }
+        if !self.contending_modules.is_empty() {
+            let _ = writeln!(out, "  conflict between: {}", self.contending_modules.join(", "));
+        }
+        for rule in &self.live_rules {
+            let _ = writeln!(out, "  live: {} [{}]", rule.rule, rule.owner);
+        }
+        if self.live_rules_below > 0 {
+            let _ = writeln!(out, "  live: {} more rules below this path", self.live_rules_below);
+        }
+        for reason in &self.rejected {
+            let _ = writeln!(out, "  rejected: {}", reason);
+        }
+        out
+    }
+}
+
+pub fn absolute(path: &Path) -> PathBuf {
+    if path.is_absolute() { path.to_path_buf() } else { Path::new("/").join(path) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::{core::{inventory::BootWindow, planner::{DirectOperation, HymoOperation, OverlayOperation}}, mount::hymofs::HymoFileType};
+
+    struct Fixture {
+        root: PathBuf,
+        plan: MountPlan,
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    fn fixture(name: &str) -> Fixture {
+        let root = std::env::temp_dir().join(format!("explain-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for (module, file) in [("a", "system/etc/hosts"), ("b", "system/etc/hosts"), ("c", "vendor/etc/x.conf"), ("c", "vendor/etc/y.conf")] {
+            let path = root.join(module).join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, module).unwrap();
+        }
+        let plan = MountPlan {
+            overlay_ops: vec![OverlayOperation {
+                partition_name: "system".into(),
+                target: "/system".into(),
+                lowerdirs: vec![root.join("b/system"), root.join("a/system")],
+            }],
+            hymo_ops: vec![HymoOperation {
+                module_id: "c".into(),
+                source: root.join("c/vendor"),
+                target: "/vendor".into(),
+                window: BootWindow::Always,
+            }],
+            ..Default::default()
+        };
+        Fixture { root, plan }
+    }
+
+    fn state(overlay: &[&str], hymo: &[&str], magic: &[&str]) -> RuntimeState {
+        let ids = |ids: &[&str]| ids.iter().map(|s| s.to_string()).collect();
+        RuntimeState {
+            overlay_modules: ids(overlay),
+            hymo_modules: ids(hymo),
+            magic_modules: ids(magic),
+            active_mounts: vec!["system".into()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn top_overlay_layer_serves_and_conflict_is_reported() {
+        let fx = fixture("overlay");
+        let result = explain(Path::new("/system/etc/hosts"), &fx.plan, &state(&["a", "b"], &[], &[]), None, &RuleLedger::default());
+        assert_eq!(result.serving, Backend::Overlay);
+        assert_eq!(result.served_by.as_deref(), Some("b"));
+        assert_eq!(result.contending_modules, vec!["a".to_string(), "b".to_string()]);
+        assert!(result.steps[1].detail.contains("shadowed"));
+        assert_eq!(result.shadowed, vec![Shadowed { module_id: "a".into(), backend: Backend::Overlay }]);
+        assert!(result.render().contains("  shadowed: a (OverlayFS)\n"));
+        let dir = explain(Path::new("/system/etc"), &fx.plan, &state(&["a", "b"], &[], &[]), None, &RuleLedger::default());
+        assert_eq!(dir.serving, Backend::Overlay);
+        assert_eq!(dir.served_by, None);
+        assert_eq!(dir.steps.len(), 2);
+        let below = explain(Path::new("/system/etc/hosts"), &fx.plan, &state(&["a"], &[], &[]), None, &RuleLedger::default());
+        assert_eq!(below.served_by.as_deref(), Some("a"), "an inactive top layer shadows nothing");
+        assert!(below.shadowed.is_empty());
+    }
+
+    #[test]
+    fn live_hymofs_rule_takes_precedence() {
+        let fx = fixture("hymo");
+        let target = fx.root.join("c/vendor/etc/x.conf").display().to_string();
+        let live = vec![RuleOp::Add { src: "/vendor/etc/x.conf".into(), target: target.clone(), file_type: HymoFileType::Reg }];
+        let mut ledger = RuleLedger::default();
+        ledger.claim([("c", &live[0])]);
+        let result = explain(Path::new("/vendor/etc/x.conf"), &fx.plan, &state(&[], &["c"], &[]), Some(&live), &ledger);
+        assert_eq!(result.serving, Backend::HymoFs);
+        assert_eq!(result.live_rules[0].owner, "c");
+        assert_eq!(result.live_rules[0].rule, format!("add /vendor/etc/x.conf -> {}", target));
+
+        let missing = explain(Path::new("/vendor/etc/y.conf"), &fx.plan, &state(&[], &["c"], &[]), Some(&live), &ledger);
+        assert_eq!(missing.serving, Backend::Stock, "planned but not live");
+
+        let dir = explain(Path::new("/vendor/etc"), &fx.plan, &state(&[], &["c"], &[]), Some(&live), &ledger);
+        assert_eq!(dir.steps[0].detail, "injects 2 entries into this directory (1 live)");
+        assert_eq!(dir.live_rules_below, 1);
+    }
+
+    #[test]
+    fn hymofs_fallback_is_reported_as_magic() {
+        let fx = fixture("fallback");
+        let result = explain(Path::new("/vendor/etc/x.conf"), &fx.plan, &state(&[], &[], &["c"]), None, &RuleLedger::default());
+        assert_eq!(result.serving, Backend::Magic);
+        assert!(result.render().contains("(HymoFS fallback)"));
+    }
+
+    #[test]
+    fn direct_write_sits_below_the_mounts() {
+        let mut fx = fixture("direct");
+        fx.plan.direct_ops.push(DirectOperation { module_id: "a".into(), source: fx.root.join("a/system"), target: "/system".into() });
+        let mut direct = state(&["b"], &[], &[]);
+        direct.direct_modules = vec!["a".into()];
+        let result = explain(Path::new("/system/etc/hosts"), &fx.plan, &direct, None, &RuleLedger::default());
+        assert_eq!(result.served_by.as_deref(), Some("b"));
+        assert_eq!(result.steps.last().map(|s| s.backend), Some(Backend::Direct));
+        direct.active_mounts.clear();
+        let result = explain(Path::new("/system/etc/hosts"), &fx.plan, &direct, None, &RuleLedger::default());
+        assert_eq!(result.serving, Backend::Direct);
+        assert_eq!(result.served_by.as_deref(), Some("a"));
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use crate::{core::{integrity::Digest, inventory::Module}, mount::hymofs::RuleOp};
+
+/// Real path to the digest the file there must have for a module's rules on
+/// that path to apply, e.g. `"/vendor/lib64/libfoo.so": "sha256:..."`.
+pub type HashGuards = BTreeMap<String, Digest>;
+
+/// A rule left out because the file it would replace is not the one the
+/// module was made for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Skipped {
+    pub path: String,
+    pub expected: Digest,
+    /// `None` when the original is missing or unreadable.
+    pub actual: Option<Digest>,
+}
+
+impl fmt::Display for Skipped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.actual {
+            Some(actual) => write!(f, "skipped {}: expected {}, found {}", self.path, self.expected, actual),
+            None => write!(f, "skipped {}: expected {}, original is missing", self.path, self.expected),
+        }
+    }
+}
+
+/// Guards of every module that declares some, by module id.
+pub fn of_modules(modules: &[Module]) -> BTreeMap<String, HashGuards> {
+    modules.iter()
+        .filter(|m| !m.rules.expect_hash.is_empty())
+        .map(|m| (m.id.clone(), m.rules.expect_hash.clone()))
+        .collect()
+}
+
+/// Drops the rules on guarded paths whose real file no longer hashes to the
+/// expected digest, e.g. after an OTA replaced it, and returns what was
+/// dropped. Each guarded file is hashed once.
+pub fn retain_matching(ops: &mut Vec<RuleOp>, guards: &HashGuards) -> Vec<Skipped> {
+    let mut skipped: Vec<Skipped> = Vec::new();
+    let mut checked: BTreeMap<&str, bool> = BTreeMap::new();
+    ops.retain(|op| {
+        let Some((path, expected)) = guards.get_key_value(op.path()) else {
+            return true;
+        };
+        *checked.entry(path).or_insert_with(|| {
+            let actual = Digest::of_file(expected.algorithm, Path::new(path)).ok();
+            if actual.as_ref() == Some(expected) {
+                return true;
+            }
+            skipped.push(Skipped { path: path.clone(), expected: expected.clone(), actual });
+            false
+        })
+    });
+    skipped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::{core::integrity::HashAlgorithm, mount::hymofs::HymoFileType};
+
+    fn add(src: &Path) -> RuleOp {
+        let src = src.to_string_lossy().into_owned();
+        RuleOp::Add { target: format!("/mnt/demo{}", src), src, file_type: HymoFileType::Reg }
+    }
+
+    #[test]
+    fn skips_rules_whose_original_changed() {
+        let dir = std::env::temp_dir().join(format!("guards-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let (same, changed, missing, free) = (dir.join("same"), dir.join("changed"), dir.join("missing"), dir.join("free"));
+        fs::write(&same, "blob v1").unwrap();
+        fs::write(&changed, "blob v2").unwrap();
+        let v1 = Digest::of_file(HashAlgorithm::Sha256, &same).unwrap();
+        let guards: HashGuards = [&same, &changed, &missing]
+            .into_iter()
+            .map(|p| (p.to_string_lossy().into_owned(), v1.clone()))
+            .collect();
+
+        let mut ops = vec![add(&same), add(&changed), add(&missing), add(&free), RuleOp::Hide { path: changed.to_string_lossy().into_owned() }];
+        let skipped = retain_matching(&mut ops, &guards);
+        assert_eq!(ops, vec![add(&same), add(&free)]);
+        assert_eq!(skipped.len(), 2);
+        assert_eq!(skipped[0].actual, Some(Digest::of_file(HashAlgorithm::Sha256, &changed).unwrap()));
+        assert!(skipped[1].to_string().ends_with("original is missing"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
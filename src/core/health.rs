@@ -0,0 +1,186 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::time::Duration;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use crate::{conf::config::Config, core::planner::HymoFallback, defs, mount::BackendKind, utils};
+
+/// Boots a backend has to have mounted something in before its record
+/// counts against the configured order.
+pub const MIN_BOOTS: u32 = 3;
+/// Weight of the latest boot in the running mean latency.
+const LATENCY_WEIGHT: f64 = 0.3;
+
+/// What one backend did during one boot apply: mount groups it was given,
+/// how many of them failed, and the time it spent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackendRun {
+    pub backend: BackendKind,
+    pub attempts: usize,
+    pub failures: usize,
+    pub elapsed: Duration,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BackendHealth {
+    pub boots: u32,
+    pub attempts: u64,
+    pub failures: u64,
+    /// Running mean of the time one mount group took, in milliseconds.
+    pub latency_ms: f64,
+}
+
+impl BackendHealth {
+    fn record(&mut self, run: &BackendRun) {
+        let latency = run.elapsed.as_secs_f64() * 1000.0 / run.attempts as f64;
+        self.latency_ms = if self.boots == 0 { latency } else { self.latency_ms + LATENCY_WEIGHT * (latency - self.latency_ms) };
+        self.boots += 1;
+        self.attempts += run.attempts as u64;
+        self.failures += run.failures.min(run.attempts) as u64;
+    }
+
+    /// Share of mount groups that went through, smoothed so a backend with
+    /// a handful of samples stays near one half.
+    pub fn reliability(&self) -> f64 {
+        (self.attempts.saturating_sub(self.failures) + 1) as f64 / (self.attempts + 2) as f64
+    }
+
+    pub fn is_known(&self) -> bool {
+        self.boots >= MIN_BOOTS
+    }
+}
+
+/// Per-backend record of boot applies on this device.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HealthStore {
+    pub backends: BTreeMap<BackendKind, BackendHealth>,
+}
+
+impl HealthStore {
+    pub fn load() -> Self {
+        fs::read_to_string(defs::BACKEND_HEALTH_FILE)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        utils::atomic_write(defs::BACKEND_HEALTH_FILE, serde_json::to_string_pretty(self)?)
+    }
+
+    /// Adds a boot to the record of every backend that had something to
+    /// mount in it.
+    pub fn record(&mut self, runs: &[BackendRun]) {
+        for run in runs.iter().filter(|run| run.attempts > 0) {
+            self.backends.entry(run.backend).or_default().record(run);
+        }
+    }
+
+    fn known(&self, kind: BackendKind) -> Option<&BackendHealth> {
+        self.backends.get(&kind).filter(|h| h.is_known())
+    }
+
+    /// `order` with the backends that have a record sorted by reliability,
+    /// then latency, among the places they hold; backends without one stay
+    /// where they were configured.
+    pub fn rank(&self, order: &[BackendKind]) -> Vec<BackendKind> {
+        let slots: Vec<usize> = order.iter().enumerate()
+            .filter(|(_, kind)| self.known(**kind).is_some())
+            .map(|(i, _)| i)
+            .collect();
+        let mut known: Vec<(BackendKind, &BackendHealth)> = order.iter()
+            .filter_map(|kind| Some((*kind, self.known(*kind)?)))
+            .collect();
+        known.sort_by(|(_, a), (_, b)| b.reliability().total_cmp(&a.reliability()).then(a.latency_ms.total_cmp(&b.latency_ms)));
+        let mut ranked = order.to_vec();
+        for (slot, (kind, _)) in slots.into_iter().zip(known) {
+            if let Some(place) = ranked.get_mut(slot) {
+                *place = kind;
+            }
+        }
+        ranked
+    }
+
+    /// Whether the record of this device puts `kind` ahead of `other`.
+    pub fn outranks(&self, kind: BackendKind, other: BackendKind) -> bool {
+        self.rank(&[other, kind]).first() == Some(&kind)
+    }
+}
+
+/// The backend the plan's HymoFS operations go to instead, when the pinned
+/// backend is another one or, without a pin, when HymoFS has done worse on
+/// this device than its fallback.
+pub fn preferred_over_hymofs(config: &Config, health: &HealthStore) -> Option<BackendKind> {
+    match config.pinned_backend {
+        Some(BackendKind::HymoFs) => None,
+        Some(kind) => Some(kind),
+        None if config.disable_backend_learning => None,
+        None => {
+            let fallback = match config.hymofs_fallback {
+                HymoFallback::Overlay => BackendKind::Overlay,
+                HymoFallback::Magic => BackendKind::Magic,
+            };
+            health.outranks(fallback, BackendKind::HymoFs).then_some(fallback)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn boots(store: &mut HealthStore, backend: BackendKind, count: u32, failures: usize, millis: u64) {
+        for _ in 0..count {
+            store.record(&[BackendRun { backend, attempts: 4, failures, elapsed: Duration::from_millis(millis) }]);
+        }
+    }
+
+    #[test]
+    fn learns_reliability_and_latency() {
+        let mut store = HealthStore::default();
+        boots(&mut store, BackendKind::HymoFs, 2, 4, 40);
+        store.record(&[BackendRun { backend: BackendKind::Magic, attempts: 0, failures: 0, elapsed: Duration::ZERO }]);
+        let hymofs = &store.backends[&BackendKind::HymoFs];
+        assert_eq!((hymofs.boots, hymofs.attempts, hymofs.failures), (2, 8, 8));
+        assert!((hymofs.latency_ms - 10.0).abs() < 1e-9);
+        assert!((hymofs.reliability() - 0.1).abs() < 1e-9);
+        assert!(!store.backends.contains_key(&BackendKind::Magic));
+
+        let json = serde_json::to_string(&store).unwrap();
+        assert_eq!(serde_json::from_str::<HealthStore>(&json).unwrap(), store);
+    }
+
+    #[test]
+    fn ranks_only_backends_with_a_record() {
+        let order = vec![BackendKind::HymoFs, BackendKind::Overlay, BackendKind::Magic];
+        let mut store = HealthStore::default();
+        boots(&mut store, BackendKind::HymoFs, 2, 4, 10);
+        assert_eq!(store.rank(&order), order);
+
+        boots(&mut store, BackendKind::HymoFs, 1, 4, 10);
+        boots(&mut store, BackendKind::Magic, 3, 0, 80);
+        assert_eq!(store.rank(&order), vec![BackendKind::Magic, BackendKind::Overlay, BackendKind::HymoFs]);
+
+        boots(&mut store, BackendKind::Overlay, 3, 0, 20);
+        assert_eq!(store.rank(&order), vec![BackendKind::Overlay, BackendKind::Magic, BackendKind::HymoFs]);
+    }
+
+    #[test]
+    fn pin_overrides_what_was_learned() {
+        let mut store = HealthStore::default();
+        boots(&mut store, BackendKind::HymoFs, 3, 4, 10);
+        boots(&mut store, BackendKind::Magic, 3, 0, 10);
+        let mut config = Config::default();
+        assert_eq!(preferred_over_hymofs(&config, &store), Some(BackendKind::Magic));
+        assert_eq!(preferred_over_hymofs(&config, &HealthStore::default()), None);
+
+        config.disable_backend_learning = true;
+        assert_eq!(preferred_over_hymofs(&config, &store), None);
+        config.pinned_backend = Some(BackendKind::Overlay);
+        assert_eq!(preferred_over_hymofs(&config, &store), Some(BackendKind::Overlay));
+        config.pinned_backend = Some(BackendKind::HymoFs);
+        config.disable_backend_learning = false;
+        assert_eq!(preferred_over_hymofs(&config, &store), None);
+    }
+}
@@ -0,0 +1,328 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+use crate::{conf::config::Config, core::{inventory::{ModuleRules, MountMode}, manager::ModuleFlags, modules::ModuleProp, roots}, defs, utils};
+
+const MAX_EVENTS: usize = 500;
+
+/// What one module looked like the last time it was recorded: its version,
+/// whether it was enabled, and every entry it would stage with its mode.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ModuleSnapshot {
+    pub version: String,
+    pub enabled: bool,
+    pub files: BTreeMap<String, MountMode>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    Install,
+    Update,
+    Enable,
+    Disable,
+    Change,
+    Remove,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModeChange {
+    pub path: String,
+    pub from: MountMode,
+    pub to: MountMode,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PlanDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<ModeChange>,
+}
+
+impl PlanDiff {
+    pub fn between(old: &BTreeMap<String, MountMode>, new: &BTreeMap<String, MountMode>) -> Self {
+        let mut diff = Self::default();
+        for (path, mode) in new {
+            match old.get(path) {
+                None => diff.added.push(path.clone()),
+                Some(previous) if previous != mode => diff.changed.push(ModeChange {
+                    path: path.clone(),
+                    from: previous.clone(),
+                    to: mode.clone(),
+                }),
+                Some(_) => {}
+            }
+        }
+        diff.removed = old.keys().filter(|path| !new.contains_key(*path)).cloned().collect();
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    pub timestamp: u64,
+    pub session: String,
+    pub module_id: String,
+    pub kind: EventKind,
+    pub version: String,
+    #[serde(default)]
+    pub previous_version: Option<String>,
+    #[serde(default)]
+    pub diff: PlanDiff,
+}
+
+pub fn snapshot_module(module_dir: &Path, id: &str, partitions: &[&str]) -> ModuleSnapshot {
+    let version = ModuleProp::load(&module_dir.join("module.prop")).version;
+    let enabled = ModuleFlags::read(module_dir).is_enabled();
+    let rules = ModuleRules::load(module_dir, id);
+    let mut files = BTreeMap::new();
+    for partition in partitions {
+        let root = module_dir.join(partition);
+        if !root.is_dir() {
+            continue;
+        }
+        let mode = rules.get_mode(partition);
+        for entry in WalkDir::new(&root).min_depth(1).into_iter().flatten() {
+            if entry.file_type().is_dir() {
+                continue;
+            }
+            if let Ok(relative) = entry.path().strip_prefix(module_dir) {
+                files.insert(relative.to_string_lossy().into_owned(), mode.clone());
+            }
+        }
+    }
+    ModuleSnapshot { version, enabled, files }
+}
+
+pub fn snapshot_all(config: &Config) -> Result<BTreeMap<String, ModuleSnapshot>> {
+    let mut partitions = defs::BUILTIN_PARTITIONS.to_vec();
+    partitions.extend(config.partitions.iter().map(String::as_str));
+    let mut snapshots = BTreeMap::new();
+    let active = roots::active(config);
+    for root in &active {
+        for entry in fs::read_dir(root)?.flatten() {
+            let path = entry.path();
+            let id = entry.file_name().to_string_lossy().into_owned();
+            if !path.is_dir() || id == "meta-hybrid" || id == "lost+found" || id == ".git" || snapshots.contains_key(&id) {
+                continue;
+            }
+            if roots::is_root(&path, &active) {
+                continue;
+            }
+            snapshots.insert(id.clone(), snapshot_module(&path, &id, &partitions));
+        }
+    }
+    Ok(snapshots)
+}
+
+/// Events that turn `previous` into `current`. An enable toggle wins over a
+/// version bump, which wins over a content-only change.
+pub fn detect(previous: &BTreeMap<String, ModuleSnapshot>, current: &BTreeMap<String, ModuleSnapshot>, timestamp: u64, session: &str) -> Vec<Event> {
+    let empty = BTreeMap::new();
+    let event = |id: &str, kind, version: &str, previous_version: Option<&str>, diff| Event {
+        timestamp,
+        session: session.to_string(),
+        module_id: id.to_string(),
+        kind,
+        version: version.to_string(),
+        previous_version: previous_version.map(str::to_string),
+        diff,
+    };
+    let mut events = Vec::new();
+    for (id, now) in current {
+        let Some(before) = previous.get(id) else {
+            events.push(event(id, EventKind::Install, &now.version, None, PlanDiff::between(&empty, &now.files)));
+            continue;
+        };
+        let diff = PlanDiff::between(&before.files, &now.files);
+        let kind = if before.enabled != now.enabled {
+            if now.enabled { EventKind::Enable } else { EventKind::Disable }
+        } else if before.version != now.version {
+            EventKind::Update
+        } else if !diff.is_empty() {
+            EventKind::Change
+        } else {
+            continue;
+        };
+        events.push(event(id, kind, &now.version, Some(&before.version), diff));
+    }
+    for (id, before) in previous.iter().filter(|(id, _)| !current.contains_key(*id)) {
+        events.push(event(id, EventKind::Remove, &before.version, Some(&before.version), PlanDiff::between(&before.files, &empty)));
+    }
+    events
+}
+
+fn load_snapshots() -> BTreeMap<String, ModuleSnapshot> {
+    fs::read_to_string(defs::MODULE_SNAPSHOT_FILE)
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+pub fn parse(content: &str) -> Vec<Event> {
+    content.lines().filter_map(|line| serde_json::from_str(line).ok()).collect()
+}
+
+pub fn load() -> Vec<Event> {
+    fs::read_to_string(defs::HISTORY_FILE).map(|s| parse(&s)).unwrap_or_default()
+}
+
+fn append(events: &[Event]) -> Result<()> {
+    let mut kept = load();
+    if kept.len() + events.len() <= MAX_EVENTS {
+        let mut file = OpenOptions::new().create(true).append(true).open(defs::HISTORY_FILE)?;
+        for event in events {
+            writeln!(file, "{}", serde_json::to_string(event)?)?;
+        }
+        return Ok(());
+    }
+    kept.extend(events.iter().cloned());
+    let mut out = String::new();
+    for event in kept.iter().skip(kept.len() - MAX_EVENTS) {
+        out.push_str(&serde_json::to_string(event)?);
+        out.push('\n');
+    }
+    fs::write(defs::HISTORY_FILE, out)?;
+    Ok(())
+}
+
+/// Compares the module directory with the last snapshot and appends what
+/// changed to the history log.
+pub fn record(config: &Config) -> Result<Vec<Event>> {
+    let current = snapshot_all(config)?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let events = detect(&load_snapshots(), &current, now, utils::session_id());
+    if !events.is_empty() {
+        utils::ensure_dir_exists(defs::BASE_DIR)?;
+        append(&events)?;
+    }
+    fs::write(defs::MODULE_SNAPSHOT_FILE, serde_json::to_string(&current)?)?;
+    Ok(events)
+}
+
+fn mode_name(mode: &MountMode) -> &'static str {
+    match mode {
+        MountMode::Overlay => "overlay",
+        MountMode::HymoFs => "hymofs",
+        MountMode::Magic => "magic",
+        MountMode::Ignore => "ignore",
+    }
+}
+
+pub fn render(events: &[Event], module: Option<&str>) -> String {
+    let mut out = String::new();
+    for event in events.iter().filter(|e| module.is_none_or(|id| e.module_id == id)) {
+        let version = match &event.previous_version {
+            Some(previous) if *previous != event.version => format!("{} -> {}", previous, event.version),
+            _ => event.version.clone(),
+        };
+        let _ = writeln!(out, "{} [{}] {} {:?} {}", utils::format_utc(event.timestamp), event.session, event.module_id, event.kind, version);
+        for path in &event.diff.added {
+            let _ = writeln!(out, "    + {}", path);
+        }
+        for path in &event.diff.removed {
+            let _ = writeln!(out, "    - {}", path);
+        }
+        for change in &event.diff.changed {
+            let _ = writeln!(out, "    ~ {} ({} -> {})", change.path, mode_name(&change.from), mode_name(&change.to));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(version: &str, enabled: bool, files: &[(&str, MountMode)]) -> ModuleSnapshot {
+        ModuleSnapshot {
+            version: version.into(),
+            enabled,
+            files: files.iter().map(|(p, m)| (p.to_string(), m.clone())).collect(),
+        }
+    }
+
+    fn kinds(events: &[Event]) -> Vec<(&str, EventKind)> {
+        events.iter().map(|e| (e.module_id.as_str(), e.kind)).collect()
+    }
+
+    #[test]
+    fn diffs_files_and_modes() {
+        let old = snapshot("1", true, &[("system/a", MountMode::Overlay), ("system/b", MountMode::Overlay)]).files;
+        let new = snapshot("2", true, &[("system/b", MountMode::HymoFs), ("system/c", MountMode::HymoFs)]).files;
+        let diff = PlanDiff::between(&old, &new);
+        assert_eq!(diff.added, vec!["system/c"]);
+        assert_eq!(diff.removed, vec!["system/a"]);
+        assert_eq!(diff.changed, vec![ModeChange { path: "system/b".into(), from: MountMode::Overlay, to: MountMode::HymoFs }]);
+        assert!(PlanDiff::between(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn detects_lifecycle_events() {
+        let file = [("system/bin/x", MountMode::Overlay)];
+        let previous = BTreeMap::from([
+            ("gone".to_string(), snapshot("1", true, &file)),
+            ("off".to_string(), snapshot("1", true, &file)),
+            ("same".to_string(), snapshot("1", true, &file)),
+            ("tweaked".to_string(), snapshot("1", true, &file)),
+            ("updated".to_string(), snapshot("1", true, &file)),
+        ]);
+        let current = BTreeMap::from([
+            ("new".to_string(), snapshot("1", true, &file)),
+            ("off".to_string(), snapshot("2", false, &file)),
+            ("same".to_string(), snapshot("1", true, &file)),
+            ("tweaked".to_string(), snapshot("1", true, &[("system/bin/x", MountMode::Magic)])),
+            ("updated".to_string(), snapshot("2", true, &file)),
+        ]);
+        let events = detect(&previous, &current, 7, "s");
+        assert_eq!(kinds(&events), vec![
+            ("new", EventKind::Install),
+            ("off", EventKind::Disable),
+            ("tweaked", EventKind::Change),
+            ("updated", EventKind::Update),
+            ("gone", EventKind::Remove),
+        ]);
+        assert_eq!(events[0].diff.added, vec!["system/bin/x"]);
+        assert_eq!(events[4].diff.removed, vec!["system/bin/x"]);
+        assert_eq!(events[3].previous_version.as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn renders_a_filtered_timeline() {
+        let previous = BTreeMap::from([("a".to_string(), snapshot("1", true, &[("system/x", MountMode::Overlay)]))]);
+        let current = BTreeMap::from([
+            ("a".to_string(), snapshot("2", true, &[("system/y", MountMode::Overlay)])),
+            ("b".to_string(), snapshot("1", true, &[])),
+        ]);
+        let events = detect(&previous, &current, 0, "sess");
+        let round_trip = parse(&events.iter().map(|e| serde_json::to_string(e).unwrap() + "\n").collect::<String>());
+        assert_eq!(round_trip, events);
+        assert_eq!(render(&events, Some("a")),
+            "1970-01-01 00:00:00 [sess] a Update 1 -> 2\n    + system/y\n    - system/x\n");
+    }
+
+    #[test]
+    fn snapshots_module_directories() {
+        let root = std::env::temp_dir().join(format!("history-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("system/bin")).unwrap();
+        fs::write(root.join("system/bin/tool"), b"x").unwrap();
+        fs::write(root.join("module.prop"), "id=m\nversion=v3\n").unwrap();
+        fs::write(root.join(defs::DISABLE_FILE_NAME), b"").unwrap();
+        let snap = snapshot_module(&root, "m", &["system", "vendor"]);
+        assert_eq!(snap.version, "v3");
+        assert!(!snap.enabled);
+        assert_eq!(snap.files.keys().collect::<Vec<_>>(), vec!["system/bin/tool"]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
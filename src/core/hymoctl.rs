@@ -0,0 +1,104 @@
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+use crate::{defs, mount::{hymo_abi::{KernelAbi, KernelCaps}, hymofs::{HymoFs, HymoFsStatus, InjectPlan, PlannedOp, Rule, Unresolved}}};
+
+/// What `hymo status` reports about the device.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Status {
+    pub device: PathBuf,
+    pub state: &'static str,
+    /// Why HymoFS is unusable, when it is.
+    pub detail: Option<String>,
+    pub protocol: Option<i32>,
+    pub expected_protocol: i32,
+    /// The ioctl generation the binary talks to the kernel in.
+    pub abi: Option<String>,
+    pub caps: Option<KernelCaps>,
+    pub rules: Option<usize>,
+}
+
+pub fn status(device: &Path) -> Status {
+    let handle = HymoFs::open_at(device).ok();
+    let status = handle.as_ref().map(HymoFs::status);
+    let available = handle.as_ref().filter(|h| h.is_available());
+    Status {
+        device: device.to_path_buf(),
+        state: status.as_ref().map_or("unopenable", HymoFsStatus::label),
+        detail: status.filter(|s| *s != HymoFsStatus::Available).map(|s| s.describe()),
+        protocol: handle.as_ref().and_then(|h| h.get_version().ok()),
+        expected_protocol: defs::HYMO_PROTOCOL_VERSION,
+        abi: available.and_then(|h| h.abi().ok()).map(KernelAbi::describe),
+        caps: available.and_then(|h| h.caps().ok()),
+        rules: available.and_then(|h| h.list_rules().ok()).map(|rules| rules.len()),
+    }
+}
+
+pub fn rule_rows(rules: &[Rule]) -> Vec<Vec<String>> {
+    rules.iter()
+        .map(|rule| vec![
+            format!("{:?}", rule.kind).to_lowercase(),
+            rule.src.clone(),
+            rule.target.clone().unwrap_or_else(|| "-".to_string()),
+            rule.file_type.map_or_else(|| "-".to_string(), |t| format!("{:?}", t).to_lowercase()),
+            rule.priority.map_or_else(|| "-".to_string(), |p| p.to_string()),
+            if rule.flags.is_empty() { "-".to_string() } else { rule.flags.join(",") },
+        ])
+        .collect()
+}
+
+pub fn plan_rows(plan: &InjectPlan) -> Vec<Vec<String>> {
+    plan.steps.iter()
+        .map(|step| match step {
+            PlannedOp::InjectDir { path, source, opaque } => vec![
+                if *opaque { "replace-dir" } else { "inject-dir" }.to_string(), path.clone(), source.clone(),
+            ],
+            PlannedOp::Add { path, source, file_type } => vec![format!("add:{:?}", file_type).to_lowercase(), path.clone(), source.clone()],
+            PlannedOp::Hide { path } => vec!["hide".to_string(), path.clone(), "-".to_string()],
+        })
+        .collect()
+}
+
+pub fn unresolved_rows(unresolved: &[Unresolved]) -> Vec<Vec<String>> {
+    unresolved.iter()
+        .map(|u| vec![
+            serde_json::to_value(u.miss).ok().and_then(|v| v.as_str().map(str::to_string)).unwrap_or_default(),
+            u.op.path().to_string(),
+            u.mount.clone().unwrap_or_else(|| "-".to_string()),
+        ])
+        .collect()
+}
+
+/// Lays `rows` out under `header` in columns two spaces apart.
+pub fn table(header: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = header.iter().map(|h| h.chars().count()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let header: Vec<String> = header.iter().map(|h| h.to_string()).collect();
+    let mut out = String::new();
+    for row in std::iter::once(&header).chain(rows) {
+        let line: Vec<String> = row.iter().zip(&widths).map(|(cell, width)| format!("{:<width$}", cell, width = width)).collect();
+        out.push_str(line.join("  ").trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mount::hymofs;
+
+    #[test]
+    fn lays_rules_out_in_columns() {
+        let rules = hymofs::parse_rules("add /system/bin/sh /data/sh 8 prio=3\nhide /system/app/Bloat ro\n");
+        let out = table(&["KIND", "SRC", "TARGET", "TYPE", "PRIO", "FLAGS"], &rule_rows(&rules));
+        assert_eq!(out, "\
+KIND  SRC                TARGET    TYPE  PRIO  FLAGS
+add   /system/bin/sh     /data/sh  reg   3     -
+hide  /system/app/Bloat  -         -     -     ro
+");
+    }
+}
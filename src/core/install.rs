@@ -0,0 +1,278 @@
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::{FileExt, MetadataExt, symlink};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+use crate::{conf::config::Config, core::{modules::ModuleProp, uninstall}, defs};
+
+/// Copies are done, verified and recorded this many bytes at a time.
+pub const CHUNK_LEN: usize = 1024 * 1024;
+
+/// How far an install has come. `bytes_resumed` were kept from an earlier,
+/// interrupted attempt instead of being read from the source again.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Progress {
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub bytes_resumed: u64,
+    pub files_done: usize,
+    pub files_total: usize,
+}
+
+/// The error of an install stopped through its cancel flag.
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "install cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// The id a module directory declares in its module.prop.
+pub fn module_id(source: &Path) -> Result<String> {
+    let prop = ModuleProp::load(&source.join("module.prop"));
+    if prop.id.is_empty() {
+        bail!("{} has no module.prop with an id", source.display());
+    }
+    uninstall::validate_id(&prop.id)?;
+    Ok(prop.id)
+}
+
+fn chunk_sum(data: &[u8]) -> String {
+    blake3::hash(data).to_hex().to_string()
+}
+
+/// `path` with `suffix` appended to its file name.
+fn sidecar(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Reads until `buf` is full or the input ends; slow FUSE sources return
+/// short reads long before the end.
+fn read_full(input: &mut impl Read, buf: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match input.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(filled)
+}
+
+struct Copier<'a, F> {
+    chunk_len: usize,
+    cancel: &'a AtomicBool,
+    progress: Progress,
+    report: F,
+}
+
+impl<F: FnMut(&Progress)> Copier<'_, F> {
+    /// Copies `src` to `dst` through `dst.part`, writing each chunk's
+    /// checksum to `dst.sums` once the chunk reads back intact. A retry
+    /// keeps the leading chunks that still match their checksums, as long
+    /// as the source has the same size and mtime.
+    fn copy_file(&mut self, src: &Path, dst: &Path) -> Result<()> {
+        let meta = fs::metadata(src).with_context(|| format!("Failed to stat {}", src.display()))?;
+        let total = meta.len();
+        if fs::symlink_metadata(dst).is_ok_and(|m| m.is_file() && m.len() == total) {
+            self.progress.bytes_resumed += total;
+            self.progress.bytes_done += total;
+            return Ok(());
+        }
+        let (part, sums_path) = (sidecar(dst, ".part"), sidecar(dst, ".sums"));
+        let stamp = format!("{} {} {}", total, meta.mtime(), self.chunk_len);
+        let recorded = fs::read_to_string(&sums_path).unwrap_or_default();
+        let mut lines = recorded.lines();
+        let sums: Vec<&str> = if lines.next() == Some(stamp.as_str()) { lines.collect() } else { Vec::new() };
+
+        let out = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&part)
+            .with_context(|| format!("Failed to open {}", part.display()))?;
+        let mut buf = vec![0; self.chunk_len];
+        let mut offset = 0u64;
+        let mut kept = Vec::new();
+        for sum in sums {
+            let len = (total - offset).min(self.chunk_len as u64) as usize;
+            if len == 0 || out.read_exact_at(&mut buf[..len], offset).is_err() || chunk_sum(&buf[..len]) != sum {
+                break;
+            }
+            kept.push(sum);
+            offset += len as u64;
+        }
+        out.set_len(offset)?;
+        let mut sums_file = File::create(&sums_path)?;
+        writeln!(sums_file, "{}", stamp)?;
+        kept.iter().try_for_each(|sum| writeln!(sums_file, "{}", sum))?;
+        self.progress.bytes_resumed += offset;
+        self.progress.bytes_done += offset;
+
+        let mut input = File::open(src).with_context(|| format!("Failed to open {}", src.display()))?;
+        input.seek(SeekFrom::Start(offset))?;
+        let mut back = vec![0; self.chunk_len];
+        loop {
+            if self.cancel.load(Ordering::Relaxed) {
+                return Err(Cancelled.into());
+            }
+            let len = read_full(&mut input, &mut buf)?;
+            if len == 0 {
+                break;
+            }
+            let sum = chunk_sum(&buf[..len]);
+            out.write_all_at(&buf[..len], offset)?;
+            out.sync_data()?;
+            out.read_exact_at(&mut back[..len], offset)?;
+            if chunk_sum(&back[..len]) != sum {
+                bail!("Chunk at {} of {} did not read back intact", offset, dst.display());
+            }
+            writeln!(sums_file, "{}", sum)?;
+            offset += len as u64;
+            self.progress.bytes_done += len as u64;
+            (self.report)(&self.progress);
+        }
+        if offset != total {
+            bail!("{} changed while it was copied", src.display());
+        }
+        fs::set_permissions(&part, meta.permissions())?;
+        fs::rename(&part, dst)?;
+        fs::remove_file(&sums_path)?;
+        Ok(())
+    }
+
+    fn copy_tree(&mut self, src: &Path, dst: &Path) -> Result<()> {
+        fs::create_dir_all(dst)?;
+        fs::set_permissions(dst, fs::metadata(src)?.permissions())?;
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            let (src_path, dst_path) = (entry.path(), dst.join(entry.file_name()));
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                self.copy_tree(&src_path, &dst_path)?;
+            } else if file_type.is_symlink() {
+                if fs::symlink_metadata(&dst_path).is_err() {
+                    symlink(fs::read_link(&src_path)?, &dst_path)?;
+                }
+            } else {
+                self.copy_file(&src_path, &dst_path)?;
+                self.progress.files_done += 1;
+                (self.report)(&self.progress);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Files and bytes below `dir`, symlinks not counted.
+fn measure(dir: &Path) -> Result<(usize, u64)> {
+    let mut total = (0, 0);
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            let (files, bytes) = measure(&entry.path())?;
+            total = (total.0 + files, total.1 + bytes);
+        } else if !file_type.is_symlink() {
+            total = (total.0 + 1, total.1 + entry.metadata()?.len());
+        }
+    }
+    Ok(total)
+}
+
+fn install_with<F>(source: &Path, moduledir: &Path, work_root: &Path, chunk_len: usize, cancel: &AtomicBool, report: F) -> Result<PathBuf>
+where
+    F: FnMut(&Progress),
+{
+    let id = module_id(source)?;
+    let work = work_root.join(&id);
+    let origin = work_root.join(format!("{}.source", id));
+    let source_text = source.to_string_lossy();
+    if fs::read_to_string(&origin).is_ok_and(|previous| previous != source_text) {
+        fs::remove_dir_all(&work).or_else(|e| if work.exists() { Err(e) } else { Ok(()) })?;
+    }
+    fs::create_dir_all(work_root)?;
+    fs::write(&origin, source_text.as_bytes())?;
+
+    let (files_total, bytes_total) = measure(source)?;
+    let mut copier = Copier {
+        chunk_len,
+        cancel,
+        progress: Progress { bytes_total, files_total, ..Progress::default() },
+        report,
+    };
+    copier.copy_tree(source, &work)?;
+
+    let dest = moduledir.join(&id);
+    if dest.exists() {
+        fs::remove_dir_all(&dest).with_context(|| format!("Failed to replace {}", dest.display()))?;
+    }
+    fs::create_dir_all(moduledir)?;
+    fs::rename(&work, &dest).with_context(|| format!("Failed to move {} into {}", id, moduledir.display()))?;
+    fs::remove_file(&origin)?;
+    Ok(dest)
+}
+
+/// Copies the unpacked module at `source`, e.g. on an SD card or an MTP
+/// mount, into the module directory. The copy is staged under
+/// [`defs::INSTALL_WORK_DIR`] and only moved into place once complete, so
+/// calling this again after a failure or [`Cancelled`] picks up where the
+/// last attempt stopped. `report` hears about every chunk and file.
+pub fn install<F>(config: &Config, source: &Path, cancel: &AtomicBool, report: F) -> Result<PathBuf>
+where
+    F: FnMut(&Progress),
+{
+    install_with(source, &config.moduledir, Path::new(defs::INSTALL_WORK_DIR), CHUNK_LEN, cancel, report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resumes_an_interrupted_copy_from_its_last_good_chunk() {
+        let root = std::env::temp_dir().join(format!("install-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let (source, moduledir, work) = (root.join("sdcard/demo"), root.join("modules"), root.join("work"));
+        fs::create_dir_all(source.join("system/lib")).unwrap();
+        let prop = "id=demo\nversion=v1\n";
+        fs::write(source.join("module.prop"), prop).unwrap();
+        let data: Vec<u8> = (0..200u32).map(|i| (i % 251) as u8).collect();
+        fs::write(source.join("system/lib/libdemo.so"), &data).unwrap();
+        std::os::unix::fs::symlink("libdemo.so", source.join("system/lib/libalias.so")).unwrap();
+
+        let cancel = AtomicBool::new(false);
+        let err = install_with(&source, &moduledir, &work, 64, &cancel, |progress| {
+            if progress.bytes_done > 100 {
+                cancel.store(true, Ordering::Relaxed);
+            }
+        }).unwrap_err();
+        assert!(err.is::<Cancelled>());
+        assert!(!moduledir.join("demo").exists());
+        let prop_kept = if work.join("demo/module.prop").exists() { prop.len() as u64 } else { 0 };
+
+        let part = work.join("demo/system/lib/libdemo.so.part");
+        let mut partial = fs::read(&part).unwrap();
+        partial[70] ^= 0xff;
+        fs::write(&part, partial).unwrap();
+
+        cancel.store(false, Ordering::Relaxed);
+        let mut last = Progress::default();
+        let dest = install_with(&source, &moduledir, &work, 64, &cancel, |progress| last = *progress).unwrap();
+        assert_eq!(dest, moduledir.join("demo"));
+        assert_eq!(fs::read(dest.join("system/lib/libdemo.so")).unwrap(), data);
+        assert_eq!(fs::read_link(dest.join("system/lib/libalias.so")).unwrap(), Path::new("libdemo.so"));
+        assert_eq!((last.files_done, last.files_total, last.bytes_total), (2, 2, 200 + prop.len() as u64));
+        assert_eq!(last.bytes_resumed, 64 + prop_kept);
+        assert_eq!(last.bytes_done, last.bytes_total);
+        assert!(!work.join("demo").exists() && !work.join("demo.source").exists());
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest as _, Sha256};
+use walkdir::WalkDir;
+use crate::{core::memory, defs};
+
+const READ_BUF_LEN: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Blake3,
+    Sha256,
+}
+
+impl HashAlgorithm {
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Blake3 => "blake3",
+            HashAlgorithm::Sha256 => "sha256",
+        }
+    }
+
+    pub fn hasher(self) -> Box<dyn ContentHasher> {
+        match self {
+            HashAlgorithm::Blake3 => Box::new(blake3::Hasher::new()),
+            HashAlgorithm::Sha256 => Box::new(Sha256::new()),
+        }
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "blake3" => Ok(HashAlgorithm::Blake3),
+            "sha256" | "sha-256" => Ok(HashAlgorithm::Sha256),
+            other => bail!("unsupported hash algorithm '{}'", other),
+        }
+    }
+}
+
+pub trait ContentHasher {
+    fn update(&mut self, data: &[u8]);
+    fn finish(self: Box<Self>) -> [u8; 32];
+}
+
+impl ContentHasher for blake3::Hasher {
+    fn update(&mut self, data: &[u8]) {
+        blake3::Hasher::update(self, data);
+    }
+
+    fn finish(self: Box<Self>) -> [u8; 32] {
+        self.finalize().into()
+    }
+}
+
+impl ContentHasher for Sha256 {
+    fn update(&mut self, data: &[u8]) {
+        sha2::Digest::update(self, data);
+    }
+
+    fn finish(self: Box<Self>) -> [u8; 32] {
+        self.finalize().into()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Digest {
+    pub algorithm: HashAlgorithm,
+    pub bytes: [u8; 32],
+}
+
+impl Digest {
+    pub fn of_reader<R: Read>(algorithm: HashAlgorithm, reader: &mut R) -> std::io::Result<Self> {
+        let mut hasher = algorithm.hasher();
+        let mut buf = vec![0u8; memory::buffer_len(READ_BUF_LEN)];
+        loop {
+            let n = reader.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+        }
+        Ok(Self { algorithm, bytes: hasher.finish() })
+    }
+
+    pub fn of_file(algorithm: HashAlgorithm, path: &Path) -> Result<Self> {
+        let mut file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        Self::of_reader(algorithm, &mut file).with_context(|| format!("Failed to hash {}", path.display()))
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.algorithm.name())?;
+        self.bytes.iter().try_for_each(|b| write!(f, "{:02x}", b))
+    }
+}
+
+/// Accepts `<algorithm>:<hex>`; a bare 64-digit hex string is read as sha256,
+/// which is what `sha256sum`-style manifests from other tools contain.
+impl FromStr for Digest {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (algorithm, hex) = match s.split_once(':') {
+            Some((algo, hex)) => (algo.parse()?, hex),
+            None => (HashAlgorithm::Sha256, s),
+        };
+        if hex.len() != 64 || !hex.is_ascii() {
+            bail!("digest must be 64 hex digits");
+        }
+        let mut bytes = [0u8; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).context("invalid hex digit in digest")?;
+        }
+        Ok(Self { algorithm, bytes })
+    }
+}
+
+impl Serialize for Digest {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Digest {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub algorithm: HashAlgorithm,
+    pub files: BTreeMap<String, Digest>,
+}
+
+impl Manifest {
+    pub fn build(root: &Path, algorithm: HashAlgorithm) -> Result<Self> {
+        let mut files = BTreeMap::new();
+        for entry in WalkDir::new(root).min_depth(1).into_iter().flatten() {
+            if !entry.file_type().is_file() || entry.file_name() == defs::MANIFEST_FILE_NAME {
+                continue;
+            }
+            let Ok(relative) = entry.path().strip_prefix(root) else { continue };
+            files.insert(relative.to_string_lossy().into_owned(), Digest::of_file(algorithm, entry.path())?);
+        }
+        Ok(Self { algorithm, files })
+    }
+
+    pub fn load(root: &Path) -> Option<Self> {
+        let content = fs::read_to_string(root.join(defs::MANIFEST_FILE_NAME)).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    pub fn save(&self, root: &Path) -> Result<()> {
+        fs::write(root.join(defs::MANIFEST_FILE_NAME), serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Re-hashes every listed file with the algorithm its digest records, so
+    /// manifests mixing sha256 and blake3 entries verify as written.
+    pub fn mismatches(&self, root: &Path) -> Vec<String> {
+        self.files.iter()
+            .filter(|(relative, expected)| {
+                Digest::of_file(expected.algorithm, &root.join(relative.as_str()))
+                    .map_or(true, |actual| &actual != *expected)
+            })
+            .map(|(relative, _)| relative.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn hashes_with_both_backends() {
+        let blake = Digest::of_reader(HashAlgorithm::Blake3, &mut Cursor::new(b"abc")).unwrap();
+        assert_eq!(blake.to_string(), "blake3:6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85");
+        let sha = Digest::of_reader(HashAlgorithm::Sha256, &mut Cursor::new(b"abc")).unwrap();
+        assert_eq!(sha.to_string(), "sha256:ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    }
+
+    #[test]
+    fn parses_tagged_and_bare_digests() {
+        let tagged: Digest = "blake3:af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262".parse().unwrap();
+        assert_eq!(tagged.algorithm, HashAlgorithm::Blake3);
+        let bare: Digest = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".parse().unwrap();
+        assert_eq!(bare.algorithm, HashAlgorithm::Sha256);
+        assert_eq!(bare.to_string().parse::<Digest>().unwrap(), bare);
+        assert!("md5:abcd".parse::<Digest>().is_err());
+        assert!("sha256:xyz".parse::<Digest>().is_err());
+    }
+
+    #[test]
+    fn manifest_detects_modified_files() {
+        let root = std::env::temp_dir().join(format!("integrity-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("system/bin")).unwrap();
+        fs::write(root.join("system/bin/tool"), b"v1").unwrap();
+        fs::write(root.join("module.prop"), b"id=x").unwrap();
+        let manifest = Manifest::build(&root, HashAlgorithm::Blake3).unwrap();
+        manifest.save(&root).unwrap();
+        let loaded = Manifest::load(&root).unwrap();
+        assert_eq!(loaded.files.len(), 2);
+        assert!(loaded.mismatches(&root).is_empty());
+        fs::write(root.join("system/bin/tool"), b"v2").unwrap();
+        assert_eq!(loaded.mismatches(&root), vec!["system/bin/tool".to_string()]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -3,19 +3,31 @@ use std::fs;
 use std::path::{Path, PathBuf};
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use crate::{defs, conf::config};
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+use crate::{defs, conf::config, core::{conditions, guards::HashGuards, roots, linker::LinkerEdits, manager::ModuleFlags, services::PostApply}};
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum MountMode {
+    #[default]
     Overlay,
     HymoFs,
     Magic,
     Ignore,
 }
-impl Default for MountMode {
-    fn default() -> Self {
-        MountMode::Overlay
-    }
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BootWindow {
+    #[default]
+    Always,
+    BootOnly,
+    AfterService,
+    AfterBoot,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConditionalRule {
+    pub when: String,
+    #[serde(default)]
+    pub path: Option<String>,
+    pub mode: MountMode,
 }
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct ModuleRules {
@@ -23,6 +35,23 @@ pub struct ModuleRules {
     pub default_mode: MountMode,
     #[serde(default)]
     pub paths: HashMap<String, MountMode>, 
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conditional: Vec<ConditionalRule>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window: Option<BootWindow>,
+    #[serde(default)]
+    pub post_apply: PostApply,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cleanup_hooks: Vec<String>,
+    #[serde(default, skip_serializing_if = "LinkerEdits::is_empty")]
+    pub linker: LinkerEdits,
+    /// Mounts even when the battery is too low for the rest of the plan.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub critical: Option<bool>,
+    /// Rules on these real paths only apply while the original file still
+    /// has the given digest.
+    #[serde(default, skip_serializing_if = "HashGuards::is_empty")]
+    pub expect_hash: HashGuards,
 }
 impl ModuleRules {
     pub fn load(module_dir: &Path, module_id: &str) -> Self {
@@ -33,23 +62,61 @@ impl ModuleRules {
                 rules = r;
             }
         }
-        let user_rules_dir = Path::new("/data/adb/meta-hybrid/rules");
+        let user_rules_dir = Path::new(defs::RULES_DIR);
         let user_config = user_rules_dir.join(format!("{}.json", module_id));
         if let Ok(content) = fs::read_to_string(&user_config) {
             if let Ok(user_rules) = serde_json::from_str::<ModuleRules>(&content) {
                 rules.default_mode = user_rules.default_mode;
                 rules.paths.extend(user_rules.paths);
+                rules.conditional.extend(user_rules.conditional);
+                if user_rules.window.is_some() {
+                    rules.window = user_rules.window;
+                }
+                if user_rules.critical.is_some() {
+                    rules.critical = user_rules.critical;
+                }
+                rules.cleanup_hooks.extend(user_rules.cleanup_hooks);
+                rules.expect_hash.extend(user_rules.expect_hash);
+                rules.linker.extend(user_rules.linker);
+                if user_rules.post_apply != PostApply::default() {
+                    rules.post_apply = user_rules.post_apply;
+                }
             }
         }
         rules
     }
+    pub fn window(&self) -> BootWindow {
+        self.window.unwrap_or_default()
+    }
+    pub fn is_critical(&self) -> bool {
+        self.critical.unwrap_or(false)
+    }
     pub fn get_mode(&self, relative_path: &str) -> MountMode {
+        if let Some(mode) = self.conditional_mode(Some(relative_path)) {
+            return mode;
+        }
         if let Some(mode) = self.paths.get(relative_path) {
             return mode.clone();
         }
+        if let Some(mode) = self.conditional_mode(None) {
+            return mode;
+        }
         self.default_mode.clone()
     }
+    fn conditional_mode(&self, relative_path: Option<&str>) -> Option<MountMode> {
+        self.conditional
+            .iter()
+            .rev()
+            .filter(|rule| rule.path.as_deref() == relative_path)
+            .find(|rule| conditions::evaluate(&rule.when))
+            .map(|rule| {
+                log::debug!("Condition '{}' matched, using {:?} for {}", rule.when, rule.mode, relative_path.unwrap_or("*"));
+                rule.mode.clone()
+            })
+    }
 }
+/// Directories in a module root that are not modules.
+pub const NOT_MODULES: [&str; 3] = ["meta-hybrid", "lost+found", ".git"];
 #[derive(Debug, Clone)]
 pub struct Module {
     pub id: String,
@@ -66,10 +133,8 @@ pub fn scan(source_dir: &Path, _config: &config::Config) -> Result<Vec<Module>>
         let path = entry.path();
         if !path.is_dir() { continue; }
         let id = entry.file_name().to_string_lossy().to_string();
-        if id == "meta-hybrid" || id == "lost+found" || id == ".git" { continue; }
-        if path.join(defs::DISABLE_FILE_NAME).exists() || 
-           path.join(defs::REMOVE_FILE_NAME).exists() || 
-           path.join(defs::SKIP_MOUNT_FILE_NAME).exists() { 
+        if NOT_MODULES.contains(&id.as_str()) { continue; }
+        if !ModuleFlags::read(&path).is_enabled() {
             continue; 
         }
         let rules = ModuleRules::load(&path, &id);
@@ -82,3 +147,25 @@ pub fn scan(source_dir: &Path, _config: &config::Config) -> Result<Vec<Module>>
     modules.sort_by(|a, b| b.id.cmp(&a.id));
     Ok(modules)
 }
+
+/// Modules of every active root; a module id found in several roots is
+/// taken from the one listed first, and roots nested in another one are
+/// not modules of it.
+pub fn scan_roots(config: &config::Config) -> Result<Vec<Module>> {
+    let active = roots::active(config);
+    let mut modules: Vec<Module> = Vec::new();
+    for root in &active {
+        for module in scan(root, config)? {
+            if roots::is_root(&module.source_path, &active) {
+                continue;
+            }
+            if let Some(kept) = modules.iter().find(|m| m.id == module.id) {
+                log::warn!("!! Module {} in {} is shadowed by {}", module.id, root.display(), kept.source_path.display());
+                continue;
+            }
+            modules.push(module);
+        }
+    }
+    modules.sort_by(|a, b| b.id.cmp(&a.id));
+    Ok(modules)
+}
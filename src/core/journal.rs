@@ -0,0 +1,217 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use crate::{core::{namespace, rules::RuleLedger}, mount::hymofs::{HymoFs, HymoResult, RuleOp}};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+enum JournalEntry {
+    Begin { namespace: Option<String> },
+    Intent { op: RuleOp },
+    Applied { path: String },
+    Rejected { path: String },
+}
+
+/// Journal of the rules one command adds outside a full apply, which has
+/// its own [`crate::core::wal`]. Every rule is written down and synced
+/// before its ioctl and settled after it, so a crash in between leaves a
+/// record of what may have reached the kernel.
+pub struct RuleJournal {
+    file: File,
+    path: PathBuf,
+}
+
+impl RuleJournal {
+    /// Starts the journal of `namespace`, or of ad hoc backend injections
+    /// without one.
+    pub fn begin(dir: &Path, namespace: Option<&str>) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+        let name = namespace.map_or_else(|| "backend.jsonl".to_string(), |ns| format!("ns-{}.jsonl", ns));
+        let path = dir.join(name);
+        let mut journal = Self { file: File::create(&path)?, path };
+        journal.append(&JournalEntry::Begin { namespace: namespace.map(str::to_string) })?;
+        Ok(journal)
+    }
+
+    fn append(&mut self, entry: &JournalEntry) -> Result<()> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    pub fn intend(&mut self, op: &RuleOp) -> Result<()> {
+        self.append(&JournalEntry::Intent { op: op.clone() })
+    }
+
+    pub fn settle(&mut self, op: &RuleOp, applied: bool) -> Result<()> {
+        let path = op.path().to_string();
+        self.append(&if applied { JournalEntry::Applied { path } } else { JournalEntry::Rejected { path } })
+    }
+
+    /// Drops the journal once the caller has saved its own record.
+    pub fn finish(self) -> Result<()> {
+        fs::remove_file(&self.path)?;
+        Ok(())
+    }
+}
+
+/// Applies `op` between an intent and its settlement; a journal that fails
+/// to write only costs the recovery of this rule.
+pub fn apply(handle: &HymoFs, journal: &mut Option<RuleJournal>, op: &RuleOp) -> HymoResult<()> {
+    if let Some(Err(e)) = journal.as_mut().map(|j| j.intend(op)) {
+        log::warn!("Failed to journal {}: {}", op.path(), e);
+        *journal = None;
+    }
+    let result = handle.apply_op(op);
+    if let Some(Err(e)) = journal.as_mut().map(|j| j.settle(op, result.is_ok())) {
+        log::warn!("Failed to journal {}: {}", op.path(), e);
+        *journal = None;
+    }
+    result
+}
+
+/// What a journal that was never finished says.
+#[derive(Debug, Default, PartialEq)]
+pub struct Unfinished {
+    pub namespace: Option<String>,
+    /// Rules whose ioctl may or may not have happened.
+    pub pending: Vec<RuleOp>,
+    /// Rules the kernel took while the command's own record may be lost.
+    pub applied: Vec<RuleOp>,
+}
+
+/// Reads a journal; a torn last line is ignored.
+pub fn parse(content: &str) -> Unfinished {
+    let mut unfinished = Unfinished::default();
+    let mut pending: BTreeMap<String, RuleOp> = BTreeMap::new();
+    for entry in content.lines().filter_map(|line| serde_json::from_str::<JournalEntry>(line).ok()) {
+        match entry {
+            JournalEntry::Begin { namespace } => unfinished.namespace = namespace,
+            JournalEntry::Intent { op } => {
+                pending.insert(op.path().to_string(), op);
+            }
+            JournalEntry::Applied { path } => unfinished.applied.extend(pending.remove(&path)),
+            JournalEntry::Rejected { path } => {
+                pending.remove(&path);
+            }
+        }
+    }
+    unfinished.pending = pending.into_values().collect();
+    unfinished
+}
+
+/// How startup settles an unfinished journal against the live rule table.
+#[derive(Debug, Default, PartialEq)]
+pub struct Reconciliation {
+    /// Pending rules that did land; the command never saw them succeed, so
+    /// they are deleted again.
+    pub rolled_back: Vec<String>,
+    /// Applied namespace rules to record in the ledger again.
+    pub adopted: Vec<RuleOp>,
+}
+
+pub fn reconcile(unfinished: &Unfinished, live: &[RuleOp], ledger: &RuleLedger) -> Reconciliation {
+    let namespace = unfinished.namespace.as_deref();
+    let unclaimed = |path: &str| {
+        ledger.owned.owner(path).is_none() && !ledger.is_external(path) && ledger.namespace_of(path).is_none_or(|ns| Some(ns) == namespace)
+    };
+    Reconciliation {
+        rolled_back: unfinished.pending.iter()
+            .filter(|op| live.contains(op) && unclaimed(op.path()))
+            .map(|op| op.path().to_string())
+            .collect(),
+        adopted: unfinished.applied.iter()
+            .filter(|op| namespace.is_some() && live.contains(op))
+            .cloned()
+            .collect(),
+    }
+}
+
+/// Settles every journal left in `dir` by an interrupted command: rules it
+/// was in the middle of adding are deleted, namespace rules the kernel took
+/// are recorded in `ledger` again. The caller saves the ledger.
+pub fn recover(handle: &HymoFs, ledger: &mut RuleLedger, dir: &Path) -> Result<Reconciliation> {
+    let mut total = Reconciliation::default();
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Ok(total);
+    };
+    let journals: Vec<PathBuf> = entries.flatten().map(|e| e.path()).collect();
+    if journals.is_empty() {
+        return Ok(total);
+    }
+    let live = handle.list_ops()?;
+    for path in journals {
+        let unfinished = parse(&fs::read_to_string(&path).unwrap_or_default());
+        let found = reconcile(&unfinished, &live, ledger);
+        for rule in &found.rolled_back {
+            if let Err(e) = handle.delete_rule(rule) {
+                log::warn!("!! Failed to roll back interrupted rule {}: {:#}", rule, e);
+            }
+        }
+        if let Some(owner) = unfinished.namespace.as_deref().filter(|_| !found.adopted.is_empty()) {
+            if let Err(e) = namespace::record(ledger, owner, &found.adopted) {
+                log::warn!("!! Failed to recover rules of namespace {}: {:#}", owner, e);
+            }
+        }
+        log::info!(">> Recovered journal {}: {} rules rolled back, {} recorded again", path.display(), found.rolled_back.len(), found.adopted.len());
+        let _ = fs::remove_file(&path);
+        total.rolled_back.extend(found.rolled_back);
+        total.adopted.extend(found.adopted);
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mount::hymofs::HymoFileType;
+
+    fn add(src: &str) -> RuleOp {
+        RuleOp::Add { src: src.into(), target: format!("/data/ns{}", src), file_type: HymoFileType::Reg }
+    }
+
+    fn interrupted() -> Unfinished {
+        let dir = std::env::temp_dir().join(format!("journal-{}", std::process::id()));
+        let mut journal = RuleJournal::begin(&dir, Some("tool")).unwrap();
+        for (op, applied) in [(add("/system/a"), Some(true)), (add("/system/b"), Some(false)), (add("/system/c"), None)] {
+            journal.intend(&op).unwrap();
+            if let Some(applied) = applied {
+                journal.settle(&op, applied).unwrap();
+            }
+        }
+        let path = dir.join("ns-tool.jsonl");
+        let mut content = fs::read_to_string(&path).unwrap();
+        content.push_str("{\"event\":\"inte");
+        journal.finish().unwrap();
+        assert!(!path.exists());
+        let _ = fs::remove_dir_all(&dir);
+        parse(&content)
+    }
+
+    #[test]
+    fn splits_pending_from_applied_rules() {
+        let unfinished = interrupted();
+        assert_eq!(unfinished.namespace.as_deref(), Some("tool"));
+        assert_eq!(unfinished.applied, vec![add("/system/a")]);
+        assert_eq!(unfinished.pending, vec![add("/system/c")]);
+    }
+
+    #[test]
+    fn rolls_back_landed_pending_rules_and_readopts_applied_ones() {
+        let unfinished = interrupted();
+        let live = vec![add("/system/a"), add("/system/c")];
+        let found = reconcile(&unfinished, &live, &RuleLedger::default());
+        assert_eq!(found.rolled_back, vec!["/system/c"]);
+        assert_eq!(found.adopted, vec![add("/system/a")]);
+
+        assert!(reconcile(&unfinished, &[], &RuleLedger::default()) == Reconciliation::default());
+        let mut ledger = RuleLedger::default();
+        ledger.claim([("mod", &add("/system/c"))]);
+        assert!(reconcile(&unfinished, &live, &ledger).rolled_back.is_empty());
+    }
+}
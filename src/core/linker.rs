@@ -0,0 +1,420 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+use crate::core::{inventory::{BootWindow, Module}, planner::{HymoOperation, MountPlan}};
+
+pub const LINKER_MODULE_ID: &str = "meta-hybrid-linker";
+const LD_CONFIG: &str = "linkerconfig/ld.config.txt";
+const PUBLIC_LIBRARIES: &str = "system/etc/public.libraries.txt";
+
+/// Linker changes a module declares in its rules instead of shipping a
+/// hand-edited copy of the generated config.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LinkerEdits {
+    /// Libraries added to `public.libraries.txt`, which the native loader
+    /// preloads into every app process.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub preload: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub namespaces: Vec<NamespaceEdit>,
+}
+
+impl LinkerEdits {
+    pub fn is_empty(&self) -> bool {
+        self.preload.is_empty() && self.namespaces.is_empty()
+    }
+
+    pub fn extend(&mut self, other: LinkerEdits) {
+        self.preload.extend(other.preload);
+        self.namespaces.extend(other.namespaces);
+    }
+}
+
+/// Additions to one linker namespace. Without `section` the edit applies to
+/// every section that declares the namespace.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct NamespaceEdit {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub section: Option<String>,
+    pub namespace: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub search_paths: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub permitted_paths: Vec<String>,
+    /// Target namespace to the libraries shared with it.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub links: BTreeMap<String, Vec<String>>,
+}
+
+/// One `[name]` block of an ld.config file; the lines before the first
+/// header (the `dir.*` mappings) form a section without a name.
+#[derive(Debug, Clone, PartialEq)]
+struct Section {
+    name: Option<String>,
+    lines: Vec<String>,
+}
+
+impl Section {
+    /// Comma-separated values assigned to `key`, across `=` and `+=` lines.
+    fn values(&self, key: &str) -> Vec<&str> {
+        self.lines.iter()
+            .filter_map(|line| parse_assignment(line))
+            .filter(|(k, _, _)| *k == key)
+            .flat_map(|(_, _, value)| value.split(','))
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .collect()
+    }
+
+    fn declares(&self, namespace: &str) -> bool {
+        namespace == "default" || self.values("additional.namespaces").contains(&namespace)
+    }
+}
+
+/// `key = value` or `key += value`, returning whether it appends.
+fn parse_assignment(line: &str) -> Option<(&str, bool, &str)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') || line.starts_with('[') {
+        return None;
+    }
+    let (key, value) = line.split_once('=')?;
+    let (key, append) = match key.strip_suffix('+') {
+        Some(key) => (key, true),
+        None => (key, false),
+    };
+    Some((key.trim(), append, value.trim()))
+}
+
+fn split_sections(content: &str) -> Result<Vec<Section>> {
+    let mut sections = vec![Section { name: None, lines: Vec::new() }];
+    for (number, line) in content.lines().enumerate() {
+        let trimmed = line.trim();
+        if let Some(header) = trimmed.strip_prefix('[') {
+            let Some(name) = header.strip_suffix(']').filter(|n| !n.is_empty()) else {
+                bail!("line {}: malformed section header '{}'", number + 1, trimmed);
+            };
+            sections.push(Section { name: Some(name.to_string()), lines: Vec::new() });
+            continue;
+        }
+        if !trimmed.is_empty() && !trimmed.starts_with('#') && parse_assignment(trimmed).is_none() {
+            bail!("line {}: expected 'key = value' or 'key += value', got '{}'", number + 1, trimmed);
+        }
+        if let Some(section) = sections.last_mut() {
+            section.lines.push(line.to_string());
+        }
+    }
+    Ok(sections)
+}
+
+fn join_sections(sections: &[Section]) -> String {
+    let mut out = String::new();
+    for section in sections {
+        if let Some(name) = &section.name {
+            out.push_str(&format!("[{}]\n", name));
+        }
+        for line in &section.lines {
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// Checks that an ld.config parses and that every namespace a section
+/// configures or links to is declared in that section.
+pub fn validate(content: &str) -> Result<()> {
+    for section in split_sections(content)? {
+        let label = section.name.as_deref().unwrap_or("(global)");
+        for line in &section.lines {
+            let Some((key, _, value)) = parse_assignment(line) else { continue };
+            let Some(rest) = key.strip_prefix("namespace.") else { continue };
+            let mut parts = rest.split('.');
+            let namespace = parts.next().unwrap_or_default();
+            if !section.declares(namespace) {
+                bail!("[{}] configures undeclared namespace '{}' in '{}'", label, namespace, line.trim());
+            }
+            let linked: Vec<&str> = match (parts.next(), parts.next()) {
+                (Some("links"), None) => value.split(',').map(str::trim).filter(|v| !v.is_empty()).collect(),
+                (Some("link"), Some(target)) => vec![target],
+                _ => Vec::new(),
+            };
+            if let Some(missing) = linked.into_iter().find(|target| !section.declares(target)) {
+                bail!("[{}] links namespace '{}' to undeclared '{}'", label, namespace, missing);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn edit_lines(section: &Section, edit: &NamespaceEdit) -> Vec<String> {
+    let ns = &edit.namespace;
+    let mut lines = Vec::new();
+    for path in &edit.search_paths {
+        lines.push(format!("namespace.{}.search.paths += {}", ns, path));
+    }
+    for path in &edit.permitted_paths {
+        lines.push(format!("namespace.{}.permitted.paths += {}", ns, path));
+    }
+    let linked: HashSet<&str> = section.values(&format!("namespace.{}.links", ns)).into_iter().collect();
+    for (target, libs) in &edit.links {
+        if !linked.contains(target.as_str()) {
+            lines.push(format!("namespace.{}.links += {}", ns, target));
+        }
+        if !libs.is_empty() {
+            lines.push(format!("namespace.{}.link.{}.shared_libs += {}", ns, target, libs.join(":")));
+        }
+    }
+    lines
+}
+
+/// Appends `edits` to the sections they target. The result is validated, so
+/// an edit naming a namespace or section that does not exist is an error.
+pub fn apply_edits(content: &str, module_id: &str, edits: &[NamespaceEdit]) -> Result<String> {
+    let mut sections = split_sections(content)?;
+    for edit in edits {
+        let mut touched = 0;
+        for section in sections.iter_mut().filter(|s| s.name.is_some()) {
+            let wanted = edit.section.as_ref().is_none_or(|name| section.name.as_ref() == Some(name));
+            if !wanted || !section.declares(&edit.namespace) {
+                continue;
+            }
+            let lines = edit_lines(section, edit);
+            if !lines.is_empty() {
+                section.lines.push(format!("# added for {}", module_id));
+                section.lines.extend(lines);
+            }
+            touched += 1;
+        }
+        if touched == 0 {
+            bail!("no section{} declares namespace '{}'",
+                edit.section.as_ref().map(|s| format!(" named [{}]", s)).unwrap_or_default(), edit.namespace);
+        }
+    }
+    let rendered = join_sections(&sections);
+    validate(&rendered)?;
+    Ok(rendered)
+}
+
+/// Appends libraries missing from a `public.libraries.txt`. Entries must be
+/// bare library names; the loader only looks them up in the system lib dirs.
+pub fn add_public_libraries(content: &str, libs: &[String]) -> Result<String> {
+    if let Some(bad) = libs.iter().find(|lib| lib.contains('/') || !lib.ends_with(".so")) {
+        bail!("'{}' is not a library name", bad);
+    }
+    let present: HashSet<&str> = content.lines().filter_map(|line| line.split_whitespace().next()).collect();
+    let mut out = content.to_string();
+    if !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+    let mut added = HashSet::new();
+    for lib in libs {
+        if !present.contains(lib.as_str()) && added.insert(lib.as_str()) {
+            out.push_str(lib);
+            out.push('\n');
+        }
+    }
+    Ok(out)
+}
+
+/// Synthesized replacements relative to the filesystem root, plus modules
+/// whose edits were dropped because the result would not parse.
+#[derive(Debug, Default)]
+pub struct Synthesized {
+    pub files: Vec<(PathBuf, String)>,
+    pub rejected: Vec<String>,
+}
+
+/// Folds the linker edits of `modules` into the live configs under `root`.
+/// Each module is applied on top of the previous ones and validated on its
+/// own, so one broken declaration only rejects its module.
+pub fn synthesize(modules: &[Module], root: &Path) -> Synthesized {
+    let mut result = Synthesized::default();
+    let declaring: Vec<&Module> = modules.iter().filter(|m| !m.rules.linker.is_empty()).collect();
+    if declaring.is_empty() {
+        return result;
+    }
+    let mut ld_config = fs::read_to_string(root.join(LD_CONFIG)).ok();
+    let mut public = fs::read_to_string(root.join(PUBLIC_LIBRARIES)).ok();
+    let (mut ld_changed, mut public_changed) = (false, false);
+    for module in declaring {
+        let edits = &module.rules.linker;
+        let ld_result = match (&ld_config, edits.namespaces.is_empty()) {
+            (_, true) => Ok(None),
+            (Some(current), false) => apply_edits(current, &module.id, &edits.namespaces).map(Some),
+            (None, false) => Err(anyhow::anyhow!("{} does not exist", Path::new("/").join(LD_CONFIG).display())),
+        };
+        let public_result = match edits.preload.is_empty() {
+            true => Ok(None),
+            false => add_public_libraries(public.as_deref().unwrap_or_default(), &edits.preload).map(Some),
+        };
+        match (ld_result, public_result) {
+            (Ok(ld), Ok(libs)) => {
+                if let Some(ld) = ld {
+                    ld_config = Some(ld);
+                    ld_changed = true;
+                }
+                if let Some(libs) = libs {
+                    public = Some(libs);
+                    public_changed = true;
+                }
+            }
+            (Err(e), _) | (_, Err(e)) => {
+                log::error!("!! Rejecting linker edits of {}: {:#}", module.id, e);
+                result.rejected.push(format!("{}: linker edits rejected: {:#}", module.id, e));
+            }
+        }
+    }
+    if let Some(ld) = ld_config.filter(|_| ld_changed) {
+        result.files.push((PathBuf::from(LD_CONFIG), ld));
+    }
+    if let Some(libs) = public.filter(|_| public_changed) {
+        result.files.push((PathBuf::from(PUBLIC_LIBRARIES), libs));
+    }
+    result
+}
+
+/// Writes the synthesized files into the staging area and returns the
+/// HymoFS operations that redirect the originals to them.
+pub fn stage(synthesized: &Synthesized, storage_root: &Path) -> Result<Vec<HymoOperation>> {
+    let module_root = storage_root.join(LINKER_MODULE_ID);
+    if module_root.exists() {
+        fs::remove_dir_all(&module_root)?;
+    }
+    let mut tops = Vec::new();
+    for (relative, content) in &synthesized.files {
+        let staged = module_root.join(relative);
+        if let Some(parent) = staged.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&staged, content)?;
+        if let Some(top) = relative.components().next() {
+            let top = PathBuf::from(top.as_os_str());
+            if !tops.contains(&top) {
+                tops.push(top);
+            }
+        }
+    }
+    Ok(tops.into_iter()
+        .map(|top| HymoOperation {
+            module_id: LINKER_MODULE_ID.to_string(),
+            source: module_root.join(&top),
+            target: Path::new("/").join(&top),
+            window: BootWindow::Always,
+        })
+        .collect())
+}
+
+/// Adds the linker edits of `modules` to `plan`: rejections always, and the
+/// redirects when `storage_root` is given to stage the files into.
+pub fn extend_plan(plan: &mut MountPlan, modules: &[Module], storage_root: Option<&Path>) -> Result<()> {
+    let synthesized = synthesize(modules, Path::new("/"));
+    plan.rejected.extend(synthesized.rejected.iter().cloned());
+    let Some(storage_root) = storage_root else { return Ok(()) };
+    let ops = stage(&synthesized, storage_root)?;
+    if !ops.is_empty() {
+        log::info!(">> Linker: redirecting {} synthesized config files", synthesized.files.len());
+        plan.hymo_ops.extend(ops);
+        plan.hymo_module_ids.push(LINKER_MODULE_ID.to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::inventory::ModuleRules;
+
+    const LD_CONFIG_TXT: &str = "\
+dir.system = /system/bin/
+dir.vendor = /vendor/bin/
+[system]
+additional.namespaces = sphal,vndk
+namespace.default.search.paths = /system/${LIB}
+namespace.default.links = sphal
+namespace.sphal.search.paths = /vendor/${LIB}
+[vendor]
+namespace.default.search.paths = /vendor/${LIB}
+";
+
+    fn edit(namespace: &str) -> NamespaceEdit {
+        NamespaceEdit { namespace: namespace.into(), ..Default::default() }
+    }
+
+    #[test]
+    fn validates_structure_and_namespaces() {
+        assert!(validate(LD_CONFIG_TXT).is_ok());
+        assert!(validate("[system]\nnamespace.ghost.search.paths = /x\n").unwrap_err().to_string().contains("ghost"));
+        assert!(validate("[system]\nnamespace.default.links = ghost\n").is_err());
+        assert!(validate("[system]\nnamespace.default.link.ghost.shared_libs = libc.so\n").is_err());
+        assert!(validate("[system\n").unwrap_err().to_string().contains("line 1"));
+        assert!(validate("[system]\njust words\n").is_err());
+    }
+
+    #[test]
+    fn appends_to_every_declaring_section() {
+        let mut paths = edit("default");
+        paths.search_paths.push("/data/adb/lib".into());
+        let mut links = edit("default");
+        links.section = Some("system".into());
+        links.links.insert("sphal".into(), vec!["libfoo.so".into(), "libbar.so".into()]);
+        let out = apply_edits(LD_CONFIG_TXT, "m", &[paths, links]).unwrap();
+        assert_eq!(out.matches("namespace.default.search.paths += /data/adb/lib").count(), 2);
+        assert!(out.contains("namespace.default.link.sphal.shared_libs += libfoo.so:libbar.so"));
+        assert!(!out.contains("namespace.default.links += sphal"), "link already declared");
+        assert!(out.starts_with("dir.system = /system/bin/\n"));
+        let vendor = out.split("[vendor]").nth(1).unwrap();
+        assert!(vendor.contains("/data/adb/lib"));
+    }
+
+    #[test]
+    fn rejects_edits_that_break_the_config() {
+        let mut e = edit("default");
+        e.section = Some("vendor".into());
+        e.links.insert("sphal".into(), vec!["libfoo.so".into()]);
+        assert!(apply_edits(LD_CONFIG_TXT, "m", &[e]).is_err(), "[vendor] does not declare sphal");
+        assert!(apply_edits(LD_CONFIG_TXT, "m", &[edit("ghost")]).is_err());
+    }
+
+    #[test]
+    fn adds_missing_public_libraries() {
+        let out = add_public_libraries("libc.so\nlibm.so nopreload", &["libm.so".into(), "libfoo.so".into(), "libfoo.so".into()]).unwrap();
+        assert_eq!(out, "libc.so\nlibm.so nopreload\nlibfoo.so\n");
+        assert!(add_public_libraries("", &["/system/lib/libfoo.so".into()]).is_err());
+    }
+
+    #[test]
+    fn synthesizes_and_stages_per_module() {
+        let root = std::env::temp_dir().join(format!("linker-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("linkerconfig")).unwrap();
+        fs::create_dir_all(root.join("system/etc")).unwrap();
+        fs::write(root.join(LD_CONFIG), LD_CONFIG_TXT).unwrap();
+        fs::write(root.join(PUBLIC_LIBRARIES), "libc.so\n").unwrap();
+        let module = |id: &str, linker: LinkerEdits| Module {
+            id: id.into(),
+            source_path: root.join(id),
+            rules: ModuleRules { linker, ..Default::default() },
+        };
+        let mut good = edit("sphal");
+        good.permitted_paths.push("/data/adb/lib".into());
+        let modules = vec![
+            module("good", LinkerEdits { preload: vec!["libfoo.so".into()], namespaces: vec![good] }),
+            module("bad", LinkerEdits { preload: Vec::new(), namespaces: vec![edit("ghost")] }),
+            module("plain", LinkerEdits::default()),
+        ];
+        let synthesized = synthesize(&modules, &root);
+        assert_eq!(synthesized.rejected.len(), 1);
+        assert!(synthesized.rejected[0].starts_with("bad:"));
+        assert_eq!(synthesized.files.len(), 2);
+
+        let ops = stage(&synthesized, &root.join("staging")).unwrap();
+        let targets: Vec<&Path> = ops.iter().map(|op| op.target.as_path()).collect();
+        assert_eq!(targets, vec![Path::new("/linkerconfig"), Path::new("/system")]);
+        let staged = fs::read_to_string(root.join("staging").join(LINKER_MODULE_ID).join(LD_CONFIG)).unwrap();
+        assert!(staged.contains("namespace.sphal.permitted.paths += /data/adb/lib"));
+        assert!(synthesize(&modules[2..], &root).files.is_empty());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,431 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+use crate::{
+    conf::config::Config,
+    core::{inventory, modules::{self, ModuleProp}, partitions, rewrite, roots, rules::{self, RuleLedger}, state::RuntimeState, transaction::Transaction},
+    defs,
+    mount::hymofs::{self, HymoFs, RuleOp, RulePriorities},
+};
+
+/// The flag files a root manager leaves in a module's directory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ModuleFlags {
+    pub disable: bool,
+    pub remove: bool,
+    pub skip_mount: bool,
+}
+
+impl ModuleFlags {
+    pub fn read(module_dir: &Path) -> Self {
+        Self {
+            disable: module_dir.join(defs::DISABLE_FILE_NAME).exists(),
+            remove: module_dir.join(defs::REMOVE_FILE_NAME).exists(),
+            skip_mount: module_dir.join(defs::SKIP_MOUNT_FILE_NAME).exists(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.disable && !self.remove && !self.skip_mount
+    }
+}
+
+/// A module as installed, whether or not it is enabled.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InstalledModule {
+    pub id: String,
+    pub path: PathBuf,
+    pub prop: ModuleProp,
+    pub flags: ModuleFlags,
+    /// Content directory of each partition the module ships, e.g.
+    /// `vendor` to `<module>/vendor`.
+    pub partitions: BTreeMap<String, PathBuf>,
+}
+
+impl InstalledModule {
+    fn read(config: &Config, id: String, path: PathBuf) -> Self {
+        Self {
+            prop: ModuleProp::load(&path.join("module.prop")),
+            flags: ModuleFlags::read(&path),
+            partitions: content_dirs(config, &path),
+            id,
+            path,
+        }
+    }
+}
+
+/// The partitions `module_dir` has content for, built-in ones and the
+/// configured extra ones.
+pub fn content_dirs(config: &Config, module_dir: &Path) -> BTreeMap<String, PathBuf> {
+    partitions::enabled(config).into_iter()
+        .map(|partition| (partition.to_string(), module_dir.join(partition)))
+        .filter(|(_, dir)| dir.is_dir())
+        .collect()
+}
+
+/// Every module in the active roots, in the order their mounts stack; ids
+/// found in several roots are taken from the first one, as the boot scan
+/// does.
+pub fn discover(config: &Config) -> Result<Vec<InstalledModule>> {
+    let active = roots::active(config);
+    let mut found: Vec<InstalledModule> = Vec::new();
+    for root in active.iter().filter(|root| root.is_dir()) {
+        for entry in fs::read_dir(root)? {
+            let path = entry?.path();
+            let Some(id) = path.file_name().map(|name| name.to_string_lossy().into_owned()) else { continue };
+            if !path.is_dir() || inventory::NOT_MODULES.contains(&id.as_str()) || roots::is_root(&path, &active) {
+                continue;
+            }
+            if !found.iter().any(|m| m.id == id) {
+                found.push(InstalledModule::read(config, id, path));
+            }
+        }
+    }
+    found.sort_by(|a, b| b.id.cmp(&a.id));
+    Ok(found)
+}
+
+/// The installed module `module_id`, as [`discover`] finds it.
+pub fn find(config: &Config, module_id: &str) -> Result<Option<InstalledModule>> {
+    Ok(discover(config)?.into_iter().find(|m| m.id == module_id))
+}
+
+/// Which module keeps a path that several enabled modules ship.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictPolicy {
+    /// Modules in `module_priority` win in the order listed; between the
+    /// others the one mounted last wins, as without a resolver.
+    #[default]
+    Priority,
+    /// The module mounted first keeps the path.
+    FirstWins,
+    /// Mount nothing while any path is contended.
+    Error,
+}
+
+/// A path more than one enabled module ships.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ModuleConflict {
+    pub partition: String,
+    /// Relative to the partition, e.g. `etc/hosts`.
+    pub path: String,
+    /// Winner first.
+    pub modules: Vec<String>,
+}
+
+/// Enabled modules in the order to inject them, so the winner of every
+/// contended path goes last, and the paths that are contended.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MountOrder<'a> {
+    pub modules: Vec<&'a InstalledModule>,
+    pub conflicts: Vec<ModuleConflict>,
+}
+
+/// Paths below `content` a module puts in place: files, symlinks and
+/// whiteouts under the name they hide, not directories or markers.
+fn shipped_paths(content: &Path) -> Vec<String> {
+    WalkDir::new(content).min_depth(1).into_iter().flatten()
+        .filter(|entry| !entry.file_type().is_dir() && !modules::is_marker(entry.file_name().as_bytes()))
+        .filter_map(|entry| {
+            let relative = entry.path().strip_prefix(content).ok()?;
+            Some(modules::visible_path(relative).to_string_lossy().into_owned())
+        })
+        .collect()
+}
+
+/// The paths shipped by more than one of `ranked`, which is in winner-first
+/// order.
+pub fn find_conflicts(ranked: &[&InstalledModule]) -> Vec<ModuleConflict> {
+    let mut shippers: BTreeMap<(String, String), Vec<String>> = BTreeMap::new();
+    for module in ranked {
+        for (partition, content) in &module.partitions {
+            for path in shipped_paths(content) {
+                shippers.entry((partition.clone(), path)).or_default().push(module.id.clone());
+            }
+        }
+    }
+    shippers.into_iter()
+        .filter(|(_, modules)| modules.len() > 1)
+        .map(|((partition, path), modules)| ModuleConflict { partition, path, modules })
+        .collect()
+}
+
+/// The enabled ones of `modules`, which are in discovery order, strongest
+/// claim first under `policy`.
+pub fn rank<'a>(modules: &'a [InstalledModule], policy: ConflictPolicy, priority: &[String]) -> Vec<&'a InstalledModule> {
+    let mut ranked: Vec<&InstalledModule> = modules.iter().filter(|m| m.flags.is_enabled()).collect();
+    if policy == ConflictPolicy::Priority {
+        ranked.reverse();
+        ranked.sort_by_key(|m| priority.iter().position(|id| *id == m.id).unwrap_or(priority.len()));
+    }
+    ranked
+}
+
+/// Orders the enabled ones of `modules` so `policy` decides every contended
+/// path, or fails under [`ConflictPolicy::Error`].
+pub fn resolve<'a>(modules: &'a [InstalledModule], policy: ConflictPolicy, priority: &[String]) -> Result<MountOrder<'a>> {
+    let mut ranked = rank(modules, policy, priority);
+    let conflicts = find_conflicts(&ranked);
+    if policy == ConflictPolicy::Error && !conflicts.is_empty() {
+        let listed: Vec<String> = conflicts.iter()
+            .map(|c| format!("/{}/{} ({})", c.partition, c.path, c.modules.join(", ")))
+            .collect();
+        bail!("{} paths are shipped by several modules: {}", conflicts.len(), listed.join("; "));
+    }
+    ranked.reverse();
+    Ok(MountOrder { modules: ranked, conflicts })
+}
+
+impl MountOrder<'_> {
+    /// Kernel priorities that let each module beat the ones injected
+    /// before it, whatever order their rules reach the kernel in. `staged`
+    /// is where the modules are copied to, if anywhere.
+    pub fn priorities(&self, staged: Option<&Path>) -> RulePriorities {
+        let mut priorities = RulePriorities::default();
+        for (i, module) in self.modules.iter().enumerate() {
+            let priority = i32::try_from(i + 1).unwrap_or(i32::MAX);
+            priorities.insert(module.path.clone(), priority);
+            if let Some(staged) = staged {
+                priorities.insert(staged.join(&module.id), priority);
+            }
+        }
+        priorities
+    }
+}
+
+/// What injecting one partition of one module came to.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InjectOutcome {
+    pub module: String,
+    pub partition: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backend: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Injects every partition of every module of `order` over the partition's
+/// target through `inject`, which returns the name of the backend it used. A
+/// failure is recorded and the rest still go ahead.
+pub fn mount_all<F>(config: &Config, order: &MountOrder, mut inject: F) -> Vec<InjectOutcome>
+where
+    F: FnMut(&str, &Path, &Path) -> Result<&'static str>,
+{
+    let mut outcomes = Vec::new();
+    for module in &order.modules {
+        for (partition, content) in &module.partitions {
+            let result = inject(partition, content, &partitions::target(config, partition));
+            if let Err(e) = &result {
+                log::warn!("!! Failed to inject {} of {}: {:#}", partition, module.id, e);
+            }
+            outcomes.push(InjectOutcome {
+                module: module.id.clone(),
+                partition: partition.clone(),
+                backend: result.as_ref().ok().copied(),
+                error: result.err().map(|e| format!("{:#}", e)),
+            });
+        }
+    }
+    outcomes
+}
+
+/// What a [`reload`] changed in the live HymoFS table.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ReloadReport {
+    pub added: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+}
+
+/// The rules to apply, with the module of each, and the owned rules to
+/// delete so `live` matches `desired`.
+#[derive(Debug, Default, PartialEq)]
+struct Delta {
+    add: Vec<(String, RuleOp)>,
+    remove: Vec<String>,
+    unchanged: usize,
+}
+
+/// `live` already serves `wanted`; a hide the kernel only took as a
+/// whiteout add counts too.
+fn serves(live: &RuleOp, wanted: &RuleOp) -> bool {
+    match (live, wanted) {
+        (RuleOp::Add { target: a, .. }, RuleOp::Add { target: b, .. }) => a == b,
+        (RuleOp::Hide { .. }, RuleOp::Hide { .. }) => true,
+        (RuleOp::Add { target, .. }, RuleOp::Hide { .. }) => target == defs::WHITEOUT_NODE,
+        _ => false,
+    }
+}
+
+/// Compares the rules the enabled modules want, by path, with the live
+/// ones. Owned live rules nobody wants any more go; foreign paths are left
+/// to their owners.
+fn delta<O, F>(desired: &BTreeMap<String, (String, RuleOp)>, live: &[RuleOp], is_owned: O, is_foreign: F) -> Delta
+where
+    O: Fn(&RuleOp) -> bool,
+    F: Fn(&str) -> bool,
+{
+    let mut delta = Delta::default();
+    let live_by_path: BTreeMap<&str, &RuleOp> = live.iter().map(|op| (op.path(), op)).collect();
+    for (path, (module_id, op)) in desired {
+        if is_foreign(path) {
+            continue;
+        }
+        match live_by_path.get(path.as_str()) {
+            Some(current) if serves(current, op) => delta.unchanged += 1,
+            _ => delta.add.push((module_id.clone(), op.clone())),
+        }
+    }
+    delta.remove = live.iter()
+        .filter(|op| !desired.contains_key(op.path()) && is_owned(op))
+        .map(|op| op.path().to_string())
+        .collect();
+    delta
+}
+
+/// Brings the live HymoFS rules in line with the modules as they are now
+/// enabled, so toggling one takes effect without a reboot: rules of newly
+/// enabled modules are added and those of disabled or removed ones deleted,
+/// while rules that are already right stay untouched.
+pub fn reload(config: &Config, hymofs: &HymoFs) -> Result<ReloadReport> {
+    let modules = discover(config)?;
+    let order = resolve(&modules, config.conflict_policy, &config.module_priority)?;
+    let state = RuntimeState::load().unwrap_or_default();
+    let storage_root = state.storage_root();
+    let rewrites = rewrite::active(&config.path_rewrites);
+    let mut desired = BTreeMap::new();
+    for module in &order.modules {
+        let staged = storage_root.join(&module.id);
+        for partition in module.partitions.keys() {
+            let source = if staged.join(partition).is_dir() { staged.join(partition) } else { module.path.join(partition) };
+            let mut ops = hymofs::collect_directory_ops(&partitions::target(config, partition), &source);
+            rewrite::apply(&mut ops, &rewrites);
+            desired.extend(ops.into_iter().map(|op| (op.path().to_string(), (module.id.clone(), op))));
+        }
+    }
+
+    let live = hymofs.list_ops()?;
+    let mut ledger = RuleLedger::load();
+    let owned_roots = rules::owned_roots(config, Some(&storage_root));
+    let delta = delta(&desired, &live, |op| ledger.is_owned(op, &owned_roots), |path| ledger.is_foreign(path));
+    let report = ReloadReport { added: delta.add.len(), removed: delta.remove.len(), unchanged: delta.unchanged };
+    if delta.add.is_empty() && delta.remove.is_empty() {
+        return Ok(report);
+    }
+    let hymofs = hymofs.clone().with_priorities(order.priorities(Some(&storage_root)));
+    let mut transaction = Transaction::begin(&hymofs);
+    for path in &delta.remove {
+        transaction.apply(&RuleOp::Delete { src: path.clone() })?;
+    }
+    for (_, op) in &delta.add {
+        transaction.apply(op)?;
+    }
+    for path in &delta.remove {
+        ledger.owned.remove(path);
+    }
+    ledger.claim(delta.add.iter().map(|(module_id, op)| (module_id.as_str(), op)));
+    ledger.save()?;
+    transaction.commit();
+    log::info!(">> Reloaded modules: {} rules added, {} removed", report.added, report.removed);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::bail;
+    use crate::mount::hymofs::HymoFileType;
+
+    #[test]
+    fn discovers_flags_and_partitions() {
+        let root = std::env::temp_dir().join(format!("manager-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("a/system/bin")).unwrap();
+        fs::create_dir_all(root.join("a/vendor/etc")).unwrap();
+        fs::write(root.join("a/module.prop"), "id=a\nname=Module A\nversion=v2\n").unwrap();
+        fs::create_dir_all(root.join("b/product")).unwrap();
+        fs::write(root.join("b/skip_mount"), "").unwrap();
+        fs::create_dir_all(root.join("lost+found")).unwrap();
+        let config = Config { moduledir: root.clone(), ..Config::default() };
+
+        let found = discover(&config).unwrap();
+        assert_eq!(found.iter().map(|m| m.id.as_str()).collect::<Vec<_>>(), ["b", "a"]);
+        let a = found.iter().find(|m| m.id == "a").unwrap();
+        assert_eq!((a.prop.name.as_str(), a.prop.version.as_str()), ("Module A", "v2"));
+        assert_eq!(find(&config, "b").unwrap().map(|m| m.path), Some(root.join("b")));
+        assert_eq!(a.partitions.keys().collect::<Vec<_>>(), ["system", "vendor"]);
+        assert!(a.flags.is_enabled());
+        assert_eq!(found.iter().find(|m| m.id == "b").unwrap().flags, ModuleFlags { skip_mount: true, ..Default::default() });
+
+        let mut seen = Vec::new();
+        let order = resolve(&found, ConflictPolicy::Priority, &[]).unwrap();
+        let outcomes = mount_all(&config, &order, |partition, content, target| {
+            seen.push((content.to_path_buf(), target.to_path_buf()));
+            if partition == "vendor" { bail!("read-only") } else { Ok("overlay") }
+        });
+        assert_eq!(seen, [(root.join("a/system"), PathBuf::from("/system")), (root.join("a/vendor"), PathBuf::from("/vendor"))]);
+        assert_eq!(outcomes.iter().map(|o| (o.backend, o.error.as_deref())).collect::<Vec<_>>(), [(Some("overlay"), None), (None, Some("read-only"))]);
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn resolves_contended_paths_by_policy() {
+        let root = std::env::temp_dir().join(format!("manager-conflicts-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for (module, file) in [("a", "system/etc/hosts"), ("b", "system/etc/hosts"), ("b", "system/etc/.wh.gps.conf"),
+            ("c", "system/etc/gps.conf"), ("c", "system/etc/.replace"), ("d", "vendor/lib/libx.so")] {
+            fs::create_dir_all(root.join(module).join(file).parent().unwrap()).unwrap();
+            fs::write(root.join(module).join(file), "").unwrap();
+        }
+        let config = Config { moduledir: root.clone(), ..Config::default() };
+        let found = discover(&config).unwrap();
+        let ids = |order: &MountOrder| order.modules.iter().map(|m| m.id.clone()).collect::<Vec<_>>();
+
+        let order = resolve(&found, ConflictPolicy::Priority, &[]).unwrap();
+        assert_eq!(ids(&order), ["d", "c", "b", "a"]);
+        assert_eq!(order.conflicts, vec![
+            ModuleConflict { partition: "system".into(), path: "etc/gps.conf".into(), modules: vec!["b".into(), "c".into()] },
+            ModuleConflict { partition: "system".into(), path: "etc/hosts".into(), modules: vec!["a".into(), "b".into()] },
+        ]);
+        let order = resolve(&found, ConflictPolicy::Priority, &["c".into(), "b".into()]).unwrap();
+        assert_eq!(ids(&order), ["d", "a", "b", "c"]);
+        let priorities = order.priorities(Some(Path::new("/mnt/staged")));
+        assert_eq!(priorities.of(&root.join("c/system/etc/gps.conf").to_string_lossy()), 4);
+        assert_eq!(priorities.of("/mnt/staged/a/system/etc/hosts"), 2);
+        assert_eq!(order.conflicts[1].modules, ["b", "a"]);
+        let order = resolve(&found, ConflictPolicy::FirstWins, &[]).unwrap();
+        assert_eq!(ids(&order), ["a", "b", "c", "d"]);
+        assert_eq!(order.conflicts[0].modules, ["c", "b"]);
+        let err = resolve(&found, ConflictPolicy::Error, &[]).unwrap_err().to_string();
+        assert!(err.starts_with("2 paths are shipped by several modules: /system/etc/gps.conf (c, b)"), "{}", err);
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn reload_applies_only_the_delta() {
+        let add = |src: &str, target: &str| RuleOp::Add { src: src.into(), target: target.into(), file_type: HymoFileType::Reg };
+        let desired: BTreeMap<String, (String, RuleOp)> = [
+            ("a", add("/system/bin/a", "/mnt/a/system/bin/a")),
+            ("b", add("/system/bin/b", "/mnt/b/system/bin/b")),
+            ("b", RuleOp::Hide { path: "/system/app/Gone".into() }),
+            ("c", add("/system/bin/shared", "/mnt/c/system/bin/shared")),
+        ].into_iter().map(|(id, op)| (op.path().to_string(), (id.to_string(), op))).collect();
+        let live = vec![
+            add("/system/bin/a", "/mnt/a/system/bin/a"),
+            add("/system/app/Gone", defs::WHITEOUT_NODE),
+            add("/system/bin/old", "/mnt/old/system/bin/old"),
+            add("/system/bin/tool", "/vendor_tool/bin/tool"),
+        ];
+        let delta = delta(&desired, &live, |op| op.path() != "/system/bin/tool", |path| path == "/system/bin/shared");
+        assert_eq!(delta, Delta {
+            add: vec![("b".to_string(), add("/system/bin/b", "/mnt/b/system/bin/b"))],
+            remove: vec!["/system/bin/old".to_string()],
+            unchanged: 2,
+        });
+    }
+}
@@ -0,0 +1,104 @@
+use std::sync::OnceLock;
+
+/// Batch size of queued HymoFS rules in low-memory mode.
+pub const LOW_MEMORY_FLUSH_THRESHOLD: usize = 32;
+/// Worker threads in low-memory mode; every walker holds its own buffers.
+const LOW_MEMORY_THREADS: usize = 2;
+const MIN_BUFFER_LEN: usize = 4 * 1024;
+/// Share of the ceiling at which the heap counts as close to it.
+const WARN_PERCENT: u64 = 90;
+
+static MODE: OnceLock<Mode> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Mode {
+    low_memory: bool,
+    ceiling: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pressure {
+    Near,
+    Over,
+}
+
+/// Sets the memory mode from the config for the rest of the process. In
+/// low-memory mode the thread pool is kept small as well.
+pub fn init(low_memory: bool, ceiling_mb: u64) {
+    let _ = MODE.set(Mode { low_memory, ceiling: ceiling_mb.saturating_mul(1024 * 1024) });
+    if low_memory {
+        let _ = rayon::ThreadPoolBuilder::new().num_threads(LOW_MEMORY_THREADS).build_global();
+    }
+}
+
+fn mode() -> Mode {
+    MODE.get().copied().unwrap_or_default()
+}
+
+pub fn low_memory() -> bool {
+    mode().low_memory
+}
+
+/// `normal`, or an eighth of it in low-memory mode.
+pub fn buffer_len(normal: usize) -> usize {
+    if low_memory() { (normal / 8).max(MIN_BUFFER_LEN).min(normal) } else { normal }
+}
+
+/// Bytes the allocator has handed out and not got back.
+#[cfg(target_os = "android")]
+pub fn heap_in_use() -> Option<u64> {
+    let info = unsafe { libc::mallinfo() };
+    Some((info.uordblks + info.hblkhd) as u64)
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+pub fn heap_in_use() -> Option<u64> {
+    let info = unsafe { libc::mallinfo2() };
+    Some((info.uordblks + info.hblkhd) as u64)
+}
+
+#[cfg(not(any(target_os = "android", all(target_os = "linux", target_env = "gnu"))))]
+pub fn heap_in_use() -> Option<u64> {
+    None
+}
+
+pub fn pressure(used: u64, ceiling: u64) -> Option<Pressure> {
+    if ceiling == 0 {
+        None
+    } else if used > ceiling {
+        Some(Pressure::Over)
+    } else if used.saturating_mul(100) >= ceiling.saturating_mul(WARN_PERCENT) {
+        Some(Pressure::Near)
+    } else {
+        None
+    }
+}
+
+/// Compares the heap against the configured ceiling after `phase` and
+/// returns a warning when it is close to or over it.
+pub fn check(phase: &str) -> Option<String> {
+    let ceiling = mode().ceiling;
+    let used = heap_in_use()?;
+    let mib = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+    let warning = match pressure(used, ceiling)? {
+        Pressure::Near => format!("heap at {:.1} MiB after {}, close to the {:.0} MiB ceiling", mib(used), phase, mib(ceiling)),
+        Pressure::Over => format!("heap at {:.1} MiB after {}, over the {:.0} MiB ceiling", mib(used), phase, mib(ceiling)),
+    };
+    log::warn!("!! Memory: {}", warning);
+    Some(warning)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_pressure_against_the_ceiling() {
+        assert_eq!(pressure(10, 0), None);
+        assert_eq!(pressure(89, 100), None);
+        assert_eq!(pressure(90, 100), Some(Pressure::Near));
+        assert_eq!(pressure(100, 100), Some(Pressure::Near));
+        assert_eq!(pressure(101, 100), Some(Pressure::Over));
+        assert_eq!(pressure(u64::MAX, u64::MAX / 2), Some(Pressure::Over));
+    }
+}
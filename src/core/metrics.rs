@@ -0,0 +1,146 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use crate::{defs, utils, core::report::ApplyReport, mount::hymofs::{HymoFs}};
+
+/// Numbers from the last boot apply, kept so the exposition can be rendered
+/// again later with fresh live counts.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ApplyMetrics {
+    pub timestamp: u64,
+    pub success: bool,
+    pub duration_secs: f64,
+    /// Phase name to seconds spent in it, in execution order.
+    pub phases: Vec<(String, f64)>,
+    pub rules_applied: usize,
+    pub rules_failed: usize,
+    pub failures: usize,
+    pub warnings: usize,
+    /// Backend name to number of modules it mounted.
+    pub modules: Vec<(String, usize)>,
+    /// Staged module copies found incomplete or corrupted and resynced.
+    pub staging_repairs: usize,
+}
+
+impl ApplyMetrics {
+    pub fn from_report(report: &ApplyReport, total: Duration, phases: &[(&str, Duration)], staging_repairs: usize) -> Self {
+        Self {
+            timestamp: report.timestamp,
+            success: report.success,
+            duration_secs: total.as_secs_f64(),
+            phases: phases.iter().map(|(name, d)| (name.to_string(), d.as_secs_f64())).collect(),
+            rules_applied: report.rules_applied,
+            rules_failed: report.rules_failed,
+            failures: report.failures.len(),
+            warnings: report.warnings.len(),
+            modules: vec![
+                ("overlay".to_string(), report.overlay_modules.len()),
+                ("magic".to_string(), report.magic_modules.len()),
+                ("hymofs".to_string(), report.hymo_modules.len()),
+                ("direct".to_string(), report.direct_modules.len()),
+            ],
+            staging_repairs,
+        }
+    }
+
+    pub fn load() -> Option<Self> {
+        let content = fs::read_to_string(defs::METRICS_STATE_FILE).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        utils::atomic_write(defs::METRICS_STATE_FILE, serde_json::to_string(self)?)
+    }
+}
+
+fn family(out: &mut String, name: &str, help: &str, samples: &[(Option<(&str, &str)>, String)]) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+    for (label, value) in samples {
+        match label {
+            Some((key, val)) => { let _ = writeln!(out, "{}{{{}=\"{}\"}} {}", name, key, val, value); }
+            None => { let _ = writeln!(out, "{} {}", name, value); }
+        }
+    }
+}
+
+/// Prometheus text exposition of `metrics`, plus the number of rules the
+/// kernel holds right now when HymoFS could be asked.
+pub fn render(metrics: &ApplyMetrics, live_rules: Option<usize>) -> String {
+    let mut out = String::new();
+    family(&mut out, "meta_hybrid_last_apply_timestamp_seconds", "Unix time the last boot apply finished.",
+        &[(None, metrics.timestamp.to_string())]);
+    family(&mut out, "meta_hybrid_last_apply_success", "Whether the last boot apply succeeded.",
+        &[(None, u8::from(metrics.success).to_string())]);
+    family(&mut out, "meta_hybrid_apply_duration_seconds", "Wall time of the last boot apply.",
+        &[(None, format!("{:.6}", metrics.duration_secs))]);
+    let phases: Vec<_> = metrics.phases.iter()
+        .map(|(phase, secs)| (Some(("phase", phase.as_str())), format!("{:.6}", secs)))
+        .collect();
+    family(&mut out, "meta_hybrid_apply_phase_seconds", "Wall time of each phase of the last boot apply.", &phases);
+    family(&mut out, "meta_hybrid_rules", "HymoFS rules written by the last boot apply.", &[
+        (Some(("result", "applied")), metrics.rules_applied.to_string()),
+        (Some(("result", "failed")), metrics.rules_failed.to_string()),
+    ]);
+    let modules: Vec<_> = metrics.modules.iter()
+        .map(|(backend, count)| (Some(("backend", backend.as_str())), count.to_string()))
+        .collect();
+    family(&mut out, "meta_hybrid_modules", "Modules mounted by each backend.", &modules);
+    family(&mut out, "meta_hybrid_apply_failures", "Failures reported by the last boot apply.",
+        &[(None, metrics.failures.to_string())]);
+    family(&mut out, "meta_hybrid_apply_warnings", "Warnings reported by the last boot apply.",
+        &[(None, metrics.warnings.to_string())]);
+    family(&mut out, "meta_hybrid_staging_repairs", "Staged module copies resynced after going missing in part or failing verification.",
+        &[(None, metrics.staging_repairs.to_string())]);
+    if let Some(live) = live_rules {
+        family(&mut out, "meta_hybrid_hymofs_active_rules", "Rules HymoFS currently holds.",
+            &[(None, live.to_string())]);
+    }
+    out
+}
+
+pub fn live_rules(device: &Path) -> Option<usize> {
+    let handle = HymoFs::open_at(device).ok().filter(|h| h.is_available())?;
+    handle.list_ops().ok().map(|live| live.len())
+}
+
+/// Writes the exposition where a node exporter textfile collector reads it.
+pub fn export(path: &Path, metrics: &ApplyMetrics, live_rules: Option<usize>) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    utils::atomic_write(path, render(metrics, live_rules))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_exposition_format() {
+        let mut report = ApplyReport::new("apply");
+        report.rules_applied = 12;
+        report.rules_failed = 1;
+        report.overlay_modules = vec!["a".into(), "b".into()];
+        report.warnings.push("w".into());
+        let phases = [("sync", Duration::from_millis(250)), ("execute", Duration::from_millis(750))];
+        let metrics = ApplyMetrics::from_report(&report, Duration::from_millis(1500), &phases, 2);
+        assert_eq!(metrics.phases[1], ("execute".to_string(), 0.75));
+
+        let text = render(&metrics, Some(40));
+        assert!(text.contains("# TYPE meta_hybrid_rules gauge\nmeta_hybrid_rules{result=\"applied\"} 12\nmeta_hybrid_rules{result=\"failed\"} 1\n"));
+        assert!(text.contains("meta_hybrid_apply_phase_seconds{phase=\"sync\"} 0.250000\n"));
+        assert!(text.contains("meta_hybrid_modules{backend=\"overlay\"} 2\n"));
+        assert!(text.contains("meta_hybrid_last_apply_success 1\n"));
+        assert!(text.contains("meta_hybrid_apply_duration_seconds 1.500000\n"));
+        assert!(text.contains("meta_hybrid_staging_repairs 2\n"));
+        assert!(text.contains("meta_hybrid_hymofs_active_rules 40\n"));
+        assert!(!render(&metrics, None).contains("active_rules"));
+        for line in text.lines().filter(|l| !l.starts_with('#')) {
+            assert_eq!(line.split(' ').count(), 2, "malformed sample: {}", line);
+        }
+    }
+}
@@ -0,0 +1,356 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::{Result, bail};
+use serde_json::{Map, Value, json};
+use crate::defs;
+
+pub const VERSION_KEY: &str = "format_version";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Document {
+    State,
+    Config,
+}
+
+type Step = fn(&mut Map<String, Value>);
+
+pub struct Migration {
+    pub name: &'static str,
+    up: Step,
+    down: Option<Step>,
+}
+
+/// Step `i` upgrades format `i` to `i + 1`; append new steps, never reorder.
+const STATE_MIGRATIONS: &[Migration] = &[
+    Migration { name: "state-hymofs-fields", up: state_v0_up, down: Some(state_v0_down) },
+    Migration { name: "state-hymofs-device", up: state_v1_up, down: Some(state_v1_down) },
+    Migration { name: "state-session-id", up: state_v2_up, down: Some(state_v2_down) },
+    Migration { name: "state-staging-root", up: state_v3_up, down: Some(state_v3_down) },
+    Migration { name: "state-direct-modules", up: state_v4_up, down: Some(state_v4_down) },
+    Migration { name: "state-battery-deferred", up: state_v5_up, down: Some(state_v5_down) },
+];
+
+const CONFIG_MIGRATIONS: &[Migration] = &[
+    Migration { name: "config-partitions-list", up: config_v0_up, down: None },
+];
+
+const STATE_V0_FIELDS: [&str; 7] = [
+    "hymo_modules", "active_mounts", "storage_total", "storage_used",
+    "storage_percent", "hymofs_available", "hymofs_version",
+];
+
+fn state_v0_default(key: &str) -> Value {
+    match key {
+        "hymo_modules" | "active_mounts" => json!([]),
+        "hymofs_available" => json!(false),
+        "hymofs_version" => Value::Null,
+        _ => json!(0),
+    }
+}
+
+fn state_v0_up(doc: &mut Map<String, Value>) {
+    for key in STATE_V0_FIELDS {
+        doc.entry(key).or_insert_with(|| state_v0_default(key));
+    }
+}
+
+fn state_v0_down(doc: &mut Map<String, Value>) {
+    for key in STATE_V0_FIELDS {
+        doc.remove(key);
+    }
+}
+
+fn state_v1_up(doc: &mut Map<String, Value>) {
+    let available = doc.get("hymofs_available").and_then(Value::as_bool).unwrap_or(false);
+    doc.entry("hymofs_device")
+        .or_insert_with(|| if available { json!("/dev/hymo_ctl") } else { Value::Null });
+    doc.entry("hymofs_selftest").or_insert(Value::Null);
+    doc.entry("early_readers").or_insert_with(|| json!([]));
+}
+
+fn state_v1_down(doc: &mut Map<String, Value>) {
+    for key in ["hymofs_device", "hymofs_selftest", "early_readers"] {
+        doc.remove(key);
+    }
+}
+
+fn state_v2_up(doc: &mut Map<String, Value>) {
+    doc.entry("session_id").or_insert_with(|| json!(""));
+}
+
+fn state_v2_down(doc: &mut Map<String, Value>) {
+    doc.remove("session_id");
+}
+
+fn state_v3_up(doc: &mut Map<String, Value>) {
+    doc.entry("staging_root").or_insert(Value::Null);
+}
+
+fn state_v3_down(doc: &mut Map<String, Value>) {
+    doc.remove("staging_root");
+}
+
+fn state_v4_up(doc: &mut Map<String, Value>) {
+    doc.entry("direct_modules").or_insert_with(|| json!([]));
+}
+
+fn state_v4_down(doc: &mut Map<String, Value>) {
+    doc.remove("direct_modules");
+}
+
+fn state_v5_up(doc: &mut Map<String, Value>) {
+    doc.entry("battery_deferred").or_insert_with(|| json!([]));
+}
+
+fn state_v5_down(doc: &mut Map<String, Value>) {
+    doc.remove("battery_deferred");
+}
+
+fn config_v0_up(doc: &mut Map<String, Value>) {
+    if let Some(Value::String(list)) = doc.get("partitions") {
+        let items = list.split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| Value::String(item.to_string()))
+            .collect();
+        doc.insert("partitions".to_string(), Value::Array(items));
+    }
+}
+
+impl Document {
+    pub fn migrations(self) -> &'static [Migration] {
+        match self {
+            Document::State => STATE_MIGRATIONS,
+            Document::Config => CONFIG_MIGRATIONS,
+        }
+    }
+
+    pub fn current(self) -> u32 {
+        self.migrations().len() as u32
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Document::State => "state",
+            Document::Config => "config",
+        }
+    }
+
+    fn detect(self, doc: &Map<String, Value>) -> u32 {
+        if let Some(version) = doc.get(VERSION_KEY).and_then(Value::as_u64) {
+            return version as u32;
+        }
+        match self {
+            Document::State if doc.contains_key("battery_deferred") => 6,
+            Document::State if doc.contains_key("direct_modules") => 5,
+            Document::State if doc.contains_key("staging_root") => 4,
+            Document::State if doc.contains_key("session_id") => 3,
+            Document::State if doc.contains_key("hymofs_device") => 2,
+            Document::State if doc.contains_key("hymo_modules") => 1,
+            _ => 0,
+        }
+    }
+}
+
+pub fn version_of(kind: Document, doc: &Value) -> u32 {
+    doc.as_object().map(|map| kind.detect(map)).unwrap_or(0)
+}
+
+/// Moves `doc` to `target`, returning the names of the migrations that ran.
+/// Downgrades fail before touching the document if any step is irreversible.
+pub fn migrate_to(kind: Document, doc: &mut Value, target: u32) -> Result<Vec<&'static str>> {
+    let Some(map) = doc.as_object_mut() else {
+        bail!("{} document is not an object", kind.label());
+    };
+    let current = kind.detect(map);
+    let migrations = kind.migrations();
+    if current > kind.current() || target > kind.current() {
+        bail!("{} format v{} is newer than this build supports (v{})",
+            kind.label(), current.max(target), kind.current());
+    }
+    let mut applied = Vec::new();
+    if target >= current {
+        for migration in &migrations[current as usize..target as usize] {
+            (migration.up)(map);
+            applied.push(migration.name);
+        }
+    } else {
+        let steps = &migrations[target as usize..current as usize];
+        if let Some(blocked) = steps.iter().find(|m| m.down.is_none()) {
+            bail!("{} migration '{}' cannot be reversed", kind.label(), blocked.name);
+        }
+        for migration in steps.iter().rev() {
+            if let Some(down) = migration.down {
+                down(map);
+            }
+            applied.push(migration.name);
+        }
+    }
+    if target == 0 {
+        map.remove(VERSION_KEY);
+    } else {
+        map.insert(VERSION_KEY.to_string(), json!(target));
+    }
+    Ok(applied)
+}
+
+pub fn stamp(kind: Document, doc: &mut Value) {
+    if let Some(map) = doc.as_object_mut() {
+        map.insert(VERSION_KEY.to_string(), json!(kind.current()));
+    }
+}
+
+pub fn upgrade(kind: Document, doc: &mut Value) -> Result<Vec<&'static str>> {
+    migrate_to(kind, doc, kind.current())
+}
+
+pub fn record(kind: Document, names: &[&str], target: u32) {
+    if names.is_empty() {
+        return;
+    }
+    log::info!(">> Migrated {} to v{} ({})", kind.label(), target, names.join(", "));
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let entry = format!("{} {} v{} {}\n", now, kind.label(), target, names.join(","));
+    let ledger = Path::new(defs::MIGRATIONS_LOG);
+    let written = ledger.parent()
+        .map(fs::create_dir_all)
+        .transpose()
+        .and_then(|_| OpenOptions::new().create(true).append(true).open(ledger))
+        .and_then(|mut file| file.write_all(entry.as_bytes()));
+    if let Err(e) = written {
+        log::warn!("Failed to record migration: {}", e);
+    }
+}
+
+pub fn migrate_state_file(path: &Path, target: Option<u32>) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    let mut doc: Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+    let target = target.unwrap_or(Document::State.current());
+    let applied = migrate_to(Document::State, &mut doc, target)?;
+    if !applied.is_empty() {
+        crate::utils::atomic_write(path, serde_json::to_string_pretty(&doc)?)?;
+        record(Document::State, &applied, target);
+    }
+    Ok(())
+}
+
+pub fn migrate_config_file(path: &Path) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    let content = fs::read_to_string(path)?;
+    let mut doc = serde_json::to_value(toml::from_str::<toml::Table>(&content)?)?;
+    let previous = version_of(Document::Config, &doc);
+    let applied = upgrade(Document::Config, &mut doc)?;
+    if !applied.is_empty() {
+        let backup = path.with_extension(format!("toml.v{}", previous));
+        fs::copy(path, &backup)?;
+        fs::write(path, toml::to_string_pretty(&doc)?)?;
+        record(Document::Config, &applied, Document::Config.current());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state_v0() -> Value {
+        json!({
+            "timestamp": 1, "pid": 2, "storage_mode": "tmpfs", "mount_point": "/mnt",
+            "overlay_modules": ["a"], "magic_modules": [], "nuke_active": false
+        })
+    }
+
+    fn state_v1(available: bool) -> Value {
+        let mut doc = state_v0();
+        let map = doc.as_object_mut().unwrap();
+        map.insert("hymo_modules".into(), json!(["b"]));
+        map.insert("active_mounts".into(), json!(["system"]));
+        map.insert("storage_total".into(), json!(100));
+        map.insert("storage_used".into(), json!(10));
+        map.insert("storage_percent".into(), json!(10));
+        map.insert("hymofs_available".into(), json!(available));
+        map.insert("hymofs_version".into(), json!(5));
+        doc
+    }
+
+    fn loads_as_state(doc: Value) -> crate::core::state::RuntimeState {
+        serde_json::from_value(doc).expect("migrated state must deserialize")
+    }
+
+    #[test]
+    fn detects_unversioned_formats() {
+        assert_eq!(version_of(Document::State, &state_v0()), 0);
+        assert_eq!(version_of(Document::State, &state_v1(false)), 1);
+        assert_eq!(version_of(Document::Config, &json!({"verbose": true})), 0);
+        assert_eq!(version_of(Document::State, &json!({"format_version": 2})), 2);
+    }
+
+    #[test]
+    fn upgrades_state_v0() {
+        let mut doc = state_v0();
+        assert_eq!(upgrade(Document::State, &mut doc).unwrap(), vec!["state-hymofs-fields", "state-hymofs-device", "state-session-id", "state-staging-root", "state-direct-modules", "state-battery-deferred"]);
+        assert_eq!(doc[VERSION_KEY], json!(Document::State.current()));
+        assert_eq!(doc["hymo_modules"], json!([]));
+        assert_eq!(doc["hymofs_device"], Value::Null);
+        let state = loads_as_state(doc);
+        assert_eq!(state.overlay_modules, vec!["a".to_string()]);
+    }
+
+    #[test]
+    fn upgrades_state_v1_and_keeps_values() {
+        let mut doc = state_v1(true);
+        assert_eq!(upgrade(Document::State, &mut doc).unwrap(), vec!["state-hymofs-device", "state-session-id", "state-staging-root", "state-direct-modules", "state-battery-deferred"]);
+        assert_eq!(doc["hymofs_device"], json!("/dev/hymo_ctl"));
+        let state = loads_as_state(doc);
+        assert_eq!(state.hymo_modules, vec!["b".to_string()]);
+        assert_eq!(state.storage_total, 100);
+        assert_eq!(state.hymofs_version, Some(5));
+    }
+
+    #[test]
+    fn migrations_run_once() {
+        let mut doc = state_v0();
+        upgrade(Document::State, &mut doc).unwrap();
+        let snapshot = doc.clone();
+        assert!(upgrade(Document::State, &mut doc).unwrap().is_empty());
+        assert_eq!(doc, snapshot);
+    }
+
+    #[test]
+    fn state_downgrade_round_trips() {
+        let original = state_v1(false);
+        let mut doc = original.clone();
+        upgrade(Document::State, &mut doc).unwrap();
+        assert_eq!(migrate_to(Document::State, &mut doc, 1).unwrap(), vec!["state-battery-deferred", "state-direct-modules", "state-staging-root", "state-session-id", "state-hymofs-device"]);
+        let mut expected = original;
+        expected.as_object_mut().unwrap().insert(VERSION_KEY.into(), json!(1));
+        assert_eq!(doc, expected);
+        migrate_to(Document::State, &mut doc, 0).unwrap();
+        assert_eq!(doc, state_v0());
+    }
+
+    #[test]
+    fn upgrades_config_v0_partitions() {
+        let mut doc = json!({"verbose": true, "partitions": "my_custom, odm ,"});
+        upgrade(Document::Config, &mut doc).unwrap();
+        assert_eq!(doc["partitions"], json!(["my_custom", "odm"]));
+        let config: crate::conf::config::Config = serde_json::from_value(doc).unwrap();
+        assert_eq!(config.partitions, vec!["my_custom".to_string(), "odm".to_string()]);
+        assert!(config.verbose);
+    }
+
+    #[test]
+    fn refuses_irreversible_and_future_versions() {
+        let mut doc = json!({"format_version": 1, "partitions": ["odm"]});
+        assert!(migrate_to(Document::Config, &mut doc, 0).is_err());
+        assert_eq!(doc["format_version"], json!(1));
+        let mut future = json!({"format_version": 99});
+        assert!(upgrade(Document::State, &mut future).is_err());
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result, anyhow};
+use rustix::mount::{UnmountFlags, unmount};
+use serde::{Deserialize, Serialize};
+use crate::mount::hymofs::RuleOp;
+
+/// Which mount namespaces see the mounts modules are injected with.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NamespaceScope {
+    /// Detach the mounts from every namespace but init's, such as the ones
+    /// zygote gives apps. HymoFS rules are kernel-wide and stay visible.
+    pub global_only: bool,
+    /// How often hymod looks for new namespaces, to detach the mounts from
+    /// or to mount emulated per-app rules into.
+    pub scan_interval_ms: u64,
+}
+
+impl Default for NamespaceScope {
+    fn default() -> Self {
+        Self { global_only: false, scan_interval_ms: 5000 }
+    }
+}
+
+/// The mount namespace of `pid`, by the inode of its `ns/mnt` link.
+pub fn namespace_of(pid: u32) -> Result<u64> {
+    let link = format!("/proc/{}/ns/mnt", pid);
+    Ok(fs::metadata(&link).with_context(|| format!("Cannot read {}", link))?.ino())
+}
+
+/// One process in every mount namespace other than init's, the lowest pid
+/// of each.
+pub fn foreign_namespaces() -> Result<BTreeMap<u64, u32>> {
+    let global = namespace_of(1)?;
+    let mut found = BTreeMap::new();
+    for entry in fs::read_dir("/proc").context("Cannot list /proc")?.flatten() {
+        let Some(pid) = entry.file_name().to_str().and_then(|name| name.parse::<u32>().ok()) else { continue };
+        match namespace_of(pid) {
+            Ok(ns) if ns != global => {
+                found.entry(ns).and_modify(|lowest: &mut u32| *lowest = (*lowest).min(pid)).or_insert(pid);
+            }
+            _ => {}
+        }
+    }
+    Ok(found)
+}
+
+/// Runs `f` on a thread that joined the mount namespace of `pid`; the rest
+/// of the process stays where it is.
+pub fn enter<T, F>(pid: u32, f: F) -> Result<T>
+where
+    T: Send,
+    F: FnOnce() -> Result<T> + Send,
+{
+    let link = format!("/proc/{}/ns/mnt", pid);
+    let ns = File::open(&link).with_context(|| format!("Cannot open {}", link))?;
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            // setns refuses a thread that shares its root and cwd with others.
+            if unsafe { libc::unshare(libc::CLONE_FS) } != 0 {
+                return Err(std::io::Error::last_os_error()).context("unshare(CLONE_FS) failed");
+            }
+            if unsafe { libc::setns(ns.as_raw_fd(), libc::CLONE_NEWNS) } != 0 {
+                return Err(std::io::Error::last_os_error()).with_context(|| format!("Cannot enter the mount namespace of {}", pid));
+            }
+            f()
+        })
+        .join()
+        .unwrap_or_else(|_| Err(anyhow!("Task in the mount namespace of {} panicked", pid)))
+    })
+}
+
+/// Whether a rule shows at its path.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Visibility {
+    pub path: String,
+    pub visible: bool,
+}
+
+/// Whether the effect of `op` shows in the current namespace: an add
+/// resolves to its target, a hide makes its path vanish.
+pub fn visible(op: &RuleOp) -> Option<bool> {
+    match op {
+        RuleOp::Add { src, target, .. } => Some(resolves_to(Path::new(src), Path::new(target))),
+        RuleOp::Hide { path } => Some(fs::symlink_metadata(path).is_err()),
+        RuleOp::Delete { .. } => None,
+    }
+}
+
+/// Whether `path` is `target`, or a file of the same content as overlay
+/// and bind mounts leave it.
+fn resolves_to(path: &Path, target: &Path) -> bool {
+    let (Ok(seen), Ok(expected)) = (fs::symlink_metadata(path), fs::symlink_metadata(target)) else {
+        return false;
+    };
+    if (seen.dev(), seen.ino()) == (expected.dev(), expected.ino()) {
+        return true;
+    }
+    if seen.is_dir() && expected.is_dir() {
+        return true;
+    }
+    if seen.file_type().is_symlink() && expected.file_type().is_symlink() {
+        return fs::read_link(path).ok() == fs::read_link(target).ok();
+    }
+    seen.is_file() && expected.is_file() && seen.len() == expected.len() && fs::read(path).ok() == fs::read(target).ok()
+}
+
+/// How `ops` show in the mount namespace of `pid`.
+pub fn verify(pid: u32, ops: &[RuleOp]) -> Result<Vec<Visibility>> {
+    enter(pid, || {
+        Ok(ops.iter()
+            .filter_map(|op| visible(op).map(|visible| Visibility { path: op.path().to_string(), visible }))
+            .collect())
+    })
+}
+
+/// Mount points in `mountinfo` whose source is `source`, deepest first so
+/// each goes before the ones it is mounted on. A point mounted over several
+/// times is listed once per mount.
+pub fn owned_mounts(mountinfo: &str, source: &str) -> Vec<PathBuf> {
+    let mut points: Vec<PathBuf> = mountinfo.lines()
+        .filter_map(|line| {
+            let (left, right) = line.split_once(" - ")?;
+            let point = left.split_whitespace().nth(4)?;
+            let mounted_from = right.split_whitespace().nth(1)?;
+            (mounted_from == source).then(|| PathBuf::from(point.replace("\\040", " ")))
+        })
+        .collect();
+    points.sort_by_key(|point| std::cmp::Reverse(point.components().count()));
+    points
+}
+
+/// Detaches the mounts of `source` in the namespace of the calling thread.
+pub fn detach_owned(source: &str) -> Result<usize> {
+    let mountinfo = fs::read_to_string("/proc/thread-self/mountinfo").context("Cannot read mountinfo")?;
+    Ok(owned_mounts(&mountinfo, source).iter().filter(|point| unmount(*point, UnmountFlags::DETACH).is_ok()).count())
+}
+
+/// Detaches the mounts of `source` from every namespace but init's and
+/// returns how many went.
+pub fn isolate(source: &str) -> Result<usize> {
+    let mut detached = 0;
+    for (ns, pid) in foreign_namespaces()? {
+        match enter(pid, || detach_owned(source)) {
+            Ok(count) => detached += count,
+            Err(e) => log::debug!("Cannot isolate mount namespace {} of {}: {:#}", ns, pid, e),
+        }
+    }
+    Ok(detached)
+}
+
+/// Keeps detaching the mounts of `source` from namespaces as they appear;
+/// never returns.
+pub fn keep_isolated(scope: &NamespaceScope, source: &str) {
+    loop {
+        match isolate(source) {
+            Ok(0) => {}
+            Ok(count) => log::info!(">> Detached {} mounts from app mount namespaces", count),
+            Err(e) => log::warn!("!! Mount namespace scan failed: {:#}", e),
+        }
+        std::thread::sleep(std::time::Duration::from_millis(scope.scan_interval_ms.max(100)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mount::hymofs::HymoFileType;
+
+    #[test]
+    fn picks_own_mounts_deepest_first() {
+        let mountinfo = "\
+22 1 259:3 / / ro,relatime shared:1 - ext4 /dev/block/dm-0 ro
+40 22 0:35 / /system/etc rw shared:12 - overlay KSU rw,lowerdir=/a
+41 40 0:36 / /system/etc/my\\040dir rw - tmpfs KSU rw
+42 22 0:37 / /vendor rw - tmpfs tmpfs rw
+";
+        assert_eq!(owned_mounts(mountinfo, "KSU"), [PathBuf::from("/system/etc/my dir"), PathBuf::from("/system/etc")]);
+        assert!(owned_mounts(mountinfo, "magisk").is_empty());
+    }
+
+    #[test]
+    fn checks_how_rules_show() {
+        let dir = std::env::temp_dir().join(format!("mntns-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for (name, content) in [("a", "one"), ("b", "one"), ("c", "two")] {
+            fs::write(dir.join(name), content).unwrap();
+        }
+        let at = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        let add = |src: &str, target: &str| RuleOp::Add { src: at(src), target: at(target), file_type: HymoFileType::Reg };
+        let ops = [add("a", "b"), add("a", "c"), RuleOp::Hide { path: at("gone") }, RuleOp::Hide { path: at("a") }, RuleOp::Delete { src: at("a") }];
+        let seen: Vec<Option<bool>> = ops.iter().map(visible).collect();
+        assert_eq!(seen, [Some(true), Some(false), Some(true), Some(false), None]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
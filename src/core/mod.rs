@@ -1,7 +1,71 @@
+// apk, executor, guards and planner run during post-fs-data, where a panic can hang
+// the boot; clippy rejects panicking constructs in them (see also hymofs).
+#[cfg_attr(not(test), deny(clippy::unwrap_used, clippy::expect_used, clippy::panic, clippy::indexing_slicing))]
+pub mod apk;
+pub mod apps;
+pub mod appscope;
+pub mod arch;
+pub mod batch;
+pub mod battery;
+pub mod bootguard;
+pub mod bootreason;
+pub mod bootstage;
+pub mod budget;
+pub mod certify;
+pub mod compact;
+pub mod conditions;
+pub mod crash;
+pub mod daemon;
+pub mod direct;
+pub mod disable;
+pub mod early_readers;
+#[cfg_attr(not(test), deny(clippy::unwrap_used, clippy::expect_used, clippy::panic, clippy::indexing_slicing))]
 pub mod executor;
+pub mod explain;
+#[cfg_attr(not(test), deny(clippy::unwrap_used, clippy::expect_used, clippy::panic, clippy::indexing_slicing))]
+pub mod guards;
+pub mod health;
+pub mod hymoctl;
+pub mod history;
+pub mod install;
+pub mod integrity;
 pub mod inventory;
+pub mod journal;
+pub mod linker;
+pub mod manager;
+pub mod memory;
+pub mod metrics;
+pub mod migrate;
+pub mod mntns;
+pub mod namespace;
+pub mod ordering;
+pub mod partitions;
+#[cfg_attr(not(test), deny(clippy::unwrap_used, clippy::expect_used, clippy::panic, clippy::indexing_slicing))]
 pub mod planner;
+pub mod preview;
+pub mod profiles;
+pub mod protect;
+pub mod remote;
+pub mod report;
+pub mod rewrite;
+pub mod roots;
+pub mod rules;
+pub mod ruleset;
+pub mod scripts;
+pub mod selfcheck;
+pub mod selinux;
+pub mod sepolicy;
+pub mod services;
+pub mod staging;
 pub mod state;
+pub mod stealth;
 pub mod storage;
 pub mod modules;
-pub mod sync;
\ No newline at end of file
+pub mod symlinks;
+pub mod sync;
+pub mod transaction;
+pub mod uninstall;
+pub mod verity;
+pub mod wait;
+pub mod wal;
+pub mod watcher;
\ No newline at end of file
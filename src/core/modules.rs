@@ -1,12 +1,14 @@
 use std::fs::{self, OpenOptions};
 use std::io::{BufRead, BufReader, Write};
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
 use std::os::unix::fs::{FileTypeExt, MetadataExt};
 use std::path::{Path, PathBuf};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use anyhow::Result;
 use serde::Serialize;
 use crate::conf::config::Config;
-use crate::core::inventory;
+use crate::core::{crash, disable::{self, DisableReason}, inventory, roots};
 use crate::defs;
 use crate::core::state::RuntimeState;
 #[derive(Serialize)]
@@ -19,6 +21,10 @@ struct ModuleInfo {
     mode: String,
     is_mounted: bool,
     rules: inventory::ModuleRules,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    crash_correlations: BTreeMap<String, u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    disabled: Option<DisableReason>,
 }
 pub struct ModuleFile {
     pub relative_path: PathBuf,
@@ -33,18 +39,18 @@ impl ModuleFile {
         let real_path = root.join(relative);
         let metadata = fs::symlink_metadata(&real_path)?;
         let file_type = metadata.file_type();
+        let file_name = real_path.file_name().map(|n| n.as_bytes()).unwrap_or_default();
         let is_whiteout = if file_type.is_char_device() {
             metadata.rdev() == 0
         } else {
-            false
+            whiteout_target(file_name).is_some()
         };
         let is_replace = if file_type.is_dir() {
-            real_path.join(defs::REPLACE_DIR_FILE_NAME).exists()
+            is_opaque_dir(&real_path)
         } else {
             false
         };
-        let file_name = real_path.file_name().and_then(|n| n.to_str()).unwrap_or("");
-        let is_replace_file = file_name == defs::REPLACE_DIR_FILE_NAME;
+        let is_replace_file = is_marker(file_name);
         Ok(Self {
             relative_path: relative.to_path_buf(),
             real_path,
@@ -55,17 +61,56 @@ impl ModuleFile {
         })
     }
 }
+/// The name a `.wh.<name>` whiteout hides; `None` for other names and for
+/// the opaque marker.
+pub fn whiteout_target(name: &[u8]) -> Option<&[u8]> {
+    name.strip_prefix(defs::WHITEOUT_PREFIX.as_bytes())
+        .filter(|target| !target.is_empty() && name != defs::OPAQUE_MARKER_FILE_NAME.as_bytes())
+}
+
+/// Files that only mark their directory as replacing the target one.
+pub fn is_marker(name: &[u8]) -> bool {
+    name == defs::REPLACE_DIR_FILE_NAME.as_bytes() || name == defs::OPAQUE_MARKER_FILE_NAME.as_bytes()
+}
+
+/// Whether `dir` hides the directory it is mounted over instead of merging
+/// into it: it holds `.replace` or the aufs opaque marker, or carries the
+/// overlayfs opaque xattr.
+pub fn is_opaque_dir(dir: &Path) -> bool {
+    dir.join(defs::REPLACE_DIR_FILE_NAME).exists()
+        || dir.join(defs::OPAQUE_MARKER_FILE_NAME).exists()
+        || extattr::lgetxattr(dir, defs::REPLACE_DIR_XATTR).is_ok_and(|value| value == b"y")
+}
+
+/// `relative` with a whiteout's last component renamed to what it hides.
+pub fn visible_path(relative: &Path) -> PathBuf {
+    match relative.file_name().and_then(|name| whiteout_target(name.as_bytes())) {
+        Some(target) => relative.with_file_name(OsStr::from_bytes(target)),
+        None => relative.to_path_buf(),
+    }
+}
+
 pub fn print_list(config: &Config) -> Result<()> {
-    let modules = inventory::scan(&config.moduledir, config)?;
+    let modules = inventory::scan_roots(config)?;
     let state = RuntimeState::load().unwrap_or_default();
     let mut mounted_ids = HashSet::new();
     mounted_ids.extend(state.overlay_modules);
     mounted_ids.extend(state.magic_modules);
     mounted_ids.extend(state.hymo_modules);
+    mounted_ids.extend(state.direct_modules);
+    let mut crash_stats = crash::load_stats();
+    let mut reasons = disable::current(Path::new(defs::DISABLE_REASONS_FILE), &roots::active(config));
+    // The scan skips disabled modules; list the ones meta-hybrid turned off
+    // itself so the manager can say why they stopped mounting.
+    let disabled = reasons.keys()
+        .map(|id| {
+            let source_path = roots::locate(config, id);
+            inventory::Module { id: id.clone(), rules: inventory::ModuleRules::load(&source_path, id), source_path }
+        })
+        .collect::<Vec<_>>();
     let mut infos = Vec::new();
-    for m in modules {
-        let prop_path = m.source_path.join("module.prop");
-        let (name, version, author, description) = read_module_prop(&prop_path);
+    for m in modules.into_iter().chain(disabled) {
+        let prop = ModuleProp::load(&m.source_path.join("module.prop"));
         let mode_str = match m.rules.default_mode {
             inventory::MountMode::Overlay => "auto",
             inventory::MountMode::HymoFs => "hymofs",
@@ -74,41 +119,81 @@ pub fn print_list(config: &Config) -> Result<()> {
         };
         infos.push(ModuleInfo {
             id: m.id.clone(),
-            name,
-            version,
-            author,
-            description,
+            name: prop.name,
+            version: prop.version,
+            author: prop.author,
+            description: prop.description,
             mode: mode_str.to_string(),
             is_mounted: mounted_ids.contains(&m.id),
+            crash_correlations: crash_stats.remove(&m.id).map(|s| s.crash_correlations).unwrap_or_default(),
+            disabled: reasons.remove(&m.id),
             rules: m.rules,
         });
     }
     println!("{}", serde_json::to_string(&infos)?);
     Ok(())
 }
-fn read_module_prop(path: &Path) -> (String, String, String, String) {
-    let mut name = String::new();
-    let mut version = String::new();
-    let mut author = String::new();
-    let mut description = String::new();
-    if let Ok(file) = fs::File::open(path) {
-        let reader = BufReader::new(file);
-        for line in reader.lines() {
-            if let Ok(l) = line {
-                if let Some((k, v)) = l.split_once('=') {
-                    let val = v.trim().to_string();
-                    match k.trim() {
-                        "name" => name = val,
-                        "version" => version = val,
-                        "author" => author = val,
-                        "description" => description = val,
-                        _ => {}
-                    }
+/// The metadata a module declares in its `module.prop`. Keys other than the
+/// standard ones are kept in `extra`, e.g. `updateJson`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ModuleProp {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    #[serde(rename = "versionCode", skip_serializing_if = "Option::is_none")]
+    pub version_code: Option<i64>,
+    pub author: String,
+    pub description: String,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra: BTreeMap<String, String>,
+}
+impl ModuleProp {
+    /// Reads `key=value` lines; blank lines and `#` comments are skipped and
+    /// a key given twice keeps its last value. A `versionCode` that is not
+    /// a number stays in `extra`.
+    pub fn parse(content: &str) -> Self {
+        let mut prop = Self::default();
+        for line in content.lines().map(str::trim) {
+            if line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else { continue };
+            let value = value.trim().to_string();
+            match key.trim() {
+                "id" => prop.id = value,
+                "name" => prop.name = value,
+                "version" => prop.version = value,
+                "author" => prop.author = value,
+                "description" => prop.description = value,
+                "versionCode" if value.parse::<i64>().is_ok() => {
+                    prop.version_code = value.parse().ok();
+                    prop.extra.remove("versionCode");
+                }
+                "" => {}
+                key => {
+                    prop.extra.insert(key.to_string(), value);
                 }
             }
         }
+        prop
+    }
+    /// The module.prop at `path`, empty when it cannot be read.
+    pub fn load(path: &Path) -> Self {
+        fs::read_to_string(path).map(|content| Self::parse(&content)).unwrap_or_default()
+    }
+    /// Value of `key` as written, standard or custom.
+    pub fn get(&self, key: &str) -> Option<String> {
+        let standard = match key {
+            "id" => &self.id,
+            "name" => &self.name,
+            "version" => &self.version,
+            "author" => &self.author,
+            "description" => &self.description,
+            "versionCode" if self.version_code.is_some() => return self.version_code.map(|code| code.to_string()),
+            _ => return self.extra.get(key).cloned(),
+        };
+        Some(standard.clone())
     }
-    (name, version, author, description)
 }
 pub fn update_description(
     storage_mode: &str, 
@@ -131,13 +216,11 @@ pub fn update_description(
     let mut lines = Vec::new();
     if let Ok(file) = fs::File::open(prop_path) {
         let reader = BufReader::new(file);
-        for line in reader.lines() {
-            if let Ok(l) = line {
-                if l.starts_with("description=") {
-                    lines.push(desc_text.clone());
-                } else {
-                    lines.push(l);
-                }
+        for l in reader.lines().map_while(Result::ok) {
+            if l.starts_with("description=") {
+                lines.push(desc_text.clone());
+            } else {
+                lines.push(l);
             }
         }
     }
@@ -147,3 +230,41 @@ pub fn update_description(
         }
     }
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_standard_and_custom_keys() {
+        let prop = ModuleProp::parse("\
+# comment
+id=demo
+name=Demo Module
+version=v1.2 (beta)
+versionCode=120
+author=someone
+description=Does a = b things
+updateJson=https://example.com/update.json
+version=v1.3
+");
+        assert_eq!((prop.id.as_str(), prop.name.as_str(), prop.version.as_str()), ("demo", "Demo Module", "v1.3"));
+        assert_eq!(prop.version_code, Some(120));
+        assert_eq!(prop.description, "Does a = b things");
+        assert_eq!(prop.get("updateJson").as_deref(), Some("https://example.com/update.json"));
+        assert_eq!(prop.get("versionCode").as_deref(), Some("120"));
+        assert_eq!(prop.get("missing"), None);
+
+        let odd = ModuleProp::parse("versionCode=twelve\n");
+        assert_eq!((odd.version_code, odd.get("versionCode").as_deref()), (None, Some("twelve")));
+    }
+
+    #[test]
+    fn recognises_whiteouts_and_markers() {
+        assert_eq!(whiteout_target(b".wh.foo.apk"), Some(&b"foo.apk"[..]));
+        assert_eq!(whiteout_target(b".wh..wh..opq"), None);
+        assert_eq!(whiteout_target(b".wh."), None);
+        assert!(is_marker(b".replace") && is_marker(b".wh..wh..opq") && !is_marker(b"replace"));
+        assert_eq!(visible_path(Path::new("system/app/.wh.Foo")), Path::new("system/app/Foo"));
+        assert_eq!(visible_path(Path::new("system/.wh.x/y")), Path::new("system/.wh.x/y"));
+    }
+}
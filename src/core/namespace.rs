@@ -0,0 +1,159 @@
+use anyhow::{Result, bail};
+use crate::{core::{journal::{self, RuleJournal}, rules::{RuleLedger, EXTERNAL_GROUP}}, mount::hymofs::{HymoFs, RuleOp}};
+
+/// Owner names other consumers cannot take, since they would read as this
+/// crate's own groups.
+const RESERVED_OWNERS: &[&str] = &["meta-hybrid", EXTERNAL_GROUP];
+
+/// Owner names are short lowercase identifiers, e.g. `lsposed` or `my-daemon`.
+pub fn validate_owner(owner: &str) -> Result<()> {
+    let valid = !owner.is_empty()
+        && owner.len() <= 64
+        && owner.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || matches!(b, b'-' | b'_' | b'.'));
+    if !valid {
+        bail!("Invalid namespace {:?}: use 1-64 lowercase letters, digits, '-', '_' or '.'", owner);
+    }
+    if RESERVED_OWNERS.contains(&owner) {
+        bail!("Namespace {:?} is reserved", owner);
+    }
+    Ok(())
+}
+
+/// Who other than `owner` holds `path`: a module of this crate, an adopted
+/// external rule or another namespace.
+fn holder(ledger: &RuleLedger, owner: &str, path: &str) -> Option<String> {
+    if let Some(module_id) = ledger.owned.owner(path) {
+        return Some(format!("module {}", module_id));
+    }
+    if ledger.is_external(path) {
+        return Some("an adopted external rule".to_string());
+    }
+    ledger.namespace_of(path).filter(|ns| *ns != owner).map(|ns| format!("namespace {}", ns))
+}
+
+/// Records `ops` under `owner`, replacing its earlier rules on the same
+/// paths. Paths held by anyone else are refused as a whole, so one consumer
+/// can never take over, and later delete, another one's rule.
+pub fn record(ledger: &mut RuleLedger, owner: &str, ops: &[RuleOp]) -> Result<()> {
+    validate_owner(owner)?;
+    if let Some((op, other)) = ops.iter().find_map(|op| holder(ledger, owner, op.path()).map(|h| (op, h))) {
+        bail!("{} is held by {}", op.path(), other);
+    }
+    let rules = ledger.namespaces.entry(owner.to_string()).or_default();
+    rules.retain(|existing| !ops.iter().any(|op| op.path() == existing.path()));
+    rules.extend(ops.iter().cloned());
+    Ok(())
+}
+
+/// Applies `ops` for `owner` once the ledger accepted them; rules the kernel
+/// rejects are not recorded. Fails when it rejects all of them. Each rule
+/// goes through `journal`, which the caller finishes after saving `ledger`.
+pub fn add(handle: &HymoFs, ledger: &mut RuleLedger, owner: &str, ops: &[RuleOp], journal: &mut Option<RuleJournal>) -> Result<usize> {
+    record(ledger, owner, ops)?;
+    let mut applied = 0;
+    let mut last_error = None;
+    for op in ops {
+        match journal::apply(handle, journal, op) {
+            Ok(()) => applied += 1,
+            Err(e) => {
+                forget(ledger, owner, &[op.path().to_string()]);
+                log::warn!("!! Namespace {}: {}: {:#}", owner, op.path(), e);
+                last_error = Some(e);
+            }
+        }
+    }
+    match last_error {
+        Some(e) if applied == 0 => Err(anyhow::Error::from(e).context(format!("HymoFS rejected the rules of namespace {}", owner))),
+        _ => Ok(applied),
+    }
+}
+
+/// Drops `paths` from `owner`'s record and returns the rules it held there.
+pub fn forget(ledger: &mut RuleLedger, owner: &str, paths: &[String]) -> Vec<RuleOp> {
+    let Some(rules) = ledger.namespaces.get_mut(owner) else {
+        return Vec::new();
+    };
+    let (dropped, kept): (Vec<_>, Vec<_>) = std::mem::take(rules).into_iter()
+        .partition(|op| paths.iter().any(|p| p == op.path()));
+    *rules = kept;
+    if rules.is_empty() {
+        ledger.namespaces.remove(owner);
+    }
+    for op in &dropped {
+        ledger.packages.remove(op.path());
+    }
+    dropped
+}
+
+/// Deletes `owner`'s rules on `paths`. Paths it does not hold are left
+/// alone, whoever owns them.
+pub fn remove(handle: &HymoFs, ledger: &mut RuleLedger, owner: &str, paths: &[String]) -> usize {
+    let dropped = forget(ledger, owner, paths);
+    for op in &dropped {
+        if let Err(e) = handle.delete_rule(op.path()) {
+            log::warn!("!! Namespace {}: failed to delete {}: {:#}", owner, op.path(), e);
+        }
+    }
+    dropped.len()
+}
+
+/// Deletes every rule `owner` holds and nothing else.
+pub fn clear(handle: &HymoFs, ledger: &mut RuleLedger, owner: &str) -> usize {
+    let paths: Vec<String> = list(ledger, owner, None).iter().map(|op| op.path().to_string()).collect();
+    remove(handle, ledger, owner, &paths)
+}
+
+/// `owner`'s recorded rules, limited to the ones still in the kernel when
+/// its rule list is at hand.
+pub fn list(ledger: &RuleLedger, owner: &str, live: Option<&[RuleOp]>) -> Vec<RuleOp> {
+    ledger.namespaces.get(owner).into_iter().flatten()
+        .filter(|op| live.is_none_or(|live| live.iter().any(|l| l.path() == op.path())))
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mount::hymofs::HymoFileType;
+
+    fn add_op(src: &str, target: &str) -> RuleOp {
+        RuleOp::Add { src: src.into(), target: target.into(), file_type: HymoFileType::Reg }
+    }
+
+    #[test]
+    fn validates_owner_names() {
+        assert!(validate_owner("my-daemon.v2").is_ok());
+        assert!(validate_owner("").is_err());
+        assert!(validate_owner("Tool").is_err());
+        assert!(validate_owner("a/b").is_err());
+        assert!(validate_owner(EXTERNAL_GROUP).is_err());
+    }
+
+    #[test]
+    fn namespaces_cannot_take_each_others_paths() {
+        let mut ledger = RuleLedger::default();
+        ledger.claim([("mod_a", &RuleOp::Hide { path: "/system/app/A".into() })]);
+        record(&mut ledger, "one", &[add_op("/system/bin/x", "/data/one/x")]).unwrap();
+        assert!(record(&mut ledger, "two", &[add_op("/system/bin/y", "/data/two/y"), add_op("/system/bin/x", "/data/two/x")]).is_err());
+        assert!(record(&mut ledger, "two", &[RuleOp::Hide { path: "/system/app/A".into() }]).is_err());
+        assert!(!ledger.namespaces.contains_key("two"));
+
+        record(&mut ledger, "one", &[add_op("/system/bin/x", "/data/one/x2")]).unwrap();
+        assert_eq!(list(&ledger, "one", None), vec![add_op("/system/bin/x", "/data/one/x2")]);
+    }
+
+    #[test]
+    fn forgets_only_the_owners_paths() {
+        let mut ledger = RuleLedger::default();
+        record(&mut ledger, "one", &[add_op("/system/bin/x", "/data/one/x"), add_op("/system/bin/z", "/data/one/z")]).unwrap();
+        record(&mut ledger, "two", &[add_op("/system/bin/y", "/data/two/y")]).unwrap();
+        assert!(forget(&mut ledger, "two", &["/system/bin/x".into()]).is_empty());
+        assert_eq!(forget(&mut ledger, "one", &["/system/bin/x".into()]).len(), 1);
+        let live = vec![add_op("/system/bin/y", "/data/two/y")];
+        assert!(list(&ledger, "one", Some(&live)).is_empty());
+        assert_eq!(list(&ledger, "two", Some(&live)).len(), 1);
+        forget(&mut ledger, "one", &["/system/bin/z".into()]);
+        assert_eq!(ledger.namespaces.keys().collect::<Vec<_>>(), vec!["two"]);
+    }
+}
@@ -0,0 +1,53 @@
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::hash::BuildHasher;
+
+/// Hasher of the maps and sets a plan is built from. Deterministic mode
+/// uses fixed keys instead of ones drawn from the OS, so the same inputs
+/// always iterate in the same order.
+#[derive(Debug, Clone)]
+pub enum PlanHasher {
+    Random(RandomState),
+    Fixed,
+}
+
+impl PlanHasher {
+    pub fn new(deterministic: bool) -> Self {
+        if deterministic { Self::Fixed } else { Self::Random(RandomState::new()) }
+    }
+
+    pub fn map<K, V>(&self) -> HashMap<K, V, Self> {
+        HashMap::with_hasher(self.clone())
+    }
+
+    pub fn set<T>(&self) -> HashSet<T, Self> {
+        HashSet::with_hasher(self.clone())
+    }
+}
+
+impl BuildHasher for PlanHasher {
+    type Hasher = DefaultHasher;
+
+    fn build_hasher(&self) -> DefaultHasher {
+        match self {
+            Self::Random(state) => state.build_hasher(),
+            Self::Fixed => DefaultHasher::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_hasher_iterates_alike_every_time() {
+        let order = || {
+            let mut set = PlanHasher::new(true).set();
+            set.extend((0..64).map(|i| format!("module-{}", i)));
+            set.into_iter().collect::<Vec<_>>()
+        };
+        assert_eq!(order(), order());
+        assert_eq!(PlanHasher::new(true).hash_one("a"), PlanHasher::new(true).hash_one("a"));
+    }
+}
@@ -0,0 +1,83 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use crate::{conf::config::Config, defs};
+
+/// How the subdirectory of the same name in every module is mounted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PartitionMount {
+    pub enabled: bool,
+    /// Where the subdirectory is merged; `/<name>` when unset. Relative
+    /// paths are taken from `/`.
+    pub target: Option<PathBuf>,
+}
+
+impl Default for PartitionMount {
+    fn default() -> Self {
+        Self { enabled: true, target: None }
+    }
+}
+
+/// Every module subdirectory that names a partition: the builtin ones,
+/// `partitions` and the ones given a `partition_mounts` entry.
+pub fn known(config: &Config) -> Vec<&str> {
+    let mut names: Vec<&str> = Vec::new();
+    let configured = defs::BUILTIN_PARTITIONS.iter().copied()
+        .chain(config.partitions.iter().map(String::as_str))
+        .chain(config.partition_mounts.keys().map(String::as_str));
+    for name in configured {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+pub fn is_enabled(config: &Config, name: &str) -> bool {
+    config.partition_mounts.get(name).is_none_or(|mount| mount.enabled)
+}
+
+/// The partitions modules are mounted into, in [`known`] order.
+pub fn enabled(config: &Config) -> Vec<&str> {
+    known(config).into_iter().filter(|name| is_enabled(config, name)).collect()
+}
+
+/// Where the `name` subdirectory of a module is merged.
+pub fn target(config: &Config, name: &str) -> PathBuf {
+    let root = PathBuf::from("/");
+    match config.partition_mounts.get(name).and_then(|mount| mount.target.as_ref()) {
+        Some(target) => root.join(target),
+        None => root.join(name),
+    }
+}
+
+/// Subdirectories magic mount leaves out of every module: it only knows the
+/// stock layout, so besides the disabled partitions this holds the ones
+/// moved to another target.
+pub fn magic_exclusions(config: &Config) -> HashSet<String> {
+    known(config).into_iter()
+        .filter(|name| !is_enabled(config, name) || target(config, name) != PathBuf::from("/").join(name))
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_partitions_to_targets() {
+        let mut config = Config { partitions: vec!["my_custom".into(), "odm".into()], ..Config::default() };
+        config.partition_mounts.insert("oem".into(), PartitionMount { enabled: false, target: None });
+        config.partition_mounts.insert("my_custom".into(), PartitionMount { enabled: true, target: Some("mnt/vendor/my_custom".into()) });
+        config.partition_mounts.insert("my_bigball".into(), PartitionMount::default());
+        let enabled = enabled(&config);
+        assert_eq!(enabled, ["system", "vendor", "product", "system_ext", "odm", "apex", "my_custom", "my_bigball"]);
+        assert_eq!(target(&config, "vendor"), PathBuf::from("/vendor"));
+        assert_eq!(target(&config, "my_custom"), PathBuf::from("/mnt/vendor/my_custom"));
+        let mut excluded: Vec<String> = magic_exclusions(&config).into_iter().collect();
+        excluded.sort();
+        assert_eq!(excluded, ["my_custom", "oem"]);
+    }
+}
@@ -1,10 +1,10 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
 use anyhow::Result;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use walkdir::WalkDir;
-use crate::{conf::config, defs, core::inventory::{Module, MountMode}};
+use crate::{conf::config, core::{bootstage, direct::{self, WritablePolicy}, guards::{self, HashGuards}, inventory::{BootWindow, Module, MountMode}, ordering::PlanHasher, partitions, rewrite::{self, PathRewrite}}, mount::hymofs::{self, RuleOp, RulePriorities}};
 
 #[derive(Debug, Clone)]
 pub struct OverlayOperation {
@@ -13,28 +13,68 @@ pub struct OverlayOperation {
     pub lowerdirs: Vec<PathBuf>,
 }
 
+/// Backend that takes over HymoFS operations on a boot without a usable
+/// HymoFS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HymoFallback {
+    #[default]
+    Magic,
+    /// Stack each module partition as an overlayfs lower layer above the
+    /// overlay modules; partitions with path rewrites still use Magic Mount.
+    Overlay,
+}
+
 #[derive(Debug, Clone)]
 pub struct HymoOperation {
     pub module_id: String,
     pub source: PathBuf,
     pub target: PathBuf,
+    pub window: BootWindow,
+}
+
+#[derive(Debug, Clone)]
+pub struct DirectOperation {
+    pub module_id: String,
+    pub source: PathBuf,
+    pub target: PathBuf,
 }
 
 #[derive(Debug, Default)]
 pub struct MountPlan {
     pub overlay_ops: Vec<OverlayOperation>,
     pub hymo_ops: Vec<HymoOperation>,
+    pub direct_ops: Vec<DirectOperation>,
     pub magic_module_paths: Vec<PathBuf>,
     pub overlay_module_ids: Vec<String>,
     pub hymo_module_ids: Vec<String>,
+    pub direct_module_ids: Vec<String>,
     pub magic_module_ids: Vec<String>,
+    pub rejected: Vec<String>,
+    /// Hash conditions of the modules injected through HymoFS, by module id.
+    pub hash_guards: BTreeMap<String, HashGuards>,
+    /// Path rewrites in effect on this device, applied to every HymoFS rule.
+    pub rewrites: Vec<PathRewrite>,
+}
+
+/// One module offering a file, with the backend that would serve it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Candidate {
+    pub module_id: String,
+    pub backend: &'static str,
+    pub source: PathBuf,
 }
 
 #[derive(Debug, Clone, Serialize)]
 pub struct ConflictEntry {
     pub partition: String,
     pub relative_path: String,
+    /// Modules providing the file, winner first.
     pub contending_modules: Vec<String>,
+    pub winner: String,
+    /// Every candidate in precedence order: the one that serves the file,
+    /// then the ones it shadows.
+    pub provenance: Vec<Candidate>,
 }
 
 #[derive(Debug, Default)]
@@ -42,38 +82,70 @@ pub struct ConflictReport {
     pub details: Vec<ConflictEntry>,
 }
 
+fn collect_files(root: &Path, mut found: impl FnMut(String, PathBuf)) {
+    for entry in WalkDir::new(root).min_depth(1).into_iter().flatten() {
+        if !entry.file_type().is_file() { continue; }
+        if let Ok(rel) = entry.path().strip_prefix(root) {
+            found(rel.to_string_lossy().to_string(), entry.path().to_path_buf());
+        }
+    }
+}
+
 impl MountPlan {
+    /// The HymoFS rules of `op`, at their rewritten paths.
+    pub fn rules_of(&self, op: &HymoOperation) -> Vec<RuleOp> {
+        let mut rules = hymofs::collect_directory_ops(&op.target, &op.source);
+        rewrite::apply(&mut rules, &self.rewrites);
+        rules
+    }
+
+    /// Kernel priorities that keep later HymoFS operations winning over
+    /// earlier ones whatever order their rules reach the kernel in.
+    pub fn hymo_priorities(&self) -> RulePriorities {
+        let mut priorities = RulePriorities::default();
+        for (i, op) in self.hymo_ops.iter().enumerate() {
+            priorities.insert(op.source.clone(), i32::try_from(i + 1).unwrap_or(i32::MAX));
+        }
+        priorities
+    }
+
+    /// Files that more than one module provides. HymoFS redirects win over
+    /// the overlays, later HymoFS rules replace earlier ones, and higher
+    /// overlay layers hide lower ones.
     pub fn analyze_conflicts(&self) -> ConflictReport {
-        let mut conflicts = Vec::new();
+        let mut files: HashMap<(String, String), Vec<Candidate>> = HashMap::new();
+        for op in self.hymo_ops.iter().rev() {
+            let Some(partition) = op.target.file_name().map(|n| n.to_string_lossy().to_string()) else { continue };
+            collect_files(&op.source, |rel, source| {
+                files.entry((partition.clone(), rel)).or_default()
+                    .push(Candidate { module_id: op.module_id.clone(), backend: "hymofs", source });
+            });
+        }
         for op in &self.overlay_ops {
-            let mut file_map: HashMap<String, Vec<String>> = HashMap::new();
             for layer_path in &op.lowerdirs {
                 let module_id = layer_path.parent()
                     .and_then(|p| p.file_name())
                     .map(|s| s.to_string_lossy().to_string())
                     .unwrap_or_else(|| "UNKNOWN".into());
-                
-                for entry in WalkDir::new(layer_path).min_depth(1) {
-                    if let Ok(entry) = entry {
-                        if !entry.file_type().is_file() { continue; }
-                        if let Ok(rel) = entry.path().strip_prefix(layer_path) {
-                            let rel_str = rel.to_string_lossy().to_string();
-                            file_map.entry(rel_str).or_default().push(module_id.clone());
-                        }
-                    }
-                }
+                collect_files(layer_path, |rel, source| {
+                    files.entry((op.partition_name.clone(), rel)).or_default()
+                        .push(Candidate { module_id: module_id.clone(), backend: "overlay", source });
+                });
             }
+        }
 
-            for (rel_path, modules) in file_map {
-                if modules.len() > 1 {
-                    conflicts.push(ConflictEntry {
-                        partition: op.partition_name.clone(),
-                        relative_path: rel_path,
-                        contending_modules: modules,
-                    });
+        let mut conflicts: Vec<ConflictEntry> = files.into_iter()
+            .filter_map(|((partition, relative_path), provenance)| {
+                let winner = provenance.first()?.module_id.clone();
+                let mut contending_modules: Vec<String> = Vec::new();
+                for candidate in &provenance {
+                    if !contending_modules.contains(&candidate.module_id) {
+                        contending_modules.push(candidate.module_id.clone());
+                    }
                 }
-            }
-        }
+                (contending_modules.len() > 1).then_some(ConflictEntry { partition, relative_path, contending_modules, winner, provenance })
+            })
+            .collect();
 
         conflicts.sort_by(|a, b| {
             a.partition.cmp(&b.partition)
@@ -83,12 +155,104 @@ impl MountPlan {
         ConflictReport { details: conflicts }
     }
 
+    /// Moves every HymoFS operation to Magic Mount, for a boot on which
+    /// HymoFS cannot be trusted. Returns the ids of the modules moved.
+    pub fn demote_hymo_to_magic(&mut self) -> Vec<String> {
+        for op in std::mem::take(&mut self.hymo_ops) {
+            let Some(root) = op.source.parent().map(Path::to_path_buf) else { continue };
+            if !self.magic_module_paths.contains(&root) {
+                self.magic_module_paths.push(root);
+            }
+        }
+        let moved = std::mem::take(&mut self.hymo_module_ids);
+        self.magic_module_ids.extend(moved.iter().cloned());
+        self.magic_module_ids.sort();
+        self.magic_module_ids.dedup();
+        self.hash_guards.clear();
+        moved
+    }
+
+    /// Moves every overlay layer to Magic Mount, for a kernel without
+    /// overlayfs. Returns the ids of the modules moved.
+    pub fn demote_overlay_to_magic(&mut self) -> Vec<String> {
+        for op in std::mem::take(&mut self.overlay_ops) {
+            for root in op.lowerdirs.iter().filter_map(|layer| layer.parent()) {
+                if !self.magic_module_paths.iter().any(|p| p == root) {
+                    self.magic_module_paths.push(root.to_path_buf());
+                }
+            }
+        }
+        let moved = std::mem::take(&mut self.overlay_module_ids);
+        self.magic_module_ids.extend(moved.iter().cloned());
+        self.magic_module_ids.sort();
+        self.magic_module_ids.dedup();
+        moved
+    }
+
+    /// Moves every HymoFS operation onto an overlayfs layer of its
+    /// partition, above the overlay modules there and in the order HymoFS
+    /// would have let them win. Operations only per-file rules can express,
+    /// or whose partition has no mountable target, go to Magic Mount.
+    /// Returns the ids of the modules that moved to overlayfs.
+    pub fn demote_hymo_to_overlay(&mut self) -> Vec<String> {
+        let mut layered = BTreeSet::new();
+        let mut to_magic = Vec::new();
+        for op in std::mem::take(&mut self.hymo_ops) {
+            let partition = op.target.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+            let rewritten = self.rewrites.iter().any(|r| r.touches(&partition, &op.source));
+            let target = resolve(&op.target);
+            if partition.is_empty() || rewritten || !target.is_dir() {
+                to_magic.push(op);
+                continue;
+            }
+            let index = match self.overlay_ops.iter().position(|o| o.partition_name == partition) {
+                Some(index) => index,
+                None => {
+                    self.overlay_ops.push(OverlayOperation {
+                        partition_name: partition,
+                        target: target.to_string_lossy().into_owned(),
+                        lowerdirs: Vec::new(),
+                    });
+                    self.overlay_ops.len() - 1
+                }
+            };
+            if let Some(overlay) = self.overlay_ops.get_mut(index) {
+                overlay.lowerdirs.insert(0, op.source);
+                layered.insert(op.module_id);
+            }
+        }
+        for op in to_magic {
+            if let Some(root) = op.source.parent().map(Path::to_path_buf) {
+                if !self.magic_module_paths.contains(&root) {
+                    self.magic_module_paths.push(root);
+                }
+            }
+            self.magic_module_ids.push(op.module_id);
+        }
+        let moved: Vec<String> = layered.into_iter().collect();
+        self.overlay_module_ids.extend(moved.iter().cloned());
+        for ids in [&mut self.overlay_module_ids, &mut self.magic_module_ids] {
+            ids.sort();
+            ids.dedup();
+        }
+        self.hymo_module_ids.clear();
+        self.hash_guards.clear();
+        moved
+    }
+
     pub fn print_visuals(&self) {
-        if self.overlay_ops.is_empty() && self.magic_module_paths.is_empty() && self.hymo_ops.is_empty() {
+        if self.overlay_ops.is_empty() && self.magic_module_paths.is_empty() && self.hymo_ops.is_empty() && self.direct_ops.is_empty() {
             log::info!(">> Empty plan. Standby mode.");
             return;
         }
 
+        if !self.direct_ops.is_empty() {
+            log::info!("[Direct Partition Modification]");
+            for op in &self.direct_ops {
+                log::info!("├── [Write] {} -> {}", op.module_id, op.target.display());
+            }
+        }
+
         if !self.hymo_ops.is_empty() {
             log::info!("[HymoFS Injection Protocol]");
             let mut shown_modules = HashSet::new();
@@ -141,15 +305,24 @@ pub fn generate(
     storage_root: &Path
 ) -> Result<MountPlan> {
     let mut plan = MountPlan::default();
-    let mut overlay_groups: HashMap<String, Vec<PathBuf>> = HashMap::new();
-    let mut magic_paths = HashSet::new();
+    let hasher = PlanHasher::new(config.deterministic);
+    let mut overlay_groups: HashMap<String, Vec<PathBuf>, PlanHasher> = hasher.map();
+    let mut magic_paths = hasher.set();
     
-    let mut overlay_ids = HashSet::new();
-    let mut hymo_ids = HashSet::new();
-    let mut magic_ids = HashSet::new();
+    let mut overlay_ids = hasher.set();
+    let mut hymo_ids = hasher.set();
+    let mut magic_ids = hasher.set();
+    let mut direct_ids = hasher.set();
+
+    let target_partitions = partitions::enabled(config);
 
-    let mut target_partitions = defs::BUILTIN_PARTITIONS.to_vec();
-    target_partitions.extend(config.partitions.iter().map(|s| s.as_str()));
+    let policy = config.writable_partition_policy;
+    let writable = match policy {
+        WritablePolicy::AlwaysRules => HashSet::new(),
+        _ => direct::writable_partitions(&fs::read_to_string("/proc/self/mountinfo").unwrap_or_default(), &target_partitions),
+    };
+    let approvals = if policy == WritablePolicy::Ask { direct::load_approvals() } else { HashSet::new() };
+    let rewrites = rewrite::active(&config.path_rewrites);
 
     for module in modules {
         let mut content_path = storage_root.join(&module.id);
@@ -160,44 +333,76 @@ pub fn generate(
         if !content_path.exists() { continue; }
 
         if let Ok(entries) = fs::read_dir(&content_path) {
+            let mut entries: Vec<fs::DirEntry> = entries.flatten().collect();
+            if config.deterministic {
+                entries.sort_by_key(|entry| entry.file_name());
+            }
             for entry in entries {
-                if let Ok(entry) = entry {
-                    let path = entry.path();
-                    if !path.is_dir() { continue; }
-                    
-                    let dir_name = entry.file_name().to_string_lossy().to_string();
-                    
-                    if !target_partitions.contains(&dir_name.as_str()) {
+                let path = entry.path();
+                if !path.is_dir() { continue; }
+                
+                let dir_name = entry.file_name().to_string_lossy().to_string();
+                
+                if !target_partitions.contains(&dir_name.as_str()) {
+                    continue;
+                }
+
+                if !has_files(&path) { continue; }
+                let target_base = partitions::target(config, &dir_name);
+
+                let mut mode = module.rules.get_mode(&dir_name);
+                // Only per-file rules can move files, so rewritten partitions
+                // are injected through HymoFS whatever mode they asked for.
+                if matches!(mode, MountMode::Overlay | MountMode::Magic) && rewrites.iter().any(|r| r.touches(&dir_name, &path)) {
+                    log::info!("{}/{}: path rewrites apply, injecting through HymoFS", module.id, dir_name);
+                    mode = MountMode::HymoFs;
+                }
+                let window = bootstage::window(&config.boot_stages, &module.rules, &dir_name);
+                if module.rules.window() != BootWindow::Always && mode != MountMode::HymoFs {
+                    log::warn!("{}/{}: boot window {:?} requires HymoFS mode, mounting for the whole session", module.id, dir_name, module.rules.window());
+                }
+                let guarded = module.rules.expect_hash.keys().any(|p| Path::new(p).starts_with(&target_base));
+                if guarded && matches!(mode, MountMode::Overlay | MountMode::Magic) {
+                    log::warn!("{}/{}: hash conditions only apply to HymoFS rules, mounting unconditionally", module.id, dir_name);
+                }
+
+                if mode != MountMode::Ignore && writable.contains(&dir_name) {
+                    if direct::uses_direct(policy, true, approvals.contains(&module.id)) {
+                        plan.direct_ops.push(DirectOperation {
+                            module_id: module.id.clone(),
+                            source: path,
+                            target: target_base,
+                        });
+                        direct_ids.insert(module.id.clone());
                         continue;
                     }
+                    if policy == WritablePolicy::Ask {
+                        log::info!("{}/{}: /{} is writable; run `approve-direct {}` to modify it directly", module.id, dir_name, dir_name, module.id);
+                    }
+                }
 
-                    if !has_files(&path) { continue; }
-
-                    let mode = module.rules.get_mode(&dir_name);
-
-                    match mode {
-                        MountMode::Overlay => {
-                            overlay_groups.entry(dir_name)
-                                .or_default()
-                                .push(path);
-                            overlay_ids.insert(module.id.clone());
-                        },
-                        MountMode::HymoFs => {
-                            let target_base = PathBuf::from("/").join(&dir_name);
-                            plan.hymo_ops.push(HymoOperation {
-                                module_id: module.id.clone(),
-                                source: path,
-                                target: target_base,
-                            });
-                            hymo_ids.insert(module.id.clone());
-                        },
-                        MountMode::Magic => {
-                            magic_paths.insert(content_path.clone());
-                            magic_ids.insert(module.id.clone());
-                        },
-                        MountMode::Ignore => {
-                            log::debug!("Ignoring {}/{} per rule", module.id, dir_name);
-                        }
+                match mode {
+                    MountMode::Overlay => {
+                        overlay_groups.entry(dir_name)
+                            .or_default()
+                            .push(path);
+                        overlay_ids.insert(module.id.clone());
+                    },
+                    MountMode::HymoFs => {
+                        plan.hymo_ops.push(HymoOperation {
+                            module_id: module.id.clone(),
+                            source: path,
+                            target: target_base,
+                            window,
+                        });
+                        hymo_ids.insert(module.id.clone());
+                    },
+                    MountMode::Magic => {
+                        magic_paths.insert(content_path.clone());
+                        magic_ids.insert(module.id.clone());
+                    },
+                    MountMode::Ignore => {
+                        log::debug!("Ignoring {}/{} per rule", module.id, dir_name);
                     }
                 }
             }
@@ -205,8 +410,8 @@ pub fn generate(
     }
 
     for (part, layers) in overlay_groups {
-        let initial_target_path = format!("/{}", part);
-        let target_path_obj = Path::new(&initial_target_path);
+        let initial_target_path = partitions::target(config, &part);
+        let target_path_obj = initial_target_path.as_path();
         
         let resolved_target = if target_path_obj.exists() {
              match target_path_obj.canonicalize() {
@@ -228,24 +433,242 @@ pub fn generate(
         });
     }
 
+    reject_redirect_loops(&mut plan, &mut hymo_ids, &mut overlay_ids);
+
     plan.magic_module_paths = magic_paths.into_iter().collect();
+    if config.deterministic {
+        plan.overlay_ops.sort_by(|a, b| a.partition_name.cmp(&b.partition_name));
+        plan.magic_module_paths.sort();
+    }
     
     plan.overlay_module_ids = overlay_ids.into_iter().collect();
     plan.magic_module_ids = magic_ids.into_iter().collect();
     plan.hymo_module_ids = hymo_ids.into_iter().collect();
+    plan.hash_guards = guards::of_modules(modules).into_iter()
+        .filter(|(id, _)| plan.hymo_module_ids.contains(id))
+        .collect();
+    plan.direct_module_ids = direct_ids.into_iter().collect();
+    plan.rewrites = rewrites;
     
     plan.overlay_module_ids.sort();
     plan.magic_module_ids.sort();
     plan.hymo_module_ids.sort();
+    plan.direct_module_ids.sort();
 
     Ok(plan)
 }
 
+fn resolve(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn find_redirect_loop(source: &Path, redirected: &[(PathBuf, String)]) -> Option<String> {
+    redirected.iter()
+        .find(|(root, _)| source.starts_with(root))
+        .map(|(root, owner)| format!(
+            "source {} lies inside {}, which is itself redirected by {}; the redirect would resolve back into itself",
+            source.display(), root.display(), owner))
+}
+
+fn reject_redirect_loops(plan: &mut MountPlan, hymo_ids: &mut HashSet<String, PlanHasher>, overlay_ids: &mut HashSet<String, PlanHasher>) {
+    let mut redirected: Vec<(PathBuf, String)> = plan.hymo_ops.iter()
+        .map(|op| (resolve(&op.target), format!("HymoFS rule of {}", op.module_id)))
+        .collect();
+    redirected.extend(plan.overlay_ops.iter()
+        .map(|op| (PathBuf::from(&op.target), format!("the {} overlay", op.partition_name))));
+
+    let mut rejected = Vec::new();
+    plan.hymo_ops.retain(|op| match find_redirect_loop(&resolve(&op.source), &redirected) {
+        Some(reason) => {
+            rejected.push((op.module_id.clone(), reason));
+            false
+        }
+        None => true,
+    });
+    for op in &mut plan.overlay_ops {
+        op.lowerdirs.retain(|layer| match find_redirect_loop(&resolve(layer), &redirected) {
+            Some(reason) => {
+                let id = layer.parent()
+                    .and_then(|p| p.file_name())
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or_else(|| "unknown".into());
+                rejected.push((id, reason));
+                false
+            }
+            None => true,
+        });
+    }
+    plan.overlay_ops.retain(|op| !op.lowerdirs.is_empty());
+
+    for (id, reason) in rejected {
+        log::error!("!! Rejecting {}: {}", id, reason);
+        if !plan.hymo_ops.iter().any(|op| op.module_id == id) {
+            hymo_ids.remove(&id);
+        }
+        let still_layered = plan.overlay_ops.iter()
+            .flat_map(|op| &op.lowerdirs)
+            .any(|layer| layer.parent().and_then(|p| p.file_name()).is_some_and(|n| n.to_string_lossy() == id));
+        if !still_layered {
+            overlay_ids.remove(&id);
+        }
+        plan.rejected.push(format!("{}: {}", id, reason));
+    }
+}
+
 fn has_files(path: &Path) -> bool {
-    if let Ok(entries) = fs::read_dir(path) {
-        for _ in entries.flatten() {
-            return true;
+    fs::read_dir(path).is_ok_and(|mut entries| entries.next().is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::inventory::ModuleRules;
+
+    #[test]
+    fn conflicts_list_the_shadowing_stack() {
+        let root = std::env::temp_dir().join(format!("planner-conflicts-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for module in ["a", "b", "c"] {
+            let path = root.join(module).join("system/etc/hosts");
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, module).unwrap();
         }
+        fs::write(root.join("a/system/etc/only-a"), "a").unwrap();
+        let plan = MountPlan {
+            overlay_ops: vec![OverlayOperation {
+                partition_name: "system".into(),
+                target: "/system".into(),
+                lowerdirs: vec![root.join("b/system"), root.join("a/system")],
+            }],
+            hymo_ops: vec![HymoOperation {
+                module_id: "c".into(),
+                source: root.join("c/system"),
+                target: "/system".into(),
+                window: BootWindow::Always,
+            }],
+            ..Default::default()
+        };
+        let details = plan.analyze_conflicts().details;
+        assert_eq!(details.len(), 1);
+        assert_eq!(details[0].relative_path, "etc/hosts");
+        assert_eq!(details[0].winner, "c");
+        assert_eq!(details[0].contending_modules, vec!["c", "b", "a"]);
+        let stack: Vec<(&str, &str)> = details[0].provenance.iter().map(|c| (c.module_id.as_str(), c.backend)).collect();
+        assert_eq!(stack, vec![("c", "hymofs"), ("b", "overlay"), ("a", "overlay")]);
+        assert_eq!(details[0].provenance[2].source, root.join("a/system/etc/hosts"));
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn deterministic_plans_come_out_sorted() {
+        let root = std::env::temp_dir().join(format!("planner-deterministic-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let module = |id: &str, mode: MountMode| {
+            for partition in ["vendor", "system", "product"] {
+                fs::create_dir_all(root.join(id).join(partition).join("etc")).unwrap();
+                fs::write(root.join(id).join(partition).join("etc/file"), id).unwrap();
+            }
+            Module { id: id.into(), source_path: root.join(id), rules: ModuleRules { default_mode: mode, ..Default::default() } }
+        };
+        let modules = vec![module("z", MountMode::Magic), module("h", MountMode::HymoFs), module("a", MountMode::Magic)];
+        let config = config::Config { writable_partition_policy: WritablePolicy::AlwaysRules, ..Default::default() };
+        assert!(config.deterministic);
+        let plan = generate(&config, &modules, &root.join("storage")).unwrap();
+        assert_eq!(plan.magic_module_paths, vec![root.join("a"), root.join("z")]);
+        let targets: Vec<&Path> = plan.hymo_ops.iter().map(|op| op.target.as_path()).collect();
+        assert_eq!(targets, vec![Path::new("/product"), Path::new("/system"), Path::new("/vendor")]);
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn demoting_moves_hymofs_modules_to_magic_mount() {
+        let op = |id: &str, partition: &str| HymoOperation {
+            module_id: id.into(),
+            source: PathBuf::from("/mnt").join(id).join(partition),
+            target: PathBuf::from("/").join(partition),
+            window: BootWindow::Always,
+        };
+        let mut plan = MountPlan {
+            hymo_ops: vec![op("a", "system"), op("a", "vendor"), op("b", "system")],
+            hymo_module_ids: vec!["a".into(), "b".into()],
+            magic_module_paths: vec![PathBuf::from("/mnt/c")],
+            magic_module_ids: vec!["c".into()],
+            ..Default::default()
+        };
+        let priorities = plan.hymo_priorities();
+        assert_eq!(priorities.of("/mnt/a/vendor/etc/x"), 2);
+        assert_eq!(priorities.of("/mnt/b/system/bin/y"), 3);
+        assert_eq!(priorities.of("/data/elsewhere"), 0);
+        assert_eq!(plan.demote_hymo_to_magic(), vec!["a", "b"]);
+        assert!(plan.hymo_ops.is_empty() && plan.hymo_module_ids.is_empty());
+        assert_eq!(plan.magic_module_paths, vec![PathBuf::from("/mnt/c"), PathBuf::from("/mnt/a"), PathBuf::from("/mnt/b")]);
+        assert_eq!(plan.magic_module_ids, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn demoting_to_overlay_stacks_hymofs_layers_on_top() {
+        let root = std::env::temp_dir().join(format!("planner-overlay-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let system = root.join("system");
+        fs::create_dir_all(&system).unwrap();
+        fs::create_dir_all(root.join("b/system/etc/permissions")).unwrap();
+        let op = |id: &str, target: PathBuf| HymoOperation {
+            module_id: id.into(),
+            source: root.join(id).join(target.file_name().unwrap()),
+            target,
+            window: BootWindow::Always,
+        };
+        let mut plan = MountPlan {
+            overlay_ops: vec![OverlayOperation { partition_name: "system".into(), target: system.to_string_lossy().into(), lowerdirs: vec![root.join("o/system")] }],
+            overlay_module_ids: vec!["o".into()],
+            hymo_ops: vec![op("a", system.clone()), op("b", system.clone()), op("c", root.join("missing"))],
+            hymo_module_ids: vec!["a".into(), "b".into(), "c".into()],
+            rewrites: vec![PathRewrite { from: "/system/etc/permissions".into(), to: "/system_ext/etc/permissions".into(), when: None }],
+            ..Default::default()
+        };
+        assert_eq!(plan.demote_hymo_to_overlay(), vec!["a"]);
+        assert_eq!(plan.overlay_ops[0].lowerdirs, vec![root.join("a/system"), root.join("o/system")]);
+        assert_eq!(plan.overlay_module_ids, vec!["a", "o"]);
+        assert_eq!(plan.magic_module_ids, vec!["b", "c"]);
+        assert!(plan.hymo_ops.is_empty() && plan.hymo_module_ids.is_empty());
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn demoting_overlay_moves_layers_to_magic_mount() {
+        let mut plan = MountPlan {
+            overlay_ops: vec![
+                OverlayOperation { partition_name: "system".into(), target: "/system".into(), lowerdirs: vec![PathBuf::from("/mnt/a/system"), PathBuf::from("/mnt/b/system")] },
+                OverlayOperation { partition_name: "vendor".into(), target: "/vendor".into(), lowerdirs: vec![PathBuf::from("/mnt/a/vendor")] },
+            ],
+            overlay_module_ids: vec!["a".into(), "b".into()],
+            magic_module_ids: vec!["c".into()],
+            ..Default::default()
+        };
+        assert_eq!(plan.demote_overlay_to_magic(), vec!["a", "b"]);
+        assert!(plan.overlay_ops.is_empty());
+        assert_eq!(plan.magic_module_paths, vec![PathBuf::from("/mnt/a"), PathBuf::from("/mnt/b")]);
+        assert_eq!(plan.magic_module_ids, vec!["a", "b", "c"]);
+    }
+
+    fn redirected() -> Vec<(PathBuf, String)> {
+        vec![
+            (PathBuf::from("/system"), "HymoFS rule of a".to_string()),
+            (PathBuf::from("/vendor"), "the vendor overlay".to_string()),
+        ]
+    }
+
+    #[test]
+    fn detects_source_inside_redirected_root() {
+        let reason = find_redirect_loop(Path::new("/system/etc/mods/b/system"), &redirected()).unwrap();
+        assert!(reason.contains("/system/etc/mods/b/system"));
+        assert!(reason.contains("HymoFS rule of a"));
+        assert!(find_redirect_loop(Path::new("/vendor"), &redirected()).is_some());
+    }
+
+    #[test]
+    fn allows_unrelated_sources() {
+        assert!(find_redirect_loop(Path::new("/dev/meta_hybrid_mnt/b/system"), &redirected()).is_none());
+        assert!(find_redirect_loop(Path::new("/system_ext_mods/b"), &redirected()).is_none());
     }
-    false
 }
@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use anyhow::{Context, Result, bail};
+use rustix::mount::{MountPropagationFlags, mount_change};
+use crate::{conf::config::Config, core::roots, defs, mount::magic, utils};
+
+const DEFAULT_SHELL: &str = "/system/bin/sh";
+
+/// Program and arguments to run inside the preview: `command` when one was
+/// given, an interactive shell otherwise.
+pub fn program(command: &[String], shell: Option<String>) -> (String, Vec<String>) {
+    match command.split_first() {
+        Some((program, args)) => (program.clone(), args.to_vec()),
+        None => (shell.filter(|s| !s.is_empty()).unwrap_or_else(|| DEFAULT_SHELL.to_string()), Vec::new()),
+    }
+}
+
+/// Moves this process into a mount namespace of its own whose mounts never
+/// propagate back, so everything mounted afterwards is private to it and
+/// the children it starts.
+fn enter_private_namespace() -> Result<()> {
+    if unsafe { libc::unshare(libc::CLONE_NEWNS) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("unshare(CLONE_NEWNS) failed");
+    }
+    mount_change("/", MountPropagationFlags::PRIVATE | MountPropagationFlags::REC)
+        .context("failed to make / private")?;
+    Ok(())
+}
+
+/// Magic-mounts `module_id` inside a fresh private mount namespace and runs
+/// `command` there. The namespace goes away with this process, so the global
+/// view never changes. Returns the exit code of the command.
+pub fn run(config: &Config, module_id: &str, command: &[String]) -> Result<i32> {
+    let module_dir = roots::locate(config, module_id);
+    if module_id.contains('/') || !module_dir.is_dir() {
+        bail!("module '{}' not found in any module root", module_id);
+    }
+    enter_private_namespace()?;
+
+    let scratch: PathBuf = Path::new(defs::RUN_DIR).join(format!("preview-{}", std::process::id()));
+    utils::mount_tmpfs(&scratch, "tmpfs")?;
+    magic::mount_partitions(&scratch, &[module_dir], &config.mountsource, &config.partitions, HashMap::new(), &config.mount_propagation, true)
+        .with_context(|| format!("failed to mount {} for preview", module_id))?;
+
+    let (program, args) = program(command, std::env::var("SHELL").ok());
+    eprintln!(">> Previewing {} in a private mount namespace; exit {} to discard it.", module_id, program);
+    let status = Command::new(&program)
+        .args(&args)
+        .env("META_HYBRID_PREVIEW", module_id)
+        .status()
+        .with_context(|| format!("failed to run {}", program))?;
+
+    let _ = rustix::mount::unmount(&scratch, rustix::mount::UnmountFlags::DETACH);
+    let _ = std::fs::remove_dir(&scratch);
+    Ok(status.code().unwrap_or(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_the_command_or_a_shell() {
+        let command = vec!["ls".to_string(), "-l".to_string(), "/system/bin".to_string()];
+        assert_eq!(program(&command, None), ("ls".to_string(), vec!["-l".to_string(), "/system/bin".to_string()]));
+        assert_eq!(program(&[], Some("/bin/bash".into())).0, "/bin/bash");
+        assert_eq!(program(&[], Some(String::new())).0, DEFAULT_SHELL);
+        assert_eq!(program(&[], None), (DEFAULT_SHELL.to_string(), Vec::new()));
+    }
+}
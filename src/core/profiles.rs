@@ -0,0 +1,257 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{Result, bail};
+use rustix::fs::{FileType, Mode};
+use serde::Serialize;
+use crate::{defs, utils, core::{inventory::BootWindow, planner::{HymoOperation, MountPlan}}};
+
+pub const PROFILES_MODULE_ID: &str = "meta-hybrid-profiles";
+const HOSTS: &str = "system/etc/hosts";
+const DEFAULT_HOSTS: &str = "127.0.0.1       localhost\n::1             ip6-localhost\n";
+
+/// A built-in bundle of hides and hosts entries that users switch on by name
+/// instead of installing a module for it.
+#[derive(Debug, Serialize)]
+pub struct Recipe {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Partition-relative paths hidden while the profile is enabled.
+    pub hides: &'static [&'static str],
+    /// Domains pointed at 0.0.0.0 in `/system/etc/hosts`.
+    pub blocked_hosts: &'static [&'static str],
+}
+
+const RECIPES: &[Recipe] = &[
+    Recipe {
+        name: "adblock-hosts",
+        description: "Systemless hosts file blocking common ad and tracking domains",
+        hides: &[],
+        blocked_hosts: &[
+            "doubleclick.net",
+            "ad.doubleclick.net",
+            "googleads.g.doubleclick.net",
+            "pagead2.googlesyndication.com",
+            "googleadservices.com",
+            "app-measurement.com",
+            "ads.mopub.com",
+            "an.facebook.com",
+        ],
+    },
+    Recipe {
+        name: "debloat",
+        description: "Hide preinstalled apps that are safe to remove on most ROMs",
+        hides: &[
+            "system/app/BasicDreams",
+            "system/app/BookmarkProvider",
+            "system/app/PartnerBookmarksProvider",
+            "system/app/PrintRecommendationService",
+            "product/app/Drive",
+            "product/app/Music2",
+            "product/app/Videos",
+            "product/app/YouTube",
+        ],
+        blocked_hosts: &[],
+    },
+    Recipe {
+        name: "safetynet-paths",
+        description: "Hide leftover su binaries and root apps that integrity checks look for",
+        hides: &[
+            "system/bin/su",
+            "system/xbin/su",
+            "system/xbin/busybox",
+            "system/app/Superuser",
+            "system/app/Superuser.apk",
+        ],
+        blocked_hosts: &[],
+    },
+];
+
+pub fn recipes() -> &'static [Recipe] {
+    RECIPES
+}
+
+pub fn find(name: &str) -> Option<&'static Recipe> {
+    RECIPES.iter().find(|r| r.name == name)
+}
+
+pub fn load_enabled() -> Vec<String> {
+    fs::read_to_string(defs::PROFILES_FILE)
+        .map(|content| content.lines().map(str::trim).filter(|l| !l.is_empty()).map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+pub fn set_enabled(name: &str, enabled: bool) -> Result<()> {
+    if find(name).is_none() {
+        bail!("unknown profile '{}'", name);
+    }
+    let mut names: HashSet<String> = load_enabled().into_iter().collect();
+    if enabled {
+        names.insert(name.to_string());
+    } else {
+        names.remove(name);
+    }
+    let mut names: Vec<String> = names.into_iter().collect();
+    names.sort();
+    utils::ensure_dir_exists(defs::BASE_DIR)?;
+    fs::write(defs::PROFILES_FILE, names.iter().map(|n| format!("{}\n", n)).collect::<String>())?;
+    Ok(())
+}
+
+/// The rules a set of profiles turns into, relative to the partition root.
+#[derive(Debug, Default, Serialize)]
+pub struct Compiled {
+    pub profiles: Vec<String>,
+    pub hides: BTreeSet<PathBuf>,
+    pub files: BTreeMap<PathBuf, String>,
+}
+
+fn blocked(hosts: &str) -> HashSet<&str> {
+    hosts.lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let addr = fields.next()?;
+            matches!(addr, "0.0.0.0" | "127.0.0.1" | "::" | "::1").then_some(fields)
+        })
+        .flatten()
+        .collect()
+}
+
+/// Compiles `names` against the files under `root`. Hides only cover paths
+/// that exist there, and the hosts file extends the stock one.
+pub fn compile(names: &[String], root: &Path) -> Result<Compiled> {
+    let mut compiled = Compiled::default();
+    let stock_hosts = fs::read_to_string(root.join(HOSTS)).unwrap_or_else(|_| DEFAULT_HOSTS.to_string());
+    let mut hosts = stock_hosts.clone();
+    for name in names {
+        let Some(recipe) = find(name) else {
+            bail!("unknown profile '{}'", name);
+        };
+        compiled.profiles.push(recipe.name.to_string());
+        compiled.hides.extend(recipe.hides.iter()
+            .map(PathBuf::from)
+            .filter(|path| fs::symlink_metadata(root.join(path)).is_ok()));
+        let present = blocked(&hosts);
+        let added: Vec<&str> = recipe.blocked_hosts.iter().copied().filter(|d| !present.contains(d)).collect();
+        if !added.is_empty() {
+            if !hosts.ends_with('\n') {
+                hosts.push('\n');
+            }
+            hosts.push_str(&format!("# added by profile {}\n", recipe.name));
+            for domain in added {
+                hosts.push_str(&format!("0.0.0.0 {}\n", domain));
+            }
+        }
+    }
+    if hosts != stock_hosts {
+        compiled.files.insert(PathBuf::from(HOSTS), hosts);
+    }
+    Ok(compiled)
+}
+
+/// Writes the compiled profiles out as a module under `storage_root`, hides
+/// as whiteout nodes, and returns the redirects that mount it.
+pub fn stage(compiled: &Compiled, storage_root: &Path) -> Result<Vec<HymoOperation>> {
+    let module_root = storage_root.join(PROFILES_MODULE_ID);
+    if module_root.exists() {
+        fs::remove_dir_all(&module_root)?;
+    }
+    for (relative, content) in &compiled.files {
+        let staged = module_root.join(relative);
+        if let Some(parent) = staged.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&staged, content)?;
+        let _ = utils::copy_path_context(Path::new("/").join(relative), &staged);
+    }
+    for relative in &compiled.hides {
+        let staged = module_root.join(relative);
+        if let Some(parent) = staged.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        rustix::fs::mknodat(rustix::fs::CWD, &staged, FileType::CharacterDevice, Mode::empty(), 0)?;
+    }
+    let tops: BTreeSet<PathBuf> = compiled.files.keys()
+        .chain(&compiled.hides)
+        .filter_map(|relative| relative.components().next())
+        .map(|top| PathBuf::from(top.as_os_str()))
+        .collect();
+    Ok(tops.into_iter()
+        .map(|top| HymoOperation {
+            module_id: PROFILES_MODULE_ID.to_string(),
+            source: module_root.join(&top),
+            target: Path::new("/").join(&top),
+            window: BootWindow::Always,
+        })
+        .collect())
+}
+
+/// Adds the enabled profiles to `plan`, staging them into `storage_root`
+/// when one is given.
+pub fn extend_plan(plan: &mut MountPlan, names: &[String], storage_root: Option<&Path>) -> Result<()> {
+    let names: Vec<String> = names.iter()
+        .filter(|name| {
+            let known = find(name).is_some();
+            if !known {
+                log::warn!("!! Ignoring unknown profile '{}'", name);
+            }
+            known
+        })
+        .cloned()
+        .collect();
+    if names.is_empty() {
+        if let Some(storage_root) = storage_root {
+            let _ = fs::remove_dir_all(storage_root.join(PROFILES_MODULE_ID));
+        }
+        return Ok(());
+    }
+    let compiled = compile(&names, Path::new("/"))?;
+    log::info!(">> Profiles [{}]: {} hides, {} files",
+        compiled.profiles.join(", "), compiled.hides.len(), compiled.files.len());
+    let Some(storage_root) = storage_root else { return Ok(()) };
+    let ops = stage(&compiled, storage_root)?;
+    if !ops.is_empty() {
+        plan.hymo_ops.extend(ops);
+        plan.hymo_module_ids.push(PROFILES_MODULE_ID.to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recipes_are_unique_and_partition_relative() {
+        let mut names = HashSet::new();
+        for recipe in recipes() {
+            assert!(names.insert(recipe.name), "duplicate profile {}", recipe.name);
+            for hide in recipe.hides {
+                let top = hide.split('/').next().unwrap();
+                assert!(defs::BUILTIN_PARTITIONS.contains(&top), "{} is outside the partitions", hide);
+            }
+        }
+    }
+
+    #[test]
+    fn compiles_hides_and_hosts_against_the_root() {
+        let root = std::env::temp_dir().join(format!("profiles-compile-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("system/app/BasicDreams")).unwrap();
+        fs::create_dir_all(root.join("system/etc")).unwrap();
+        fs::write(root.join(HOSTS), "127.0.0.1 localhost\n0.0.0.0 doubleclick.net # already\n").unwrap();
+
+        let names = vec!["debloat".to_string(), "adblock-hosts".to_string()];
+        let compiled = compile(&names, &root).unwrap();
+        assert_eq!(compiled.hides.iter().collect::<Vec<_>>(), vec![Path::new("system/app/BasicDreams")]);
+        let hosts = &compiled.files[Path::new(HOSTS)];
+        assert!(hosts.starts_with("127.0.0.1 localhost\n"));
+        assert!(hosts.contains("# added by profile adblock-hosts\n0.0.0.0 ad.doubleclick.net\n"));
+        assert_eq!(hosts.matches("0.0.0.0 doubleclick.net").count(), 1, "the stock entry is not repeated");
+
+        assert!(compile(&["debloat".to_string()], &root).unwrap().files.is_empty());
+        assert!(compile(&["nope".to_string()], &root).unwrap_err().to_string().contains("nope"));
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,95 @@
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use crate::mount::hymofs::RuleOp;
+
+static PROTECTED: OnceLock<ProtectedPaths> = OnceLock::new();
+
+/// Files the device cannot boot without: init, the dynamic linker and the
+/// bionic libraries every process loads.
+pub fn default_paths() -> Vec<PathBuf> {
+    [
+        "/init",
+        "/system/bin/init",
+        "/system/bin/linker",
+        "/system/bin/linker64",
+        "/system/lib/libc.so",
+        "/system/lib/libdl.so",
+        "/system/lib/libm.so",
+        "/system/lib64/libc.so",
+        "/system/lib64/libdl.so",
+        "/system/lib64/libm.so",
+        "/apex/com.android.runtime/bin",
+        "/apex/com.android.runtime/lib/bionic",
+        "/apex/com.android.runtime/lib64/bionic",
+    ]
+    .into_iter()
+    .map(PathBuf::from)
+    .collect()
+}
+
+/// Paths no module may replace or hide, whatever it ships.
+#[derive(Debug, Default)]
+pub struct ProtectedPaths {
+    paths: Vec<PathBuf>,
+}
+
+impl ProtectedPaths {
+    pub fn new(paths: &[PathBuf]) -> Self {
+        Self { paths: paths.iter().filter(|path| path.is_absolute()).cloned().collect() }
+    }
+
+    /// Whether a rule at `path` would shadow a protected path: replace or
+    /// hide it, a directory above it, or anything inside it.
+    pub fn shadows(&self, path: &Path) -> bool {
+        self.paths.iter().any(|protected| path.starts_with(protected) || protected.starts_with(path))
+    }
+
+    /// Drops the rules of `ops` that would shadow a protected path and
+    /// returns their paths. Deletions always stay.
+    pub fn retain(&self, ops: &mut Vec<RuleOp>) -> Vec<String> {
+        let mut refused = Vec::new();
+        ops.retain(|op| {
+            let keep = matches!(op, RuleOp::Delete { .. }) || !self.shadows(Path::new(op.path()));
+            if !keep {
+                refused.push(op.path().to_string());
+            }
+            keep
+        });
+        refused
+    }
+}
+
+/// Sets the protected paths from the config for the rest of the process.
+pub fn init(paths: &[PathBuf]) {
+    let _ = PROTECTED.set(ProtectedPaths::new(paths));
+}
+
+pub fn protected() -> &'static ProtectedPaths {
+    PROTECTED.get_or_init(|| ProtectedPaths::new(&default_paths()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mount::hymofs::HymoFileType;
+
+    #[test]
+    fn refuses_rules_that_shadow_protected_paths() {
+        let protected = ProtectedPaths::new(&default_paths());
+        let add = |path: &str| RuleOp::Add { src: path.into(), target: "/data/adb/modules/m/x".into(), file_type: HymoFileType::Reg };
+        let mut ops = vec![
+            add("/system/bin/init"),
+            add("/system/bin/sh"),
+            RuleOp::Hide { path: "/system/bin".into() },
+            add("/apex/com.android.runtime/lib64/bionic/libc.so"),
+            add("/system/lib64/libc.so.bak"),
+            RuleOp::Delete { src: "/system/bin/linker64".into() },
+        ];
+        let refused = protected.retain(&mut ops);
+        assert_eq!(refused, ["/system/bin/init", "/system/bin", "/apex/com.android.runtime/lib64/bionic/libc.so"]);
+        assert_eq!(ops.iter().map(RuleOp::path).collect::<Vec<_>>(), ["/system/bin/sh", "/system/lib64/libc.so.bak", "/system/bin/linker64"]);
+
+        assert!(!ProtectedPaths::new(&[PathBuf::from("relative/init")]).shadows(Path::new("relative/init")));
+        assert!(!ProtectedPaths::default().shadows(Path::new("/system/bin/init")));
+    }
+}
@@ -0,0 +1,185 @@
+use std::ffi::CStr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+/// A terminator that keeps running this long is restarted right away when
+/// it exits; one that dies sooner waits before the next try.
+const STABLE_RUN: Duration = Duration::from_secs(60);
+const RESTART_DELAY: Duration = Duration::from_secs(10);
+
+/// The hymod protocol over TLS for devices managed without adb. Both sides
+/// authenticate: clients must present a certificate signed by `client_ca`.
+/// TLS is terminated by `socat`, which forwards each connection to the
+/// local control socket, so remote clients speak the same JSON lines.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RemoteControl {
+    pub enabled: bool,
+    /// Network interface to listen on, e.g. `eth0`; loopback when unset.
+    pub interface: Option<String>,
+    pub port: u16,
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub client_ca: PathBuf,
+    pub socat: PathBuf,
+}
+
+impl Default for RemoteControl {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interface: None,
+            port: 7443,
+            cert: PathBuf::from("/data/adb/meta-hybrid/remote/server.crt"),
+            key: PathBuf::from("/data/adb/meta-hybrid/remote/server.key"),
+            client_ca: PathBuf::from("/data/adb/meta-hybrid/remote/clients.crt"),
+            socat: PathBuf::from("socat"),
+        }
+    }
+}
+
+/// socat reads `,`, `:` and `!` in an address as separators.
+fn address_value(path: &Path) -> Result<&str> {
+    let value = path.to_str().with_context(|| format!("{} is not valid UTF-8", path.display()))?;
+    if value.is_empty() || value.contains([',', ':', '!']) {
+        bail!("{:?} cannot be passed to socat", value);
+    }
+    Ok(value)
+}
+
+/// The socat arguments that accept mutually authenticated TLS on `listen`
+/// and hand every connection to `socket`.
+pub fn terminator_args(remote: &RemoteControl, listen: IpAddr, socket: &Path) -> Result<Vec<String>> {
+    let (bind, family) = match listen {
+        IpAddr::V4(addr) => (addr.to_string(), "ip4"),
+        IpAddr::V6(addr) => (format!("[{}]", addr), "ip6"),
+    };
+    Ok(vec![
+        "-d".to_string(),
+        format!(
+            "OPENSSL-LISTEN:{},bind={},pf={},reuseaddr,fork,cert={},key={},cafile={},verify=1,openssl-min-proto-version=TLS1.2",
+            remote.port, bind, family,
+            address_value(&remote.cert)?, address_value(&remote.key)?, address_value(&remote.client_ca)?,
+        ),
+        format!("UNIX-CONNECT:{}", address_value(socket)?),
+    ])
+}
+
+/// First address of `interface`, IPv4 before IPv6.
+pub fn interface_address(interface: &str) -> Result<IpAddr> {
+    let mut found: Vec<IpAddr> = Vec::new();
+    let mut addrs: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut addrs) } != 0 {
+        bail!("Failed to list interfaces: {}", std::io::Error::last_os_error());
+    }
+    let mut cursor = addrs;
+    while !cursor.is_null() {
+        let entry = unsafe { &*cursor };
+        cursor = entry.ifa_next;
+        if entry.ifa_addr.is_null() || unsafe { CStr::from_ptr(entry.ifa_name) }.to_bytes() != interface.as_bytes() {
+            continue;
+        }
+        match libc::c_int::from(unsafe { (*entry.ifa_addr).sa_family }) {
+            libc::AF_INET => {
+                let addr = unsafe { &*(entry.ifa_addr as *const libc::sockaddr_in) };
+                found.push(IpAddr::V4(Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr))));
+            }
+            libc::AF_INET6 => {
+                let addr = unsafe { &*(entry.ifa_addr as *const libc::sockaddr_in6) };
+                found.push(IpAddr::V6(Ipv6Addr::from(addr.sin6_addr.s6_addr)));
+            }
+            _ => {}
+        }
+    }
+    unsafe { libc::freeifaddrs(addrs) };
+    preferred(found).with_context(|| format!("Interface {} has no address", interface))
+}
+
+/// The first IPv4 address of `found`, else its first IPv6 one.
+fn preferred(mut found: Vec<IpAddr>) -> Option<IpAddr> {
+    found.sort_by_key(|addr| addr.is_ipv6());
+    found.first().copied()
+}
+
+/// Address the listener binds to.
+pub fn listen_address(remote: &RemoteControl) -> Result<IpAddr> {
+    match &remote.interface {
+        Some(interface) => interface_address(interface),
+        None => Ok(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+    }
+}
+
+/// Checks what the listener needs before it is started, so a broken setup
+/// shows up in the log instead of as a terminator that keeps dying.
+pub fn check(remote: &RemoteControl) -> Result<()> {
+    for (what, path) in [("certificate", &remote.cert), ("key", &remote.key), ("client CA", &remote.client_ca)] {
+        if !path.is_file() {
+            bail!("Remote control {} {} is missing", what, path.display());
+        }
+    }
+    Ok(())
+}
+
+fn spawn(remote: &RemoteControl, socket: &Path) -> Result<Child> {
+    let listen = listen_address(remote)?;
+    let args = terminator_args(remote, listen, socket)?;
+    log::info!(">> Remote control: TLS on {}:{} for {}", listen, remote.port, socket.display());
+    Command::new(&remote.socat)
+        .args(&args)
+        .stdin(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Failed to start {}", remote.socat.display()))
+}
+
+/// Keeps the TLS listener running for as long as hymod does, restarting it
+/// when it exits. Never returns.
+pub fn supervise(remote: &RemoteControl, socket: &Path) -> ! {
+    loop {
+        let started = Instant::now();
+        match spawn(remote, socket).and_then(|mut child| Ok(child.wait()?)) {
+            Ok(status) => log::warn!("!! Remote control listener exited ({})", status),
+            Err(e) => log::warn!("!! Remote control listener: {:#}", e),
+        }
+        if started.elapsed() < STABLE_RUN {
+            std::thread::sleep(RESTART_DELAY);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn terminates_mutual_tls_into_the_control_socket() {
+        let remote = RemoteControl::default();
+        let args = terminator_args(&remote, IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), Path::new("/data/adb/meta-hybrid/run/hymod.sock")).unwrap();
+        assert_eq!(args, [
+            "-d",
+            "OPENSSL-LISTEN:7443,bind=10.0.0.2,pf=ip4,reuseaddr,fork,cert=/data/adb/meta-hybrid/remote/server.crt,\
+key=/data/adb/meta-hybrid/remote/server.key,cafile=/data/adb/meta-hybrid/remote/clients.crt,verify=1,openssl-min-proto-version=TLS1.2",
+            "UNIX-CONNECT:/data/adb/meta-hybrid/run/hymod.sock",
+        ]);
+        let v6 = terminator_args(&remote, IpAddr::V6(Ipv6Addr::LOCALHOST), Path::new("/run/h.sock")).unwrap();
+        assert!(v6[1].starts_with("OPENSSL-LISTEN:7443,bind=[::1],pf=ip6,"));
+    }
+
+    #[test]
+    fn rejects_paths_socat_would_split() {
+        let remote = RemoteControl { cert: PathBuf::from("/data/a,b.crt"), ..RemoteControl::default() };
+        assert!(terminator_args(&remote, IpAddr::V4(Ipv4Addr::LOCALHOST), Path::new("/run/h.sock")).is_err());
+        assert_eq!(listen_address(&RemoteControl::default()).unwrap(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+    }
+
+    #[test]
+    fn prefers_the_first_ipv4_address() {
+        let v4 = |last| IpAddr::V4(Ipv4Addr::new(10, 0, 0, last));
+        assert_eq!(preferred(vec![IpAddr::V6(Ipv6Addr::LOCALHOST), v4(2), v4(1)]), Some(v4(2)));
+        assert_eq!(preferred(vec![IpAddr::V6(Ipv6Addr::LOCALHOST)]), Some(IpAddr::V6(Ipv6Addr::LOCALHOST)));
+        assert_eq!(preferred(Vec::new()), None);
+    }
+}
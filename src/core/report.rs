@@ -0,0 +1,58 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use crate::core::disable::DisableReason;
+
+#[derive(Debug, Serialize, Default)]
+pub struct ApplyReport {
+    pub success: bool,
+    pub command: String,
+    pub session_id: String,
+    pub timestamp: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub storage_mode: Option<String>,
+    pub overlay_modules: Vec<String>,
+    pub magic_modules: Vec<String>,
+    pub hymo_modules: Vec<String>,
+    pub direct_modules: Vec<String>,
+    pub rules_applied: usize,
+    pub rules_failed: usize,
+    pub failures: Vec<String>,
+    pub warnings: Vec<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub disabled_modules: BTreeMap<String, DisableReason>,
+}
+
+impl ApplyReport {
+    pub fn new(command: &str) -> Self {
+        Self {
+            success: true,
+            command: command.to_string(),
+            session_id: crate::utils::session_id().to_string(),
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+            ..Default::default()
+        }
+    }
+
+    pub fn failed(command: &str, reason: String) -> Self {
+        let mut report = Self::new(command);
+        report.fail(reason);
+        report
+    }
+
+    pub fn fail(&mut self, reason: String) {
+        self.success = false;
+        self.failures.push(reason);
+    }
+
+    pub fn write_to(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).ok();
+        }
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json).with_context(|| format!("Failed to write report to {}", path.display()))
+    }
+}
@@ -0,0 +1,105 @@
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::{core::conditions, mount::hymofs::RuleOp};
+
+/// Config rule that moves everything a module places below `from` to the
+/// same place below `to`, e.g. `/system/etc/permissions` to
+/// `/system_ext/etc/permissions` on ROMs that only read the latter. `when`
+/// takes the same property conditions as module rules.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PathRewrite {
+    pub from: String,
+    pub to: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub when: Option<String>,
+}
+
+impl PathRewrite {
+    /// `path` moved below `to`, when it is `from` or lies below it.
+    pub fn rewrite(&self, path: &str) -> Option<String> {
+        let from = self.from.trim_end_matches('/');
+        let rest = path.strip_prefix(from)?;
+        if !rest.is_empty() && !rest.starts_with('/') {
+            return None;
+        }
+        Some(format!("{}{}", self.to.trim_end_matches('/'), rest))
+    }
+
+    /// Whether a module partition `content` mounted at `/partition`
+    /// provides anything this rewrite moves.
+    pub fn touches(&self, partition: &str, content: &Path) -> bool {
+        Path::new(&self.from).strip_prefix(Path::new("/").join(partition))
+            .is_ok_and(|relative| content.join(relative).symlink_metadata().is_ok())
+    }
+}
+
+/// The rewrites whose condition holds on this device.
+pub fn active(rewrites: &[PathRewrite]) -> Vec<PathRewrite> {
+    rewrites.iter()
+        .filter(|r| r.when.as_deref().is_none_or(conditions::evaluate))
+        .cloned()
+        .collect()
+}
+
+fn rewrite_path(path: &mut String, rewrites: &[PathRewrite]) {
+    if let Some(moved) = rewrites.iter().find_map(|r| r.rewrite(path)) {
+        *path = moved;
+    }
+}
+
+/// Points each rule at its rewritten path; the first matching rewrite wins.
+pub fn apply(ops: &mut [RuleOp], rewrites: &[PathRewrite]) {
+    if rewrites.is_empty() {
+        return;
+    }
+    for op in ops {
+        match op {
+            RuleOp::Add { src, .. } | RuleOp::Delete { src } => rewrite_path(src, rewrites),
+            RuleOp::Hide { path } => rewrite_path(path, rewrites),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mount::hymofs::HymoFileType;
+
+    fn permissions() -> PathRewrite {
+        PathRewrite { from: "/system/etc/permissions".into(), to: "/system_ext/etc/permissions/".into(), when: None }
+    }
+
+    #[test]
+    fn rewrites_whole_components_only() {
+        let rule = permissions();
+        assert_eq!(rule.rewrite("/system/etc/permissions"), Some("/system_ext/etc/permissions".into()));
+        assert_eq!(rule.rewrite("/system/etc/permissions/a.xml"), Some("/system_ext/etc/permissions/a.xml".into()));
+        assert_eq!(rule.rewrite("/system/etc/permissions-extra/a.xml"), None);
+        assert_eq!(rule.rewrite("/system/etc/hosts"), None);
+    }
+
+    #[test]
+    fn applies_to_every_rule_kind() {
+        let rewrites = vec![permissions(), PathRewrite { from: "/system".into(), to: "/never".into(), when: None }];
+        let mut ops = vec![
+            RuleOp::Add { src: "/system/etc/permissions/a.xml".into(), target: "/mnt/m/system/etc/permissions/a.xml".into(), file_type: HymoFileType::Reg },
+            RuleOp::Hide { path: "/system/etc/permissions/b.xml".into() },
+            RuleOp::Delete { src: "/vendor/etc/c.xml".into() },
+        ];
+        apply(&mut ops, &rewrites);
+        assert_eq!(ops[0], RuleOp::Add { src: "/system_ext/etc/permissions/a.xml".into(), target: "/mnt/m/system/etc/permissions/a.xml".into(), file_type: HymoFileType::Reg });
+        assert_eq!(ops[1].path(), "/system_ext/etc/permissions/b.xml");
+        assert_eq!(ops[2].path(), "/vendor/etc/c.xml");
+    }
+
+    #[test]
+    fn touches_only_modules_that_provide_the_source() {
+        let root = std::env::temp_dir().join(format!("rewrite-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("system/etc/permissions")).unwrap();
+        assert!(permissions().touches("system", &root.join("system")));
+        assert!(!permissions().touches("vendor", &root.join("system")));
+        assert!(!permissions().touches("system", &root.join("vendor")));
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,95 @@
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use crate::conf::config::Config;
+
+/// Module roots in precedence order: `moduledir`, then `module_roots`. A
+/// module found in several roots is taken from the first.
+pub fn configured(config: &Config) -> Vec<PathBuf> {
+    std::iter::once(&config.moduledir)
+        .chain(&config.module_roots)
+        .cloned()
+        .collect()
+}
+
+fn identity(path: &Path) -> Option<(u64, u64)> {
+    fs::metadata(path).ok().map(|m| (m.dev(), m.ino()))
+}
+
+/// Keeps the roots that exist, dropping a root that is the same directory
+/// as an earlier one, e.g. a bind mount of it or the same tree reached
+/// through a relocated `/data/adb`.
+pub fn resolve(roots: &[PathBuf]) -> Vec<PathBuf> {
+    let mut seen = Vec::new();
+    let mut active = Vec::new();
+    for root in roots {
+        let Some(id) = identity(root) else {
+            continue;
+        };
+        if seen.contains(&id) {
+            log::debug!("Module root {} repeats an earlier one", root.display());
+            continue;
+        }
+        seen.push(id);
+        active.push(root.clone());
+    }
+    active
+}
+
+pub fn active(config: &Config) -> Vec<PathBuf> {
+    resolve(&configured(config))
+}
+
+/// Whether `path` is itself one of `roots`, so a root nested inside another
+/// is not scanned as a module of the outer one.
+pub fn is_root(path: &Path, roots: &[PathBuf]) -> bool {
+    identity(path).is_some_and(|id| roots.iter().any(|root| identity(root) == Some(id)))
+}
+
+/// Directory of `module_id` in the first root that has it; below
+/// `moduledir` when none does.
+pub fn locate(config: &Config, module_id: &str) -> PathBuf {
+    active(config).iter()
+        .map(|root| root.join(module_id))
+        .find(|dir| dir.is_dir())
+        .unwrap_or_else(|| config.moduledir.join(module_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_missing_and_repeated_roots() {
+        let root = std::env::temp_dir().join(format!("roots-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("adb/modules/nested")).unwrap();
+        fs::create_dir_all(root.join("sd/modules")).unwrap();
+        std::os::unix::fs::symlink(root.join("adb"), root.join("relocated")).unwrap();
+        let roots = resolve(&[
+            root.join("adb/modules"),
+            root.join("missing"),
+            root.join("relocated/modules"),
+            root.join("sd/modules"),
+            root.join("adb/modules/nested"),
+        ]);
+        assert_eq!(roots, vec![root.join("adb/modules"), root.join("sd/modules"), root.join("adb/modules/nested")]);
+        assert!(is_root(&root.join("adb/modules/nested"), &roots));
+        assert!(!is_root(&root.join("sd"), &roots));
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn locates_modules_by_precedence() {
+        let root = std::env::temp_dir().join(format!("roots-locate-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for dir in ["first/a", "second/a", "second/b"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        let config = Config { moduledir: root.join("first"), module_roots: vec![root.join("second")], ..Default::default() };
+        assert_eq!(locate(&config, "a"), root.join("first/a"));
+        assert_eq!(locate(&config, "b"), root.join("second/b"));
+        assert_eq!(locate(&config, "c"), root.join("first/c"));
+        let _ = fs::remove_dir_all(&root);
+    }
+}
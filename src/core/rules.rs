@@ -0,0 +1,254 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use crate::{conf::config::Config, core::{roots, ruleset::RuleSet, staging}, defs, mount::hymofs::{HymoFs, RuleOp}};
+
+pub const EXTERNAL_GROUP: &str = "external";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RuleLedger {
+    #[serde(default)]
+    pub owned: RuleSet,
+    #[serde(default)]
+    pub external: Vec<RuleOp>,
+    /// Rules other userspace consumers added under their own owner name.
+    #[serde(default)]
+    pub namespaces: BTreeMap<String, Vec<RuleOp>>,
+    /// Namespaced rule paths tied to the package they go away with.
+    #[serde(default)]
+    pub packages: BTreeMap<String, String>,
+}
+
+impl RuleLedger {
+    pub fn load() -> Self {
+        fs::read_to_string(defs::RULE_LEDGER_FILE)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    /// The ledger without its owned rules, which can run into the tens of
+    /// thousands. Low-memory mode finds its own live rules by content root
+    /// instead and records them afresh.
+    pub fn load_foreign() -> Self {
+        fs::read_to_string(defs::RULE_LEDGER_FILE).map(|s| Self::foreign_from(&s)).unwrap_or_default()
+    }
+
+    fn foreign_from(json: &str) -> Self {
+        #[derive(Deserialize)]
+        struct Foreign {
+            #[serde(default)]
+            external: Vec<RuleOp>,
+            #[serde(default)]
+            namespaces: BTreeMap<String, Vec<RuleOp>>,
+            #[serde(default)]
+            packages: BTreeMap<String, String>,
+        }
+        serde_json::from_str::<Foreign>(json)
+            .map(|f| Self { owned: RuleSet::default(), external: f.external, namespaces: f.namespaces, packages: f.packages })
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = Path::new(defs::RULE_LEDGER_FILE).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(defs::RULE_LEDGER_FILE, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn is_external(&self, path: &str) -> bool {
+        self.external.iter().any(|op| op.path() == path)
+    }
+
+    /// The namespace that holds `path`, if another consumer added it.
+    pub fn namespace_of(&self, path: &str) -> Option<&str> {
+        self.namespaces.iter()
+            .find(|(_, ops)| ops.iter().any(|op| op.path() == path))
+            .map(|(owner, _)| owner.as_str())
+    }
+
+    /// Rules this crate must neither claim, reset nor adopt.
+    pub fn is_foreign(&self, path: &str) -> bool {
+        self.is_external(path) || self.namespace_of(path).is_some()
+    }
+
+    /// Adopted and namespaced rules, restored after a full HymoFS reset.
+    pub fn foreign_rules(&self) -> Vec<RuleOp> {
+        self.external.iter()
+            .chain(self.namespaces.values().flatten())
+            .cloned()
+            .collect()
+    }
+
+    /// A live rule is ours if the ledger recorded it, or if it redirects into
+    /// one of our content roots even though the ledger lost track of it.
+    pub fn is_owned(&self, op: &RuleOp, roots: &[PathBuf]) -> bool {
+        if self.owned.contains(op.path()) {
+            return true;
+        }
+        match op {
+            RuleOp::Add { target, .. } if !self.is_foreign(op.path()) => {
+                roots.iter().any(|root| Path::new(target).starts_with(root))
+            }
+            _ => false,
+        }
+    }
+
+    pub fn owned_live<'a>(&self, live: &'a [RuleOp], roots: &[PathBuf]) -> Vec<&'a RuleOp> {
+        live.iter().filter(|op| self.is_owned(op, roots)).collect()
+    }
+
+    /// Live rules that neither this crate created nor were adopted before.
+    pub fn unmanaged<'a>(&self, live: &'a [RuleOp], roots: &[PathBuf]) -> Vec<&'a RuleOp> {
+        live.iter()
+            .filter(|op| !self.is_owned(op, roots) && !self.is_foreign(op.path()))
+            .collect()
+    }
+
+    pub fn adopt(&mut self, live: &[RuleOp], roots: &[PathBuf]) -> usize {
+        let adopted: Vec<RuleOp> = self.unmanaged(live, roots).into_iter().cloned().collect();
+        let count = adopted.len();
+        self.external.extend(adopted);
+        count
+    }
+
+    pub fn release(&mut self, paths: &[String]) -> usize {
+        let before = self.external.len();
+        self.external.retain(|op| !paths.iter().any(|p| p == op.path()));
+        before - self.external.len()
+    }
+
+    pub fn record_owned<'a, I: IntoIterator<Item = (&'a str, &'a RuleOp)>>(&mut self, rules: I) {
+        self.owned.clear();
+        self.claim(rules);
+    }
+
+    pub fn claim<'a, I: IntoIterator<Item = (&'a str, &'a RuleOp)>>(&mut self, rules: I) {
+        let claimed: Vec<(&str, &str)> = rules.into_iter()
+            .filter(|(_, op)| !self.is_foreign(op.path()))
+            .map(|(module_id, op)| (op.path(), module_id))
+            .collect();
+        self.owned.extend(claimed);
+    }
+
+    pub fn disown(&mut self, module_id: &str) {
+        self.owned.remove_owner(module_id);
+    }
+
+    /// Rules of `module_id` recorded below `prefix`, e.g. one partition.
+    pub fn owned_by_under(&self, module_id: &str, prefix: &str) -> Vec<String> {
+        self.owned.under(prefix)
+            .into_iter()
+            .filter(|(_, owner)| *owner == module_id)
+            .map(|(path, _)| path)
+            .collect()
+    }
+
+    /// Deletes every rule recorded for `module_id` and forgets them, with no
+    /// need for the module's tree; returns how many went and how many failed.
+    pub fn remove_module(&mut self, hymofs: &HymoFs, module_id: &str) -> (usize, usize) {
+        let (mut removed, mut failed) = (0, 0);
+        for src in self.owned_by_under(module_id, "/") {
+            match hymofs.apply_op(&RuleOp::Delete { src }) {
+                Ok(()) => removed += 1,
+                Err(e) => {
+                    log::warn!("!! {:#}", e);
+                    failed += 1;
+                }
+            }
+        }
+        self.disown(module_id);
+        (removed, failed)
+    }
+}
+
+pub fn owned_roots(config: &Config, storage_root: Option<&Path>) -> Vec<PathBuf> {
+    let mut roots = roots::configured(config);
+    roots.push(staging::content_dir());
+    roots.extend(storage_root.filter(|p| !p.as_os_str().is_empty()).map(Path::to_path_buf));
+    roots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mount::hymofs::HymoFileType;
+
+    fn add(src: &str) -> RuleOp {
+        RuleOp::Add { src: src.into(), target: format!("/vendor_tool{}", src), file_type: HymoFileType::Reg }
+    }
+
+    #[test]
+    fn adopts_only_unmanaged_rules_once() {
+        let mut ledger = RuleLedger::default();
+        ledger.record_owned([("mod_a", &add("/system/bin/a"))]);
+        let live = vec![add("/system/bin/a"), add("/system/bin/b"), RuleOp::Hide { path: "/system/app/C".into() }];
+        assert_eq!(ledger.adopt(&live, &[]), 2);
+        assert_eq!(ledger.adopt(&live, &[]), 0);
+        assert!(ledger.is_external("/system/app/C"));
+        assert!(!ledger.is_external("/system/bin/a"));
+    }
+
+    #[test]
+    fn loads_only_the_foreign_rules_for_low_memory() {
+        let mut ledger = RuleLedger::default();
+        ledger.claim([("mod_a", &add("/system/bin/a"))]);
+        ledger.adopt(&[add("/system/bin/b")], &[]);
+        let foreign = RuleLedger::foreign_from(&serde_json::to_string(&ledger).unwrap());
+        assert!(foreign.owned.is_empty());
+        assert!(foreign.is_external("/system/bin/b"));
+    }
+
+    #[test]
+    fn external_rules_are_never_claimed_as_owned() {
+        let mut ledger = RuleLedger::default();
+        ledger.adopt(&[add("/system/bin/b")], &[]);
+        ledger.record_owned([("mod_a", &add("/system/bin/a")), ("mod_b", &add("/system/bin/b"))]);
+        assert_eq!(ledger.owned.len(), 1);
+        assert_eq!(ledger.release(&["/system/bin/b".to_string()]), 1);
+        assert!(ledger.external.is_empty());
+    }
+
+    #[test]
+    fn recognizes_owned_rules_by_ledger_or_content_root() {
+        let mut ledger = RuleLedger::default();
+        ledger.claim([("mod_a", &RuleOp::Hide { path: "/system/app/X".into() })]);
+        let roots = vec![PathBuf::from("/vendor_tool/system/bin/mine")];
+        let live = vec![
+            RuleOp::Hide { path: "/system/app/X".into() },
+            RuleOp::Hide { path: "/system/app/Y".into() },
+            add("/system/bin/mine"),
+            add("/system/bin/theirs"),
+        ];
+        let owned: Vec<&str> = ledger.owned_live(&live, &roots).iter().map(|op| op.path()).collect();
+        assert_eq!(owned, vec!["/system/app/X", "/system/bin/mine"]);
+        assert_eq!(ledger.unmanaged(&live, &roots).len(), 2);
+        ledger.disown("mod_a");
+        assert!(!ledger.is_owned(&live[0], &roots));
+    }
+
+    #[test]
+    fn namespaced_rules_are_neither_claimed_nor_adopted() {
+        let mut ledger = RuleLedger::default();
+        ledger.namespaces.insert("tool".into(), vec![add("/system/bin/t")]);
+        let roots = vec![PathBuf::from("/vendor_tool")];
+        ledger.record_owned([("mod_a", &add("/system/bin/t"))]);
+        assert!(ledger.owned.is_empty());
+        assert!(!ledger.is_owned(&add("/system/bin/t"), &roots));
+        assert_eq!(ledger.adopt(&[add("/system/bin/t")], &roots), 0);
+        assert_eq!(ledger.namespace_of("/system/bin/t"), Some("tool"));
+        assert_eq!(ledger.foreign_rules(), vec![add("/system/bin/t")]);
+    }
+
+    #[test]
+    fn ledger_round_trips_through_json() {
+        let mut ledger = RuleLedger::default();
+        ledger.adopt(&[add("/system/bin/b"), RuleOp::Hide { path: "/system/app/C".into() }], &[]);
+        let json = serde_json::to_string(&ledger).unwrap();
+        let loaded: RuleLedger = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.external, ledger.external);
+    }
+}
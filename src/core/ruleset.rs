@@ -0,0 +1,224 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[derive(Debug, Default, Clone)]
+struct Interner {
+    strings: Vec<Arc<str>>,
+    index: HashMap<Arc<str>, u32>,
+}
+
+impl Interner {
+    fn intern(&mut self, value: &str) -> u32 {
+        if let Some(&id) = self.index.get(value) {
+            return id;
+        }
+        let id = self.strings.len() as u32;
+        let shared: Arc<str> = Arc::from(value);
+        self.strings.push(shared.clone());
+        self.index.insert(shared, id);
+        id
+    }
+
+    fn get(&self, value: &str) -> Option<u32> {
+        self.index.get(value).copied()
+    }
+
+    fn resolve(&self, id: u32) -> &str {
+        &self.strings[id as usize]
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    dir: u32,
+    name: Box<str>,
+    owner: u32,
+}
+
+/// Path -> owner map for large rule caches. Parent directories and owners are
+/// interned, so each rule only stores its file name, and entries stay sorted
+/// by (directory, name) for binary-searched lookups and prefix ranges.
+#[derive(Debug, Default, Clone)]
+pub struct RuleSet {
+    dirs: Interner,
+    owners: Interner,
+    entries: Vec<Entry>,
+}
+
+fn split(path: &str) -> (&str, &str) {
+    match path.rfind('/') {
+        Some(i) => path.split_at(i + 1),
+        None => ("", path),
+    }
+}
+
+impl RuleSet {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn key<'a>(&'a self, entry: &'a Entry) -> (&'a str, &'a str) {
+        (self.dirs.resolve(entry.dir), &entry.name)
+    }
+
+    fn position(&self, path: &str) -> Result<usize, usize> {
+        let wanted = split(path);
+        self.entries.binary_search_by(|entry| self.key(entry).cmp(&wanted))
+    }
+
+    fn full_path(&self, entry: &Entry) -> String {
+        let dir = self.dirs.resolve(entry.dir);
+        let mut path = String::with_capacity(dir.len() + entry.name.len());
+        path.push_str(dir);
+        path.push_str(&entry.name);
+        path
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        self.position(path).is_ok()
+    }
+
+    pub fn owner(&self, path: &str) -> Option<&str> {
+        let i = self.position(path).ok()?;
+        Some(self.owners.resolve(self.entries[i].owner))
+    }
+
+    pub fn remove(&mut self, path: &str) -> bool {
+        match self.position(path) {
+            Ok(i) => {
+                self.entries.remove(i);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Bulk insert; later duplicates replace earlier owners.
+    pub fn extend<'a, I: IntoIterator<Item = (&'a str, &'a str)>>(&mut self, rules: I) {
+        for (path, owner) in rules {
+            let (dir, name) = split(path);
+            let entry = Entry { dir: self.dirs.intern(dir), name: name.into(), owner: self.owners.intern(owner) };
+            self.entries.push(entry);
+        }
+        let dirs = &self.dirs;
+        self.entries.sort_by(|a, b| (dirs.resolve(a.dir), &a.name).cmp(&(dirs.resolve(b.dir), &b.name)));
+        let mut deduped: Vec<Entry> = Vec::with_capacity(self.entries.len());
+        for entry in self.entries.drain(..) {
+            match deduped.last_mut() {
+                Some(last) if last.dir == entry.dir && last.name == entry.name => *last = entry,
+                _ => deduped.push(entry),
+            }
+        }
+        self.entries = deduped;
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn remove_owner(&mut self, owner: &str) {
+        if let Some(id) = self.owners.get(owner) {
+            self.entries.retain(|entry| entry.owner != id);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (String, &str)> + '_ {
+        self.entries.iter().map(|entry| (self.full_path(entry), self.owners.resolve(entry.owner)))
+    }
+
+    /// Rules at `prefix` or anywhere below it, found with two binary searches.
+    pub fn under(&self, prefix: &str) -> Vec<(String, &str)> {
+        let prefix = prefix.trim_end_matches('/');
+        let mut found = Vec::new();
+        if let Ok(i) = self.position(prefix) {
+            found.push((self.full_path(&self.entries[i]), self.owners.resolve(self.entries[i].owner)));
+        }
+        let dir_prefix = format!("{}/", prefix);
+        let start = self.entries.partition_point(|e| self.dirs.resolve(e.dir) < dir_prefix.as_str());
+        found.extend(self.entries[start..].iter()
+            .take_while(|e| self.dirs.resolve(e.dir).starts_with(&dir_prefix))
+            .map(|e| (self.full_path(e), self.owners.resolve(e.owner))));
+        found
+    }
+}
+
+impl Serialize for RuleSet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter())
+    }
+}
+
+impl<'de> Deserialize<'de> for RuleSet {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let map = BTreeMap::<String, String>::deserialize(deserializer)?;
+        let mut set = RuleSet::default();
+        set.extend(map.iter().map(|(path, owner)| (path.as_str(), owner.as_str())));
+        Ok(set)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> RuleSet {
+        let mut set = RuleSet::default();
+        set.extend([
+            ("/system/app/Foo/Foo.apk", "a"),
+            ("/system/app-extra/x", "b"),
+            ("/system/app", "c"),
+            ("/system/apple", "c"),
+            ("/system/app/Bar/lib/arm64/libbar.so", "b"),
+            ("/vendor/etc/foo.conf", "a"),
+        ]);
+        set
+    }
+
+    #[test]
+    fn looks_up_and_replaces_owners() {
+        let mut set = sample();
+        assert!(set.contains("/system/app/Foo/Foo.apk"));
+        assert!(!set.contains("/system/app/Foo"));
+        set.extend([("/system/app/Foo/Foo.apk", "z")]);
+        assert_eq!(set.under("/system/app/Foo/Foo.apk"), vec![("/system/app/Foo/Foo.apk".to_string(), "z")]);
+        assert_eq!(set.owner("/system/app/Foo/Foo.apk"), Some("z"));
+        assert_eq!(set.len(), 6);
+        assert!(set.remove("/system/app/Foo/Foo.apk"));
+        assert!(!set.remove("/system/app/Foo/Foo.apk"));
+        assert_eq!(set.owner("/system/app/Foo/Foo.apk"), None);
+    }
+
+    #[test]
+    fn prefix_queries_respect_component_boundaries() {
+        let set = sample();
+        let mut under: Vec<String> = set.under("/system/app/").into_iter().map(|(p, _)| p).collect();
+        under.sort();
+        assert_eq!(under, vec!["/system/app", "/system/app/Bar/lib/arm64/libbar.so", "/system/app/Foo/Foo.apk"]);
+        assert!(set.under("/odm").is_empty());
+    }
+
+    #[test]
+    fn interns_shared_directories_and_owners() {
+        let mut set = RuleSet::default();
+        let paths: Vec<String> = (0..100).map(|i| format!("/system/lib64/lib{}.so", i)).collect();
+        set.extend(paths.iter().map(|p| (p.as_str(), "mod")));
+        assert_eq!(set.dirs.strings.len(), 1);
+        assert_eq!(set.owners.strings.len(), 1);
+        set.remove_owner("mod");
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn serializes_as_plain_path_map() {
+        let set = sample();
+        let json = serde_json::to_value(&set).unwrap();
+        assert_eq!(json["/vendor/etc/foo.conf"], "a");
+        let back: RuleSet = serde_json::from_value(json).unwrap();
+        assert_eq!(back.iter().collect::<Vec<_>>(), set.iter().collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+use anyhow::{Context, Result, bail};
+use crate::{defs, utils};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+const SCRIPT_PATH: &str = "/system/bin:/system/xbin:/data/adb/ksu/bin";
+/// Optional `KEY=value` file in the module root whose values are added to
+/// the environment of every script the module runs.
+pub const ENV_FILE_NAME: &str = "hybrid.env";
+
+/// Why a module script is being run, passed to it as `STAGE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Uninstall,
+    Cleanup,
+}
+
+impl Stage {
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Uninstall => "uninstall",
+            Stage::Cleanup => "cleanup",
+        }
+    }
+}
+
+/// Parses an env file: `KEY=value` lines, optionally prefixed with `export`
+/// and with the value in single or double quotes. Blank lines, comments and
+/// malformed keys are skipped.
+pub fn parse_env_file(content: &str) -> Vec<(String, String)> {
+    content.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            let (key, value) = line.split_once('=')?;
+            let key = key.trim();
+            let valid = key.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !valid {
+                return None;
+            }
+            let value = value.trim();
+            let unquoted = ['"', '\'']
+                .into_iter()
+                .find_map(|q| value.strip_prefix(q).and_then(|v| v.strip_suffix(q)))
+                .unwrap_or(value);
+            Some((key.to_string(), unquoted.to_string()))
+        })
+        .collect()
+}
+
+/// Runs the scripts and hooks of one module with a fixed environment:
+///
+/// - `MODDIR` (and `MODPATH`): the module directory
+/// - `MODULE_ID`: the module id
+/// - `HYMO_VERSION`: version of meta-hybrid
+/// - `BACKEND`: `overlay`, `magic`, `hymofs` or `direct`, or `none` when the
+///   module is not mounted
+/// - `STAGE`: why the script runs, see [`Stage`]
+/// - `ARCH`: primary ABI of the device
+/// - `API`: Android SDK level
+///
+/// Values from the module's [`ENV_FILE_NAME`] are added first and cannot
+/// replace any of these, or `PATH`.
+pub struct ScriptRunner {
+    env: BTreeMap<String, String>,
+    module_dir: PathBuf,
+    timeout: Duration,
+}
+
+impl ScriptRunner {
+    pub fn new(module_id: &str, module_dir: &Path, backend: Option<&str>, stage: Stage, timeout: Duration) -> Self {
+        let mut env: BTreeMap<String, String> = fs::read_to_string(module_dir.join(ENV_FILE_NAME))
+            .map(|content| parse_env_file(&content).into_iter().collect())
+            .unwrap_or_default();
+        let moddir = module_dir.to_string_lossy().into_owned();
+        let fixed = [
+            ("PATH", SCRIPT_PATH.to_string()),
+            ("MODDIR", moddir.clone()),
+            ("MODPATH", moddir),
+            ("MODULE_ID", module_id.to_string()),
+            ("HYMO_VERSION", env!("CARGO_PKG_VERSION").to_string()),
+            ("BACKEND", backend.unwrap_or("none").to_string()),
+            ("STAGE", stage.name().to_string()),
+            ("ARCH", utils::get_prop("ro.product.cpu.abi").unwrap_or_default()),
+            ("API", utils::get_prop("ro.build.version.sdk").unwrap_or_default()),
+        ];
+        for (key, value) in fixed {
+            if env.insert(key.to_string(), value).is_some() {
+                log::warn!("!! {}: {} sets {}, which meta-hybrid provides", module_id, ENV_FILE_NAME, key);
+            }
+        }
+        Self { env, module_dir: module_dir.to_path_buf(), timeout }
+    }
+
+    pub fn run(&self, script: &Path) -> Result<()> {
+        let log = OpenOptions::new().create(true).append(true).open(defs::DAEMON_LOG_FILE)
+            .context("Failed to open log for module script")?;
+        let mut child = Command::new("sh")
+            .arg(script)
+            .current_dir(&self.module_dir)
+            .env_clear()
+            .envs(&self.env)
+            .stdin(Stdio::null())
+            .stdout(log.try_clone()?)
+            .stderr(log)
+            .spawn()
+            .with_context(|| format!("Failed to spawn {}", script.display()))?;
+
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            if let Some(status) = child.try_wait()? {
+                if !status.success() {
+                    bail!("{} exited with {}", script.display(), status);
+                }
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                bail!("{} timed out after {}s", script.display(), self.timeout.as_secs());
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_env_files() {
+        let parsed = parse_env_file("# comment\nFOO=bar\nexport QUOTED=\"a b\"\nSINGLE='x'\n1BAD=no\nnot a pair\n EMPTY=\n");
+        assert_eq!(parsed, vec![
+            ("FOO".to_string(), "bar".to_string()),
+            ("QUOTED".to_string(), "a b".to_string()),
+            ("SINGLE".to_string(), "x".to_string()),
+            ("EMPTY".to_string(), String::new()),
+        ]);
+    }
+
+    #[test]
+    fn module_values_never_replace_the_fixed_ones() {
+        let dir = std::env::temp_dir().join(format!("scripts-env-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(ENV_FILE_NAME), "BACKEND=overlay\nPATH=/tmp\nCUSTOM=1\n").unwrap();
+        let runner = ScriptRunner::new("demo", &dir, Some("hymofs"), Stage::Uninstall, Duration::from_secs(1));
+        let env = &runner.env;
+        assert_eq!(env["BACKEND"], "hymofs");
+        assert_eq!(env["PATH"], SCRIPT_PATH);
+        assert_eq!(env["CUSTOM"], "1");
+        assert_eq!(env["STAGE"], "uninstall");
+        assert_eq!(env["MODDIR"], dir.to_string_lossy());
+        assert_eq!(env["HYMO_VERSION"], env!("CARGO_PKG_VERSION"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
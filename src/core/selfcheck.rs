@@ -0,0 +1,192 @@
+use std::fs;
+use std::path::Path;
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+use crate::{defs, utils, core::planner::MountPlan, mount::hymofs::{HymoFileType, HymoFs, RuleOp}};
+
+const SRCVERSION_FILE: &str = "/sys/module/hymofs/srcversion";
+const OSRELEASE_FILE: &str = "/proc/sys/kernel/osrelease";
+/// Rules per module redirected and read back through the VFS.
+const SAMPLES_PER_MODULE: usize = 2;
+/// Sampled files are read whole, so large ones are left to the full apply.
+const SAMPLE_MAX_BYTES: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: String,
+    pub passed: bool,
+    pub detail: String,
+}
+
+/// Result of checking a HymoFS build this device had not verified yet.
+#[derive(Debug, Clone, Serialize)]
+pub struct SelfCheck {
+    pub fingerprint: String,
+    pub previous: Option<String>,
+    pub checks: Vec<Check>,
+}
+
+impl SelfCheck {
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|c| c.passed)
+    }
+
+    pub fn failures(&self) -> Vec<String> {
+        self.checks.iter()
+            .filter(|c| !c.passed)
+            .map(|c| format!("{}: {}", c.name, c.detail))
+            .collect()
+    }
+}
+
+fn read_trimmed(path: &str) -> Option<String> {
+    fs::read_to_string(path).ok().map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+/// Identifies the loaded HymoFS build: its protocol, the kernel it runs in
+/// and, for a loadable module, the srcversion the kernel computed for it.
+pub fn fingerprint(handle: &HymoFs) -> Option<String> {
+    let version = handle.get_version().ok()?;
+    let mut fingerprint = format!("protocol={} kernel={}", version, read_trimmed(OSRELEASE_FILE).unwrap_or_default());
+    if let Some(srcversion) = read_trimmed(SRCVERSION_FILE) {
+        fingerprint.push_str(&format!(" srcversion={}", srcversion));
+    }
+    Some(fingerprint)
+}
+
+/// The first few regular-file redirects of every HymoFS operation, small
+/// enough to read back whole.
+pub fn sample(plan: &MountPlan) -> Vec<(String, RuleOp)> {
+    plan.hymo_ops.iter()
+        .flat_map(|op| {
+            plan.rules_of(op).into_iter()
+                .filter(|rule| match rule {
+                    RuleOp::Add { target, file_type: HymoFileType::Reg, .. } => {
+                        fs::metadata(target).is_ok_and(|m| m.len() <= SAMPLE_MAX_BYTES)
+                    }
+                    _ => false,
+                })
+                .take(SAMPLES_PER_MODULE)
+                .map(|rule| (op.module_id.clone(), rule))
+        })
+        .collect()
+}
+
+/// Probes that the listing did not return exactly as they were written.
+pub fn missing<'a>(probes: &'a [RuleOp], listed: &[RuleOp]) -> Vec<&'a RuleOp> {
+    probes.iter().filter(|probe| !listed.contains(probe)).collect()
+}
+
+pub fn check(name: &str, result: Result<String>) -> Check {
+    match result {
+        Ok(detail) => Check { name: name.to_string(), passed: true, detail },
+        Err(e) => Check { name: name.to_string(), passed: false, detail: format!("{:#}", e) },
+    }
+}
+
+fn round_trip(handle: &HymoFs, scratch: &Path) -> Result<String> {
+    let source = scratch.join(defs::CANARY_SOURCE_NAME);
+    fs::write(&source, "meta-hybrid round trip").context("Failed to write probe source")?;
+    let probes = [
+        RuleOp::Add { src: defs::CANARY_TARGET.to_string(), target: source.to_string_lossy().into_owned(), file_type: HymoFileType::Reg },
+        RuleOp::Hide { path: format!("{}.hidden", defs::CANARY_TARGET) },
+    ];
+    let result = probes.iter().try_for_each(|probe| handle.apply_op(probe))
+        .and_then(|()| handle.list_ops())
+        .map_err(anyhow::Error::from)
+        .and_then(|listed| {
+            let lost = missing(&probes, &listed);
+            if let Some(probe) = lost.first() {
+                bail!("listing does not round-trip {}", probe.path());
+            }
+            Ok(format!("{} probe rules listed back unchanged", probes.len()))
+        });
+    for probe in &probes {
+        let _ = handle.delete_rule(probe.path());
+    }
+    let _ = fs::remove_file(&source);
+    result
+}
+
+fn verify_sample(handle: &HymoFs, samples: &[(String, RuleOp)]) -> Result<String> {
+    for (module_id, rule) in samples {
+        let RuleOp::Add { src, target, .. } = rule else { continue };
+        let expected = fs::read(target).with_context(|| format!("{}: cannot read {}", module_id, target))?;
+        let result = handle.apply_op(rule).map_err(anyhow::Error::from).and_then(|()| {
+            let seen = fs::read(src).with_context(|| format!("{}: {} is not readable through the rule", module_id, src))?;
+            if seen != expected {
+                bail!("{}: {} does not resolve to {}", module_id, src, target);
+            }
+            Ok(())
+        });
+        let _ = handle.delete_rule(src);
+        result?;
+    }
+    Ok(format!("{} module files resolved through the VFS", samples.len()))
+}
+
+/// Runs the canary, a rule-list round trip and a sample of every module's
+/// redirects when the loaded HymoFS differs from the last one that passed.
+/// Returns `None` when it is the same build. A pass is remembered; a failure
+/// is checked again on the next boot.
+pub fn after_update(handle: &HymoFs, plan: &MountPlan) -> Option<SelfCheck> {
+    let fingerprint = fingerprint(handle)?;
+    let previous = read_trimmed(defs::HYMOFS_VERIFIED_FILE);
+    if previous.as_deref() == Some(fingerprint.as_str()) {
+        return None;
+    }
+    let scratch = Path::new(defs::RUN_DIR);
+    let checks = vec![
+        check("canary", handle.self_test(scratch)),
+        check("list round trip", round_trip(handle, scratch)),
+        check("module sample", verify_sample(handle, &sample(plan))),
+    ];
+    let result = SelfCheck { fingerprint, previous, checks };
+    if result.passed() {
+        if let Err(e) = utils::atomic_write(defs::HYMOFS_VERIFIED_FILE, &result.fingerprint) {
+            log::warn!("Failed to record verified HymoFS build: {}", e);
+        }
+    }
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::{core::{inventory::BootWindow, planner::HymoOperation}, mount::hymofs};
+
+    #[test]
+    fn samples_small_regular_files_of_every_module() {
+        let root = std::env::temp_dir().join(format!("selfcheck-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for (module, file) in [("a", "vendor/lib/1.so"), ("a", "vendor/lib/2.so"), ("a", "vendor/lib/3.so"), ("b", "vendor/etc/b.conf")] {
+            let path = root.join(module).join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, module).unwrap();
+        }
+        let op = |id: &str| HymoOperation {
+            module_id: id.into(),
+            source: root.join(id).join("vendor"),
+            target: PathBuf::from("/vendor"),
+            window: BootWindow::Always,
+        };
+        let plan = MountPlan { hymo_ops: vec![op("a"), op("b")], ..Default::default() };
+        let samples = sample(&plan);
+        let per_module = |id: &str| samples.iter().filter(|(m, _)| m == id).count();
+        assert_eq!((per_module("a"), per_module("b")), (SAMPLES_PER_MODULE, 1));
+        assert!(samples.iter().all(|(_, rule)| rule.path().starts_with("/vendor/")));
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn round_trip_compares_whole_rules() {
+        let add = RuleOp::Add { src: "/system/a".into(), target: "/mnt/a".into(), file_type: HymoFileType::Reg };
+        let hide = RuleOp::Hide { path: "/system/b".into() };
+        let ops = |listing: &str| -> Vec<RuleOp> { hymofs::parse_rules(listing).iter().filter_map(hymofs::Rule::op).collect() };
+        let listed = ops("add /system/a /mnt/a 8\nhide /system/b\n");
+        assert!(missing(&[add.clone(), hide.clone()], &listed).is_empty());
+        let mangled = ops("add /system/a /mnt/a 0\n");
+        assert_eq!(missing(&[add.clone(), hide.clone()], &mangled), vec![&add, &hide]);
+    }
+}
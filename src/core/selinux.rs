@@ -0,0 +1,432 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use serde::{Deserialize, Serialize};
+use crate::{core::sepolicy::SELINUXFS, mount::hymofs::{HymoFileType, RuleOp}, utils};
+
+/// Labels module files with the context the path they appear at expects
+/// before HymoFS redirects it, so apps reading them are not denied.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SelinuxLabels {
+    pub enabled: bool,
+    /// Leave labels alone while SELinux is permissive or disabled.
+    pub skip_permissive: bool,
+    /// Consulted, in order, for paths no original file exists at; later
+    /// files override earlier ones.
+    pub file_contexts: Vec<PathBuf>,
+}
+
+impl Default for SelinuxLabels {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            skip_permissive: true,
+            file_contexts: [
+                "/system/etc/selinux/plat_file_contexts",
+                "/system_ext/etc/selinux/system_ext_file_contexts",
+                "/product/etc/selinux/product_file_contexts",
+                "/vendor/etc/selinux/vendor_file_contexts",
+                "/odm/etc/selinux/odm_file_contexts",
+            ].into_iter().map(PathBuf::from).collect(),
+        }
+    }
+}
+
+/// The POSIX extended regex subset file_contexts is written in: literals,
+/// `.`, bracket classes, groups with `|`, and `*`, `+`, `?` and `{n,m}`.
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Byte(u8),
+    Any,
+    Class { negated: bool, ranges: Vec<(u8, u8)> },
+    Group(Vec<Vec<Node>>),
+    Repeat(Box<Node>, usize, Option<usize>),
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        self.pos += 1;
+        Some(byte)
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        let found = self.peek() == Some(byte);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn alternation(&mut self) -> Option<Vec<Vec<Node>>> {
+        let mut alternatives = vec![self.sequence()?];
+        while self.eat(b'|') {
+            alternatives.push(self.sequence()?);
+        }
+        Some(alternatives)
+    }
+
+    fn sequence(&mut self) -> Option<Vec<Node>> {
+        let mut nodes = Vec::new();
+        while let Some(byte) = self.peek() {
+            if byte == b'|' || byte == b')' {
+                break;
+            }
+            self.pos += 1;
+            let atom = match byte {
+                b'.' => Node::Any,
+                b'(' => {
+                    let group = self.alternation()?;
+                    if !self.eat(b')') {
+                        return None;
+                    }
+                    Node::Group(group)
+                }
+                b'[' => self.class()?,
+                b'\\' => Node::Byte(self.next()?),
+                b'^' | b'$' => continue,
+                b'*' | b'+' | b'?' | b'{' => return None,
+                byte => Node::Byte(byte),
+            };
+            nodes.push(match self.quantifier()? {
+                Some((min, max)) => Node::Repeat(Box::new(atom), min, max),
+                None => atom,
+            });
+        }
+        Some(nodes)
+    }
+
+    /// `None` for a malformed quantifier, `Some(None)` for none at all.
+    fn quantifier(&mut self) -> Option<Option<(usize, Option<usize>)>> {
+        let bounds = match self.peek() {
+            Some(b'*') => (0, None),
+            Some(b'+') => (1, None),
+            Some(b'?') => (0, Some(1)),
+            Some(b'{') => {
+                self.pos += 1;
+                let min = self.number()?;
+                let max = if self.eat(b',') { if self.peek() == Some(b'}') { None } else { Some(self.number()?) } } else { Some(min) };
+                if self.peek() != Some(b'}') || max.is_some_and(|max| max < min) {
+                    return None;
+                }
+                (min, max)
+            }
+            _ => return Some(None),
+        };
+        self.pos += 1;
+        Some(Some(bounds))
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        let start = self.pos;
+        while self.peek().is_some_and(|b| b.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        std::str::from_utf8(self.text.get(start..self.pos)?).ok()?.parse().ok()
+    }
+
+    fn class(&mut self) -> Option<Node> {
+        let negated = self.eat(b'^');
+        let mut ranges = Vec::new();
+        let mut first = true;
+        loop {
+            let mut low = self.next()?;
+            if low == b']' && !first {
+                break;
+            }
+            first = false;
+            if low == b'\\' {
+                low = self.next()?;
+            }
+            let mut high = low;
+            if self.peek() == Some(b'-') && self.text.get(self.pos + 1).is_some_and(|b| *b != b']') {
+                self.pos += 1;
+                high = self.next()?;
+                if high == b'\\' {
+                    high = self.next()?;
+                }
+            }
+            ranges.push((low, high));
+        }
+        Some(Node::Class { negated, ranges })
+    }
+}
+
+/// Positions in `input` where `node`, started at `pos`, can end.
+fn node_ends(node: &Node, input: &[u8], pos: usize) -> BTreeSet<usize> {
+    let next = |matched: bool| if matched { BTreeSet::from([pos + 1]) } else { BTreeSet::new() };
+    match node {
+        Node::Byte(byte) => next(input.get(pos) == Some(byte)),
+        Node::Any => next(pos < input.len()),
+        Node::Class { negated, ranges } => next(input.get(pos).is_some_and(|b| ranges.iter().any(|(lo, hi)| (lo..=hi).contains(&b)) != *negated)),
+        Node::Group(alternatives) => alternatives.iter().flat_map(|seq| seq_ends(seq, input, BTreeSet::from([pos]))).collect(),
+        Node::Repeat(inner, min, max) => {
+            let mut ends = BTreeSet::new();
+            if *min == 0 {
+                ends.insert(pos);
+            }
+            let mut seen = BTreeSet::from([pos]);
+            let mut frontier = BTreeSet::from([pos]);
+            let mut count = 0;
+            while !frontier.is_empty() && max.is_none_or(|max| count < max) {
+                let reached: BTreeSet<usize> = frontier.iter().flat_map(|p| node_ends(inner, input, *p)).collect();
+                count += 1;
+                if count >= *min {
+                    ends.extend(&reached);
+                    frontier = reached.difference(&seen).copied().collect();
+                    seen.extend(&reached);
+                } else {
+                    frontier = reached;
+                }
+            }
+            ends
+        }
+    }
+}
+
+fn seq_ends(nodes: &[Node], input: &[u8], starts: BTreeSet<usize>) -> BTreeSet<usize> {
+    nodes.iter().fold(starts, |set, node| set.into_iter().flat_map(|pos| node_ends(node, input, pos)).collect())
+}
+
+#[derive(Debug, Clone)]
+struct Spec {
+    nodes: Vec<Node>,
+    /// The literal start every match has, to skip most specs cheaply.
+    prefix: Vec<u8>,
+    exact: bool,
+    file_type: Option<HymoFileType>,
+    /// `None` for `<<none>>`: the path is deliberately left unlabeled.
+    context: Option<String>,
+}
+
+impl Spec {
+    fn parse(line: &str) -> Option<Self> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (pattern, file_type, context) = match fields.as_slice() {
+            [pattern, context] => (*pattern, None, *context),
+            [pattern, kind, context] => (*pattern, Some(file_type(kind)?), *context),
+            _ => return None,
+        };
+        let mut parser = Parser { text: pattern.as_bytes(), pos: 0 };
+        let alternatives = parser.alternation()?;
+        if parser.pos != pattern.len() {
+            return None;
+        }
+        let nodes = match <[Vec<Node>; 1]>::try_from(alternatives) {
+            Ok([nodes]) => nodes,
+            Err(alternatives) => vec![Node::Group(alternatives)],
+        };
+        let prefix: Vec<u8> = nodes.iter().map_while(|node| match node {
+            Node::Byte(byte) => Some(*byte),
+            _ => None,
+        }).collect();
+        Some(Self {
+            exact: prefix.len() == nodes.len(),
+            prefix,
+            nodes,
+            file_type,
+            context: (context != "<<none>>").then(|| context.to_string()),
+        })
+    }
+
+    fn matches(&self, path: &[u8], kind: HymoFileType) -> bool {
+        path.starts_with(&self.prefix)
+            && self.file_type.is_none_or(|wanted| wanted == kind)
+            && seq_ends(&self.nodes, path, BTreeSet::from([0])).contains(&path.len())
+    }
+}
+
+fn file_type(field: &str) -> Option<HymoFileType> {
+    Some(match field {
+        "--" => HymoFileType::Reg,
+        "-d" => HymoFileType::Dir,
+        "-l" => HymoFileType::Lnk,
+        "-c" => HymoFileType::Chr,
+        "-b" => HymoFileType::Blk,
+        "-s" => HymoFileType::Sock,
+        "-p" => HymoFileType::Fifo,
+        _ => return None,
+    })
+}
+
+/// The path-to-context rules of the loaded policy's file_contexts files.
+#[derive(Debug, Clone, Default)]
+pub struct FileContexts {
+    specs: Vec<Spec>,
+}
+
+impl FileContexts {
+    /// Lines that are not `regex [type] context` are skipped.
+    pub fn parse(text: &str) -> Self {
+        let specs = text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let spec = Spec::parse(line);
+                if spec.is_none() {
+                    log::debug!("file_contexts: skipping {:?}", line);
+                }
+                spec
+            })
+            .collect();
+        Self { specs }
+    }
+
+    pub fn load(paths: &[PathBuf]) -> Self {
+        let mut all = Self::default();
+        for path in paths {
+            if let Ok(text) = fs::read_to_string(path) {
+                all.specs.extend(Self::parse(&text).specs);
+            }
+        }
+        all
+    }
+
+    /// The context `path` should have, as libselinux picks it: the last
+    /// matching spec, with specs free of regex syntax taking precedence.
+    pub fn lookup(&self, path: &str, kind: HymoFileType) -> Option<&str> {
+        let path = path.as_bytes();
+        let spec = self.specs.iter().rev().filter(|s| s.exact).find(|s| s.matches(path, kind))
+            .or_else(|| self.specs.iter().rev().filter(|s| !s.exact).find(|s| s.matches(path, kind)))?;
+        spec.context.as_deref()
+    }
+}
+
+/// The staged sources among `ops` whose label differs from the one the path
+/// they appear at expects, each with that label. The shadowed original's
+/// label (`original`) is preferred and `lookup` asks file_contexts for new
+/// files; `current` reads a source's label.
+fn mislabeled<O, L, C>(ops: &[RuleOp], mut original: O, mut lookup: L, mut current: C) -> Vec<(String, String)>
+where
+    O: FnMut(&Path) -> Option<String>,
+    L: FnMut(&str, HymoFileType) -> Option<String>,
+    C: FnMut(&Path) -> Option<String>,
+{
+    let mut wrong = Vec::new();
+    for op in ops {
+        let RuleOp::Add { src, target, file_type } = op else { continue };
+        if !matches!(file_type, HymoFileType::Reg | HymoFileType::Lnk | HymoFileType::Dir) {
+            continue;
+        }
+        let expected = match fs::symlink_metadata(src) {
+            Ok(_) => original(Path::new(src)),
+            Err(_) => None,
+        }.or_else(|| lookup(src, *file_type));
+        let Some(expected) = expected else { continue };
+        if current(Path::new(target)).as_deref() != Some(expected.as_str()) {
+            wrong.push((target.clone(), expected));
+        }
+    }
+    wrong
+}
+
+/// Applies [`SelinuxLabels`]; file_contexts are only read once a module
+/// adds a file no original exists for.
+#[derive(Debug)]
+pub struct Labeler {
+    file_contexts: Vec<PathBuf>,
+    contexts: OnceLock<FileContexts>,
+}
+
+fn enforcing() -> bool {
+    fs::read_to_string(Path::new(SELINUXFS).join("enforce")).is_ok_and(|mode| mode.trim() == "1")
+}
+
+impl Labeler {
+    /// `None` when labels are to be left alone.
+    pub fn new(labels: &SelinuxLabels) -> Option<Self> {
+        if !labels.enabled {
+            return None;
+        }
+        if labels.skip_permissive && !enforcing() {
+            log::debug!("SELinux is not enforcing; module files keep their labels");
+            return None;
+        }
+        Some(Self { file_contexts: labels.file_contexts.clone(), contexts: OnceLock::new() })
+    }
+
+    /// Sets the expected context on the source of every add in `ops` that
+    /// lacks it and returns how many were relabeled.
+    pub fn relabel(&self, ops: &[RuleOp]) -> usize {
+        let contexts = || self.contexts.get_or_init(|| FileContexts::load(&self.file_contexts));
+        let wrong = mislabeled(ops, |path| utils::lgetfilecon(path).ok().filter(|c| !c.is_empty()),
+            |path, kind| contexts().lookup(path, kind).map(str::to_string),
+            |path| utils::lgetfilecon(path).ok());
+        for (source, context) in &wrong {
+            log::debug!("Relabeling {} as {}", source, context);
+            if let Err(e) = utils::lsetfilecon(source, context) {
+                log::warn!("!! Failed to relabel {}: {:#}", source, e);
+            }
+        }
+        wrong.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTEXTS: &str = r"
+# comment
+/system(/.*)?                       u:object_r:system_file:s0
+/system/lib(64)?/libgles[a-z_]+\.so u:object_r:same_process_hal_file:s0
+/system/bin/[^/]+d             --   u:object_r:daemon_exec:s0
+/system/etc/hosts              --   u:object_r:system_hosts_file:s0
+/system/etc/h[aeiou]sts        --   u:object_r:system_file:s0
+/system/etc/nolabel                 <<none>>
+/system/fonts/[a-z]{2,3}\.ttf       u:object_r:font_file:s0
+/broken(                            u:object_r:system_file:s0
+";
+
+    #[test]
+    fn looks_up_file_contexts() {
+        let contexts = FileContexts::parse(CONTEXTS);
+        assert_eq!(contexts.specs.len(), 7);
+        let lookup = |path, kind| contexts.lookup(path, kind);
+        assert_eq!(lookup("/system", HymoFileType::Dir), Some("u:object_r:system_file:s0"));
+        assert_eq!(lookup("/system/lib64/libgles_mali.so", HymoFileType::Reg), Some("u:object_r:same_process_hal_file:s0"));
+        assert_eq!(lookup("/system/lib/libgles.so", HymoFileType::Reg), Some("u:object_r:system_file:s0"));
+        assert_eq!(lookup("/system/bin/netd", HymoFileType::Reg), Some("u:object_r:daemon_exec:s0"));
+        assert_eq!(lookup("/system/bin/netd", HymoFileType::Lnk), Some("u:object_r:system_file:s0"));
+        assert_eq!(lookup("/system/bin/sub/netd", HymoFileType::Reg), Some("u:object_r:system_file:s0"));
+        assert_eq!(lookup("/system/etc/hosts", HymoFileType::Reg), Some("u:object_r:system_hosts_file:s0"));
+        assert_eq!(lookup("/system/etc/nolabel", HymoFileType::Reg), None);
+        assert_eq!(lookup("/system/fonts/abc.ttf", HymoFileType::Reg), Some("u:object_r:font_file:s0"));
+        assert_eq!(lookup("/system/fonts/abcd.ttf", HymoFileType::Reg), Some("u:object_r:system_file:s0"));
+        assert_eq!(lookup("/vendor/lib/x.so", HymoFileType::Reg), None);
+    }
+
+    #[test]
+    fn prefers_the_shadowed_originals_label() {
+        let root = std::env::temp_dir().join(format!("selinux-labels-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("present"), "").unwrap();
+        let add = |src: &Path, target: &str, file_type| RuleOp::Add { src: src.to_string_lossy().into_owned(), target: target.into(), file_type };
+        let ops = vec![
+            add(&root.join("present"), "/stage/present", HymoFileType::Reg),
+            add(&root.join("new.so"), "/stage/new.so", HymoFileType::Reg),
+            add(&root.join("right"), "/stage/right", HymoFileType::Reg),
+            add(&root.join("chr"), "/stage/chr", HymoFileType::Chr),
+            RuleOp::Hide { path: "/system/app/X".into() },
+        ];
+        let wrong = mislabeled(&ops, |_| Some("u:object_r:system_lib_file:s0".into()),
+            |path, _| Some(if path.ends_with("right") { "u:object_r:right:s0" } else { "u:object_r:system_file:s0" }.into()),
+            |path| path.ends_with("right").then(|| "u:object_r:right:s0".into()));
+        assert_eq!(wrong, vec![
+            ("/stage/present".to_string(), "u:object_r:system_lib_file:s0".to_string()),
+            ("/stage/new.so".to_string(), "u:object_r:system_file:s0".to_string()),
+        ]);
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+use crate::{core::{planner::MountPlan, rewrite}, mount::hymofs::{self, HymoFileType, RuleOp}, utils};
+
+pub const SELINUXFS: &str = "/sys/fs/selinux";
+
+/// Domains that typically read module files: a third-party app and the
+/// system server.
+pub fn default_consumers() -> Vec<String> {
+    vec!["u:r:untrusted_app:s0:c512,c768".to_string(), "u:r:system_server:s0".to_string()]
+}
+
+/// Object class and permissions a consumer needs to use a file of `file_type`.
+fn access_for(file_type: HymoFileType) -> Option<(&'static str, &'static [&'static str])> {
+    match file_type {
+        HymoFileType::Reg => Some(("file", &["getattr", "open", "read"])),
+        HymoFileType::Dir => Some(("dir", &["getattr", "open", "read", "search"])),
+        HymoFileType::Lnk => Some(("lnk_file", &["getattr", "read"])),
+        _ => None,
+    }
+}
+
+/// A rule whose staged source a consumer domain would be denied.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Denial {
+    pub module_id: String,
+    /// The path the rule makes visible.
+    pub path: String,
+    pub source: String,
+    pub context: String,
+    pub consumer: String,
+}
+
+impl Denial {
+    pub fn describe(&self) -> String {
+        format!("{}: {} ({} from {}) is unreadable for {}", self.module_id, self.path, self.context, self.source, self.consumer)
+    }
+}
+
+/// Asks the kernel, through the selinuxfs access interface, what the loaded
+/// policy allows; the same query libselinux makes for `security_compute_av`.
+pub struct PolicyOracle {
+    root: PathBuf,
+    classes: HashMap<String, (u32, HashMap<String, u32>)>,
+}
+
+/// The allowed vector of an access reply, `allowed decided auditallow ...`
+/// in hex.
+fn parse_allowed(reply: &str) -> Option<u32> {
+    u32::from_str_radix(reply.split_whitespace().next()?, 16).ok()
+}
+
+fn read_number(path: &Path) -> Result<u32> {
+    let text = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    text.trim().parse().with_context(|| format!("{} is not a number", path.display()))
+}
+
+impl PolicyOracle {
+    pub fn open(root: &Path) -> Result<Self> {
+        if !root.join("access").exists() {
+            bail!("SELinux is not enabled ({} has no access interface)", root.display());
+        }
+        Ok(Self { root: root.to_path_buf(), classes: HashMap::new() })
+    }
+
+    /// Index of `class` and the bit of each of its permissions, as the
+    /// policy assigned them.
+    fn class(&mut self, class: &str) -> Result<&(u32, HashMap<String, u32>)> {
+        if !self.classes.contains_key(class) {
+            let dir = self.root.join("class").join(class);
+            let index = read_number(&dir.join("index"))?;
+            let mut perms = HashMap::new();
+            for entry in fs::read_dir(dir.join("perms"))? {
+                let entry = entry?;
+                let value = read_number(&entry.path())?;
+                if (1..=32).contains(&value) {
+                    perms.insert(entry.file_name().to_string_lossy().into_owned(), 1 << (value - 1));
+                }
+            }
+            self.classes.insert(class.to_string(), (index, perms));
+        }
+        self.classes.get(class).context("class lookup")
+    }
+
+    pub fn allows(&mut self, source: &str, target: &str, class: &str, perms: &[&str]) -> Result<bool> {
+        let (index, bits) = self.class(class)?;
+        let mut wanted = 0;
+        for perm in perms {
+            wanted |= bits.get(*perm).with_context(|| format!("Policy has no {} permission on {}", perm, class))?;
+        }
+        let request = format!("{} {} {}", source, target, index);
+        let mut access = OpenOptions::new().read(true).write(true).open(self.root.join("access"))?;
+        access.write_all(request.as_bytes())?;
+        access.rewind()?;
+        let mut reply = String::new();
+        access.read_to_string(&mut reply)?;
+        let allowed = parse_allowed(&reply).with_context(|| format!("Unexpected access reply {:?}", reply))?;
+        Ok(allowed & wanted == wanted)
+    }
+}
+
+/// The rules of `ops` whose source some of `consumers` could not read,
+/// asking `allows(consumer, context, class, perms)` once per distinct
+/// question. `context_of` labels a staged source.
+pub fn check<A, C>(ops: &[(String, RuleOp)], consumers: &[String], mut context_of: C, mut allows: A) -> Vec<Denial>
+where
+    A: FnMut(&str, &str, &str, &[&str]) -> Result<bool>,
+    C: FnMut(&Path) -> Option<String>,
+{
+    let mut answers: HashMap<(String, String, &'static str), bool> = HashMap::new();
+    let mut denials = Vec::new();
+    for (module_id, op) in ops {
+        let RuleOp::Add { src, target, file_type } = op else { continue };
+        let Some((class, perms)) = access_for(*file_type) else { continue };
+        let context = context_of(Path::new(target)).unwrap_or_else(|| "unlabeled".to_string());
+        for consumer in consumers {
+            let key = (consumer.clone(), context.clone(), class);
+            let allowed = *answers.entry(key).or_insert_with(|| match allows(consumer, &context, class, perms) {
+                Ok(allowed) => allowed,
+                Err(e) => {
+                    log::debug!("SELinux query {} -> {} ({}) failed: {:#}", consumer, context, class, e);
+                    true
+                }
+            });
+            if !allowed {
+                denials.push(Denial { module_id: module_id.clone(), path: src.clone(), source: target.clone(), context: context.clone(), consumer: consumer.clone() });
+            }
+        }
+    }
+    denials
+}
+
+/// The HymoFS rules `plan` would register, with the module of each.
+pub fn plan_rules(plan: &MountPlan) -> Vec<(String, RuleOp)> {
+    let mut rules = Vec::new();
+    for op in &plan.hymo_ops {
+        let mut ops = hymofs::collect_directory_ops(&op.target, &op.source);
+        rewrite::apply(&mut ops, &plan.rewrites);
+        rules.extend(ops.into_iter().map(|rule| (op.module_id.clone(), rule)));
+    }
+    rules
+}
+
+/// Runs [`check`] over the plan's HymoFS rules against the loaded policy.
+pub fn verify(plan: &MountPlan, consumers: &[String]) -> Result<Vec<Denial>> {
+    let mut oracle = PolicyOracle::open(Path::new(SELINUXFS))?;
+    Ok(check(&plan_rules(plan), consumers, |path| utils::lgetfilecon(path).ok(), |source, target, class, perms| {
+        oracle.allows(source, target, class, perms)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add(src: &str, target: &str, file_type: HymoFileType) -> (String, RuleOp) {
+        ("mod".to_string(), RuleOp::Add { src: src.into(), target: target.into(), file_type })
+    }
+
+    #[test]
+    fn flags_sources_consumers_cannot_read() {
+        let ops = vec![
+            add("/system/etc/a.xml", "/mnt/mod/system/etc/a.xml", HymoFileType::Reg),
+            add("/system/etc/b.xml", "/mnt/mod/system/etc/b.xml", HymoFileType::Reg),
+            add("/system/bin/c", "/mnt/mod/system/bin/c", HymoFileType::Reg),
+            ("mod".to_string(), RuleOp::Hide { path: "/system/app/X".into() }),
+        ];
+        let consumers = default_consumers();
+        let mut asked = 0;
+        let denials = check(&ops, &consumers, |path| {
+            Some(if path.starts_with("/mnt/mod/system/etc") { "u:object_r:adb_data_file:s0" } else { "u:object_r:system_file:s0" }.to_string())
+        }, |consumer, context, class, perms| {
+            asked += 1;
+            assert_eq!((class, perms), ("file", &["getattr", "open", "read"][..]));
+            Ok(!(consumer.contains("untrusted_app") && context.contains("adb_data_file")))
+        });
+        assert_eq!(asked, 4);
+        assert_eq!(denials.iter().map(|d| d.path.as_str()).collect::<Vec<_>>(), ["/system/etc/a.xml", "/system/etc/b.xml"]);
+        assert_eq!(denials[0].describe(), "mod: /system/etc/a.xml (u:object_r:adb_data_file:s0 from /mnt/mod/system/etc/a.xml) is unreadable for u:r:untrusted_app:s0:c512,c768");
+    }
+
+    #[test]
+    fn reads_policy_classes() {
+        let root = std::env::temp_dir().join(format!("selinuxfs-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("class/file/perms")).unwrap();
+        fs::write(root.join("access"), "").unwrap();
+        fs::write(root.join("class/file/index"), "6\n").unwrap();
+        fs::write(root.join("class/file/perms/read"), "2").unwrap();
+        fs::write(root.join("class/file/perms/open"), "17").unwrap();
+        let mut oracle = PolicyOracle::open(&root).unwrap();
+        let (index, perms) = oracle.class("file").unwrap();
+        assert_eq!((*index, perms["read"], perms["open"]), (6, 0b10, 1 << 16));
+        assert_eq!(parse_allowed("fffffffe ffffffff 0 fffffffd 12 0"), Some(0xffff_fffe));
+        assert_eq!(parse_allowed(""), None);
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,93 @@
+use std::collections::BTreeSet;
+use std::process::Command;
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+use crate::{core::inventory::Module, utils};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct PostApply {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub restart_services: Vec<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub soft_reboot: bool,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct PostApplyActions {
+    pub services: BTreeSet<String>,
+    pub soft_reboot: bool,
+}
+
+pub fn plan_actions<'a, I: IntoIterator<Item = &'a PostApply>>(policies: I) -> PostApplyActions {
+    let mut actions = PostApplyActions::default();
+    for policy in policies {
+        actions.soft_reboot |= policy.soft_reboot;
+        actions.services.extend(policy.restart_services.iter().cloned());
+    }
+    if actions.soft_reboot {
+        actions.services.clear();
+    }
+    actions
+}
+
+fn ctl(action: &str, service: &str) -> Result<()> {
+    let status = Command::new("setprop").arg(format!("ctl.{}", action)).arg(service).status()?;
+    if !status.success() {
+        bail!("setprop ctl.{} {} exited with {}", action, service, status);
+    }
+    Ok(())
+}
+
+pub fn run_post_apply(modules: &[Module], applied: &[String]) {
+    let actions = plan_actions(modules.iter()
+        .filter(|m| applied.contains(&m.id))
+        .map(|m| &m.rules.post_apply));
+    if actions.services.is_empty() && !actions.soft_reboot {
+        return;
+    }
+    if utils::get_prop("sys.boot_completed").as_deref() != Some("1") {
+        log::debug!("Services not started yet, skipping post-apply restarts");
+        return;
+    }
+    if actions.soft_reboot {
+        log::info!(">> Post-apply: soft reboot requested, restarting zygote");
+        if let Err(e) = ctl("restart", "zygote") {
+            log::warn!("!! Soft reboot failed: {:#}", e);
+        }
+        return;
+    }
+    for service in &actions.services {
+        log::info!(">> Post-apply: restarting {}", service);
+        if let Err(e) = ctl("restart", service) {
+            log::warn!("!! Failed to restart {}: {:#}", service, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(services: &[&str], soft_reboot: bool) -> PostApply {
+        PostApply {
+            restart_services: services.iter().map(|s| s.to_string()).collect(),
+            soft_reboot,
+        }
+    }
+
+    #[test]
+    fn merges_and_dedups_services() {
+        let policies = [policy(&["audioserver"], false), policy(&["surfaceflinger", "audioserver"], false)];
+        let actions = plan_actions(&policies);
+        assert_eq!(actions.services.into_iter().collect::<Vec<_>>(), vec!["audioserver", "surfaceflinger"]);
+        assert!(!actions.soft_reboot);
+    }
+
+    #[test]
+    fn soft_reboot_supersedes_restarts() {
+        let policies = [policy(&["audioserver"], false), policy(&[], true)];
+        let actions = plan_actions(&policies);
+        assert!(actions.soft_reboot);
+        assert!(actions.services.is_empty());
+    }
+}
@@ -0,0 +1,215 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use crate::{conf::config::Config, defs, utils};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MountFlags {
+    pub read_only: bool,
+    pub noexec: bool,
+    pub nodev: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountEntry {
+    pub mount_point: String,
+    pub fstype: String,
+    pub source: String,
+    pub flags: MountFlags,
+}
+
+/// Flags of the mount holding `path`, taken from the mountinfo entry with the
+/// longest matching mount point; the last of several stacked mounts wins.
+pub fn mount_flags(mountinfo: &str, path: &Path) -> Option<MountFlags> {
+    mount_entry(mountinfo, path).map(|entry| entry.flags)
+}
+
+/// The mountinfo entry holding `path`, chosen as in [`mount_flags`].
+pub fn mount_entry(mountinfo: &str, path: &Path) -> Option<MountEntry> {
+    let mut best: Option<MountEntry> = None;
+    for line in mountinfo.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (Some(mount_point), Some(options)) = (fields.get(4), fields.get(5)) else { continue };
+        let mount_point = mount_point.replace("\\040", " ");
+        if !path.starts_with(&mount_point) {
+            continue;
+        }
+        let separator = fields.iter().position(|f| *f == "-");
+        let super_field = |offset: usize| separator.and_then(|i| fields.get(i + offset)).copied().unwrap_or_default();
+        let super_options = super_field(3);
+        let has = |opt: &str| options.split(',').any(|o| o == opt);
+        let flags = MountFlags {
+            read_only: has("ro") || super_options.split(',').any(|o| o == "ro"),
+            noexec: has("noexec"),
+            nodev: has("nodev"),
+        };
+        if best.as_ref().is_none_or(|entry| mount_point.len() >= entry.mount_point.len()) {
+            best = Some(MountEntry {
+                mount_point,
+                fstype: super_field(1).to_string(),
+                source: super_field(2).to_string(),
+                flags,
+            });
+        }
+    }
+    best
+}
+
+/// An early-boot location the module content is staged under, together with
+/// the properties it was chosen for. The choice is recorded so later commands
+/// tear down what the boot set up even if the candidates change in between.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StagingRoot {
+    pub root: PathBuf,
+    pub exec: bool,
+    pub dev: bool,
+    pub context: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Requirements {
+    pub exec: bool,
+    pub dev: bool,
+    pub labeled: bool,
+}
+
+impl Requirements {
+    fn met_by(&self, root: &StagingRoot) -> bool {
+        (root.exec || !self.exec) && (root.dev || !self.dev) && (root.context.is_some() || !self.labeled)
+    }
+}
+
+impl StagingRoot {
+    pub fn content_dir(&self) -> PathBuf {
+        self.root.join(defs::STAGING_DIR_NAME)
+    }
+
+    /// `None` for roots that are missing, not directories or mounted read-only.
+    pub fn probe(root: &Path, mountinfo: &str) -> Option<Self> {
+        if !root.is_dir() {
+            return None;
+        }
+        let flags = mount_flags(mountinfo, root)?;
+        if flags.read_only {
+            return None;
+        }
+        Some(Self {
+            root: root.to_path_buf(),
+            exec: !flags.noexec,
+            dev: !flags.nodev,
+            context: utils::lgetfilecon(root).ok().filter(|c| !c.is_empty()),
+        })
+    }
+
+    pub fn load() -> Option<Self> {
+        let content = fs::read_to_string(defs::STAGING_ROOT_FILE).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        utils::ensure_dir_exists(defs::RUN_DIR)?;
+        fs::write(defs::STAGING_ROOT_FILE, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// First probed candidate meeting `requirements`, in candidate order.
+pub fn choose(candidates: impl IntoIterator<Item = StagingRoot>, requirements: Requirements) -> Option<StagingRoot> {
+    candidates.into_iter().find(|root| requirements.met_by(root))
+}
+
+/// Picks the staging root for this boot and records it. `staging_root` in the
+/// config skips the search; when no candidate qualifies the legacy `/dev`
+/// location is used so boot still proceeds.
+pub fn select(config: &Config) -> Result<StagingRoot> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo").unwrap_or_default();
+    let requirements = Requirements {
+        exec: true,
+        dev: true,
+        labeled: Path::new("/sys/fs/selinux/enforce").exists(),
+    };
+    let candidates: Vec<PathBuf> = match &config.staging_root {
+        Some(root) => vec![root.clone()],
+        None => defs::STAGING_ROOT_CANDIDATES.iter().map(PathBuf::from).collect(),
+    };
+    let probed = candidates.iter().filter_map(|root| {
+        let probed = StagingRoot::probe(root, &mountinfo);
+        if probed.is_none() {
+            log::debug!("Staging root {} is unavailable or read-only", root.display());
+        }
+        probed
+    });
+    let selected = choose(probed, requirements).unwrap_or_else(|| {
+        log::warn!("!! No staging root among {:?} is writable with exec/dev; using /dev", candidates);
+        StagingRoot { root: PathBuf::from("/dev"), exec: true, dev: true, context: None }
+    });
+    log::info!(">> Staging root: {} (exec={}, dev={}, context={})",
+        selected.root.display(), selected.exec, selected.dev, selected.context.as_deref().unwrap_or("none"));
+    selected.save()?;
+    Ok(selected)
+}
+
+/// Content directory of the recorded staging root, for commands that run
+/// after boot and must find what the boot staged.
+pub fn content_dir() -> PathBuf {
+    StagingRoot::load()
+        .map(|root| root.content_dir())
+        .unwrap_or_else(|| PathBuf::from(defs::FALLBACK_CONTENT_DIR))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOUNTINFO: &str = "\
+22 1 253:0 / / ro,relatime shared:1 - ext4 /dev/block/dm-0 ro,seclabel
+23 22 0:5 / /dev rw,nosuid,relatime shared:2 - tmpfs tmpfs rw,seclabel,mode=755
+30 22 0:21 / /debug_ramdisk rw,nosuid,nodev,relatime - tmpfs tmpfs rw,seclabel
+31 22 0:22 / /mnt/vendor rw,nosuid,nodev,noexec,relatime - ext4 /dev/block/persist rw
+32 22 0:23 / /mnt/my\\040dir ro,relatime - tmpfs tmpfs rw
+33 22 0:24 / /debug_ramdisk rw,nosuid,relatime - tmpfs magisk rw,seclabel
+";
+
+    fn root(path: &str, exec: bool, dev: bool, context: Option<&str>) -> StagingRoot {
+        StagingRoot { root: path.into(), exec, dev, context: context.map(str::to_string) }
+    }
+
+    #[test]
+    fn reads_flags_of_the_innermost_mount() {
+        let flags = |p: &str| mount_flags(MOUNTINFO, Path::new(p)).unwrap();
+        assert_eq!(flags("/sbin"), MountFlags { read_only: true, noexec: false, nodev: false });
+        assert_eq!(flags("/dev/meta"), MountFlags::default());
+        assert_eq!(flags("/mnt/vendor"), MountFlags { read_only: false, noexec: true, nodev: true });
+        assert!(flags("/mnt/my dir/x").read_only);
+        assert!(flags("/devices").read_only, "/devices belongs to /, not /dev");
+        assert!(!flags("/debug_ramdisk").nodev, "the later stacked mount wins");
+        assert_eq!(mount_flags("", Path::new("/dev")), None);
+        let entry = mount_entry(MOUNTINFO, Path::new("/system/bin")).unwrap();
+        assert_eq!((entry.mount_point.as_str(), entry.fstype.as_str(), entry.source.as_str()), ("/", "ext4", "/dev/block/dm-0"));
+    }
+
+    #[test]
+    fn chooses_first_candidate_meeting_requirements() {
+        let candidates = vec![
+            root("/debug_ramdisk", true, false, Some("u:object_r:tmpfs:s0")),
+            root("/sbin", true, true, None),
+            root("/dev", true, true, Some("u:object_r:device:s0")),
+        ];
+        let strict = Requirements { exec: true, dev: true, labeled: true };
+        assert_eq!(choose(candidates.clone(), strict).unwrap().root, PathBuf::from("/dev"));
+        let unlabeled = Requirements { labeled: false, ..strict };
+        assert_eq!(choose(candidates.clone(), unlabeled).unwrap().root, PathBuf::from("/sbin"));
+        let lax = Requirements { exec: false, dev: false, labeled: false };
+        assert_eq!(choose(candidates, lax).unwrap().root, PathBuf::from("/debug_ramdisk"));
+        assert_eq!(choose(Vec::new(), lax), None);
+    }
+
+    #[test]
+    fn content_dir_lives_under_the_root() {
+        let staged = root("/debug_ramdisk", true, true, None);
+        assert_eq!(staged.content_dir(), PathBuf::from("/debug_ramdisk/meta_hybrid_mnt"));
+        let json = serde_json::to_string(&staged).unwrap();
+        assert_eq!(serde_json::from_str::<StagingRoot>(&json).unwrap(), staged);
+    }
+}
@@ -3,11 +3,13 @@ use std::path::PathBuf;
 use std::time::{SystemTime, UNIX_EPOCH};
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use crate::defs;
+use crate::{defs, utils, core::{early_readers::EarlyReader, migrate::{self, Document}, staging::{self, StagingRoot}}};
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct RuntimeState {
     pub timestamp: u64,
     pub pid: u32,
+    #[serde(default)]
+    pub session_id: String,
     pub storage_mode: String,
     pub mount_point: PathBuf,
     pub overlay_modules: Vec<String>,
@@ -27,8 +29,58 @@ pub struct RuntimeState {
     pub hymofs_available: bool,
     #[serde(default)]
     pub hymofs_version: Option<i32>,
+    #[serde(default)]
+    pub hymofs_device: Option<PathBuf>,
+    #[serde(default)]
+    pub hymofs_selftest: Option<SelfTest>,
+    #[serde(default)]
+    pub early_readers: Vec<EarlyReader>,
+    #[serde(default)]
+    pub staging_root: Option<StagingRoot>,
+    #[serde(default)]
+    pub direct_modules: Vec<String>,
+    #[serde(default)]
+    pub battery_deferred: Vec<String>,
+    #[serde(default)]
+    pub boot_reason_skipped: Vec<String>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SelfTest {
+    pub passed: bool,
+    pub detail: String,
+}
+impl SelfTest {
+    /// The record of a self-test that returned `result`.
+    pub fn from_result(result: &Result<String>) -> Self {
+        match result {
+            Ok(detail) => Self { passed: true, detail: detail.clone() },
+            Err(e) => Self { passed: false, detail: format!("{:#}", e) },
+        }
+    }
 }
 impl RuntimeState {
+    /// Name of the backend that mounted `module_id` in this state.
+    pub fn backend_of(&self, module_id: &str) -> Option<&'static str> {
+        [
+            ("overlay", &self.overlay_modules),
+            ("magic", &self.magic_modules),
+            ("hymofs", &self.hymo_modules),
+            ("direct", &self.direct_modules),
+        ]
+        .into_iter()
+        .find(|(_, ids)| ids.iter().any(|id| id == module_id))
+        .map(|(name, _)| name)
+    }
+    /// Where the last apply staged module content, or the default staging
+    /// directory when no apply recorded one.
+    pub fn storage_root(&self) -> PathBuf {
+        if self.mount_point.as_os_str().is_empty() {
+            staging::content_dir()
+        } else {
+            self.mount_point.clone()
+        }
+    }
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         storage_mode: String, 
         mount_point: PathBuf, 
@@ -40,6 +92,7 @@ impl RuntimeState {
         storage_info: (u64, u64, u8),
         hymofs_available: bool,
         hymofs_version: Option<i32>,
+        hymofs_device: Option<PathBuf>,
     ) -> Self {
         let start = SystemTime::now();
         let timestamp = start.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
@@ -47,6 +100,7 @@ impl RuntimeState {
         Self {
             timestamp,
             pid,
+            session_id: crate::utils::session_id().to_string(),
             storage_mode,
             mount_point,
             overlay_modules,
@@ -59,19 +113,86 @@ impl RuntimeState {
             storage_percent: storage_info.2,
             hymofs_available,
             hymofs_version,
+            hymofs_device,
+            hymofs_selftest: None,
+            early_readers: Vec::new(),
+            staging_root: None,
+            direct_modules: Vec::new(),
+            battery_deferred: Vec::new(),
+            boot_reason_skipped: Vec::new(),
         }
     }
     pub fn save(&self) -> Result<()> {
-        let json = serde_json::to_string_pretty(self)?;
-        fs::write(defs::STATE_FILE, json)?;
+        let mut doc = serde_json::to_value(self)?;
+        migrate::stamp(Document::State, &mut doc);
+        let json = serde_json::to_string_pretty(&doc)?;
+        utils::atomic_write(defs::STATE_FILE, json)?;
         Ok(())
     }
+    /// Reads the last committed state. Saves replace the file atomically, so
+    /// one read sees a whole snapshot even while an apply is writing; older
+    /// formats are upgraded in memory and persisted by the next save.
     pub fn load() -> Result<Self> {
-        if !std::path::Path::new(defs::STATE_FILE).exists() {
-            return Ok(Self::default());
+        let content = match fs::read_to_string(defs::STATE_FILE) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut doc: serde_json::Value = serde_json::from_str(&content)?;
+        if let Err(e) = migrate::upgrade(Document::State, &mut doc) {
+            log::warn!("State migration failed: {:#}", e);
         }
-        let content = fs::read_to_string(defs::STATE_FILE)?;
-        let state = serde_json::from_str(&content)?;
-        Ok(state)
+        Ok(serde_json::from_value(doc)?)
+    }
+}
+
+/// Marks an apply as running until [`end_apply`], so status queries can tell
+/// that the committed state belongs to the previous run.
+pub fn begin_apply() -> Result<()> {
+    utils::atomic_write(defs::APPLY_MARKER_FILE, utils::session_id())
+}
+
+pub fn end_apply() {
+    let _ = fs::remove_file(defs::APPLY_MARKER_FILE);
+}
+
+/// Session of the apply that is running right now. A marker left by an
+/// earlier boot, or by a run that died before clearing it, is stale.
+pub fn apply_in_progress() -> Option<String> {
+    let session = fs::read_to_string(defs::APPLY_MARKER_FILE).ok()?;
+    let alive = marker_pid(&session)
+        .is_some_and(|pid| std::path::Path::new(&format!("/proc/{}", pid)).exists());
+    (alive && utils::same_boot(&session, utils::session_id())).then_some(session)
+}
+
+fn marker_pid(session: &str) -> Option<u32> {
+    u32::from_str_radix(session.trim().rsplit('-').next()?, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn falls_back_to_the_default_staging_directory() {
+        assert_eq!(RuntimeState::default().storage_root(), staging::content_dir());
+        let state = RuntimeState { mount_point: PathBuf::from("/mnt/vendor/hybrid"), ..RuntimeState::default() };
+        assert_eq!(state.storage_root(), PathBuf::from("/mnt/vendor/hybrid"));
+    }
+
+    #[test]
+    fn records_self_test_results() {
+        let passed = SelfTest::from_result(&Ok("canary rule resolved through the VFS".to_string()));
+        assert!(passed.passed);
+        assert_eq!(passed.detail, "canary rule resolved through the VFS");
+        let failed = SelfTest::from_result(&Err(anyhow::anyhow!("not visible").context("Canary")));
+        assert!(!failed.passed);
+        assert_eq!(failed.detail, "Canary: not visible");
+    }
+
+    #[test]
+    fn reads_the_pid_from_a_marker() {
+        assert_eq!(marker_pid("3f2a9c1e-65231a00-1092\n"), Some(4242));
+        assert_eq!(marker_pid(""), None);
     }
 }
@@ -0,0 +1,147 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use crate::{core::{apps::{self, AppProcess}, mntns, namespace, protect, rules::RuleLedger}, mount::hymofs::{HymoFs, RuleOp}};
+
+/// Namespace the hide rules of this crate's own footprint are recorded in.
+pub const OWNER: &str = "meta-hybrid-stealth";
+
+/// Keeping this crate's footprint out of sight of apps.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Stealth {
+    pub enabled: bool,
+    /// Paths hidden from every process with HymoFS rules once boot has
+    /// completed. Handles opened before stay usable, so hymod keeps working
+    /// on a hidden control device while `hymo` cannot open it any more.
+    pub hide_paths: Vec<PathBuf>,
+    /// Apps whose mount namespaces lose the module mounts, so neither
+    /// mountinfo nor maps show them; empty means every app.
+    pub packages: Vec<String>,
+    /// How often hymod looks for new app processes.
+    pub scan_interval_ms: u64,
+}
+
+impl Default for Stealth {
+    fn default() -> Self {
+        Self { enabled: false, hide_paths: Vec::new(), packages: Vec::new(), scan_interval_ms: 2000 }
+    }
+}
+
+/// Whether `stealth` covers the app running as `process`.
+fn covers(stealth: &Stealth, process: &AppProcess) -> bool {
+    stealth.packages.is_empty() || stealth.packages.contains(&process.package)
+}
+
+/// One process in each mount namespace of a covered app, the lowest pid of
+/// each.
+fn covered_namespaces(stealth: &Stealth) -> Result<BTreeMap<u64, u32>> {
+    let mut covered = BTreeMap::new();
+    for process in apps::processes()?.iter().filter(|process| covers(stealth, process)) {
+        covered.entry(process.namespace).or_insert(process.pid);
+    }
+    Ok(covered)
+}
+
+/// The namespaces of `covered` not in `done`, which forgets the ones no
+/// process is left in.
+fn unvisited(covered: BTreeMap<u64, u32>, done: &mut HashSet<u64>) -> Vec<(u64, u32)> {
+    done.retain(|ns| covered.contains_key(ns));
+    covered.into_iter().filter(|(ns, _)| !done.contains(ns)).collect()
+}
+
+/// Detaches the mounts of `source` from the namespaces of covered apps not
+/// in `done` and returns how many went. `done` remembers the namespaces
+/// entered, which are not entered again.
+pub fn conceal(stealth: &Stealth, source: &str, done: &mut HashSet<u64>) -> Result<usize> {
+    let mut detached = 0;
+    for (ns, pid) in unvisited(covered_namespaces(stealth)?, done) {
+        match mntns::enter(pid, || mntns::detach_owned(source)) {
+            Ok(count) => detached += count,
+            Err(e) => log::debug!("Cannot conceal mounts in namespace {} of {}: {:#}", ns, pid, e),
+        }
+        done.insert(ns);
+    }
+    Ok(detached)
+}
+
+/// Keeps concealing the mounts of `source` from apps as they start; never
+/// returns.
+pub fn keep_concealed(stealth: &Stealth, source: &str) {
+    let mut done = HashSet::new();
+    loop {
+        match conceal(stealth, source, &mut done) {
+            Ok(0) => {}
+            Ok(count) => log::info!(">> Concealed {} mounts from apps", count),
+            Err(e) => log::warn!("!! Stealth scan failed: {:#}", e),
+        }
+        std::thread::sleep(std::time::Duration::from_millis(stealth.scan_interval_ms.max(100)));
+    }
+}
+
+/// The hide rules `stealth` asks for; protected paths are left out.
+fn hide_ops(stealth: &Stealth) -> Vec<RuleOp> {
+    if !stealth.enabled {
+        return Vec::new();
+    }
+    let mut ops: Vec<RuleOp> = stealth.hide_paths.iter()
+        .map(|path| RuleOp::Hide { path: path.to_string_lossy().into_owned() })
+        .collect();
+    for refused in protect::protected().retain(&mut ops) {
+        log::warn!("!! Stealth: not hiding protected path {}", refused);
+    }
+    ops
+}
+
+/// Brings the HymoFS hide rules of this crate's footprint in line with
+/// `stealth`, dropping the ones no longer asked for; returns how many are
+/// in place.
+pub fn hide(handle: &HymoFs, ledger: &mut RuleLedger, stealth: &Stealth) -> Result<usize> {
+    let ops = hide_ops(stealth);
+    let wanted: HashSet<&str> = ops.iter().map(RuleOp::path).collect();
+    let stale: Vec<String> = namespace::list(ledger, OWNER, None).iter()
+        .map(|op| op.path().to_string())
+        .filter(|path| !wanted.contains(path.as_str()))
+        .collect();
+    namespace::remove(handle, ledger, OWNER, &stale);
+    if ops.is_empty() {
+        return Ok(0);
+    }
+    namespace::add(handle, ledger, OWNER, &ops, &mut None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn covers_only_the_configured_apps() {
+        let process = |package: &str| AppProcess { pid: 1234, namespace: 1, uid: 10_123, package: package.into() };
+        let every = Stealth { enabled: true, ..Stealth::default() };
+        assert!(covers(&every, &process("com.bank.app")));
+        let chosen = Stealth { packages: vec!["com.bank.app".into()], ..every };
+        assert!(covers(&chosen, &process("com.bank.app")));
+        assert!(!covers(&chosen, &process("com.game.app")));
+    }
+
+    #[test]
+    fn enters_each_namespace_once() {
+        let mut done = HashSet::new();
+        assert_eq!(unvisited(BTreeMap::from([(1, 100), (2, 200)]), &mut done), [(1, 100), (2, 200)]);
+        done.extend([1, 2]);
+        assert_eq!(unvisited(BTreeMap::from([(2, 200), (3, 300)]), &mut done), [(3, 300)]);
+        assert_eq!(done, HashSet::from([2]));
+    }
+
+    #[test]
+    fn hides_nothing_protected_or_disabled() {
+        let stealth = Stealth {
+            enabled: true,
+            hide_paths: vec![PathBuf::from("/data/adb/hymo"), PathBuf::from("/system/bin/linker64")],
+            ..Stealth::default()
+        };
+        assert_eq!(hide_ops(&stealth), [RuleOp::Hide { path: "/data/adb/hymo".into() }]);
+        assert!(hide_ops(&Stealth { enabled: false, ..stealth }).is_empty());
+    }
+}
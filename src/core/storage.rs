@@ -6,8 +6,8 @@ use anyhow::{Context, Result, bail};
 use rustix::fs::Mode;
 use rustix::mount::{unmount, UnmountFlags};
 use serde::Serialize;
-use crate::{defs, utils, mount::hymofs::HymoFs};
-use crate::core::state::RuntimeState;
+use crate::{conf::config::Config, defs, utils, mount::hymofs::{self, HymoFs}};
+use crate::core::{roots, staging, state::{self, RuntimeState}};
 
 const DEFAULT_SELINUX_CONTEXT: &str = "u:object_r:system_file:s0";
 const SELINUX_XATTR_KEY: &str = "security.selinux";
@@ -27,6 +27,11 @@ struct StorageStatus {
     used_size: u64,
     hymofs_available: bool,
     hymofs_version: Option<i32>,
+    hymofs_device: String,
+    apply_in_progress: bool,
+    module_roots: Vec<String>,
+    image_path: Option<String>,
+    image_size: u64,
 }
 
 pub fn get_usage(path: &Path) -> (u64, u64, u8) {
@@ -34,38 +39,41 @@ pub fn get_usage(path: &Path) -> (u64, u64, u8) {
         let total = stat.f_blocks * stat.f_frsize;
         let free = stat.f_bfree * stat.f_frsize;
         let used = total - free;
-        let percent = if total > 0 { (used * 100 / total) as u8 } else { 0 };
+        let percent = (used * 100).checked_div(total).unwrap_or(0) as u8;
         (total, used, percent)
     } else {
         (0, 0, 0)
     }
 }
 
-pub fn is_hymofs_active() -> bool {
-    HymoFs::is_available()
-}
-
-pub fn setup(mnt_base: &Path, img_path: &Path, force_ext4: bool, mount_source: &str) -> Result<StorageHandle> {
+pub fn setup(mnt_base: &Path, img_path: &Path, config: &Config) -> Result<StorageHandle> {
     if utils::is_mounted(mnt_base) {
         let _ = unmount(mnt_base, UnmountFlags::DETACH);
     }
     
     fs::create_dir_all(mnt_base)?;
 
-    if !force_ext4 {
-        if try_setup_tmpfs(mnt_base, mount_source)? {
-            return Ok(StorageHandle {
-                mount_point: mnt_base.to_path_buf(),
-                mode: "tmpfs".to_string(),
-            });
-        }
+    if !config.force_ext4 && try_setup_tmpfs(mnt_base, &config.mountsource, config.staging_tmpfs_mb)? {
+        return Ok(StorageHandle {
+            mount_point: mnt_base.to_path_buf(),
+            mode: "tmpfs".to_string(),
+        });
     }
 
-    setup_ext4_image(mnt_base, img_path)
+    setup_ext4_image(mnt_base, img_path, config.staging_image_mb)
+}
+
+/// Mount options of the staging tmpfs; a size of 0 keeps the kernel's
+/// default of half the RAM.
+fn tmpfs_options(size_mb: u64) -> String {
+    match size_mb {
+        0 => "mode=0755".to_string(),
+        size => format!("mode=0755,size={}m", size),
+    }
 }
 
-fn try_setup_tmpfs(target: &Path, mount_source: &str) -> Result<bool> {
-    if utils::mount_tmpfs(target, mount_source).is_ok() {
+fn try_setup_tmpfs(target: &Path, mount_source: &str, size_mb: u64) -> Result<bool> {
+    if utils::mount_tmpfs_with(target, mount_source, &tmpfs_options(size_mb)).is_ok() {
         if utils::is_xattr_supported(target) {
             return Ok(true);
         } else {
@@ -75,15 +83,20 @@ fn try_setup_tmpfs(target: &Path, mount_source: &str) -> Result<bool> {
     Ok(false)
 }
 
-fn setup_ext4_image(target: &Path, img_path: &Path) -> Result<StorageHandle> {
+fn setup_ext4_image(target: &Path, img_path: &Path, size_mb: u64) -> Result<StorageHandle> {
     if !img_path.exists() {
         if let Some(parent) = img_path.parent() {
             fs::create_dir_all(parent)?;
         }
-        create_image(img_path).context("Failed to create modules.img")?;
+        create_image(img_path, size_mb).context("Failed to create modules.img")?;
+    } else {
+        check_image(img_path);
+        if let Err(e) = grow_image(img_path, size_mb) {
+            log::warn!("!! Failed to grow modules.img to {} MiB: {:#}", size_mb, e);
+        }
     }
 
-    if let Err(_) = utils::mount_image(img_path, target) {
+    if utils::mount_image(img_path, target).is_err() {
         if utils::repair_image(img_path).is_ok() {
             utils::mount_image(img_path, target).context("Failed to mount modules.img after repair")?;
         } else {
@@ -97,9 +110,49 @@ fn setup_ext4_image(target: &Path, img_path: &Path) -> Result<StorageHandle> {
     })
 }
 
-fn create_image(path: &Path) -> Result<()> {
+/// Where the ext4 image of the staged module content lives.
+pub fn image_path() -> PathBuf {
+    Path::new(defs::BASE_DIR).join("modules.img")
+}
+
+/// How a preen run of e2fsck left the image, by its exit code.
+#[derive(Debug, PartialEq, Eq)]
+enum FsckOutcome {
+    Clean,
+    Fixed,
+    Failed,
+}
+
+impl FsckOutcome {
+    fn from_code(code: Option<i32>) -> Self {
+        match code {
+            Some(0) => Self::Clean,
+            Some(1 | 2) => Self::Fixed,
+            _ => Self::Failed,
+        }
+    }
+}
+
+/// Checks the image before it is mounted. The image has no journal, so a
+/// boot after an unclean shutdown finds it unclean and has it preened;
+/// whatever preening cannot fix gets a full forced repair.
+fn check_image(path: &Path) {
+    let code = Command::new("e2fsck").arg("-p").arg(path).status().ok().and_then(|status| status.code());
+    match FsckOutcome::from_code(code) {
+        FsckOutcome::Clean => {}
+        FsckOutcome::Fixed => log::info!(">> e2fsck fixed errors in {}", path.display()),
+        FsckOutcome::Failed => {
+            log::warn!("!! e2fsck could not preen {} (exit {:?}), repairing", path.display(), code);
+            if let Err(e) = utils::repair_image(path) {
+                log::warn!("!! {:#}", e);
+            }
+        }
+    }
+}
+
+fn create_image(path: &Path, size_mb: u64) -> Result<()> {
     let status = Command::new("truncate")
-        .arg("-s").arg("2G")
+        .arg("-s").arg(format!("{}M", size_mb))
         .arg(path)
         .status()?;
     if !status.success() { bail!("Failed to allocate image file"); }
@@ -145,13 +198,32 @@ fn set_selinux_context(path: &Path, context: &str) -> Result<()> {
     Ok(())
 }
 
-pub fn print_status() -> Result<()> {
+/// Grows an image created with a smaller `staging_image_mb`; it is never
+/// shrunk, as that could cut off staged content.
+fn grow_image(path: &Path, size_mb: u64) -> Result<()> {
+    if fs::metadata(path)?.len() >= size_mb * 1024 * 1024 {
+        return Ok(());
+    }
+    log::info!(">> Growing modules.img to {} MiB", size_mb);
+    let status = Command::new("truncate").arg("-s").arg(format!("{}M", size_mb)).arg(path).status()?;
+    if !status.success() { bail!("Failed to extend image file"); }
+    utils::repair_image(path)?;
+    let status = Command::new("resize2fs").arg(path).status().context("Failed to execute resize2fs")?;
+    if !status.success() { bail!("resize2fs failed"); }
+    Ok(())
+}
+
+pub fn print_status(config: &Config) -> Result<()> {
     let state = RuntimeState::load().ok();
     let (mnt_base, expected_mode) = if let Some(ref s) = state {
         (s.mount_point.clone(), s.storage_mode.clone())
     } else {
-        (PathBuf::from(defs::FALLBACK_CONTENT_DIR), "unknown".to_string())
+        (staging::content_dir(), "unknown".to_string())
     };
+    let hymofs_device = state.as_ref()
+        .and_then(|s| s.hymofs_device.clone())
+        .unwrap_or_else(|| PathBuf::from(hymofs::DEV_PATH));
+    let hymofs = HymoFs::open_at(&hymofs_device).ok();
 
     let mut mode = "unknown".to_string();
     let mut total = 0;
@@ -168,22 +240,46 @@ pub fn print_status() -> Result<()> {
             total = stat.f_blocks * stat.f_frsize;
             let free = stat.f_bfree * stat.f_frsize;
             used = total - free;
-            if total > 0 {
-                percent = (used * 100 / total) as u8;
-            }
+            percent = (used * 100).checked_div(total).unwrap_or(0) as u8;
         }
     }
 
+    let image = Some(image_path()).filter(|path| mode == "ext4" && path.exists());
     let status = StorageStatus {
         mode,
         mount_point: mnt_base.to_string_lossy().to_string(),
         usage_percent: percent,
         total_size: total,
         used_size: used,
-        hymofs_available: HymoFs::is_available(),
-        hymofs_version: HymoFs::get_version(),
+        hymofs_available: hymofs.as_ref().is_some_and(|h| h.is_available()),
+        hymofs_version: hymofs.as_ref().and_then(|h| h.get_version().ok()),
+        hymofs_device: hymofs_device.to_string_lossy().to_string(),
+        apply_in_progress: state::apply_in_progress().is_some(),
+        module_roots: roots::active(config).iter().map(|root| root.to_string_lossy().to_string()).collect(),
+        image_path: image.as_ref().map(|path| path.to_string_lossy().to_string()),
+        image_size: image.as_ref().and_then(|path| fs::metadata(path).ok()).map_or(0, |meta| meta.len()),
     };
 
     println!("{}", serde_json::to_string(&status)?);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caps_the_tmpfs_only_when_sized() {
+        assert_eq!(tmpfs_options(0), "mode=0755");
+        assert_eq!(tmpfs_options(512), "mode=0755,size=512m");
+    }
+
+    #[test]
+    fn reads_e2fsck_exit_codes() {
+        assert_eq!(FsckOutcome::from_code(Some(0)), FsckOutcome::Clean);
+        assert_eq!(FsckOutcome::from_code(Some(1)), FsckOutcome::Fixed);
+        assert_eq!(FsckOutcome::from_code(Some(2)), FsckOutcome::Fixed);
+        assert_eq!(FsckOutcome::from_code(Some(4)), FsckOutcome::Failed);
+        assert_eq!(FsckOutcome::from_code(None), FsckOutcome::Failed);
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::OnceLock;
+use serde::{Deserialize, Serialize};
+use crate::mount::hymofs::{HymoFileType, RuleOp};
+
+/// Links followed before a chain counts as a loop, as the kernel's limit.
+const MAX_HOPS: usize = 40;
+
+static POLICY: OnceLock<SymlinkPolicy> = OnceLock::new();
+
+/// What becomes of a module's absolute symlinks. The kernel follows a
+/// redirected link from the live tree, where its target may be another
+/// rule of the same injection, not applied yet, or the link itself.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SymlinkPolicy {
+    /// Registers every symlink as is.
+    Keep,
+    /// Redirects a link to a file the same injection provides straight to
+    /// that file.
+    #[default]
+    Resolve,
+    /// Also redirects a link to a live file straight to it, as kernels
+    /// without symlink rules need anyway.
+    Materialize,
+}
+
+/// How an absolute link chain ended.
+#[derive(Debug, PartialEq)]
+enum Resolved {
+    File(String),
+    Unchanged,
+    Loop,
+}
+
+/// Follows the module symlink at `link` while it points at absolute paths,
+/// taking the files of `provided` over the live tree.
+fn resolve(link: &str, provided: &HashMap<String, String>, policy: SymlinkPolicy) -> Resolved {
+    let mut link = link.to_string();
+    for _ in 0..MAX_HOPS {
+        let Ok(dest) = fs::read_link(&link) else { return Resolved::Unchanged };
+        if !dest.is_absolute() {
+            return Resolved::Unchanged;
+        }
+        let dest = dest.to_string_lossy();
+        let Some(module_file) = provided.get(dest.as_ref()) else {
+            if policy != SymlinkPolicy::Materialize {
+                return Resolved::Unchanged;
+            }
+            return match fs::canonicalize(dest.as_ref()) {
+                Ok(live) if live.is_file() => Resolved::File(live.to_string_lossy().into_owned()),
+                _ => Resolved::Unchanged,
+            };
+        };
+        match fs::symlink_metadata(module_file) {
+            Ok(meta) if meta.file_type().is_symlink() => link.clone_from(module_file),
+            Ok(meta) if meta.is_file() => return Resolved::File(module_file.clone()),
+            _ => return Resolved::Unchanged,
+        }
+    }
+    Resolved::Loop
+}
+
+/// Rewrites the symlink rules of one injection by `policy`; returns how
+/// many became file redirects.
+pub fn rewrite(ops: &mut [RuleOp], policy: SymlinkPolicy) -> usize {
+    if policy == SymlinkPolicy::Keep {
+        return 0;
+    }
+    let provided: HashMap<String, String> = ops.iter()
+        .filter_map(|op| match op {
+            RuleOp::Add { src, target, .. } => Some((src.clone(), target.clone())),
+            _ => None,
+        })
+        .collect();
+    let mut rewritten = 0;
+    for op in ops.iter_mut() {
+        let RuleOp::Add { src, target, file_type } = op else { continue };
+        if *file_type != HymoFileType::Lnk {
+            continue;
+        }
+        match resolve(target, &provided, policy) {
+            Resolved::File(file) => {
+                *target = file;
+                *file_type = HymoFileType::Reg;
+                rewritten += 1;
+            }
+            Resolved::Loop => log::warn!("Symlink {} points back at itself, left as is", src),
+            Resolved::Unchanged => {}
+        }
+    }
+    rewritten
+}
+
+/// Sets the symlink policy from the config for the rest of the process.
+pub fn init(policy: SymlinkPolicy) {
+    let _ = POLICY.set(policy);
+}
+
+pub fn policy() -> SymlinkPolicy {
+    POLICY.get().copied().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn resolves_absolute_links_through_the_injection() {
+        let dir = std::env::temp_dir().join(format!("symlinks-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let at = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        fs::write(dir.join("libnew.so"), "elf").unwrap();
+        fs::write(dir.join("live.so"), "elf").unwrap();
+        symlink("/system/lib/libnew.so", dir.join("libx.so")).unwrap();
+        symlink("/system/lib/libx.so", dir.join("liby.so")).unwrap();
+        symlink("libnew.so", dir.join("librel.so")).unwrap();
+        symlink(at("live.so"), dir.join("liblive.so")).unwrap();
+        symlink("/system/lib/libloop.so", dir.join("libloop.so")).unwrap();
+        let add = |src: &str, name: &str, file_type| RuleOp::Add { src: format!("/system/lib/{}", src), target: at(name), file_type };
+        let ops = vec![
+            add("libnew.so", "libnew.so", HymoFileType::Reg),
+            add("libx.so", "libx.so", HymoFileType::Lnk),
+            add("liby.so", "liby.so", HymoFileType::Lnk),
+            add("librel.so", "librel.so", HymoFileType::Lnk),
+            add("liblive.so", "liblive.so", HymoFileType::Lnk),
+            add("libloop.so", "libloop.so", HymoFileType::Lnk),
+        ];
+
+        let mut kept = ops.clone();
+        assert_eq!(rewrite(&mut kept, SymlinkPolicy::Keep), 0);
+        assert_eq!(kept, ops);
+
+        let mut resolved = ops.clone();
+        assert_eq!(rewrite(&mut resolved, SymlinkPolicy::Resolve), 2);
+        assert_eq!(resolved[1], add("libx.so", "libnew.so", HymoFileType::Reg));
+        assert_eq!(resolved[2], add("liby.so", "libnew.so", HymoFileType::Reg));
+        assert_eq!(resolved[3..], ops[3..]);
+
+        let mut materialized = ops.clone();
+        assert_eq!(rewrite(&mut materialized, SymlinkPolicy::Materialize), 3);
+        let live = fs::canonicalize(dir.join("live.so")).unwrap().to_string_lossy().into_owned();
+        assert_eq!(materialized[4], RuleOp::Add { src: "/system/lib/liblive.so".into(), target: live, file_type: HymoFileType::Reg });
+        assert_eq!(materialized[5], ops[5]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
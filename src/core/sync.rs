@@ -1,13 +1,20 @@
 use std::collections::HashSet;
 use std::fs;
 use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use anyhow::Result;
 use rayon::prelude::*;
-use crate::{defs, utils, core::inventory::{Module, MountMode}};
+use crate::{conf::config::Config, defs, utils, core::{disable::{self, DisableReason, DisableSource}, integrity::Manifest, inventory::{Module, MountMode}, roots}};
 
-pub fn perform_sync(modules: &[Module], target_base: &Path) -> Result<()> {
+/// Syncs changed modules into `target_base` and returns how many staged
+/// copies were incomplete or failed verification and were repaired by
+/// resyncing.
+pub fn perform_sync(config: &Config, modules: &[Module], target_base: &Path) -> Result<usize> {
     log::info!("Starting smart module sync to {}", target_base.display());
     prune_orphaned_modules(modules, target_base)?;
+    let unrecoverable = Mutex::new(Vec::new());
+    let repaired = AtomicUsize::new(0);
     modules.par_iter().for_each(|module| {
         if matches!(module.rules.default_mode, MountMode::Magic) {
             log::debug!("Skipping sync for Magic Mount module: {}", module.id);
@@ -19,7 +26,10 @@ pub fn perform_sync(modules: &[Module], target_base: &Path) -> Result<()> {
             part_path.exists() && has_files_recursive(&part_path)
         });
         if has_content {
-            if should_sync(&module.source_path, &dst) {
+            let outdated = should_sync(&module.source_path, &dst);
+            let incomplete = !outdated && staging_incomplete(&module.id, &module.source_path, &dst);
+            let corrupted = !outdated && !incomplete && config.verify_staging && staging_corrupted(&module.id, &dst);
+            if outdated || incomplete || corrupted {
                 log::info!("Syncing module: {} (Updated/New)", module.id);
                 if dst.exists() {
                     if let Err(e) = fs::remove_dir_all(&dst) {
@@ -28,8 +38,25 @@ pub fn perform_sync(modules: &[Module], target_base: &Path) -> Result<()> {
                 }
                 if let Err(e) = utils::sync_dir(&module.source_path, &dst) {
                     log::error!("Failed to sync module {}: {}", module.id, e);
+                    if incomplete {
+                        // The module stays enabled and mounts from its
+                        // source instead of a partial copy.
+                        let _ = fs::remove_dir_all(&dst);
+                    }
+                    if corrupted {
+                        if let Ok(mut list) = unrecoverable.lock() {
+                            list.push((module, format!("{:#}", e)));
+                        }
+                    }
                 } else {
+                    if incomplete || corrupted {
+                        repaired.fetch_add(1, Ordering::Relaxed);
+                    }
                     repair_module_contexts(&dst, &module.id);
+                    let manifest = Manifest::build(&dst, config.integrity_hash).and_then(|m| m.save(&dst));
+                    if let Err(e) = manifest {
+                        log::warn!("Failed to record integrity manifest for {}: {:#}", module.id, e);
+                    }
                 }
             } else {
                 log::debug!("Skipping module: {} (Up-to-date)", module.id);
@@ -38,7 +65,16 @@ pub fn perform_sync(modules: &[Module], target_base: &Path) -> Result<()> {
             log::debug!("Skipping empty module: {}", module.id);
         }
     });
-    Ok(())
+    for (module, error) in unrecoverable.into_inner().unwrap_or_default() {
+        log::error!("!! Auto-disabling {}: staged copy is corrupted and resync failed", module.id);
+        let reason = DisableReason::new(DisableSource::Integrity,
+            format!("staged copy failed {} verification and could not be resynced", config.integrity_hash.name()))
+            .detail("error", error);
+        if let Err(e) = disable::disable(Path::new(defs::DISABLE_REASONS_FILE), &roots::active(config), &module.id, &module.source_path, reason) {
+            log::warn!("Failed to disable {}: {:#}", module.id, e);
+        }
+    }
+    Ok(repaired.into_inner())
 }
 
 fn prune_orphaned_modules(modules: &[Module], target_base: &Path) -> Result<()> {
@@ -51,18 +87,14 @@ fn prune_orphaned_modules(modules: &[Module], target_base: &Path) -> Result<()>
         let path = entry.path();
         let name_os = entry.file_name();
         let name = name_os.to_string_lossy();
-        if name != "lost+found" && name != "meta-hybrid" {
-            if !active_ids.contains(name.as_ref()) {
-                log::info!("Pruning orphaned module storage: {}", name);
-                if path.is_dir() {
-                    if let Err(e) = fs::remove_dir_all(&path) {
-                        log::warn!("Failed to remove orphan dir {}: {}", name, e);
-                    }
-                } else {
-                    if let Err(e) = fs::remove_file(&path) {
-                        log::warn!("Failed to remove orphan file {}: {}", name, e);
-                    }
+        if name != "lost+found" && name != "meta-hybrid" && !active_ids.contains(name.as_ref()) {
+            log::info!("Pruning orphaned module storage: {}", name);
+            if path.is_dir() {
+                if let Err(e) = fs::remove_dir_all(&path) {
+                    log::warn!("Failed to remove orphan dir {}: {}", name, e);
                 }
+            } else if let Err(e) = fs::remove_file(&path) {
+                log::warn!("Failed to remove orphan file {}: {}", name, e);
             }
         }
     });
@@ -84,6 +116,42 @@ fn should_sync(src: &Path, dst: &Path) -> bool {
     }
 }
 
+/// Files and partitions gone from a staged copy whose module.prop still
+/// matches, e.g. after a cache clear or partial reset wiped part of the
+/// staging storage. Cheap enough to run on every boot: nothing is hashed.
+fn missing_from_staging(src: &Path, dst: &Path) -> Vec<String> {
+    let Some(manifest) = Manifest::load(dst) else {
+        return vec![defs::MANIFEST_FILE_NAME.to_string()];
+    };
+    let partitions = defs::BUILTIN_PARTITIONS.iter()
+        .filter(|p| src.join(p).is_dir() && !dst.join(p).is_dir())
+        .map(|p| p.to_string());
+    let files = manifest.files.into_keys()
+        .filter(|relative| dst.join(relative).symlink_metadata().is_err());
+    partitions.chain(files).collect()
+}
+
+fn staging_incomplete(module_id: &str, src: &Path, dst: &Path) -> bool {
+    let missing = missing_from_staging(src, dst);
+    if let Some(first) = missing.first() {
+        log::warn!("!! Staged copy of {} is incomplete ({} entries missing, e.g. {}); rebuilding from module source",
+            module_id, missing.len(), first);
+    }
+    !missing.is_empty()
+}
+
+fn staging_corrupted(module_id: &str, dst: &Path) -> bool {
+    let Some(manifest) = Manifest::load(dst) else {
+        return false;
+    };
+    let mismatches = manifest.mismatches(dst);
+    if let Some(first) = mismatches.first() {
+        log::warn!("!! Staged copy of {} failed {} verification ({} files, e.g. {}); resyncing",
+            module_id, manifest.algorithm.name(), mismatches.len(), first);
+    }
+    !mismatches.is_empty()
+}
+
 fn repair_module_contexts(module_root: &Path, module_id: &str) {
     for part in defs::BUILTIN_PARTITIONS {
         let part_root = module_root.join(part);
@@ -141,3 +209,31 @@ fn has_files_recursive(path: &Path) -> bool {
     }
     false
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::integrity::HashAlgorithm;
+
+    #[test]
+    fn finds_what_a_wipe_removed_from_staging() {
+        let root = std::env::temp_dir().join(format!("sync-missing-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let (src, dst) = (root.join("src"), root.join("dst"));
+        for base in [&src, &dst] {
+            fs::create_dir_all(base.join("system/etc")).unwrap();
+            fs::write(base.join("system/etc/a.conf"), "a").unwrap();
+        }
+        fs::create_dir_all(src.join("vendor")).unwrap();
+        assert_eq!(missing_from_staging(&src, &dst), vec![defs::MANIFEST_FILE_NAME.to_string()]);
+
+        Manifest::build(&dst, HashAlgorithm::Sha256).unwrap().save(&dst).unwrap();
+        assert_eq!(missing_from_staging(&src, &dst), vec!["vendor".to_string()]);
+        fs::create_dir_all(dst.join("vendor")).unwrap();
+        assert!(missing_from_staging(&src, &dst).is_empty());
+
+        fs::remove_file(dst.join("system/etc/a.conf")).unwrap();
+        assert_eq!(missing_from_staging(&src, &dst), vec!["system/etc/a.conf".to_string()]);
+        let _ = fs::remove_dir_all(&root);
+    }
+}
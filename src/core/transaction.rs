@@ -0,0 +1,151 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use anyhow::Result;
+use crate::{core::journal::{self, RuleJournal}, defs::RULE_JOURNAL_DIR, mount::hymofs::{HymoFs, RuleOp}};
+
+/// Rules applied as one unit: if a critical one is rejected, every rule the
+/// transaction applied is taken back and the rules they replaced restored,
+/// so a failed injection leaves the table as it found it. Dropping an
+/// uncommitted transaction rolls it back as well.
+pub struct Transaction<'a> {
+    handle: &'a HymoFs,
+    journal: Option<RuleJournal>,
+    /// Live rules when the transaction began, by path.
+    before: HashMap<String, RuleOp>,
+    applied: Vec<RuleOp>,
+}
+
+/// Adds and hides are what an injection is made of; a delete that fails
+/// leaves no rule behind either way.
+fn is_critical(op: &RuleOp) -> bool {
+    !matches!(op, RuleOp::Delete { .. })
+}
+
+/// The ops that undo `applied`, newest first: each touched path loses the
+/// rule the transaction left on it and gets back the one it had in `before`.
+fn undo_ops(applied: &[RuleOp], before: &HashMap<String, RuleOp>) -> Vec<RuleOp> {
+    let mut seen = HashSet::new();
+    let mut undo = Vec::new();
+    for op in applied.iter().rev() {
+        if !seen.insert(op.path()) {
+            continue;
+        }
+        if !matches!(op, RuleOp::Delete { .. }) {
+            undo.push(RuleOp::Delete { src: op.path().to_string() });
+        }
+        undo.extend(before.get(op.path()).cloned());
+    }
+    undo
+}
+
+impl<'a> Transaction<'a> {
+    pub fn begin(handle: &'a HymoFs) -> Self {
+        let before = match handle.list_ops() {
+            Ok(live) => live.into_iter().map(|op| (op.path().to_string(), op)).collect(),
+            Err(e) => {
+                log::debug!("HymoFS transaction: cannot list live rules ({:#}); rollback only deletes", e);
+                HashMap::new()
+            }
+        };
+        let journal = RuleJournal::begin(Path::new(RULE_JOURNAL_DIR), None)
+            .map_err(|e| log::warn!("Failed to open rule journal: {}", e))
+            .ok();
+        Self { handle, journal, before, applied: Vec::new() }
+    }
+
+    /// Applies `op`. A rejected critical op rolls the whole transaction
+    /// back and is returned as the error; a rejected delete is only logged.
+    pub fn apply(&mut self, op: &RuleOp) -> Result<()> {
+        self.handle.relabel(std::slice::from_ref(op));
+        match journal::apply(self.handle, &mut self.journal, op) {
+            Ok(()) => {
+                self.applied.push(op.clone());
+                Ok(())
+            }
+            Err(e) if is_critical(op) => {
+                let undone = self.rollback();
+                Err(anyhow::Error::from(e).context(format!("HymoFS rejected {}; rolled back {} rules", op.path(), undone)))
+            }
+            Err(e) => {
+                log::warn!("Failed to delete rule for {}: {:#}", op.path(), e);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn apply_all(&mut self, ops: &[RuleOp]) -> Result<()> {
+        ops.iter().try_for_each(|op| self.apply(op))
+    }
+
+    /// Makes the applied rules final and returns how many there were.
+    pub fn commit(mut self) -> usize {
+        let applied = std::mem::take(&mut self.applied).len();
+        self.finish_journal();
+        applied
+    }
+
+    /// Takes back every rule applied so far and returns how many.
+    pub fn rollback(&mut self) -> usize {
+        let applied = std::mem::take(&mut self.applied);
+        for op in undo_ops(&applied, &self.before) {
+            if let Err(e) = journal::apply(self.handle, &mut self.journal, &op) {
+                log::warn!("!! Failed to roll back {}: {:#}", op.path(), e);
+            }
+        }
+        self.finish_journal();
+        applied.len()
+    }
+
+    fn finish_journal(&mut self) {
+        if let Some(Err(e)) = self.journal.take().map(RuleJournal::finish) {
+            log::warn!("Failed to finish rule journal: {}", e);
+        }
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if !self.applied.is_empty() {
+            log::warn!("!! HymoFS transaction dropped uncommitted; rolling back {} rules", self.applied.len());
+            self.rollback();
+        }
+        self.finish_journal();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mount::hymofs::HymoFileType;
+
+    fn add(src: &str, target: &str) -> RuleOp {
+        RuleOp::Add { src: src.into(), target: target.into(), file_type: HymoFileType::Reg }
+    }
+
+    fn del(src: &str) -> RuleOp {
+        RuleOp::Delete { src: src.into() }
+    }
+
+    #[test]
+    fn undo_restores_what_the_transaction_replaced() {
+        let before: HashMap<String, RuleOp> = [("/system/b", add("/system/b", "/old/b")), ("/system/c", add("/system/c", "/old/c"))]
+            .into_iter()
+            .map(|(path, op)| (path.to_string(), op))
+            .collect();
+        let applied = vec![
+            add("/system/a", "/new/a"),
+            add("/system/b", "/new/b"),
+            RuleOp::Hide { path: "/system/x".into() },
+            del("/system/c"),
+            add("/system/a", "/newer/a"),
+        ];
+        assert_eq!(undo_ops(&applied, &before), vec![
+            del("/system/a"),
+            add("/system/c", "/old/c"),
+            del("/system/x"),
+            del("/system/b"),
+            add("/system/b", "/old/b"),
+        ]);
+        assert!(is_critical(&applied[2]) && !is_critical(&applied[3]));
+    }
+}
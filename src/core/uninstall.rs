@@ -0,0 +1,300 @@
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+use anyhow::{Context, Result, bail};
+use crate::{
+    conf::config::Config,
+    core::{direct, inventory::ModuleRules, partitions, rewrite, roots, rules::RuleLedger, scripts::{ScriptRunner, Stage}, state::RuntimeState},
+    defs,
+    mount::hymofs::{self, HymoFs, RuleOp, SharedHymoFs},
+};
+
+pub struct UninstallOptions {
+    pub run_scripts: bool,
+    pub keep_files: bool,
+}
+
+pub fn resolve_hook(module_dir: &Path, hook: &str) -> Option<PathBuf> {
+    let relative = Path::new(hook);
+    let confined = relative.components().all(|c| matches!(c, Component::Normal(_)));
+    (confined && !hook.is_empty()).then(|| module_dir.join(relative))
+}
+
+/// Everything an uninstall of one module would remove or leave behind,
+/// computed without touching anything.
+#[derive(Debug, Default)]
+pub struct TeardownPlan {
+    pub module_id: String,
+    pub scripts: Vec<PathBuf>,
+    pub rules: Vec<String>,
+    pub mounts: Vec<String>,
+    pub direct_paths: usize,
+    pub staging_dir: Option<PathBuf>,
+    pub staging_files: usize,
+    pub staging_bytes: u64,
+    pub state_entries: Vec<String>,
+    pub removed_paths: Vec<PathBuf>,
+}
+
+pub fn validate_id(module_id: &str) -> Result<()> {
+    if module_id.is_empty() || module_id.contains('/') || module_id == ".." {
+        bail!("invalid module id '{}'", module_id);
+    }
+    Ok(())
+}
+
+/// Paths of the rules `module_id` holds in one partition: the ones the
+/// ledger tagged with it, which need no source tree, then whatever a walk of
+/// `source` finds that the ledger lost track of.
+fn partition_rules(ledger: &RuleLedger, module_id: &str, target: &Path, source: &Path, rewrites: &[rewrite::PathRewrite]) -> Vec<String> {
+    let mut paths = ledger.owned_by_under(module_id, &target.to_string_lossy());
+    if source.is_dir() {
+        let mut walked = hymofs::collect_directory_ops(target, source);
+        rewrite::apply(&mut walked, rewrites);
+        let tagged: HashSet<String> = paths.iter().cloned().collect();
+        paths.extend(walked.iter().map(|op| op.path().to_string()).filter(|path| !tagged.contains(path)));
+    }
+    paths
+}
+
+/// Works out what `uninstall` would do with the same arguments. `live` is the
+/// kernel rule table when HymoFS is reachable; without it the rules are the
+/// ones uninstall would try to delete.
+pub fn plan_teardown(config: &Config, module_id: &str, storage_root: &Path, live: Option<&[RuleOp]>, state: &RuntimeState, options: &UninstallOptions) -> Result<TeardownPlan> {
+    validate_id(module_id)?;
+    let module_dir = roots::locate(config, module_id);
+    let rules = ModuleRules::load(&module_dir, module_id);
+    let mut plan = TeardownPlan { module_id: module_id.to_string(), ..Default::default() };
+
+    if options.run_scripts {
+        plan.scripts.extend(Some(module_dir.join("uninstall.sh")).filter(|s| s.is_file()));
+        plan.scripts.extend(rules.cleanup_hooks.iter().filter_map(|hook| resolve_hook(&module_dir, hook)));
+    }
+
+    let staged = storage_root.join(module_id);
+    let content = if staged.exists() { staged.clone() } else { module_dir.clone() };
+    let ledger = RuleLedger::load();
+    let rewrites = rewrite::active(&config.path_rewrites);
+    for partition in partitions::known(config) {
+        let source = content.join(partition);
+        let target = partitions::target(config, partition);
+        plan.rules.extend(partition_rules(&ledger, module_id, &target, &source, &rewrites).into_iter()
+            .filter(|path| live.is_none_or(|live| live.iter().any(|op| op.path() == path))));
+        if state.overlay_modules.iter().any(|id| id == module_id) && state.active_mounts.iter().any(|p| p == partition) && source.is_dir() {
+            plan.mounts.push(format!("{} overlay layer (kept until reboot)", target.display()));
+        }
+    }
+    if state.magic_modules.iter().any(|id| id == module_id) {
+        plan.mounts.push("magic mount binds (kept until reboot)".to_string());
+    }
+    plan.direct_paths = direct::Manifest::load(Path::new(defs::DIRECT_BACKUP_DIR), module_id).entries.len();
+
+    if staged.exists() {
+        for entry in walkdir::WalkDir::new(&staged).into_iter().flatten().filter(|e| !e.file_type().is_dir()) {
+            plan.staging_files += 1;
+            plan.staging_bytes += entry.metadata().map(|m| m.len()).unwrap_or(0);
+        }
+        plan.staging_dir = Some(staged);
+    }
+
+    for (list, ids) in [("overlay_modules", &state.overlay_modules), ("hymo_modules", &state.hymo_modules),
+                        ("magic_modules", &state.magic_modules), ("direct_modules", &state.direct_modules)] {
+        if ids.iter().any(|id| id == module_id) {
+            plan.state_entries.push(format!("daemon state {} (until the next boot rewrites it)", list));
+        }
+    }
+    let owned = ledger.owned_by_under(module_id, "/").len();
+    if owned > 0 {
+        plan.state_entries.push(format!("{} rule ledger entries", owned));
+    }
+    let user_rules = Path::new(defs::RULES_DIR).join(format!("{}.json", module_id));
+    plan.removed_paths.extend(Some(user_rules).filter(|p| p.exists()));
+    if !options.keep_files && module_dir.exists() {
+        plan.removed_paths.push(module_dir);
+    }
+    Ok(plan)
+}
+
+impl TeardownPlan {
+    pub fn is_empty(&self) -> bool {
+        self.scripts.is_empty() && self.rules.is_empty() && self.mounts.is_empty() && self.direct_paths == 0
+            && self.staging_dir.is_none() && self.state_entries.is_empty() && self.removed_paths.is_empty()
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "{}:", self.module_id);
+        if self.is_empty() {
+            let _ = writeln!(out, "  nothing to remove");
+            return out;
+        }
+        for script in &self.scripts {
+            let _ = writeln!(out, "  run:     {}", script.display());
+        }
+        if !self.rules.is_empty() {
+            let _ = writeln!(out, "  rules:   {} HymoFS rules", self.rules.len());
+            for rule in &self.rules {
+                let _ = writeln!(out, "           delete {}", rule);
+            }
+        }
+        for mount in &self.mounts {
+            let _ = writeln!(out, "  mount:   {}", mount);
+        }
+        if self.direct_paths > 0 {
+            let _ = writeln!(out, "  restore: {} paths written directly into partitions", self.direct_paths);
+        }
+        if let Some(dir) = &self.staging_dir {
+            let _ = writeln!(out, "  staging: {} ({} files, {} bytes)", dir.display(), self.staging_files, self.staging_bytes);
+        }
+        for entry in &self.state_entries {
+            let _ = writeln!(out, "  state:   {}", entry);
+        }
+        for path in &self.removed_paths {
+            let _ = writeln!(out, "  delete:  {}", path.display());
+        }
+        out
+    }
+}
+
+pub fn uninstall(config: &Config, module_id: &str, storage_root: &Path, hymofs: Option<&HymoFs>, options: &UninstallOptions) -> Result<()> {
+    validate_id(module_id)?;
+    let module_dir = roots::locate(config, module_id);
+    let rules = ModuleRules::load(&module_dir, module_id);
+    let timeout = Duration::from_secs(config.uninstall_timeout_secs);
+
+    let backend = RuntimeState::load().ok().and_then(|state| state.backend_of(module_id));
+    if options.run_scripts {
+        let script = module_dir.join("uninstall.sh");
+        if script.is_file() {
+            log::info!(">> Running uninstall.sh of {}", module_id);
+            let runner = ScriptRunner::new(module_id, &module_dir, backend, Stage::Uninstall, timeout);
+            if let Err(e) = runner.run(&script) {
+                log::warn!("!! {}", e);
+            }
+        }
+    }
+    let hooks = ScriptRunner::new(module_id, &module_dir, backend, Stage::Cleanup, timeout);
+    for hook in &rules.cleanup_hooks {
+        let Some(path) = resolve_hook(&module_dir, hook) else {
+            log::warn!("!! Ignoring cleanup hook outside the module dir: {}", hook);
+            continue;
+        };
+        log::info!(">> Running cleanup hook {} of {}", hook, module_id);
+        if let Err(e) = hooks.run(&path) {
+            log::warn!("!! {}", e);
+        }
+    }
+
+    let staged = storage_root.join(module_id);
+    if let Some(handle) = hymofs.filter(|h| h.is_available()) {
+        let shared = SharedHymoFs::new(handle.clone());
+        let mut ledger = RuleLedger::load();
+        let content = if staged.exists() { staged.clone() } else { module_dir.clone() };
+        let rewrites = rewrite::active(&config.path_rewrites);
+        for partition in partitions::known(config) {
+            let source = content.join(partition);
+            let target = partitions::target(config, partition);
+            let rules = partition_rules(&ledger, module_id, &target, &source, &rewrites);
+            shared.enqueue(module_id, rules.into_iter().map(|src| RuleOp::Delete { src }).collect());
+        }
+        shared.flush();
+        let stats = shared.take_stats().remove(module_id).unwrap_or_default();
+        log::info!(">> Removed {} HymoFS rules of {} ({} failed)", stats.applied, module_id, stats.failed);
+        ledger.disown(module_id);
+        if let Err(e) = ledger.save() {
+            log::warn!("Failed to save HymoFS rule ledger: {}", e);
+        }
+    }
+
+    match direct::restore(module_id, Path::new(defs::DIRECT_BACKUP_DIR)) {
+        Ok(0) => {}
+        Ok(restored) => log::info!(">> Restored {} paths {} modified directly", restored, module_id),
+        Err(e) => log::warn!("!! Failed to restore direct modifications of {}: {:#}", module_id, e),
+    }
+
+    if staged.exists() {
+        fs::remove_dir_all(&staged).with_context(|| format!("Failed to remove staging {}", staged.display()))?;
+    }
+    let user_rules = Path::new(defs::RULES_DIR).join(format!("{}.json", module_id));
+    if user_rules.exists() {
+        let _ = fs::remove_file(&user_rules);
+    }
+    if !options.keep_files && module_dir.exists() {
+        fs::remove_dir_all(&module_dir).with_context(|| format!("Failed to remove {}", module_dir.display()))?;
+    }
+    log::info!(">> Module {} uninstalled", module_id);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn confines_hooks_to_module_dir() {
+        let dir = Path::new("/data/adb/modules/foo");
+        assert_eq!(resolve_hook(dir, "hooks/cleanup.sh"), Some(dir.join("hooks/cleanup.sh")));
+        assert_eq!(resolve_hook(dir, "../bar/x.sh"), None);
+        assert_eq!(resolve_hook(dir, "/system/bin/sh"), None);
+        assert_eq!(resolve_hook(dir, ""), None);
+    }
+
+    #[test]
+    fn teardown_plan_lists_what_uninstall_removes() {
+        let root = std::env::temp_dir().join(format!("teardown-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let config = Config { moduledir: root.join("modules"), ..Default::default() };
+        for base in [root.join("modules/m"), root.join("staging/m")] {
+            fs::create_dir_all(base.join("system/bin")).unwrap();
+            fs::write(base.join("system/bin/tool"), "x").unwrap();
+        }
+        fs::write(root.join("modules/m/uninstall.sh"), "true").unwrap();
+        let state = RuntimeState {
+            overlay_modules: vec!["m".into()],
+            active_mounts: vec!["system".into()],
+            ..Default::default()
+        };
+        let options = UninstallOptions { run_scripts: true, keep_files: false };
+        let live = vec![hymofs::RuleOp::Add { src: "/system/bin/tool".into(), target: "/x".into(), file_type: hymofs::HymoFileType::Reg }];
+
+        let plan = plan_teardown(&config, "m", &root.join("staging"), Some(&live), &state, &options).unwrap();
+        assert_eq!(plan.rules, vec!["/system/bin/tool".to_string()]);
+        assert_eq!(plan.scripts, vec![root.join("modules/m/uninstall.sh")]);
+        assert_eq!(plan.mounts.len(), 1);
+        assert_eq!((plan.staging_files, plan.staging_bytes), (1, 1));
+        assert_eq!(plan.removed_paths, vec![root.join("modules/m")]);
+        let rendered = plan.render();
+        assert!(rendered.starts_with("m:\n"));
+        assert!(rendered.contains("delete /system/bin/tool"));
+        assert!(rendered.contains("overlay_modules"));
+
+        let kept = UninstallOptions { run_scripts: false, keep_files: true };
+        let plan = plan_teardown(&config, "m", &root.join("staging"), Some(&[]), &RuntimeState::default(), &kept).unwrap();
+        assert!(plan.rules.is_empty() && plan.scripts.is_empty() && plan.removed_paths.is_empty());
+        assert!(plan_teardown(&config, "gone", &root.join("staging"), Some(&[]), &RuntimeState::default(), &kept).unwrap().is_empty());
+        assert!(plan_teardown(&config, "../x", &root, None, &state, &kept).is_err());
+        assert!(root.join("modules/m").exists(), "planning removes nothing");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn finds_tagged_rules_without_the_module_tree() {
+        let root = std::env::temp_dir().join(format!("tagged-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let mut ledger = RuleLedger::default();
+        let tagged = hymofs::RuleOp::Hide { path: "/system/app/Old".into() };
+        ledger.claim([("m", &tagged), ("other", &hymofs::RuleOp::Hide { path: "/system/app/Theirs".into() })]);
+        let target = Path::new("/system");
+
+        let source = root.join("m/system");
+        assert_eq!(partition_rules(&ledger, "m", target, &source, &[]), ["/system/app/Old"]);
+        assert!(partition_rules(&ledger, "m", Path::new("/vendor"), &source, &[]).is_empty());
+
+        fs::create_dir_all(source.join("bin")).unwrap();
+        fs::write(source.join("bin/tool"), "x").unwrap();
+        assert_eq!(partition_rules(&ledger, "m", target, &source, &[]), ["/system/app/Old", "/system/bin/tool"]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,137 @@
+use std::fs;
+use std::path::Path;
+use serde::Serialize;
+use crate::{core::staging, utils};
+
+/// `ro.boot.veritymode`, which the bootloader sets from the vbmeta flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VerityMode {
+    Enforcing,
+    /// Enforcing, and a corrupted block already turned reads into EIO.
+    Eio,
+    Logging,
+    Disabled,
+    Unknown,
+}
+
+impl VerityMode {
+    pub fn parse(value: Option<&str>) -> Self {
+        match value {
+            Some("enforcing") | Some("restart") => VerityMode::Enforcing,
+            Some("eio") => VerityMode::Eio,
+            Some("logging") => VerityMode::Logging,
+            Some("disabled") => VerityMode::Disabled,
+            _ => VerityMode::Unknown,
+        }
+    }
+
+    fn enforced(self) -> bool {
+        matches!(self, VerityMode::Enforcing | VerityMode::Eio | VerityMode::Unknown)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PartitionVerity {
+    pub partition: String,
+    pub device: String,
+    pub dm_name: Option<String>,
+    pub read_only: bool,
+    pub locked: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VerityReport {
+    pub mode: VerityMode,
+    pub verified_boot_state: Option<String>,
+    pub device_state: Option<String>,
+    pub partitions: Vec<PartitionVerity>,
+}
+
+impl VerityReport {
+    pub fn partition(&self, name: &str) -> Option<&PartitionVerity> {
+        self.partitions.iter().find(|p| p.partition == name)
+    }
+}
+
+/// `dm-N` for a device-mapper block device path, following the
+/// `/dev/block/mapper/<name>` links when they exist.
+fn dm_node(device: &str) -> Option<String> {
+    let resolved = fs::canonicalize(device).unwrap_or_else(|_| device.into());
+    let name = resolved.file_name()?.to_string_lossy().into_owned();
+    name.starts_with("dm-").then_some(name)
+}
+
+/// Whether reads of a partition are checked against a verity hash tree. A
+/// device-mapper node named for verity always is; otherwise a read-only
+/// dm-backed mount counts as protected unless the bootloader disabled or
+/// relaxed verification.
+pub fn is_locked(on_dm: bool, dm_name: Option<&str>, read_only: bool, mode: VerityMode) -> bool {
+    if dm_name.is_some_and(|name| name.contains("verity")) {
+        return mode != VerityMode::Disabled;
+    }
+    on_dm && read_only && mode.enforced()
+}
+
+pub fn inspect(mountinfo: &str, partition: &str, mode: VerityMode) -> Option<PartitionVerity> {
+    let target = Path::new("/").join(partition);
+    let resolved = target.canonicalize().unwrap_or(target);
+    let entry = staging::mount_entry(mountinfo, &resolved)?;
+    let node = dm_node(&entry.source);
+    let dm_name = node.as_ref()
+        .and_then(|node| fs::read_to_string(format!("/sys/block/{}/dm/name", node)).ok())
+        .map(|name| name.trim().to_string());
+    Some(PartitionVerity {
+        partition: partition.to_string(),
+        locked: is_locked(node.is_some(), dm_name.as_deref(), entry.flags.read_only, mode),
+        device: entry.source,
+        dm_name,
+        read_only: entry.flags.read_only,
+    })
+}
+
+/// Verity and AVB state of `partitions` as mounted right now.
+pub fn probe(partitions: &[&str]) -> VerityReport {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo").unwrap_or_default();
+    let mode = VerityMode::parse(utils::get_prop("ro.boot.veritymode").as_deref());
+    VerityReport {
+        mode,
+        verified_boot_state: utils::get_prop("ro.boot.verifiedbootstate"),
+        device_state: utils::get_prop("ro.boot.vbmeta.device_state"),
+        partitions: partitions.iter().filter_map(|part| inspect(&mountinfo, part, mode)).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_verity_modes() {
+        assert_eq!(VerityMode::parse(Some("enforcing")), VerityMode::Enforcing);
+        assert_eq!(VerityMode::parse(Some("eio")), VerityMode::Eio);
+        assert_eq!(VerityMode::parse(Some("disabled")), VerityMode::Disabled);
+        assert_eq!(VerityMode::parse(None), VerityMode::Unknown);
+    }
+
+    #[test]
+    fn only_read_only_dm_mounts_are_locked() {
+        assert!(is_locked(true, Some("system_a"), true, VerityMode::Enforcing));
+        assert!(is_locked(true, Some("system_a"), true, VerityMode::Unknown));
+        assert!(!is_locked(true, Some("system_a"), true, VerityMode::Logging));
+        assert!(!is_locked(true, Some("system_a"), false, VerityMode::Enforcing), "a rw dm mount has no hash tree");
+        assert!(!is_locked(false, None, true, VerityMode::Enforcing));
+        assert!(is_locked(true, Some("system-verity"), false, VerityMode::Logging));
+        assert!(!is_locked(true, Some("system-verity"), true, VerityMode::Disabled));
+    }
+
+    #[test]
+    fn inspects_the_mount_holding_a_partition() {
+        let mountinfo = "22 1 253:0 / / ro,relatime - ext4 /dev/block/sda3 ro,seclabel\n";
+        let part = inspect(mountinfo, "system", VerityMode::Enforcing).unwrap();
+        assert_eq!(part.device, "/dev/block/sda3");
+        assert!(part.read_only);
+        assert!(!part.locked, "not backed by device-mapper");
+        assert_eq!(inspect("", "system", VerityMode::Enforcing), None);
+    }
+}
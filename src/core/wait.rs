@@ -0,0 +1,132 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+use anyhow::{Result, bail};
+use crate::{core::state::RuntimeState, mount::hymofs::{HymoFs, RuleOp}, utils};
+
+pub const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModuleState {
+    Mounted,
+    Unmounted,
+}
+
+impl FromStr for ModuleState {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "mounted" => Ok(ModuleState::Mounted),
+            "unmounted" => Ok(ModuleState::Unmounted),
+            other => bail!("unknown module state '{}' (expected mounted or unmounted)", other),
+        }
+    }
+}
+
+impl fmt::Display for ModuleState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ModuleState::Mounted => "mounted",
+            ModuleState::Unmounted => "unmounted",
+        })
+    }
+}
+
+/// Calls `check` until it returns true or `timeout` passes; the last check
+/// happens at the deadline, so a zero timeout still checks once.
+pub fn poll_until(timeout: Duration, interval: Duration, mut check: impl FnMut() -> bool) -> bool {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if check() {
+            return true;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return false;
+        }
+        thread::sleep(interval.min(deadline - now));
+    }
+}
+
+pub fn module_in_state(state: &RuntimeState, module_id: &str, wanted: ModuleState) -> bool {
+    let mounted = utils::same_boot(&state.session_id, utils::session_id())
+        && [&state.overlay_modules, &state.hymo_modules, &state.magic_modules, &state.direct_modules]
+            .iter()
+            .any(|ids| ids.iter().any(|id| id == module_id));
+    mounted == (wanted == ModuleState::Mounted)
+}
+
+pub fn rule_is_live(live: &[RuleOp], target: &str) -> bool {
+    let target = target.trim_end_matches('/');
+    live.iter().any(|op| op.path() == target)
+}
+
+pub fn wait_for_rule(device: &Path, target: &str, timeout: Duration) -> Result<()> {
+    let found = poll_until(timeout, POLL_INTERVAL, || {
+        HymoFs::open_at(device).ok()
+            .filter(HymoFs::is_available)
+            .and_then(|handle| handle.list_ops().ok())
+            .is_some_and(|live| rule_is_live(&live, target))
+    });
+    if !found {
+        bail!("timed out after {}s waiting for a HymoFS rule on {}", timeout.as_secs(), target);
+    }
+    Ok(())
+}
+
+pub fn wait_for_module(module_id: &str, wanted: ModuleState, timeout: Duration) -> Result<()> {
+    let reached = poll_until(timeout, POLL_INTERVAL, || {
+        RuntimeState::load().is_ok_and(|state| module_in_state(&state, module_id, wanted))
+    });
+    if !reached {
+        bail!("timed out after {}s waiting for {} to be {}", timeout.as_secs(), module_id, wanted);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mount::hymofs::HymoFileType;
+
+    #[test]
+    fn polls_until_the_condition_holds() {
+        let mut calls = 0;
+        assert!(poll_until(Duration::from_secs(5), Duration::from_millis(1), || {
+            calls += 1;
+            calls == 3
+        }));
+        assert_eq!(calls, 3);
+        let mut calls = 0;
+        assert!(!poll_until(Duration::ZERO, Duration::from_millis(1), || {
+            calls += 1;
+            false
+        }));
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn module_state_requires_a_run_from_this_boot() {
+        let mut state = RuntimeState { hymo_modules: vec!["m".into()], ..Default::default() };
+        assert!(module_in_state(&state, "m", ModuleState::Unmounted), "state without a session is stale");
+        state.session_id = utils::session_id().to_string();
+        assert!(module_in_state(&state, "m", ModuleState::Mounted));
+        assert!(module_in_state(&state, "other", ModuleState::Unmounted));
+    }
+
+    #[test]
+    fn matches_rules_by_target_path() {
+        let live = vec![
+            RuleOp::Add { src: "/system/bin/a".into(), target: "/m/a".into(), file_type: HymoFileType::Reg },
+            RuleOp::Hide { path: "/system/app/B".into() },
+        ];
+        assert!(rule_is_live(&live, "/system/bin/a"));
+        assert!(rule_is_live(&live, "/system/app/B/"));
+        assert!(!rule_is_live(&live, "/system/bin"));
+        assert!("mounted".parse::<ModuleState>().is_ok());
+        assert!("gone".parse::<ModuleState>().is_err());
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use crate::mount::hymofs::RuleOp;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PartialApplyPolicy {
+    /// Keep rule groups whose checkpointed rules are all still live and
+    /// re-apply the rest.
+    #[default]
+    Resume,
+    /// Remove every live rule the interrupted run recorded, then apply fresh.
+    Rollback,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+enum WalEntry {
+    Begin { session: String, planned: Vec<String> },
+    Checkpoint(Checkpoint),
+    Commit,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub tag: String,
+    pub module_id: String,
+    pub rules: Vec<String>,
+}
+
+/// Append-only journal of one HymoFS apply. Each entry is a JSON line synced
+/// to disk before the apply moves on, so a power loss leaves a readable
+/// prefix behind.
+pub struct ApplyWal {
+    file: File,
+}
+
+impl ApplyWal {
+    pub fn begin(path: &Path, session: &str, planned: Vec<String>) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut wal = Self { file: File::create(path)? };
+        wal.append(&WalEntry::Begin { session: session.to_string(), planned })?;
+        Ok(wal)
+    }
+
+    fn append(&mut self, entry: &WalEntry) -> Result<()> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    pub fn checkpoint(&mut self, tag: &str, module_id: &str, rules: &[RuleOp]) -> Result<()> {
+        self.append(&WalEntry::Checkpoint(Checkpoint {
+            tag: tag.to_string(),
+            module_id: module_id.to_string(),
+            rules: rules.iter().map(|op| op.path().to_string()).collect(),
+        }))
+    }
+
+    pub fn commit(mut self) -> Result<()> {
+        self.append(&WalEntry::Commit)
+    }
+}
+
+/// What an uncommitted journal says about the run that wrote it.
+#[derive(Debug, Default, PartialEq)]
+pub struct PartialApply {
+    pub session: String,
+    pub planned: Vec<String>,
+    pub checkpoints: Vec<Checkpoint>,
+}
+
+/// How the next apply deals with a partial one: tags to leave in place and
+/// rule paths to delete before injecting.
+#[derive(Debug, Default, PartialEq)]
+pub struct RecoveryPlan {
+    pub resume: HashSet<String>,
+    pub rollback: Vec<String>,
+}
+
+impl RecoveryPlan {
+    /// Rule paths of the resumed checkpoints, which the reset must not touch.
+    pub fn kept<'a>(&self, checkpoints: &'a [Checkpoint]) -> HashSet<&'a str> {
+        checkpoints.iter()
+            .filter(|cp| self.resume.contains(&cp.tag))
+            .flat_map(|cp| cp.rules.iter().map(String::as_str))
+            .collect()
+    }
+}
+
+/// Reads a journal; `None` if it is missing, empty or committed. A torn last
+/// line is ignored, which only loses the checkpoint that was being written.
+pub fn parse(content: &str) -> Option<PartialApply> {
+    let mut partial: Option<PartialApply> = None;
+    for entry in content.lines().filter_map(|line| serde_json::from_str::<WalEntry>(line).ok()) {
+        match entry {
+            WalEntry::Begin { session, planned } => {
+                partial = Some(PartialApply { session, planned, checkpoints: Vec::new() });
+            }
+            WalEntry::Checkpoint(cp) => {
+                if let Some(p) = partial.as_mut() {
+                    p.checkpoints.push(cp);
+                }
+            }
+            WalEntry::Commit => partial = None,
+        }
+    }
+    partial
+}
+
+pub fn recover(path: &Path) -> Option<PartialApply> {
+    parse(&fs::read_to_string(path).ok()?)
+}
+
+impl PartialApply {
+    /// Checkpoints whose every rule shows up in the kernel's live listing.
+    pub fn landed(&self, live: &HashSet<&str>) -> Vec<&Checkpoint> {
+        self.checkpoints.iter()
+            .filter(|cp| !cp.rules.is_empty() && cp.rules.iter().all(|rule| live.contains(rule.as_str())))
+            .collect()
+    }
+
+    /// Without a live listing nothing can be verified, so resume keeps nothing
+    /// and rollback deletes everything the journal recorded.
+    pub fn plan(&self, live: Option<&[RuleOp]>, policy: PartialApplyPolicy) -> RecoveryPlan {
+        let live: Option<HashSet<&str>> = live.map(|ops| ops.iter().map(RuleOp::path).collect());
+        match policy {
+            PartialApplyPolicy::Resume => RecoveryPlan {
+                resume: live.map(|live| self.landed(&live).into_iter().map(|cp| cp.tag.clone()).collect()).unwrap_or_default(),
+                rollback: Vec::new(),
+            },
+            PartialApplyPolicy::Rollback => RecoveryPlan {
+                resume: HashSet::new(),
+                rollback: self.checkpoints.iter()
+                    .flat_map(|cp| cp.rules.iter())
+                    .filter(|rule| live.as_ref().is_none_or(|live| live.contains(rule.as_str())))
+                    .cloned()
+                    .collect(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mount::hymofs::HymoFileType;
+
+    fn add(src: &str) -> RuleOp {
+        RuleOp::Add { src: src.into(), target: format!("/data/adb/modules/m{}", src), file_type: HymoFileType::Reg }
+    }
+
+    fn interrupted() -> PartialApply {
+        let path = std::env::temp_dir().join(format!("wal-{}.log", std::process::id()));
+        let mut wal = ApplyWal::begin(&path, "sess", vec!["a/system".into(), "b/system".into()]).unwrap();
+        wal.checkpoint("a/system", "a", &[add("/system/bin/a1"), add("/system/bin/a2")]).unwrap();
+        wal.checkpoint("b/system", "b", &[add("/system/bin/b1")]).unwrap();
+        drop(wal);
+        let mut content = fs::read_to_string(&path).unwrap();
+        content.push_str("{\"event\":\"checkpo");
+        fs::remove_file(&path).unwrap();
+        parse(&content).expect("uncommitted journal is a partial apply")
+    }
+
+    #[test]
+    fn committed_or_empty_journals_are_not_partial() {
+        let path = std::env::temp_dir().join(format!("wal-commit-{}.log", std::process::id()));
+        let mut wal = ApplyWal::begin(&path, "sess", vec!["a/system".into()]).unwrap();
+        wal.checkpoint("a/system", "a", &[add("/system/bin/a1")]).unwrap();
+        wal.commit().unwrap();
+        assert_eq!(recover(&path), None);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(recover(&path), None);
+        assert_eq!(parse(""), None);
+    }
+
+    #[test]
+    fn reads_checkpoints_up_to_a_torn_line() {
+        let partial = interrupted();
+        assert_eq!(partial.session, "sess");
+        assert_eq!(partial.planned.len(), 2);
+        assert_eq!(partial.checkpoints.len(), 2);
+        assert_eq!(partial.checkpoints[0].rules, vec!["/system/bin/a1", "/system/bin/a2"]);
+    }
+
+    #[test]
+    fn resume_keeps_only_fully_landed_groups() {
+        let partial = interrupted();
+        let live = vec![add("/system/bin/a1"), add("/system/bin/a2"), add("/system/bin/other")];
+        let plan = partial.plan(Some(&live), PartialApplyPolicy::Resume);
+        assert_eq!(plan.resume, HashSet::from(["a/system".to_string()]));
+        assert!(plan.rollback.is_empty());
+        assert_eq!(plan.kept(&partial.checkpoints), HashSet::from(["/system/bin/a1", "/system/bin/a2"]));
+        assert!(partial.plan(None, PartialApplyPolicy::Resume).resume.is_empty());
+        assert!(partial.plan(Some(&[]), PartialApplyPolicy::Resume).resume.is_empty());
+    }
+
+    #[test]
+    fn rollback_removes_recorded_rules_that_are_live() {
+        let partial = interrupted();
+        let live = vec![add("/system/bin/a1"), add("/system/bin/b1")];
+        let plan = partial.plan(Some(&live), PartialApplyPolicy::Rollback);
+        assert_eq!(plan.rollback, vec!["/system/bin/a1", "/system/bin/b1"]);
+        assert!(plan.resume.is_empty());
+        assert_eq!(partial.plan(None, PartialApplyPolicy::Rollback).rollback.len(), 3);
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::mem::MaybeUninit;
+use std::os::fd::OwnedFd;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use anyhow::{Context, Result};
+use rustix::event::{PollFd, PollFlags, Timespec, poll};
+use rustix::fs::inotify::{self, CreateFlags, ReadFlags, WatchFlags};
+use rustix::io::Errno;
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+/// Reloading modules in daemon mode when their directories change.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModuleWatch {
+    pub enabled: bool,
+    /// Quiet time after the last change before modules are reloaded.
+    pub debounce_ms: u64,
+    /// Reload at the latest this long after the first change, even while
+    /// changes keep coming.
+    pub max_delay_ms: u64,
+}
+
+impl Default for ModuleWatch {
+    fn default() -> Self {
+        Self { enabled: true, debounce_ms: 2000, max_delay_ms: 30_000 }
+    }
+}
+
+/// Turns a burst of changes, e.g. an installer writing hundreds of files,
+/// into one reload.
+#[derive(Debug)]
+pub struct Debouncer {
+    quiet: Duration,
+    max_delay: Duration,
+    first: Option<Instant>,
+    last: Option<Instant>,
+}
+
+impl Debouncer {
+    pub fn new(quiet: Duration, max_delay: Duration) -> Self {
+        Self { quiet, max_delay, first: None, last: None }
+    }
+
+    pub fn record(&mut self, now: Instant) {
+        self.first.get_or_insert(now);
+        self.last = Some(now);
+    }
+
+    /// How long until the pending changes are due; `None` when there are
+    /// none.
+    pub fn wait(&self, now: Instant) -> Option<Duration> {
+        let (first, last) = (self.first?, self.last?);
+        let due = (last + self.quiet).min(first + self.max_delay);
+        Some(due.saturating_duration_since(now))
+    }
+
+    /// Whether the pending changes are due, forgetting them if so.
+    pub fn fire(&mut self, now: Instant) -> bool {
+        let due = self.wait(now) == Some(Duration::ZERO);
+        if due {
+            self.first = None;
+            self.last = None;
+        }
+        due
+    }
+}
+
+const WATCHED: WatchFlags = WatchFlags::CREATE
+    .union(WatchFlags::DELETE)
+    .union(WatchFlags::CLOSE_WRITE)
+    .union(WatchFlags::MOVED_FROM)
+    .union(WatchFlags::MOVED_TO)
+    .union(WatchFlags::ATTRIB)
+    .union(WatchFlags::ONLYDIR);
+
+/// inotify watches on every directory below the module roots; inotify
+/// itself does not recurse.
+struct Watcher {
+    fd: OwnedFd,
+    dirs: HashMap<i32, PathBuf>,
+}
+
+impl Watcher {
+    fn new() -> Result<Self> {
+        let fd = inotify::init(CreateFlags::CLOEXEC | CreateFlags::NONBLOCK).context("Failed to create an inotify instance")?;
+        Ok(Self { fd, dirs: HashMap::new() })
+    }
+
+    fn watch_tree(&mut self, root: &Path) {
+        for entry in WalkDir::new(root).into_iter().flatten().filter(|e| e.file_type().is_dir()) {
+            match inotify::add_watch(&self.fd, entry.path(), WATCHED) {
+                Ok(wd) => {
+                    self.dirs.insert(wd, entry.into_path());
+                }
+                Err(e) => log::debug!("Cannot watch {}: {}", entry.path().display(), e),
+            }
+        }
+    }
+
+    /// Reads the queued events, watching directories that appeared, and
+    /// returns whether there were any.
+    fn drain(&mut self, roots: &[PathBuf]) -> Result<bool> {
+        let mut buf = [MaybeUninit::uninit(); 4096];
+        let mut created = Vec::new();
+        let mut changed = false;
+        let mut overflowed = false;
+        {
+            let mut reader = inotify::Reader::new(&self.fd, &mut buf);
+            loop {
+                let event = match reader.next() {
+                    Ok(event) => event,
+                    Err(Errno::AGAIN) => break,
+                    Err(Errno::INTR) => continue,
+                    Err(e) => return Err(e).context("Failed to read inotify events"),
+                };
+                let flags = event.events();
+                if flags.contains(ReadFlags::QUEUE_OVERFLOW) {
+                    overflowed = true;
+                    changed = true;
+                    continue;
+                }
+                if flags.contains(ReadFlags::IGNORED) {
+                    self.dirs.remove(&event.wd());
+                    continue;
+                }
+                changed = true;
+                if flags.contains(ReadFlags::ISDIR) && flags.intersects(ReadFlags::CREATE | ReadFlags::MOVED_TO) {
+                    if let (Some(dir), Some(name)) = (self.dirs.get(&event.wd()), event.file_name()) {
+                        created.push(dir.join(OsStr::from_bytes(name.to_bytes())));
+                    }
+                }
+            }
+        }
+        if overflowed {
+            log::warn!("!! Module watcher: event queue overflowed; watching all roots again");
+            self.dirs.clear();
+            let fd = inotify::init(CreateFlags::CLOEXEC | CreateFlags::NONBLOCK).context("Failed to create an inotify instance")?;
+            self.fd = fd;
+            roots.iter().for_each(|root| self.watch_tree(root));
+        } else {
+            created.iter().for_each(|dir| self.watch_tree(dir));
+        }
+        Ok(changed)
+    }
+}
+
+/// Watches `roots` and calls `on_change` once changes below them have
+/// settled as `settings` asks. Only returns if inotify fails.
+pub fn watch<F: FnMut()>(settings: &ModuleWatch, roots: &[PathBuf], mut on_change: F) -> Result<()> {
+    let mut watcher = Watcher::new()?;
+    roots.iter().for_each(|root| watcher.watch_tree(root));
+    log::info!(">> Watching {} module directories for changes", watcher.dirs.len());
+    let mut debouncer = Debouncer::new(Duration::from_millis(settings.debounce_ms), Duration::from_millis(settings.max_delay_ms));
+    loop {
+        let timeout = debouncer.wait(Instant::now()).map(Timespec::try_from).transpose().context("Debounce delay out of range")?;
+        let mut fds = [PollFd::new(&watcher.fd, PollFlags::IN)];
+        match poll(&mut fds, timeout.as_ref()) {
+            Ok(_) | Err(Errno::INTR) => {}
+            Err(e) => return Err(e).context("Failed to wait for inotify events"),
+        }
+        if watcher.drain(roots)? {
+            debouncer.record(Instant::now());
+        }
+        if debouncer.fire(Instant::now()) {
+            on_change();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn debounces_bursts_but_not_forever() {
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+        let mut debouncer = Debouncer::new(ms(100), ms(250));
+        assert_eq!(debouncer.wait(start), None);
+        debouncer.record(start);
+        debouncer.record(start + ms(60));
+        assert_eq!(debouncer.wait(start + ms(60)), Some(ms(100)));
+        assert!(!debouncer.fire(start + ms(150)));
+        debouncer.record(start + ms(200));
+        assert_eq!(debouncer.wait(start + ms(200)), Some(ms(50)));
+        assert!(debouncer.fire(start + ms(250)));
+        assert_eq!(debouncer.wait(start + ms(250)), None);
+    }
+
+    #[test]
+    fn watches_directories_as_they_appear() {
+        let root = std::env::temp_dir().join(format!("watcher-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("a")).unwrap();
+        let roots = vec![root.clone()];
+        let mut watcher = Watcher::new().unwrap();
+        watcher.watch_tree(&root);
+        assert_eq!(watcher.dirs.len(), 2);
+        assert!(!watcher.drain(&roots).unwrap());
+
+        fs::create_dir_all(root.join("b/system")).unwrap();
+        assert!(watcher.drain(&roots).unwrap());
+        fs::write(root.join("b/system/hosts"), "").unwrap();
+        assert!(watcher.drain(&roots).unwrap());
+        assert!(watcher.dirs.values().any(|dir| *dir == root.join("b/system")));
+
+        fs::remove_dir_all(root.join("a")).unwrap();
+        assert!(watcher.drain(&roots).unwrap());
+        assert!(!watcher.dirs.values().any(|dir| *dir == root.join("a")));
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -1,10 +1,29 @@
 pub const FALLBACK_CONTENT_DIR: &str = "/dev/meta_hybrid_mnt/";
+pub const STAGING_DIR_NAME: &str = "meta_hybrid_mnt";
+pub const STAGING_ROOT_CANDIDATES: &[&str] = &["/debug_ramdisk", "/sbin", "/dev", "/mnt/vendor"];
 pub const BASE_DIR: &str = "/data/adb/meta-hybrid/";
 pub const RUN_DIR: &str = "/data/adb/meta-hybrid/run/";
+pub const RULES_DIR: &str = "/data/adb/meta-hybrid/rules";
 pub const STATE_FILE: &str = "/data/adb/meta-hybrid/run/daemon_state.json";
+pub const STAGING_ROOT_FILE: &str = "/data/adb/meta-hybrid/run/staging_root.json";
 pub const DAEMON_LOG_FILE: &str = "/data/adb/meta-hybrid/daemon.log";
+pub const RULE_LEDGER_FILE: &str = "/data/adb/meta-hybrid/rule_ledger.json";
+pub const APPLY_WAL_FILE: &str = "/data/adb/meta-hybrid/run/apply.wal";
+pub const APPLY_MARKER_FILE: &str = "/data/adb/meta-hybrid/run/applying";
+pub const METRICS_STATE_FILE: &str = "/data/adb/meta-hybrid/run/metrics.json";
+pub const MIGRATIONS_LOG: &str = "/data/adb/meta-hybrid/migrations.log";
+pub const MODULE_STATS_FILE: &str = "/data/adb/meta-hybrid/module_stats.json";
+pub const DISABLE_REASONS_FILE: &str = "/data/adb/meta-hybrid/disable_reasons.json";
+pub const HISTORY_FILE: &str = "/data/adb/meta-hybrid/history.jsonl";
+pub const MODULE_SNAPSHOT_FILE: &str = "/data/adb/meta-hybrid/module_snapshot.json";
+pub const DIRECT_BACKUP_DIR: &str = "/data/adb/meta-hybrid/direct";
+pub const DIRECT_APPROVALS_FILE: &str = "/data/adb/meta-hybrid/direct_approved";
+pub const PROFILES_FILE: &str = "/data/adb/meta-hybrid/profiles";
+pub const HYMOFS_VERIFIED_FILE: &str = "/data/adb/meta-hybrid/hymofs_verified";
+pub const DROPBOX_DIR: &str = "/data/system/dropbox";
 pub const DISABLE_FILE_NAME: &str = "disable";
 pub const REMOVE_FILE_NAME: &str = "remove";
+pub const MANIFEST_FILE_NAME: &str = ".meta_hybrid_manifest.json";
 pub const SKIP_MOUNT_FILE_NAME: &str = "skip_mount";
 pub const OVERLAY_SOURCE: &str = "KSU";
 pub const KSU_OVERLAY_SOURCE: &str = OVERLAY_SOURCE;
@@ -12,9 +31,28 @@ pub const KSU_OVERLAY_SOURCE: &str = OVERLAY_SOURCE;
 pub const SYSTEM_RW_DIR: &str = "/data/adb/meta-hybrid/rw";
 pub const MODULE_PROP_FILE: &str = "/data/adb/modules/meta-hybrid/module.prop";
 pub const BUILTIN_PARTITIONS: &[&str] = &["system", "vendor", "product", "system_ext", "odm", "oem", "apex"];
-#[allow(dead_code)]
 pub const REPLACE_DIR_FILE_NAME: &str = ".replace";
-#[allow(dead_code)]
 pub const REPLACE_DIR_XATTR: &str = "trusted.overlay.opaque";
+/// aufs-style whiteouts: `.wh.<name>` hides `<name>`, and a directory
+/// holding the opaque marker hides what it replaces.
+pub const WHITEOUT_PREFIX: &str = ".wh.";
+pub const OPAQUE_MARKER_FILE_NAME: &str = ".wh..wh..opq";
 pub const TMPFS_CANDIDATES: &[&str] = &["/debug_ramdisk", "/patch_hw", "/oem", "/root", "/sbin"];
-pub const HYMO_PROTOCOL_VERSION: i32 = 5;
\ No newline at end of file
+pub const HYMO_PROTOCOL_VERSION: i32 = 5;
+/// Oldest Linux release HymoFS builds for, as `(major, minor)`.
+pub const HYMOFS_MIN_KERNEL: (u32, u32) = (5, 10);
+pub const DEFAULT_HYMOFS_RULE_BUDGET: usize = 256 * 1024;
+pub const CANARY_TARGET: &str = "/system/etc/.meta_hybrid_canary";
+pub const CANARY_SOURCE_NAME: &str = "hymofs_canary";
+pub const SELFTEST_BIN_NAME: &str = "hymo-selftest";
+pub const DEFAULT_CERTIFY_RULES: usize = 10_000;
+pub const WHITEOUT_NODE: &str = "/data/adb/meta-hybrid/run/hymofs_whiteout";
+pub const MAGIC_INJECTIONS_FILE: &str = "/data/adb/meta-hybrid/run/magic_injections.json";
+pub const RULE_JOURNAL_DIR: &str = "/data/adb/meta-hybrid/run/journal";
+pub const BACKEND_HEALTH_FILE: &str = "/data/adb/meta-hybrid/backend_health.json";
+pub const BOOT_ATTEMPTS_FILE: &str = "/data/adb/meta-hybrid/boot_attempts.json";
+pub const APP_SCOPES_FILE: &str = "/data/adb/meta-hybrid/app_scopes.json";
+pub const HYMO_BIN_NAME: &str = "hymo";
+pub const HYMOD_BIN_NAME: &str = "hymod";
+pub const DAEMON_SOCKET: &str = "/data/adb/meta-hybrid/run/hymod.sock";
+pub const INSTALL_WORK_DIR: &str = "/data/adb/meta-hybrid/install";
\ No newline at end of file
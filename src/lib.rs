@@ -0,0 +1,29 @@
+pub mod builder;
+pub mod conf;
+pub mod core;
+pub mod defs;
+pub mod mount;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub mod try_umount;
+pub mod utils;
+
+pub use builder::{Backend, Hymo, HymoBuilder};
+
+/// What an embedder needs for the common case:
+///
+/// ```no_run
+/// use hymo::prelude::*;
+///
+/// let hymo = Hymo::builder().backend(Auto).config("/data/adb/meta-hybrid/config.toml").build()?;
+/// let backend = hymo.inject("/data/local/tmp/etc".as_ref(), "/system/etc".as_ref())?;
+/// println!("served by {}", backend);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub mod prelude {
+    pub use crate::builder::{Backend::{self, Auto}, Hymo, HymoBuilder};
+    pub use crate::conf::config::Config;
+    pub use crate::core::manager::{InjectOutcome, InstalledModule};
+    pub use crate::core::state::RuntimeState;
+    pub use crate::mount::{BackendKind, MountBackend};
+    pub use crate::mount::hymofs::{HymoError, HymoFileType, HymoFs, RuleOp};
+}
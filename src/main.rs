@@ -1,34 +1,90 @@
-mod conf;
-mod core;
-mod defs;
-mod mount;
+use hymo::{conf, core, defs, mount, utils};
 #[cfg(any(target_os = "linux", target_os = "android"))]
-mod try_umount;
-mod utils;
+use hymo::try_umount;
 
-use std::path::{Path, PathBuf};
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use std::time::Instant;
 use anyhow::{Context, Result};
 use clap::Parser;
 use mimalloc::MiMalloc;
 use serde::Serialize;
 
 use conf::{
-    cli::{Cli, Commands},
+    cli::{BackendAction, BootGuardAction, Cli, Commands, HymoAction, HymodAction, MntnsAction, ModuleAction, NamespaceAction},
     config::{Config, CONFIG_FILE_DEFAULT},
 };
+use mount::{magic, overlay, BackendKind, Backends, MountBackend, hymofs::{self, HymoFs}};
 use core::{
+    apps,
+    appscope,
+    arch,
+    batch,
+    battery,
+    bootguard::{self, BootAttempts, GuardAction},
+    bootreason::{self, BootReason},
+    bootstage,
+    budget,
+    certify,
+    compact,
+    crash,
+    daemon::{self, Daemon},
+    direct,
+    disable,
+    early_readers,
     executor,
+    explain,
+    guards,
+    health::{self, HealthStore},
+    hymoctl,
+    history,
+    install,
     inventory,
+    journal,
+    linker,
+    manager,
+    memory,
+    metrics::{self, ApplyMetrics},
+    migrate,
+    mntns,
+    namespace,
+    partitions,
     planner,
-    state::RuntimeState,
+    preview,
+    profiles,
+    protect,
+    report::ApplyReport,
+    remote,
+    rewrite,
+    roots,
+    rules::{self, RuleLedger},
+    selfcheck,
+    sepolicy,
+    services,
+    staging,
+    state::{self, RuntimeState, SelfTest},
+    stealth,
     storage,
+    symlinks,
     sync,
+    transaction::Transaction,
     modules,
+    uninstall,
+    wait,
+    watcher,
 };
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
 
+#[derive(Serialize)]
+struct ProfileJson {
+    name: &'static str,
+    description: &'static str,
+    enabled: bool,
+}
+
 #[derive(Serialize)]
 struct DiagnosticIssueJson {
     level: String,
@@ -57,8 +113,72 @@ fn check_zygisksu_enforce_status() -> bool {
         .unwrap_or(false)
 }
 
-fn run() -> Result<()> {
-    let cli = Cli::parse();
+fn command_name(cli: &Cli) -> &'static str {
+    match cli.command {
+        None => "mount",
+        Some(Commands::BootCompleted) => "boot-completed",
+        Some(Commands::Batch) => "batch",
+        Some(_) => "command",
+    }
+}
+
+/// Warnings for the plan's HymoFS rules whose sources the consumer domains
+/// would be denied, as far as the loaded policy tells.
+fn selinux_warnings(plan: &planner::MountPlan, consumers: &[String]) -> Vec<String> {
+    match sepolicy::verify(plan, consumers) {
+        Ok(denials) => {
+            if !denials.is_empty() {
+                log::warn!("!! SELinux: {} rule sources would be unreadable", denials.len());
+            }
+            denials.iter().map(sepolicy::Denial::describe).collect()
+        }
+        Err(e) => {
+            log::warn!("!! SELinux check skipped: {:#}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Hands the plan's HymoFS operations to the configured fallback backend,
+/// OverlayFS only when the kernel has it.
+fn demote_hymo(plan: &mut planner::MountPlan, config: &Config) -> (Vec<String>, &'static str) {
+    match config.hymofs_fallback {
+        planner::HymoFallback::Overlay if overlay::Backend::new(config).is_usable() => (plan.demote_hymo_to_overlay(), "OverlayFS"),
+        _ => (plan.demote_hymo_to_magic(), "Magic Mount"),
+    }
+}
+
+fn export_metrics(config: &Config, metrics: &ApplyMetrics) {
+    if let Some(path) = &config.metrics_file {
+        if let Err(e) = metrics::export(path, metrics, metrics::live_rules(&config.hymofs_device)) {
+            log::warn!("Failed to export metrics to {}: {:#}", path.display(), e);
+        }
+    }
+}
+
+fn write_report(cli: &Cli, report: &ApplyReport) {
+    if let Some(path) = &cli.report_file {
+        if let Err(e) = report.write_to(path) {
+            eprintln!("Error: {:#}", e);
+        }
+    }
+}
+
+fn run(cli: &Cli) -> Result<()> {
+    let mut config = load_config(cli)?;
+    config.merge_with_cli(
+        cli.moduledir.clone(), 
+        cli.tempdir.clone(), 
+        cli.mountsource.clone(), 
+        cli.verbose, 
+        cli.partitions.clone(),
+        cli.dry_run,
+    );
+    config.deterministic |= cli.deterministic || config.dry_run;
+    arch::init(!config.disable_arch_filter);
+    protect::init(&config.protected_paths);
+    symlinks::init(config.symlink_policy);
+    memory::init(config.low_memory, config.memory_ceiling_mb);
 
     if let Some(command) = &cli.command {
         match command {
@@ -67,10 +187,18 @@ fn run() -> Result<()> {
                 return Ok(()); 
             },
             Commands::ShowConfig => { 
-                let config = load_config(&cli)?;
                 println!("{}", serde_json::to_string(&config)?); 
                 return Ok(()); 
             },
+            Commands::CheckConfig => {
+                let path = cli.config.as_deref().unwrap_or(Path::new(CONFIG_FILE_DEFAULT));
+                let (_, unknown) = Config::check_file(path)?;
+                for key in &unknown {
+                    println!("unknown key ignored: {}", key);
+                }
+                println!("{} is valid.", path.display());
+                return Ok(());
+            },
             Commands::SaveConfig { payload } => {
                 let json_bytes = (0..payload.len())
                     .step_by(2)
@@ -79,6 +207,7 @@ fn run() -> Result<()> {
                     .context("Failed to decode hex payload")?;
                 let config: Config = serde_json::from_slice(&json_bytes)
                     .context("Failed to parse config JSON")?;
+                config.validate().context("Refusing to save an invalid config")?;
                 config.save_to_file(CONFIG_FILE_DEFAULT)?;
                 println!("Configuration saved successfully.");
                 return Ok(());
@@ -91,7 +220,7 @@ fn run() -> Result<()> {
                     .context("Failed to decode hex payload")?;
                 let _: inventory::ModuleRules = serde_json::from_slice(&json_bytes)
                     .context("Invalid rules JSON")?;
-                let rules_dir = std::path::Path::new("/data/adb/meta-hybrid/rules");
+                let rules_dir = Path::new(defs::RULES_DIR);
                 std::fs::create_dir_all(rules_dir)?;
                 let file_path = rules_dir.join(format!("{}.json", module));
                 std::fs::write(file_path, json_bytes)?;
@@ -99,27 +228,28 @@ fn run() -> Result<()> {
                 return Ok(());
             },
             Commands::Storage => { 
-                storage::print_status()?; 
+                storage::print_status(&config)?; 
                 return Ok(()); 
             },
             Commands::Modules => { 
-                let config = load_config(&cli)?;
                 modules::print_list(&config)?; 
                 return Ok(()); 
             },
             Commands::Conflicts => {
-                let config = load_config(&cli)?;
-                let module_list = inventory::scan(&config.moduledir, &config)?;
+                let module_list = inventory::scan_roots(&config)?;
                 let plan = planner::generate(&config, &module_list, &config.moduledir)?;
                 let report = plan.analyze_conflicts();
                 println!("{}", serde_json::to_string(&report.details)?);
                 return Ok(());
             },
             Commands::Diagnostics => {
-                let config = load_config(&cli)?;
-                let module_list = inventory::scan(&config.moduledir, &config)?;
-                let plan = planner::generate(&config, &module_list, &config.moduledir)?;
-                let issues = executor::diagnose_plan(&plan);
+                let module_list = inventory::scan_roots(&config)?;
+                let mut plan = planner::generate(&config, &module_list, &config.moduledir)?;
+                linker::extend_plan(&mut plan, &module_list, None)?;
+                let mut issues = executor::diagnose_plan(&plan);
+                if let Ok(state) = RuntimeState::load() {
+                    issues.extend(executor::diagnose_state(&state));
+                }
                 let json_issues: Vec<DiagnosticIssueJson> = issues.into_iter().map(|i| DiagnosticIssueJson {
                     level: match i.level {
                         executor::DiagnosticLevel::Info => "Info".to_string(),
@@ -131,20 +261,921 @@ fn run() -> Result<()> {
                 }).collect();
                 println!("{}", serde_json::to_string(&json_issues)?);
                 return Ok(());
+            },
+            Commands::UninstallModule { modules, skip_script, keep_files, yes } => {
+                let state = RuntimeState::load().unwrap_or_default();
+                let storage_root = state.storage_root();
+                let hymofs = HymoFs::open_at(&config.hymofs_device).ok();
+                let options = uninstall::UninstallOptions {
+                    run_scripts: !skip_script,
+                    keep_files: *keep_files,
+                };
+                let live = hymofs.as_ref()
+                    .filter(|h| h.is_available())
+                    .and_then(|h| h.list_ops().ok());
+                let interactive = std::io::IsTerminal::is_terminal(&std::io::stdin());
+                if config.dry_run || (interactive && !yes) {
+                    for module in modules {
+                        let plan = uninstall::plan_teardown(&config, module, &storage_root, live.as_deref(), &state, &options)?;
+                        print!("{}", plan.render());
+                    }
+                    if config.dry_run {
+                        return Ok(());
+                    }
+                    print!("Proceed? [y/N] ");
+                    std::io::Write::flush(&mut std::io::stdout())?;
+                    let mut answer = String::new();
+                    std::io::stdin().read_line(&mut answer)?;
+                    if !matches!(answer.trim(), "y" | "Y" | "yes") {
+                        anyhow::bail!("uninstall aborted");
+                    }
+                }
+                let _log_guard = utils::init_logging(config.verbose, Path::new(defs::DAEMON_LOG_FILE))?;
+                for module in modules {
+                    uninstall::uninstall(&config, module, &storage_root, hymofs.as_ref(), &options)?;
+                }
+                if let Err(e) = history::record(&config) {
+                    log::warn!("Failed to record module history: {:#}", e);
+                }
+                return Ok(());
+            },
+            Commands::Adopt { release } => {
+                let mut ledger = RuleLedger::load();
+                let released = ledger.release(release);
+                let mut adopted = 0;
+                if release.is_empty() {
+                    let hymofs = HymoFs::open_at(&config.hymofs_device)?.with_labels(&config.selinux_labels);
+                    if !hymofs.is_available() {
+                        anyhow::bail!("HymoFS is not available at {}", config.hymofs_device.display());
+                    }
+                    let state = RuntimeState::load().unwrap_or_default();
+                    let roots = rules::owned_roots(&config, Some(&state.mount_point));
+                    adopted = ledger.adopt(&hymofs.list_ops()?, &roots);
+                }
+                ledger.save()?;
+                eprintln!("Adopted {} external rules, released {}.", adopted, released);
+                println!("{}", serde_json::to_string(&ledger.external)?);
+                return Ok(());
+            },
+            Commands::Migrate { state_version } => {
+                migrate::migrate_state_file(Path::new(defs::STATE_FILE), *state_version)?;
+                println!("State format v{}, config format v{}.",
+                    state_version.unwrap_or(migrate::Document::State.current()),
+                    migrate::Document::Config.current());
+                return Ok(());
+            },
+            Commands::History { module, json } => {
+                let events = history::load();
+                if *json {
+                    let selected: Vec<_> = events.iter()
+                        .filter(|e| module.as_ref().is_none_or(|id| &e.module_id == id))
+                        .collect();
+                    println!("{}", serde_json::to_string(&selected)?);
+                } else {
+                    print!("{}", history::render(&events, module.as_deref()));
+                }
+                return Ok(());
+            },
+            Commands::Preview { module, command } => {
+                let code = preview::run(&config, module, command)?;
+                if code != 0 {
+                    std::process::exit(code);
+                }
+                return Ok(());
+            },
+            Commands::SelinuxCheck { json } => {
+                let state = RuntimeState::load().unwrap_or_default();
+                let storage_root = state.storage_root();
+                let module_list = inventory::scan_roots(&config)?;
+                let plan = planner::generate(&config, &module_list, &storage_root)?;
+                let denials = sepolicy::verify(&plan, &config.selinux_consumers)?;
+                if *json {
+                    println!("{}", serde_json::to_string(&denials)?);
+                } else if denials.is_empty() {
+                    println!("Every HymoFS rule source is readable for {}.", config.selinux_consumers.join(", "));
+                } else {
+                    denials.iter().for_each(|denial| println!("{}", denial.describe()));
+                }
+                return Ok(());
+            },
+            Commands::Compact => {
+                let report = compact::run(&config, config.dry_run)?;
+                if config.dry_run {
+                    eprintln!("{} of {} rules are redundant.", report.findings.len(), report.scanned);
+                } else {
+                    eprintln!("Reclaimed {} of {} rules.", report.reclaimed, report.scanned);
+                }
+                println!("{}", serde_json::to_string(&report)?);
+                return Ok(());
+            },
+            Commands::Metrics { output } => {
+                let metrics = ApplyMetrics::load().unwrap_or_default();
+                let live = metrics::live_rules(&config.hymofs_device);
+                match output {
+                    Some(path) => metrics::export(path, &metrics, live)?,
+                    None => print!("{}", metrics::render(&metrics, live)),
+                }
+                return Ok(());
+            },
+            Commands::Profiles => {
+                let enabled = profiles::load_enabled();
+                let list: Vec<ProfileJson> = profiles::recipes().iter().map(|recipe| ProfileJson {
+                    name: recipe.name,
+                    description: recipe.description,
+                    enabled: enabled.iter().any(|n| n == recipe.name),
+                }).collect();
+                println!("{}", serde_json::to_string(&list)?);
+                return Ok(());
+            },
+            Commands::Profile { name, enable, disable } => {
+                if *enable || *disable {
+                    profiles::set_enabled(name, *enable)?;
+                    println!("Profile {} {} from the next boot.", name, if *enable { "applies" } else { "is removed" });
+                } else {
+                    let compiled = profiles::compile(std::slice::from_ref(name), Path::new("/"))?;
+                    println!("{}", serde_json::to_string_pretty(&compiled)?);
+                }
+                return Ok(());
+            },
+            Commands::ApproveDirect { module, revoke } => {
+                direct::set_approval(module, !revoke)?;
+                if *revoke {
+                    println!("{} will use rules again from the next boot.", module);
+                } else {
+                    println!("{} may modify writable partitions directly from the next boot.", module);
+                }
+                return Ok(());
+            },
+            Commands::Wait { rule, module, state, timeout } => {
+                let timeout = std::time::Duration::from_secs(*timeout);
+                match (rule, module) {
+                    (Some(target), _) => wait::wait_for_rule(&config.hymofs_device, target, timeout)?,
+                    (None, Some(id)) => wait::wait_for_module(id, state.parse()?, timeout)?,
+                    (None, None) => anyhow::bail!("wait needs --rule or --module"),
+                }
+                return Ok(());
+            },
+            Commands::Explain { path, json } => {
+                let state = RuntimeState::load().unwrap_or_default();
+                let storage_root = state.storage_root();
+                let module_list = inventory::scan_roots(&config)?;
+                let plan = planner::generate(&config, &module_list, &storage_root)?;
+                let live = HymoFs::open_at(&config.hymofs_device).ok()
+                    .filter(HymoFs::is_available)
+                    .and_then(|handle| handle.list_ops().ok());
+                let explanation = explain::explain(&explain::absolute(path), &plan, &state, live.as_deref(), &RuleLedger::load());
+                if *json {
+                    println!("{}", serde_json::to_string(&explanation)?);
+                } else {
+                    print!("{}", explanation.render());
+                }
+                return Ok(());
+            },
+            Commands::Certify { rules, json } => {
+                let hymofs = HymoFs::open_at(&config.hymofs_device)?;
+                let result = certify::run(&hymofs, *rules);
+                if *json {
+                    println!("{}", serde_json::to_string(&result)?);
+                } else {
+                    print!("{}", result.render());
+                }
+                if !result.passed() {
+                    anyhow::bail!("{} of {} HymoFS checks failed", result.failed(), result.checks.len());
+                }
+                return Ok(());
+            },
+            Commands::BootGuard { action } => {
+                let path = Path::new(defs::BOOT_ATTEMPTS_FILE);
+                match action {
+                    BootGuardAction::Status { json } => {
+                        let attempts = BootAttempts::load(path);
+                        if *json {
+                            println!("{}", serde_json::json!({ "guard": config.boot_guard, "attempts": attempts }));
+                        } else {
+                            let state = if attempts.tripped { "tripped" } else if config.boot_guard.enabled { "armed" } else { "disabled" };
+                            println!("{}: {} of {} failed boots in a row", state, attempts.failed, config.boot_guard.max_failed_boots);
+                        }
+                    }
+                    BootGuardAction::Reset => {
+                        bootguard::reset(path)?;
+                        println!("Boot guard re-armed; the next boot injects modules again.");
+                    }
+                }
+                return Ok(());
+            },
+            Commands::Mntns { action } => {
+                match action {
+                    MntnsAction::List { json } => {
+                        let global = mntns::namespace_of(1)?;
+                        let foreign = mntns::foreign_namespaces()?;
+                        if *json {
+                            println!("{}", serde_json::json!({ "global": global, "namespaces": foreign }));
+                        } else {
+                            println!("{:<12} global (pid 1)", global);
+                            for (ns, pid) in &foreign {
+                                println!("{:<12} pid {}", ns, pid);
+                            }
+                        }
+                    }
+                    MntnsAction::Verify { pid, module, json } => {
+                        let ops = match module {
+                            Some(id) => {
+                                let found = manager::find(&config, id)?.with_context(|| format!("Module {} is not installed", id))?;
+                                found.partitions.iter()
+                                    .flat_map(|(partition, content)| hymofs::collect_directory_ops(&partitions::target(&config, partition), content))
+                                    .collect()
+                            }
+                            None => HymoFs::open_at(&config.hymofs_device)?.list_ops()?,
+                        };
+                        let seen = mntns::verify(*pid, &ops)?;
+                        let visible = seen.iter().filter(|v| v.visible).count();
+                        if *json {
+                            println!("{}", serde_json::json!({ "pid": pid, "visible": visible, "rules": seen }));
+                        } else {
+                            for missing in seen.iter().filter(|v| !v.visible) {
+                                println!("not visible: {}", missing.path);
+                            }
+                            println!("{} of {} rules visible in the mount namespace of {}.", visible, seen.len(), pid);
+                        }
+                        if visible < seen.len() {
+                            std::process::exit(1);
+                        }
+                    }
+                    MntnsAction::Inject { pid, source, target, backend } => {
+                        let name = mntns::enter(*pid, || {
+                            let backends = Backends::new(&config);
+                            let backend = mount::pick_backend(&backends, &config, *backend, target)?;
+                            backend.inject(source, target)?;
+                            Ok(backend.name())
+                        })?;
+                        println!("Injected {} over {} through {} in the mount namespace of {}.", source.display(), target.display(), name, pid);
+                    }
+                    MntnsAction::Isolate => {
+                        let detached = mntns::isolate(&config.mountsource)?;
+                        println!("Detached {} mounts from other mount namespaces.", detached);
+                    }
+                }
+                return Ok(());
+            },
+            Commands::Namespace { owner, action } => {
+                namespace::validate_owner(owner)?;
+                let hymofs = HymoFs::open_at(&config.hymofs_device)?;
+                let mut ledger = RuleLedger::load();
+                let mut journal = match action {
+                    NamespaceAction::Add { .. } | NamespaceAction::Hide { .. } => journal::RuleJournal::begin(Path::new(defs::RULE_JOURNAL_DIR), Some(owner))
+                        .map_err(|e| log::warn!("Failed to open rule journal: {}", e))
+                        .ok(),
+                    _ => None,
+                };
+                match action {
+                    NamespaceAction::Add { src, target, package } => {
+                        package.as_deref().map(apps::validate_package).transpose()?;
+                        let file_type = std::fs::symlink_metadata(target)
+                            .with_context(|| format!("Failed to stat {}", target.display()))?
+                            .file_type()
+                            .into();
+                        let op = hymofs::RuleOp::Add { src: src.clone(), target: target.to_string_lossy().into_owned(), file_type };
+                        namespace::add(&hymofs, &mut ledger, owner, &[op], &mut journal)?;
+                        if let Some(package) = package {
+                            apps::bind(&mut ledger, owner, std::slice::from_ref(src), package)?;
+                        }
+                    }
+                    NamespaceAction::Hide { path, package } => {
+                        package.as_deref().map(apps::validate_package).transpose()?;
+                        namespace::add(&hymofs, &mut ledger, owner, &[hymofs::RuleOp::Hide { path: path.clone() }], &mut journal)?;
+                        if let Some(package) = package {
+                            apps::bind(&mut ledger, owner, std::slice::from_ref(path), package)?;
+                        }
+                    }
+                    NamespaceAction::Remove { paths } => {
+                        let removed = namespace::remove(&hymofs, &mut ledger, owner, paths);
+                        println!("Removed {} rules from namespace {}.", removed, owner);
+                    }
+                    NamespaceAction::List { json } => {
+                        let live = hymofs.list_ops().ok();
+                        let rules = namespace::list(&ledger, owner, live.as_deref());
+                        if *json {
+                            println!("{}", serde_json::to_string(&rules)?);
+                        } else {
+                            rules.iter().for_each(|op| println!("{}", explain::describe_rule(op)));
+                        }
+                        return Ok(());
+                    }
+                    NamespaceAction::Clear => {
+                        let cleared = namespace::clear(&hymofs, &mut ledger, owner);
+                        println!("Cleared {} rules of namespace {}.", cleared, owner);
+                    }
+                }
+                ledger.save()?;
+                if let Some(Err(e)) = journal.map(journal::RuleJournal::finish) {
+                    log::warn!("Failed to finish rule journal: {}", e);
+                }
+                return Ok(());
+            },
+            Commands::Hymo { json, action } => {
+                let available = || -> Result<HymoFs> {
+                    let hymofs = HymoFs::open_at(&config.hymofs_device)?;
+                    if !hymofs.is_available() {
+                        anyhow::bail!("HymoFS is not available at {}", config.hymofs_device.display());
+                    }
+                    Ok(hymofs)
+                };
+                let (done, failed) = match action {
+                    HymoAction::Status => {
+                        let status = hymoctl::status(&config.hymofs_device);
+                        if *json {
+                            println!("{}", serde_json::to_string(&status)?);
+                        } else {
+                            println!("device:   {}", status.device.display());
+                            match &status.detail {
+                                Some(detail) => println!("state:    {} ({})", status.state, detail),
+                                None => println!("state:    {}", status.state),
+                            }
+                            println!("protocol: {} (expected {})", status.protocol.map_or_else(|| "-".to_string(), |v| v.to_string()), status.expected_protocol);
+                            println!("abi:      {}", status.abi.as_deref().unwrap_or("-"));
+                            println!("caps:     {}", status.caps.map_or_else(|| "-".to_string(), |caps| caps.describe()));
+                            println!("rules:    {}", status.rules.map_or_else(|| "-".to_string(), |n| n.to_string()));
+                        }
+                        return Ok(());
+                    }
+                    HymoAction::Version => {
+                        let protocol = HymoFs::open_at(&config.hymofs_device).ok().and_then(|h| h.get_version().ok());
+                        if *json {
+                            println!("{}", serde_json::json!({
+                                "version": env!("CARGO_PKG_VERSION"),
+                                "protocol": protocol,
+                                "expected_protocol": defs::HYMO_PROTOCOL_VERSION,
+                            }));
+                        } else {
+                            println!("meta-hybrid {}", env!("CARGO_PKG_VERSION"));
+                            println!("HymoFS protocol {} (expected {})", protocol.map_or_else(|| "-".to_string(), |v| v.to_string()), defs::HYMO_PROTOCOL_VERSION);
+                        }
+                        return Ok(());
+                    }
+                    HymoAction::Add { src, target, priority, package } => {
+                        let mut priorities = hymofs::RulePriorities::default();
+                        priorities.insert(target.clone(), *priority);
+                        let hymofs = available()?.with_priorities(priorities);
+                        let file_type = std::fs::symlink_metadata(target)
+                            .with_context(|| format!("Failed to stat {}", target.display()))?
+                            .file_type()
+                            .into();
+                        let op = hymofs::RuleOp::Add { src: src.clone(), target: target.to_string_lossy().into_owned(), file_type };
+                        if let Some(package) = package {
+                            appscope::add_all(&hymofs, package, std::slice::from_ref(&op))?
+                        } else {
+                            let failed = hymofs.apply_journaled(std::slice::from_ref(&op));
+                            (1 - failed, failed)
+                        }
+                    }
+                    HymoAction::Del { paths: _, module: Some(module_id) } => {
+                        uninstall::validate_id(module_id)?;
+                        let hymofs = available()?;
+                        let mut ledger = RuleLedger::load();
+                        let counts = ledger.remove_module(&hymofs, module_id);
+                        ledger.save()?;
+                        counts
+                    }
+                    HymoAction::Del { paths, module: None } => {
+                        let hymofs = available()?;
+                        let mut scopes = appscope::AppScopes::load();
+                        let failed = paths.iter()
+                            .filter(|path| {
+                                let scoped = scopes.remove(path) > 0;
+                                match hymofs.delete_rule(path) {
+                                    Ok(()) => false,
+                                    Err(_) if scoped => false,
+                                    Err(e) => {
+                                        log::warn!("!! {:#}", e);
+                                        true
+                                    }
+                                }
+                            })
+                            .count();
+                        scopes.save()?;
+                        (paths.len() - failed, failed)
+                    }
+                    HymoAction::Hide { paths, package } => {
+                        let hymofs = available()?;
+                        let ops: Vec<hymofs::RuleOp> = paths.iter().map(|path| hymofs::RuleOp::Hide { path: path.clone() }).collect();
+                        if let Some(package) = package {
+                            appscope::add_all(&hymofs, package, &ops)?
+                        } else {
+                            let failed = hymofs.apply_journaled(&ops);
+                            (ops.len() - failed, failed)
+                        }
+                    }
+                    HymoAction::InjectDir { source, target, dry_run, package } => {
+                        if !source.is_dir() {
+                            anyhow::bail!("{} is not a directory", source.display());
+                        }
+                        if *dry_run {
+                            let live = available().ok().and_then(|h| h.list_ops().ok()).unwrap_or_default();
+                            let plan = hymofs::inject_directory_plan(target, source, &live, &RuleLedger::load());
+                            if *json {
+                                println!("{}", serde_json::to_string(&plan)?);
+                            } else {
+                                print!("{}", hymoctl::table(&["OP", "PATH", "SOURCE"], &hymoctl::plan_rows(&plan)));
+                                for conflict in &plan.conflicts {
+                                    println!("conflict: {} replaces {}{}", explain::describe_rule(&conflict.planned), explain::describe_rule(&conflict.live),
+                                        conflict.owner.as_ref().map_or_else(String::new, |owner| format!(" (owned by {})", owner)));
+                                }
+                                for path in &plan.refused {
+                                    println!("protected: {} is left alone", path);
+                                }
+                                println!("{} rules would be applied, {} conflicting.", plan.rules.len(), plan.conflicts.len());
+                            }
+                            return Ok(());
+                        }
+                        let hymofs = available()?;
+                        let ops = hymofs::collect_directory_ops(target, source);
+                        if let Some(package) = package {
+                            appscope::add_all(&hymofs, package, &ops)?
+                        } else {
+                            let mut transaction = Transaction::begin(&hymofs);
+                            transaction.apply_all(&ops)?;
+                            (transaction.commit(), 0)
+                        }
+                    }
+                    HymoAction::Verify { source, target } => {
+                        let unresolved = available()?.verify(target, source)?;
+                        if *json {
+                            println!("{}", serde_json::to_string(&unresolved)?);
+                        } else if !unresolved.is_empty() {
+                            print!("{}", hymoctl::table(&["MISS", "PATH", "MOUNT"], &hymoctl::unresolved_rows(&unresolved)));
+                        }
+                        if !unresolved.is_empty() {
+                            anyhow::bail!("{} rules of {} do not resolve", unresolved.len(), source.display());
+                        }
+                        if !*json {
+                            println!("Every rule of {} resolves.", source.display());
+                        }
+                        return Ok(());
+                    }
+                    HymoAction::List => {
+                        let hymofs = available()?;
+                        let rules = hymofs.list_rules()?;
+                        if *json {
+                            println!("{}", serde_json::to_string(&rules)?);
+                        } else {
+                            print!("{}", hymoctl::table(&["KIND", "SRC", "TARGET", "TYPE", "PRIO", "FLAGS"], &hymoctl::rule_rows(&rules)));
+                        }
+                        return Ok(());
+                    }
+                    HymoAction::Clear => (MountBackend::clear(&available()?)?, 0),
+                };
+                if *json {
+                    println!("{}", serde_json::json!({ "applied": done, "failed": failed }));
+                } else {
+                    println!("{} rules applied, {} failed.", done, failed);
+                }
+                if failed > 0 {
+                    anyhow::bail!("HymoFS rejected {} rules", failed);
+                }
+                return Ok(());
+            },
+            Commands::Backend { action } => {
+                let backends = Backends::new(&config);
+                match action {
+                    BackendAction::Status { json } => {
+                        let health = HealthStore::load();
+                        let missing = backends.hymofs.is_none().then(|| mount::BackendStatus {
+                            name: "hymofs",
+                            usable: false,
+                            detail: format!("{} cannot be opened", config.hymofs_device.display()),
+                        });
+                        let statuses: Vec<mount::BackendStatus> = missing.into_iter()
+                            .chain(backends.all().iter().map(|b| b.status()))
+                            .collect();
+                        let selected: std::collections::BTreeMap<String, Option<&str>> = partitions::enabled(&config).into_iter()
+                            .map(|p| p.to_string())
+                            .map(|p| {
+                                let name = mount::select_backend(&backends, &config, &health, &p).map(|b| b.name());
+                                (p, name)
+                            })
+                            .collect();
+                        if *json {
+                            println!("{}", serde_json::json!({ "backends": statuses, "partitions": selected, "health": health.backends, "pinned": config.pinned_backend }));
+                        } else {
+                            for status in &statuses {
+                                let line = format!("{:<8} {:<9} {}", status.name, if status.usable { "usable" } else { "unusable" }, status.detail);
+                                println!("{}", line.trim_end());
+                            }
+                            for (kind, record) in &health.backends {
+                                println!("{:?}: {:.0}% of {} mounts went through over {} boots, {:.1} ms each",
+                                    kind, record.reliability() * 100.0, record.attempts, record.boots, record.latency_ms);
+                            }
+                            if let Some(kind) = config.pinned_backend {
+                                println!("Pinned: {:?}", kind);
+                            }
+                            for (partition, name) in &selected {
+                                println!("/{} -> {}", partition, name.unwrap_or("none"));
+                            }
+                        }
+                    }
+                    BackendAction::Inject { source, target, backend } => {
+                        let backend = mount::pick_backend(&backends, &config, *backend, target)?;
+                        backend.inject(source, target)?;
+                        println!("Injected {} over {} through {}.", source.display(), target.display(), backend.name());
+                    }
+                    BackendAction::Remove { target, backend } => {
+                        let backend = mount::pick_backend(&backends, &config, *backend, target)?;
+                        let removed = backend.remove(target)?;
+                        println!("Removed {} {} entries at {}.", removed, backend.name(), target.display());
+                    }
+                    BackendAction::List { backend, json } => {
+                        let chosen: Vec<&dyn MountBackend> = match backend {
+                            Some(kind) => backends.get(*kind).into_iter().collect(),
+                            None => backends.all().into_iter().filter(|b| b.is_usable()).collect(),
+                        };
+                        let mut listed = std::collections::BTreeMap::new();
+                        for b in chosen {
+                            listed.insert(b.name(), b.list()?);
+                        }
+                        if *json {
+                            println!("{}", serde_json::to_string(&listed)?);
+                        } else {
+                            for (name, paths) in &listed {
+                                paths.iter().for_each(|p| println!("{}\t{}", name, p.display()));
+                            }
+                        }
+                    }
+                    BackendAction::Clear { backend } => {
+                        let backend = backends.get(*backend).with_context(|| format!("{:?} is not available", backend))?;
+                        let cleared = backend.clear()?;
+                        println!("Cleared {} {} entries.", cleared, backend.name());
+                    }
+                    BackendAction::Rules { json } => {
+                        let hymofs = backends.hymofs.as_ref().filter(|h| h.is_available())
+                            .with_context(|| format!("HymoFS is not available at {}", config.hymofs_device.display()))?;
+                        let rules = hymofs.list_rules()?;
+                        if *json {
+                            println!("{}", serde_json::to_string(&rules)?);
+                        } else {
+                            for rule in &rules {
+                                let line = format!("{:?}\t{}\t{}\t{}", rule.kind, rule.src, rule.target.as_deref().unwrap_or("-"), rule.flags.join(","));
+                                println!("{}", line.trim_end());
+                            }
+                        }
+                    }
+                }
+                return Ok(());
+            },
+            Commands::Hymod { action } => {
+                let socket = Path::new(defs::DAEMON_SOCKET);
+                let request = match action {
+                    None => {
+                        let _log_guard = utils::init_logging(config.verbose, Path::new(defs::DAEMON_LOG_FILE))?;
+                        let remote_control = config.remote_control.clone();
+                        let (module_watch, watched) = (config.module_watch.clone(), roots::active(&config));
+                        let (namespace_scope, mount_source) = (config.namespace_scope.clone(), config.mountsource.clone());
+                        let stealth = config.stealth.clone();
+                        let scope_handle = HymoFs::open_at(&config.hymofs_device).ok();
+                        let mut hymod = Daemon::new(config, cli.config.clone())?;
+                        let listener = daemon::bind(socket)?;
+                        log::info!(">> hymod listening on {}", socket.display());
+                        if remote_control.enabled {
+                            match remote::check(&remote_control) {
+                                Ok(()) => {
+                                    std::thread::spawn(move || remote::supervise(&remote_control, socket));
+                                }
+                                Err(e) => log::warn!("!! Remote control disabled: {:#}", e),
+                            }
+                        }
+                        if let Some(handle) = scope_handle {
+                            let interval = std::time::Duration::from_millis(namespace_scope.scan_interval_ms.max(100));
+                            std::thread::spawn(move || appscope::keep_injected(&handle, interval));
+                        }
+                        if stealth.enabled {
+                            let mount_source = mount_source.clone();
+                            std::thread::spawn(move || stealth::keep_concealed(&stealth, &mount_source));
+                        }
+                        if namespace_scope.global_only {
+                            std::thread::spawn(move || mntns::keep_isolated(&namespace_scope, &mount_source));
+                        }
+                        if module_watch.enabled {
+                            std::thread::spawn(move || {
+                                let reload = || match daemon::request(socket, &daemon::Request::ReloadModules) {
+                                    Ok(report) => log::info!(">> Modules changed, reloaded: {}", report),
+                                    Err(e) => log::warn!("!! Modules changed, reload failed: {:#}", e),
+                                };
+                                if let Err(e) = watcher::watch(&module_watch, &watched, reload) {
+                                    log::warn!("!! Module watcher stopped: {:#}", e);
+                                }
+                            });
+                        }
+                        daemon::serve(listener, move |request| hymod.handle(request));
+                    }
+                    Some(HymodAction::AddModule { id }) => daemon::Request::AddModule { id: id.clone() },
+                    Some(HymodAction::RemoveModule { id }) => daemon::Request::RemoveModule { id: id.clone() },
+                    Some(HymodAction::Reload) => daemon::Request::Reload,
+                    Some(HymodAction::ReloadModules) => daemon::Request::ReloadModules,
+                    Some(HymodAction::Status) => daemon::Request::Status,
+                    Some(HymodAction::Install { source }) => daemon::Request::Install { source: std::path::absolute(source)? },
+                    Some(HymodAction::CancelInstall { id }) => daemon::Request::CancelInstall { id: id.clone() },
+                    Some(HymodAction::Installs) => daemon::Request::Installs,
+                };
+                println!("{}", serde_json::to_string_pretty(&daemon::request(socket, &request)?)?);
+                return Ok(());
+            },
+            Commands::Module { action } => {
+                match action {
+                    ModuleAction::Scan { json } => {
+                        let found = manager::discover(&config)?;
+                        if *json {
+                            println!("{}", serde_json::to_string(&found)?);
+                        } else {
+                            for module in &found {
+                                let state = match module.flags {
+                                    f if f.is_enabled() => "enabled",
+                                    f if f.remove => "remove",
+                                    f if f.disable => "disabled",
+                                    _ => "skip_mount",
+                                };
+                                let partitions: Vec<&str> = module.partitions.keys().map(String::as_str).collect();
+                                println!("{}\t{}\t{}\t{}", module.id, module.prop.version, state, partitions.join(","));
+                            }
+                        }
+                    }
+                    ModuleAction::Info { id, key, json } => {
+                        let module = manager::find(&config, id)?.with_context(|| format!("Module {} is not installed", id))?;
+                        match key {
+                            Some(key) => {
+                                let value = module.prop.get(key).with_context(|| format!("{} has no {} in module.prop", id, key))?;
+                                println!("{}", value);
+                            }
+                            None if *json => println!("{}", serde_json::to_string(&module)?),
+                            None => {
+                                let prop = &module.prop;
+                                println!("id:          {}", module.id);
+                                println!("name:        {}", prop.name);
+                                println!("version:     {} ({})", prop.version, prop.version_code.map_or_else(|| "-".to_string(), |c| c.to_string()));
+                                println!("author:      {}", prop.author);
+                                println!("description: {}", prop.description);
+                                for (key, value) in &prop.extra {
+                                    println!("{}: {}", key, value);
+                                }
+                                println!("path:        {}", module.path.display());
+                                println!("enabled:     {}", module.flags.is_enabled());
+                                println!("partitions:  {}", module.partitions.keys().cloned().collect::<Vec<_>>().join(", "));
+                            }
+                        }
+                    }
+                    ModuleAction::MountAll { backend, json } => {
+                        let mut backends = Backends::new(&config);
+                        let found = manager::discover(&config)?;
+                        let order = manager::resolve(&found, config.conflict_policy, &config.module_priority)?;
+                        backends.hymofs = backends.hymofs.map(|h| h.with_priorities(order.priorities(None)));
+                        for conflict in &order.conflicts {
+                            log::warn!("!! /{}/{} is shipped by {}; {} wins", conflict.partition, conflict.path, conflict.modules.join(", "), conflict.modules[0]);
+                        }
+                        let outcomes = manager::mount_all(&config, &order, |_, content, target| {
+                            let chosen = mount::pick_backend(&backends, &config, *backend, target)?;
+                            chosen.inject(content, target)?;
+                            Ok(chosen.name())
+                        });
+                        if *json {
+                            println!("{}", serde_json::to_string(&outcomes)?);
+                        } else {
+                            for outcome in &outcomes {
+                                match (&outcome.backend, &outcome.error) {
+                                    (Some(backend), _) => println!("{} {} -> {}", outcome.module, outcome.partition, backend),
+                                    (None, error) => println!("{} {} failed: {}", outcome.module, outcome.partition, error.as_deref().unwrap_or_default()),
+                                }
+                            }
+                        }
+                        if outcomes.iter().any(|o| o.error.is_some()) {
+                            anyhow::bail!("{} of {} partitions failed to mount", outcomes.iter().filter(|o| o.error.is_some()).count(), outcomes.len());
+                        }
+                    }
+                    ModuleAction::Install { source } => {
+                        let mut shown = None;
+                        let path = install::install(&config, source, &AtomicBool::new(false), |progress| {
+                            let percent = (progress.bytes_done * 100).checked_div(progress.bytes_total).unwrap_or(100);
+                            if shown != Some(percent) {
+                                shown = Some(percent);
+                                eprint!("\r{}% ({}/{} files)", percent, progress.files_done, progress.files_total);
+                            }
+                        })?;
+                        eprintln!();
+                        println!("Installed to {}.", path.display());
+                    }
+                    ModuleAction::Reload { json } => {
+                        let hymofs = HymoFs::open_at(&config.hymofs_device)?.with_labels(&config.selinux_labels);
+                        if !hymofs.is_available() {
+                            anyhow::bail!("HymoFS is not available at {}", config.hymofs_device.display());
+                        }
+                        let report = manager::reload(&config, &hymofs)?;
+                        if *json {
+                            println!("{}", serde_json::to_string(&report)?);
+                        } else {
+                            println!("{} rules added, {} removed, {} unchanged.", report.added, report.removed, report.unchanged);
+                        }
+                    }
+                    ModuleAction::Conflicts { json } => {
+                        let found = manager::discover(&config)?;
+                        let conflicts = manager::find_conflicts(&manager::rank(&found, config.conflict_policy, &config.module_priority));
+                        if *json {
+                            println!("{}", serde_json::to_string(&conflicts)?);
+                        } else if conflicts.is_empty() {
+                            println!("No conflicts.");
+                        } else {
+                            for conflict in &conflicts {
+                                println!("/{}/{}: {}", conflict.partition, conflict.path, conflict.modules.join(" > "));
+                            }
+                        }
+                    }
+                }
+                return Ok(());
+            },
+            Commands::WatchPackages { interval } => {
+                let _log_guard = utils::init_logging(config.verbose, Path::new(defs::DAEMON_LOG_FILE))?;
+                let hymofs = HymoFs::open_at(&config.hymofs_device)?;
+                apps::watch(&hymofs, std::time::Duration::from_secs(*interval));
+            },
+            Commands::CrashScan => {
+                let module_list = inventory::scan_roots(&config)?;
+                let stats = crash::scan(&config, &module_list)?;
+                println!("{}", serde_json::to_string(&stats)?);
+                return Ok(());
+            },
+            Commands::Service => {
+                let _log_guard = utils::init_logging(config.verbose, Path::new(defs::DAEMON_LOG_FILE))?;
+                let mut state = RuntimeState::load().unwrap_or_default();
+                if !utils::same_boot(&state.session_id, utils::session_id()) {
+                    log::info!(">> Service: nothing was mounted this boot, no stage to run.");
+                    return Ok(());
+                }
+                if BootAttempts::load(Path::new(defs::BOOT_ATTEMPTS_FILE)).tripped {
+                    log::info!(">> Service: boot guard tripped, injecting no modules.");
+                    return Ok(());
+                }
+                if let Some(test) = state.hymofs_selftest.as_ref().filter(|t| !t.passed) {
+                    log::warn!("!! Service: HymoFS failed its self-check this boot ({}), injecting no deferred modules.", test.detail);
+                    return Ok(());
+                }
+                let hymofs = match HymoFs::open_at(&config.hymofs_device) {
+                    Ok(handle) if handle.is_available() => handle,
+                    _ => {
+                        log::info!(">> Service: HymoFS unavailable, no service stage to apply.");
+                        return Ok(());
+                    }
+                };
+                let storage_root = state.storage_root();
+                let mut module_list = inventory::scan_roots(&config)?;
+                module_list.retain(|m| !state.battery_deferred.contains(&m.id) && !state.boot_reason_skipped.contains(&m.id));
+                let plan = planner::generate(&config, &module_list, &storage_root)?;
+                let transition = executor::apply_service(&plan, &hymofs)?;
+                log::info!(">> Service: {} deferred modules injected.", transition.injected_module_ids.len());
+                services::run_post_apply(&module_list, &transition.injected_module_ids);
+                state.hymo_modules.extend(transition.injected_module_ids);
+                state.hymo_modules.sort();
+                state.hymo_modules.dedup();
+                state.save()?;
+                return Ok(());
+            },
+            Commands::BootCompleted => {
+                let _log_guard = utils::init_logging(config.verbose, Path::new(defs::DAEMON_LOG_FILE))?;
+                if let Err(e) = bootguard::complete(Path::new(defs::BOOT_ATTEMPTS_FILE)) {
+                    log::warn!("!! Failed to record boot completion: {:#}", e);
+                }
+                if config.namespace_scope.global_only {
+                    match mntns::isolate(&config.mountsource) {
+                        Ok(detached) => log::info!(">> Boot completed: detached {} mounts from app mount namespaces", detached),
+                        Err(e) => log::warn!("!! Mount namespace isolation failed: {:#}", e),
+                    }
+                }
+                if config.stealth.enabled {
+                    match stealth::conceal(&config.stealth, &config.mountsource, &mut HashSet::new()) {
+                        Ok(detached) => log::info!(">> Boot completed: concealed {} mounts from apps", detached),
+                        Err(e) => log::warn!("!! Stealth scan failed: {:#}", e),
+                    }
+                }
+                if let Ok(module_list) = inventory::scan_roots(&config) {
+                    if let Err(e) = crash::scan(&config, &module_list) {
+                        log::warn!("Crash correlation scan failed: {:#}", e);
+                    }
+                }
+                let hymofs = match HymoFs::open_at(&config.hymofs_device) {
+                    Ok(handle) if handle.is_available() => handle,
+                    _ => {
+                        log::info!(">> Boot completed: HymoFS unavailable, no boot windows to apply.");
+                        return Ok(());
+                    }
+                };
+                match appscope::restore(&hymofs, &appscope::AppScopes::load()) {
+                    0 => {}
+                    restored => log::info!(">> Boot completed: restored {} per-app rules", restored),
+                }
+                let mut ledger = RuleLedger::load();
+                match stealth::hide(&hymofs, &mut ledger, &config.stealth).and_then(|hidden| ledger.save().map(|()| hidden)) {
+                    Ok(0) => {}
+                    Ok(hidden) => log::info!(">> Boot completed: hid {} paths from every process", hidden),
+                    Err(e) => log::warn!("!! Stealth hide rules failed: {:#}", e),
+                }
+                let mut state = RuntimeState::load().unwrap_or_default();
+                if let Some(test) = state.hymofs_selftest.as_ref().filter(|t| !t.passed) {
+                    log::warn!("!! Boot completed: HymoFS failed its self-check this boot ({}), leaving boot windows alone.", test.detail);
+                    return Ok(());
+                }
+                let storage_root = state.storage_root();
+                let mut module_list = inventory::scan_roots(&config)?;
+                module_list.retain(|m| !state.battery_deferred.contains(&m.id) && !state.boot_reason_skipped.contains(&m.id));
+                let plan = planner::generate(&config, &module_list, &storage_root)?;
+                let transition = executor::apply_boot_completed(&plan, &hymofs, &state.hymo_modules)?;
+                log::info!(">> Boot completed: {} deferred modules injected, {} boot-only modules removed.",
+                    transition.injected_module_ids.len(), transition.removed_module_ids.len());
+                services::run_post_apply(&module_list, &transition.injected_module_ids);
+                state.hymo_modules.retain(|id| !transition.removed_module_ids.contains(id));
+                state.hymo_modules.extend(transition.injected_module_ids);
+                state.hymo_modules.sort();
+                state.hymo_modules.dedup();
+                state.save()?;
+                if config.boot_stages.verify {
+                    let unresolved = bootstage::verify(&hymofs, &plan, &state.hymo_modules);
+                    for (module_id, miss) in &unresolved {
+                        log::warn!("!! {}: {} {:?}{}", module_id, miss.op.path(), miss.miss,
+                            miss.mount.as_ref().map(|m| format!(" under {}", m)).unwrap_or_default());
+                    }
+                    log::info!(">> Boot completed: verified the rules of {} modules, {} unresolved", state.hymo_modules.len(), unresolved.len());
+                }
+                match compact::run(&config, false) {
+                    Ok(report) => {
+                        if report.reclaimed > 0 {
+                            log::info!(">> Compacted HymoFS rules: reclaimed {} of {}", report.reclaimed, report.scanned);
+                        }
+                        for error in &report.errors {
+                            log::warn!("Rule compaction: {}", error);
+                        }
+                    }
+                    Err(e) => log::warn!("Rule compaction failed: {:#}", e),
+                }
+                if let Some(metrics) = ApplyMetrics::load() {
+                    export_metrics(&config, &metrics);
+                }
+                return Ok(());
+            },
+            Commands::ResumeDeferred { interval } => {
+                let state = RuntimeState::load().unwrap_or_default();
+                if state.battery_deferred.is_empty() || !utils::same_boot(&state.session_id, utils::session_id()) {
+                    return Ok(());
+                }
+                let _log_guard = utils::init_logging(config.verbose, Path::new(defs::DAEMON_LOG_FILE))?;
+                log::info!(">> Waiting for the battery to recover before mounting {} deferred modules", state.battery_deferred.len());
+                let status = battery::wait_until_safe(config.low_battery_threshold, std::time::Duration::from_secs(*interval));
+                log::info!(">> Battery at {}% (charging: {}), resuming deferred modules", status.capacity.unwrap_or_default(), status.charging);
+                let trusted = state.hymofs_selftest.as_ref().is_none_or(|t| t.passed);
+                let hymofs = match HymoFs::open_at(&config.hymofs_device) {
+                    Ok(handle) if handle.is_available() && trusted => handle,
+                    _ => {
+                        log::warn!("!! HymoFS unavailable: deferred modules will mount on the next boot.");
+                        return Ok(());
+                    }
+                };
+                let storage_root = state.storage_root();
+                let mut module_list = inventory::scan_roots(&config)?;
+                module_list.retain(|m| !state.boot_reason_skipped.contains(&m.id));
+                let ranked = planner::generate(&config, &module_list, &storage_root)?;
+                module_list.retain(|m| state.battery_deferred.contains(&m.id));
+                let plan = planner::MountPlan {
+                    hymo_ops: battery::rank_resume_ops(&ranked.hymo_ops, battery::resume_ops(&config, &module_list, &storage_root)),
+                    hash_guards: guards::of_modules(&module_list),
+                    rewrites: rewrite::active(&config.path_rewrites),
+                    ..Default::default()
+                };
+                let transition = executor::apply_boot_completed(&plan, &hymofs, &[])?;
+                log::info!(">> Resumed {} deferred modules.", transition.injected_module_ids.len());
+                services::run_post_apply(&module_list, &transition.injected_module_ids);
+                let mut state = RuntimeState::load().unwrap_or_default();
+                state.battery_deferred.retain(|id| !transition.injected_module_ids.contains(id));
+                state.hymo_modules.extend(transition.injected_module_ids);
+                state.hymo_modules.sort();
+                state.hymo_modules.dedup();
+                state.save()?;
+                return Ok(());
+            },
+            Commands::Batch => {
+                let hymofs = HymoFs::open_at(&config.hymofs_device)?;
+                if !hymofs.is_available() {
+                    anyhow::bail!("HymoFS is not available at {}", config.hymofs_device.display());
+                }
+                let stats = batch::run(&hymofs, std::io::stdin().lock())?;
+                println!("Applied {} rules, {} failed.", stats.applied, stats.failed);
+                let mut report = ApplyReport::new(command_name(cli));
+                report.rules_applied = stats.applied;
+                report.rules_failed = stats.failed;
+                if stats.failed > 0 {
+                    report.fail(format!("{} rules were rejected by the kernel", stats.failed));
+                }
+                write_report(cli, &report);
+                if !report.success {
+                    std::process::exit(2);
+                }
+                return Ok(());
             }
         }
     }
 
-    let mut config = load_config(&cli)?;
-    config.merge_with_cli(
-        cli.moduledir.clone(), 
-        cli.tempdir.clone(), 
-        cli.mountsource.clone(), 
-        cli.verbose, 
-        cli.partitions.clone(),
-        cli.dry_run,
-    );
-
     if check_zygisksu_enforce_status() {
         if config.allow_umount_coexistence {
             if config.verbose {
@@ -161,13 +1192,19 @@ fn run() -> Result<()> {
     if config.dry_run {
         env_logger::builder()
             .filter_level(if config.verbose { log::LevelFilter::Debug } else { log::LevelFilter::Info })
+            .format(|buf, record| {
+                use std::io::Write;
+                writeln!(buf, "[{}] [{}] {}", record.level(), utils::session_id(), record.args())
+            })
             .init();
         
         log::info!(":: DRY-RUN / DIAGNOSTIC MODE ::");
-        let module_list = inventory::scan(&config.moduledir, &config)?;
+        let module_list = inventory::scan_roots(&config)?;
         log::info!(">> Inventory: Found {} modules", module_list.len());
         
-        let plan = planner::generate(&config, &module_list, &config.moduledir)?;
+        let mut plan = planner::generate(&config, &module_list, &config.moduledir)?;
+        linker::extend_plan(&mut plan, &module_list, None)?;
+        profiles::extend_plan(&mut plan, &profiles::load_enabled(), None)?;
         plan.print_visuals();
         
         log::info!(">> Analyzing File Conflicts...");
@@ -177,7 +1214,8 @@ fn run() -> Result<()> {
         } else {
             log::warn!("!! DETECTED {} FILE CONFLICTS !!", report.details.len());
             for c in report.details {
-                log::warn!("   [{}] {} <== {:?}", c.partition, c.relative_path, c.contending_modules);
+                let shadowed: Vec<String> = c.provenance.iter().skip(1).map(|s| format!("{} ({})", s.module_id, s.backend)).collect();
+                log::warn!("   [{}] {} <== {} shadows {}", c.partition, c.relative_path, c.winner, shadowed.join(", "));
             }
         }
 
@@ -211,13 +1249,38 @@ fn run() -> Result<()> {
 
     let _log_guard = utils::init_logging(config.verbose, Path::new(defs::DAEMON_LOG_FILE))?;
     
-    #[cfg(any(target_os = "linux", target_os = "android"))]
-    if crate::mount::hymofs::HymoFs::is_available() {
-        if let Err(e) = crate::mount::hymofs::HymoFs::set_debug(config.verbose) {
-            log::warn!("Failed to set HymoFS debug mode: {}", e);
+    let hymofs = HymoFs::open_at(&config.hymofs_device).ok().map(|h| h.with_labels(&config.selinux_labels));
+    if let Some(handle) = hymofs.as_ref().filter(|h| h.caps().is_ok_and(|caps| caps.supports_debug)) {
+        if let Err(e) = handle.set_debug(config.verbose) {
+            log::warn!("Failed to set HymoFS debug mode on {}: {}", handle.device().display(), e);
         }
     }
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    try_umount::register_hymofs(hymofs.clone());
     
+    if config.boot_guard.safe_mode && bootguard::in_safe_mode() {
+        log::warn!("!! Android is booting into safe mode: injecting no modules.");
+        return Ok(());
+    }
+    match bootguard::begin(&config.boot_guard, Path::new(defs::BOOT_ATTEMPTS_FILE)) {
+        Ok(attempts) if attempts.tripped => {
+            log::error!("!! Boot guard tripped after {} boots that never completed: injecting no modules. Run `boot-guard reset` to re-arm it.", attempts.failed);
+            if config.boot_guard.action == GuardAction::Clear {
+                if let Some(handle) = hymofs.as_ref().filter(|h| h.is_available()) {
+                    if let Err(e) = handle.clear() {
+                        log::warn!("!! Failed to clear HymoFS rules: {}", e);
+                    }
+                }
+            }
+            return Ok(());
+        }
+        Ok(attempts) if attempts.failed > 0 => {
+            log::warn!("!! {} previous boots did not complete", attempts.failed);
+        }
+        Ok(_) => {}
+        Err(e) => log::warn!("!! Failed to record the boot attempt: {:#}", e),
+    }
+
     let camouflage_name = utils::random_kworker_name();
     if let Err(e) = utils::camouflage_process(&camouflage_name) {
         log::warn!("Failed to camouflage process: {}", e);
@@ -231,31 +1294,184 @@ fn run() -> Result<()> {
     }
 
     utils::ensure_dir_exists(defs::RUN_DIR)?;
+    if let Err(e) = state::begin_apply() {
+        log::warn!("!! Failed to mark apply in progress: {}", e);
+    }
+    let started = Instant::now();
+    let mut phase_start = started;
+    let mut phases = Vec::new();
+    let mut memory_warnings = Vec::new();
 
-    let mnt_base = PathBuf::from(defs::FALLBACK_CONTENT_DIR);
-    let img_path = Path::new(defs::BASE_DIR).join("modules.img");
+    let staging_root = staging::select(&config)?;
+    let mnt_base = staging_root.content_dir();
+    let img_path = storage::image_path();
     
-    let storage_handle = storage::setup(&mnt_base, &img_path, config.force_ext4, &config.mountsource)?;
+    let storage_handle = storage::setup(&mnt_base, &img_path, &config)?;
     log::info!(">> Storage Backend: [{}]", storage_handle.mode.to_uppercase());
+    phases.push(("storage", phase_start.elapsed()));
+    memory_warnings.extend(memory::check("storage"));
+    phase_start = Instant::now();
 
-    let module_list = inventory::scan(&config.moduledir, &config)?;
+    let active_roots: Vec<String> = roots::active(&config).iter().map(|root| root.display().to_string()).collect();
+    log::info!(">> Module roots: [{}]", active_roots.join(", "));
+    let module_list = inventory::scan_roots(&config)?;
     log::info!(">> Inventory Scan: Found {} enabled modules.", module_list.len());
+    match history::record(&config) {
+        Ok(events) if !events.is_empty() => log::info!(">> History: recorded {} module changes", events.len()),
+        Ok(_) => {}
+        Err(e) => log::warn!("Failed to record module history: {:#}", e),
+    }
     
-    sync::perform_sync(&module_list, &storage_handle.mount_point)?;
+    let budget_warnings = if storage_handle.mode == "tmpfs" {
+        budget::check_staging(&config, &module_list)?
+    } else {
+        Vec::new()
+    };
+    let staging_repairs = sync::perform_sync(&config, &module_list, &storage_handle.mount_point)?;
+    phases.push(("sync", phase_start.elapsed()));
+    memory_warnings.extend(memory::check("sync"));
+    phase_start = Instant::now();
+
+    let battery_status = battery::read(Path::new(battery::POWER_SUPPLY_DIR));
+    let (module_list, battery_deferred) = if battery_status.is_critical(config.low_battery_threshold) {
+        let (now, later) = battery::split_deferred(module_list);
+        log::warn!("!! Battery at {}% and not charging: deferring {} non-critical modules until it recovers",
+            battery_status.capacity.unwrap_or_default(), later.len());
+        (now, later)
+    } else {
+        (module_list, Vec::new())
+    };
+
+    let boot_reason = BootReason::read();
+    let (module_list, boot_reason_skipped) = match &boot_reason {
+        Some(boot) if !config.boot_reason_skips.is_empty() => {
+            let (kept, skipped) = bootreason::split_skipped(module_list, &config.boot_reason_skips, boot);
+            if !skipped.is_empty() {
+                log::warn!("!! Boot reason '{}': skipping {} modules", boot.as_str(), skipped.len());
+            }
+            (kept, skipped)
+        }
+        _ => (module_list, Vec::new()),
+    };
 
-    let plan = planner::generate(&config, &module_list, &storage_handle.mount_point)?;
+    let mut plan = planner::generate(&config, &module_list, &storage_handle.mount_point)?;
+    if let Err(e) = linker::extend_plan(&mut plan, &module_list, Some(&storage_handle.mount_point)) {
+        log::warn!("!! Failed to stage linker config edits: {:#}", e);
+    }
+    if let Err(e) = profiles::extend_plan(&mut plan, &profiles::load_enabled(), Some(&storage_handle.mount_point)) {
+        log::warn!("!! Failed to stage profiles: {:#}", e);
+    }
     plan.print_visuals();
+    let selinux_warnings = if config.verify_selinux {
+        selinux_warnings(&plan, &config.selinux_consumers)
+    } else {
+        Vec::new()
+    };
+    phases.push(("plan", phase_start.elapsed()));
+    memory_warnings.extend(memory::check("plan"));
+    phase_start = Instant::now();
 
     let active_mounts: Vec<String> = plan.overlay_ops
         .iter()
         .map(|op| op.partition_name.clone())
         .collect();
 
+    let readers = early_readers::find(&early_readers::redirected_targets(&plan));
+    for reader in &readers {
+        log::warn!("!! {} (pid {}) already maps {} targeted by {}", reader.process, reader.pid, reader.path, reader.module_id);
+    }
+
+    let update_check = hymofs.as_ref().filter(|h| h.is_available()).and_then(|h| selfcheck::after_update(h, &plan));
+    let mut update_warnings = Vec::new();
+    if let Some(check) = &update_check {
+        log::info!(">> HymoFS changed ({} -> {}), running post-update self-check",
+            check.previous.as_deref().unwrap_or("none"), check.fingerprint);
+        if check.passed() {
+            log::info!(">> Post-update self-check passed.");
+        } else {
+            for failure in check.failures() {
+                log::warn!("!! HymoFS self-check: {}", failure);
+            }
+            let (moved, backend) = demote_hymo(&mut plan, &config);
+            log::warn!("!! New HymoFS build failed its self-check: mounting {} modules through {}", moved.len(), backend);
+            update_warnings.push(format!("HymoFS failed its post-update self-check ({}), used {} instead", check.failures().join("; "), backend));
+        }
+    }
+    let overlay_backend = overlay::Backend::new(&config);
+    let magic_backend = magic::Backend::new(&config);
+    let backends: Vec<&dyn MountBackend> = hymofs.iter().map(|h| h as &dyn MountBackend)
+        .chain([&overlay_backend as &dyn MountBackend, &magic_backend])
+        .filter(|b| b.is_usable())
+        .collect();
+    log::info!(">> Usable backends: [{}]", backends.iter().map(|b| b.name()).collect::<Vec<_>>().join(", "));
+    if !plan.overlay_ops.is_empty() && !overlay_backend.is_usable() {
+        let moved = plan.demote_overlay_to_magic();
+        log::warn!("!! Kernel has no overlayfs: mounting {} modules through Magic Mount", moved.len());
+        update_warnings.push("Kernel has no overlayfs, used Magic Mount instead".to_string());
+    }
+    let hymofs_usable = hymofs.as_ref().is_some_and(|h| h.is_available());
+    if !hymofs_usable && !plan.hymo_ops.is_empty() && config.hymofs_fallback == planner::HymoFallback::Overlay {
+        let (moved, backend) = demote_hymo(&mut plan, &config);
+        log::warn!("!! HymoFS unavailable at {}: mounting {} modules through {}", config.hymofs_device.display(), moved.len(), backend);
+        update_warnings.push(format!("HymoFS unavailable at {}, used {} instead", config.hymofs_device.display(), backend));
+    }
+    let mut health = HealthStore::load();
+    if let Some(kind) = health::preferred_over_hymofs(&config, &health).filter(|_| hymofs_usable && !plan.hymo_ops.is_empty()) {
+        let (moved, backend) = if kind == BackendKind::Overlay && overlay_backend.is_usable() {
+            (plan.demote_hymo_to_overlay(), "OverlayFS")
+        } else {
+            (plan.demote_hymo_to_magic(), "Magic Mount")
+        };
+        let why = if config.pinned_backend.is_some() { "pinned" } else { "more reliable on this device" };
+        log::info!(">> {} is {}: mounting {} HymoFS modules through it", backend, why, moved.len());
+    }
+    let hymofs_trusted = update_check.as_ref().is_none_or(|c| c.passed());
+
     log::info!(">> Link Start! Executing mount plan...");
     
-    let exec_result = executor::execute(&plan, &config)?;
+    let exec_result = executor::execute(&plan, &config, hymofs.as_ref())?;
+    phases.push(("execute", phase_start.elapsed()));
+    health.record(&exec_result.backend_runs);
+    if let Err(e) = health.save() {
+        log::warn!("Failed to save backend health: {:#}", e);
+    }
+    memory_warnings.extend(memory::check("execute"));
 
     let final_magic_ids = exec_result.magic_module_ids;
+
+    early_readers::restart(&readers, &config.restart_early_readers);
+    let applied_ids = [&exec_result.overlay_module_ids, &final_magic_ids, &exec_result.hymo_module_ids, &exec_result.direct_module_ids]
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect::<Vec<_>>();
+    services::run_post_apply(&module_list, &applied_ids);
+
+    let mut report = ApplyReport::new(command_name(cli));
+    report.storage_mode = Some(storage_handle.mode.clone());
+    report.overlay_modules = exec_result.overlay_module_ids.clone();
+    report.magic_modules = final_magic_ids.clone();
+    report.hymo_modules = exec_result.hymo_module_ids.clone();
+    report.direct_modules = exec_result.direct_module_ids.clone();
+    report.rules_applied = exec_result.rules_applied;
+    report.rules_failed = exec_result.rules_failed;
+    report.warnings = exec_result.warnings;
+    report.warnings.extend(budget_warnings);
+    report.warnings.extend(update_warnings);
+    report.warnings.extend(memory_warnings);
+    report.warnings.extend(selinux_warnings);
+    if !battery_deferred.is_empty() {
+        report.warnings.push(format!("Low battery: deferred {}", battery_deferred.join(", ")));
+    }
+    if let Some(boot) = boot_reason.as_ref().filter(|_| !boot_reason_skipped.is_empty()) {
+        report.warnings.push(format!("Boot reason {}: skipped {}", boot.as_str(), boot_reason_skipped.join(", ")));
+    }
+    report.disabled_modules = disable::current(Path::new(defs::DISABLE_REASONS_FILE), &roots::active(&config));
+    report.warnings.extend(readers.iter().map(|r| format!(
+        "{}: {} (pid {}) still maps the original {}", r.module_id, r.process, r.pid, r.path)));
+    for failure in exec_result.failures {
+        report.fail(failure);
+    }
     
     let mut nuke_active = false;
     if storage_handle.mode == "ext4" && config.enable_nuke {
@@ -280,14 +1496,27 @@ fn run() -> Result<()> {
     );
 
     let storage_stats = storage::get_usage(&storage_handle.mount_point);
-    let hymofs_available = storage::is_hymofs_active();
-    
-    #[cfg(any(target_os = "linux", target_os = "android"))]
-    let hymofs_version = crate::mount::hymofs::HymoFs::get_version();
-    #[cfg(not(any(target_os = "linux", target_os = "android")))]
-    let hymofs_version = None;
+    let hymofs_available = hymofs.as_ref().is_some_and(|h| h.is_available());
+    let hymofs_version = hymofs.as_ref().and_then(|h| h.get_version().ok());
+    let hymofs_device = Some(config.hymofs_device.clone());
     
-    let state = RuntimeState::new(
+    let selftest = hymofs.as_ref().filter(|h| h.is_available()).map(|h| {
+        if !hymofs_trusted {
+            let failures = update_check.as_ref().map(selfcheck::SelfCheck::failures).unwrap_or_default();
+            return SelfTest { passed: false, detail: format!("post-update self-check failed: {}", failures.join("; ")) };
+        }
+        let result = h.self_test(Path::new(defs::RUN_DIR));
+        match &result {
+            Ok(_) => log::info!(">> HymoFS self-test passed: kernel is honoring rules."),
+            Err(e) => {
+                log::warn!("!! HymoFS self-test failed: {:#}", e);
+                report.warnings.push(format!("HymoFS self-test failed: {:#}", e));
+            }
+        }
+        SelfTest::from_result(&result)
+    });
+
+    let mut state = RuntimeState::new(
         storage_handle.mode,
         storage_handle.mount_point,
         exec_result.overlay_module_ids,
@@ -297,20 +1526,57 @@ fn run() -> Result<()> {
         active_mounts,
         storage_stats,
         hymofs_available,
-        hymofs_version
+        hymofs_version,
+        hymofs_device
     );
+    state.hymofs_selftest = selftest;
+    state.staging_root = Some(staging_root);
+    state.direct_modules = exec_result.direct_module_ids;
+    state.battery_deferred = battery_deferred;
+    state.boot_reason_skipped = boot_reason_skipped;
+    state.early_readers = readers;
 
     if let Err(e) = state.save() {
         log::error!("Failed to save runtime state: {}", e);
     }
+    state::end_apply();
+
+    let metrics = ApplyMetrics::from_report(&report, started.elapsed(), &phases, staging_repairs);
+    if let Err(e) = metrics.save() {
+        log::warn!("Failed to save apply metrics: {:#}", e);
+    }
+    export_metrics(&config, &metrics);
+
+    write_report(cli, &report);
 
     log::info!(">> System operational. Mount sequence complete.");
     Ok(())
 }
 
+/// Arguments to parse, with `certify` put in front when the binary runs as
+/// the standalone test kit, e.g. pushed over adb as `hymo-selftest`.
+fn cli_args() -> Vec<std::ffi::OsString> {
+    let mut args: Vec<std::ffi::OsString> = std::env::args_os().collect();
+    let invoked_as = args.first()
+        .and_then(|arg0| Path::new(arg0).file_name())
+        .and_then(|name| name.to_str())
+        .and_then(|name| match name {
+            defs::SELFTEST_BIN_NAME => Some("certify"),
+            defs::HYMO_BIN_NAME => Some("hymo"),
+            defs::HYMOD_BIN_NAME => Some("hymod"),
+            _ => None,
+        });
+    if let Some(command) = invoked_as {
+        args.insert(1, command.into());
+    }
+    args
+}
+
 fn main() {
-    if let Err(e) = run() {
+    let cli = Cli::parse_from(cli_args());
+    if let Err(e) = run(&cli) {
         eprintln!("Error: {:#}", e);
+        write_report(&cli, &ApplyReport::failed(command_name(&cli), format!("{:#}", e)));
         std::process::exit(1);
     }
 }
@@ -0,0 +1,363 @@
+use libc::{c_char, c_int, c_ulong};
+use serde::Serialize;
+use crate::defs::HYMO_PROTOCOL_VERSION;
+
+const HYMO_IOC_MAGIC: u8 = 0xE0;
+
+const _IOC_NRBITS: u32 = 8;
+const _IOC_TYPEBITS: u32 = 8;
+const _IOC_SIZEBITS: u32 = 14;
+const _IOC_DIRBITS: u32 = 2;
+
+const _IOC_NRSHIFT: u32 = 0;
+const _IOC_TYPESHIFT: u32 = _IOC_NRSHIFT + _IOC_NRBITS;
+const _IOC_SIZESHIFT: u32 = _IOC_TYPESHIFT + _IOC_TYPEBITS;
+const _IOC_DIRSHIFT: u32 = _IOC_SIZESHIFT + _IOC_SIZEBITS;
+
+const _IOC_NONE: u32 = 0;
+const _IOC_WRITE: u32 = 1;
+const _IOC_READ: u32 = 2;
+const _IOC_READ_WRITE: u32 = 3;
+
+const fn _ioc(dir: u32, type_: u8, nr: u8, size: usize) -> c_ulong {
+    ((dir << _IOC_DIRSHIFT) |
+     ((type_ as u32) << _IOC_TYPESHIFT) |
+     ((nr as u32) << _IOC_NRSHIFT) |
+     ((size as u32) << _IOC_SIZESHIFT)) as c_ulong
+}
+
+const fn _io(type_: u8, nr: u8) -> c_ulong {
+    _ioc(_IOC_NONE, type_, nr, 0)
+}
+
+const fn _ior<T>(type_: u8, nr: u8) -> c_ulong {
+    _ioc(_IOC_READ, type_, nr, std::mem::size_of::<T>())
+}
+
+const fn _iow<T>(type_: u8, nr: u8) -> c_ulong {
+    _ioc(_IOC_WRITE, type_, nr, std::mem::size_of::<T>())
+}
+
+const fn _iowr<T>(type_: u8, nr: u8) -> c_ulong {
+    _ioc(_IOC_READ_WRITE, type_, nr, std::mem::size_of::<T>())
+}
+
+// Requests every generation answers alike, so the version can be asked
+// before the generation is known.
+pub const HYMO_IOC_CLEAR_ALL: c_ulong   = _io(HYMO_IOC_MAGIC, 5);
+pub const HYMO_IOC_GET_VERSION: c_ulong = _ior::<c_int>(HYMO_IOC_MAGIC, 6);
+pub const HYMO_IOC_LIST_RULES: c_ulong  = _iowr::<HymoIoctlListArg>(HYMO_IOC_MAGIC, 7);
+/// Asks for the `CAP_*` bits of the loaded module; kernels that predate it
+/// reject it with ENOTTY or EINVAL.
+pub const HYMO_IOC_GET_CAPS: c_ulong    = _ior::<u64>(HYMO_IOC_MAGIC, 10);
+
+pub const CAP_BATCH: u64 = 1 << 0;
+pub const CAP_LIST: u64 = 1 << 1;
+pub const CAP_DEBUG: u64 = 1 << 2;
+pub const CAP_OPAQUE: u64 = 1 << 3;
+/// The module keeps several rules per path and the one of the highest
+/// priority wins; see [`KernelAbi::add_rule_prio`].
+pub const CAP_PRIORITY: u64 = 1 << 4;
+/// The module can limit a rule to the processes of one uid; see
+/// [`KernelAbi::add_rule_scoped`].
+pub const CAP_UID_SCOPE: u64 = 1 << 5;
+
+const NR_ADD_RULE: u8 = 1;
+const NR_DEL_RULE: u8 = 2;
+const NR_HIDE_RULE: u8 = 3;
+const NR_SET_DEBUG: u8 = 8;
+const NR_ADD_RULES_BATCH: u8 = 9;
+const NR_ADD_RULE_PRIO: u8 = 11;
+const NR_ADD_RULE_SCOPED: u8 = 12;
+const NR_HIDE_RULE_SCOPED: u8 = 13;
+
+#[repr(C)]
+pub struct HymoIoctlArg {
+    pub src: *const c_char,
+    pub target: *const c_char,
+    pub r#type: c_int,
+}
+
+/// Rule argument of the prioritized add.
+#[repr(C)]
+pub struct HymoIoctlPrioArg {
+    pub src: *const c_char,
+    pub target: *const c_char,
+    pub r#type: c_int,
+    pub priority: c_int,
+}
+
+/// Rule argument of the uid-scoped add and hide; a hide leaves `target`
+/// null.
+#[repr(C)]
+pub struct HymoIoctlScopedArg {
+    pub src: *const c_char,
+    pub target: *const c_char,
+    pub r#type: c_int,
+    pub uid: u32,
+}
+
+/// Rule argument of kernels that predate typed rules; they work out the
+/// file type from the target themselves.
+#[repr(C)]
+pub struct LegacyIoctlArg {
+    pub src: *const c_char,
+    pub target: *const c_char,
+}
+
+#[repr(C)]
+pub struct HymoIoctlBatchArg {
+    pub rules: *const HymoIoctlArg,
+    pub count: c_int,
+}
+
+#[repr(C)]
+pub struct HymoIoctlListArg {
+    pub buf: *mut c_char,
+    pub size: usize,
+}
+
+/// A rule argument in the layout of one generation.
+pub enum RuleArg {
+    Typed(HymoIoctlArg),
+    Legacy(LegacyIoctlArg),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleLayout {
+    Typed,
+    Legacy,
+}
+
+/// One generation of the kernel module's ioctl interface: the protocol
+/// versions it reports, the rule layout it takes (which is also encoded in
+/// the rule request numbers) and the requests it lacks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KernelAbi {
+    pub first_protocol: i32,
+    pub last_protocol: i32,
+    pub layout: RuleLayout,
+    pub debug: bool,
+    pub batch: bool,
+}
+
+/// Generations this binary can drive, newest first.
+pub const GENERATIONS: [KernelAbi; 3] = [
+    KernelAbi { first_protocol: HYMO_PROTOCOL_VERSION, last_protocol: HYMO_PROTOCOL_VERSION, layout: RuleLayout::Typed, debug: true, batch: true },
+    KernelAbi { first_protocol: 4, last_protocol: 4, layout: RuleLayout::Typed, debug: true, batch: false },
+    KernelAbi { first_protocol: 1, last_protocol: 3, layout: RuleLayout::Legacy, debug: false, batch: false },
+];
+
+/// The generation that reports `protocol`, if this binary knows it.
+pub fn for_protocol(protocol: i32) -> Option<&'static KernelAbi> {
+    GENERATIONS.iter().find(|abi| abi.covers(protocol))
+}
+
+/// The oldest protocol any generation in [`GENERATIONS`] reports.
+pub fn oldest_protocol() -> i32 {
+    GENERATIONS.iter().map(|abi| abi.first_protocol).min().unwrap_or(HYMO_PROTOCOL_VERSION)
+}
+
+/// Index of [`for_protocol`]'s answer in [`GENERATIONS`].
+pub fn generation_of(protocol: i32) -> Option<usize> {
+    GENERATIONS.iter().position(|abi| abi.covers(protocol))
+}
+
+impl KernelAbi {
+    pub fn covers(&self, protocol: i32) -> bool {
+        (self.first_protocol..=self.last_protocol).contains(&protocol)
+    }
+
+    pub fn is_current(&self) -> bool {
+        self.covers(HYMO_PROTOCOL_VERSION)
+    }
+
+    fn rule_request(&self, nr: u8) -> c_ulong {
+        match self.layout {
+            RuleLayout::Typed => _iow::<HymoIoctlArg>(HYMO_IOC_MAGIC, nr),
+            RuleLayout::Legacy => _iow::<LegacyIoctlArg>(HYMO_IOC_MAGIC, nr),
+        }
+    }
+
+    pub fn add_rule(&self) -> c_ulong {
+        self.rule_request(NR_ADD_RULE)
+    }
+
+    pub fn del_rule(&self) -> c_ulong {
+        self.rule_request(NR_DEL_RULE)
+    }
+
+    pub fn hide_rule(&self) -> c_ulong {
+        self.rule_request(NR_HIDE_RULE)
+    }
+
+    pub fn set_debug(&self) -> Option<c_ulong> {
+        self.debug.then(|| _iow::<c_int>(HYMO_IOC_MAGIC, NR_SET_DEBUG))
+    }
+
+    pub fn add_rules_batch(&self) -> Option<c_ulong> {
+        self.batch.then(|| _iow::<HymoIoctlBatchArg>(HYMO_IOC_MAGIC, NR_ADD_RULES_BATCH))
+    }
+
+    /// The add that takes a priority, for modules reporting
+    /// [`CAP_PRIORITY`]; only typed generations can have it.
+    pub fn add_rule_prio(&self) -> Option<c_ulong> {
+        (self.layout == RuleLayout::Typed).then(|| _iow::<HymoIoctlPrioArg>(HYMO_IOC_MAGIC, NR_ADD_RULE_PRIO))
+    }
+
+    /// The add that only the processes of one uid see, for modules
+    /// reporting [`CAP_UID_SCOPE`]; only typed generations can have it.
+    pub fn add_rule_scoped(&self) -> Option<c_ulong> {
+        (self.layout == RuleLayout::Typed).then(|| _iow::<HymoIoctlScopedArg>(HYMO_IOC_MAGIC, NR_ADD_RULE_SCOPED))
+    }
+
+    /// The hide counterpart of [`Self::add_rule_scoped`].
+    pub fn hide_rule_scoped(&self) -> Option<c_ulong> {
+        (self.layout == RuleLayout::Typed).then(|| _iow::<HymoIoctlScopedArg>(HYMO_IOC_MAGIC, NR_HIDE_RULE_SCOPED))
+    }
+
+    /// `src`, `target` and `file_type` laid out the way this generation
+    /// reads a rule; legacy kernels drop the type.
+    pub fn rule_arg(&self, src: *const c_char, target: *const c_char, file_type: c_int) -> RuleArg {
+        match self.layout {
+            RuleLayout::Typed => RuleArg::Typed(HymoIoctlArg { src, target, r#type: file_type }),
+            RuleLayout::Legacy => RuleArg::Legacy(LegacyIoctlArg { src, target }),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        let protocols = if self.first_protocol == self.last_protocol {
+            format!("protocol {}", self.first_protocol)
+        } else {
+            format!("protocols {}-{}", self.first_protocol, self.last_protocol)
+        };
+        let mut missing = Vec::new();
+        if self.layout == RuleLayout::Legacy {
+            missing.push("typed rules");
+        }
+        if !self.debug {
+            missing.push("debug");
+        }
+        if !self.batch {
+            missing.push("batches");
+        }
+        if missing.is_empty() { protocols } else { format!("{} (no {})", protocols, missing.join(", ")) }
+    }
+}
+
+/// What the loaded kernel module can do, so callers skip what it lacks
+/// instead of learning it from EINVAL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct KernelCaps {
+    pub protocol: i32,
+    pub supports_batch: bool,
+    pub supports_list: bool,
+    pub supports_debug: bool,
+    /// Opaque directories as one rule; without it their lower entries are
+    /// hidden one by one.
+    pub supports_opaque: bool,
+    /// Rule priorities; without them the rule registered last wins.
+    pub supports_priority: bool,
+    /// Rules limited to one uid; without them per-app rules are emulated
+    /// with mounts in the mount namespaces of the app.
+    pub supports_uid_scope: bool,
+    /// Whether the module answered [`HYMO_IOC_GET_CAPS`] rather than the
+    /// flags being implied by its generation.
+    pub reported: bool,
+}
+
+impl KernelCaps {
+    /// What every kernel of `abi`'s generation provides.
+    pub fn implied(protocol: i32, abi: &KernelAbi) -> Self {
+        Self {
+            protocol,
+            supports_batch: abi.batch,
+            supports_list: true,
+            supports_debug: abi.debug,
+            supports_opaque: false,
+            supports_priority: false,
+            supports_uid_scope: false,
+            reported: false,
+        }
+    }
+
+    /// What a module of `abi`'s generation that reported `bits` provides:
+    /// the bits can switch off requests of its generation, e.g. in a build
+    /// without them, but not add requests the generation lacks.
+    pub fn from_bits(protocol: i32, abi: &KernelAbi, bits: u64) -> Self {
+        let implied = Self::implied(protocol, abi);
+        Self {
+            protocol,
+            supports_batch: implied.supports_batch && bits & CAP_BATCH != 0,
+            supports_list: bits & CAP_LIST != 0,
+            supports_debug: implied.supports_debug && bits & CAP_DEBUG != 0,
+            supports_opaque: bits & CAP_OPAQUE != 0,
+            supports_priority: abi.add_rule_prio().is_some() && bits & CAP_PRIORITY != 0,
+            supports_uid_scope: abi.add_rule_scoped().is_some() && bits & CAP_UID_SCOPE != 0,
+            reported: true,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        let flags = [
+            (self.supports_batch, "batch"),
+            (self.supports_list, "list"),
+            (self.supports_debug, "debug"),
+            (self.supports_opaque, "opaque"),
+            (self.supports_priority, "priority"),
+            (self.supports_uid_scope, "uid-scope"),
+        ];
+        let names: Vec<&str> = flags.iter().filter(|(on, _)| *on).map(|(_, name)| *name).collect();
+        let names = if names.is_empty() { "none".to_string() } else { names.join(", ") };
+        if self.reported { names } else { format!("{} (implied by protocol {})", names, self.protocol) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_protocols_to_generations() {
+        assert!(for_protocol(HYMO_PROTOCOL_VERSION).unwrap().is_current());
+        assert_eq!(for_protocol(4).map(|abi| abi.batch), Some(false));
+        assert_eq!(for_protocol(2).map(|abi| abi.layout), Some(RuleLayout::Legacy));
+        assert_eq!(generation_of(3), Some(2));
+        assert_eq!(oldest_protocol(), 1);
+        assert!(for_protocol(0).is_none() && for_protocol(HYMO_PROTOCOL_VERSION + 1).is_none());
+        assert_eq!(GENERATIONS[2].describe(), "protocols 1-3 (no typed rules, debug, batches)");
+    }
+
+    #[test]
+    fn narrows_implied_caps_by_reported_bits() {
+        let implied = KernelCaps::implied(4, &GENERATIONS[1]);
+        assert!(!implied.supports_batch && implied.supports_list && !implied.supports_opaque);
+        assert_eq!(implied.describe(), "list, debug (implied by protocol 4)");
+
+        let current = &GENERATIONS[0];
+        let caps = KernelCaps::from_bits(HYMO_PROTOCOL_VERSION, current, CAP_LIST | CAP_OPAQUE);
+        assert!(!caps.supports_batch && !caps.supports_debug && caps.supports_list && caps.supports_opaque);
+        assert_eq!(caps.describe(), "list, opaque");
+        assert!(!KernelCaps::from_bits(4, &GENERATIONS[1], CAP_BATCH).supports_batch);
+        assert!(KernelCaps::from_bits(HYMO_PROTOCOL_VERSION, current, CAP_PRIORITY).supports_priority);
+        assert!(!KernelCaps::from_bits(3, &GENERATIONS[2], CAP_PRIORITY).supports_priority);
+        assert!(KernelCaps::from_bits(HYMO_PROTOCOL_VERSION, current, CAP_UID_SCOPE).supports_uid_scope);
+        assert!(!KernelCaps::from_bits(3, &GENERATIONS[2], CAP_UID_SCOPE).supports_uid_scope);
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn encodes_the_rule_layout_in_the_request() {
+        let current = &GENERATIONS[0];
+        assert_eq!(current.add_rule(), 0x4018_E001);
+        assert_eq!(current.add_rules_batch(), Some(0x4010_E009));
+        assert_eq!(GENERATIONS[2].add_rule(), 0x4010_E001);
+        assert_eq!(GENERATIONS[2].hide_rule(), 0x4010_E003);
+        assert_eq!((HYMO_IOC_CLEAR_ALL, HYMO_IOC_GET_VERSION), (0xE005, 0x8004_E006));
+        assert_eq!(HYMO_IOC_GET_CAPS, 0x8008_E00A);
+        assert_eq!(current.add_rule_prio(), Some(0x4018_E00B));
+        assert_eq!(GENERATIONS[2].add_rule_prio(), None);
+        assert_eq!((current.add_rule_scoped(), current.hide_rule_scoped()), (Some(0x4018_E00C), Some(0x4018_E00D)));
+        assert_eq!(GENERATIONS[2].hide_rule_scoped(), None);
+    }
+}
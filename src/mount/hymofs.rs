@@ -1,81 +1,41 @@
-use std::ffi::{CStr, CString};
+use std::ffi::{CStr, CString, OsStr};
 use std::fs::{File, OpenOptions};
-use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::FileTypeExt;
 use std::os::unix::io::AsRawFd;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::sync::atomic::{AtomicU32, AtomicU8, Ordering};
 use anyhow::{Context, Result, bail};
 use log::{debug, warn};
-use walkdir::WalkDir;
+use serde::{Deserialize, Serialize};
 use libc::{c_int, c_ulong, c_char};
-use crate::defs::HYMO_PROTOCOL_VERSION;
+use rayon::prelude::*;
+use super::hymo_abi::{self, HYMO_IOC_CLEAR_ALL, HYMO_IOC_GET_CAPS, HYMO_IOC_GET_VERSION, HYMO_IOC_LIST_RULES, HymoIoctlArg, HymoIoctlBatchArg, HymoIoctlListArg, HymoIoctlPrioArg, HymoIoctlScopedArg, KernelAbi, KernelCaps, RuleArg};
+use crate::{core::{arch, journal::{self, RuleJournal}, mntns, modules, protect::{self, ProtectedPaths}, rules::{EXTERNAL_GROUP, RuleLedger}, selinux::{Labeler, SelinuxLabels}, symlinks, transaction::Transaction}, defs::{CANARY_SOURCE_NAME, CANARY_TARGET, HYMOFS_MIN_KERNEL, HYMO_PROTOCOL_VERSION, RULE_JOURNAL_DIR, WHITEOUT_NODE}};
 
-const DEV_PATH: &str = "/dev/hymo_ctl";
-const HYMO_IOC_MAGIC: u8 = 0xE0;
+#[cfg(feature = "async")]
+pub mod asyncio;
+mod error;
 
-const _IOC_NRBITS: u32 = 8;
-const _IOC_TYPEBITS: u32 = 8;
-const _IOC_SIZEBITS: u32 = 14;
-const _IOC_DIRBITS: u32 = 2;
+pub use error::{HymoError, HymoResult};
 
-const _IOC_NRSHIFT: u32 = 0;
-const _IOC_TYPESHIFT: u32 = _IOC_NRSHIFT + _IOC_NRBITS;
-const _IOC_SIZESHIFT: u32 = _IOC_TYPESHIFT + _IOC_TYPEBITS;
-const _IOC_DIRSHIFT: u32 = _IOC_SIZESHIFT + _IOC_SIZEBITS;
-
-const _IOC_NONE: u32 = 0;
-const _IOC_WRITE: u32 = 1;
-const _IOC_READ: u32 = 2;
-const _IOC_READ_WRITE: u32 = 3;
-
-const fn _ioc(dir: u32, type_: u8, nr: u8, size: usize) -> c_ulong {
-    ((dir << _IOC_DIRSHIFT) |
-     ((type_ as u32) << _IOC_TYPESHIFT) |
-     ((nr as u32) << _IOC_NRSHIFT) |
-     ((size as u32) << _IOC_SIZESHIFT)) as c_ulong
-}
-
-const fn _io(type_: u8, nr: u8) -> c_ulong {
-    _ioc(_IOC_NONE, type_, nr, 0)
-}
-
-const fn _ior<T>(type_: u8, nr: u8) -> c_ulong {
-    _ioc(_IOC_READ, type_, nr, std::mem::size_of::<T>())
-}
-
-const fn _iow<T>(type_: u8, nr: u8) -> c_ulong {
-    _ioc(_IOC_WRITE, type_, nr, std::mem::size_of::<T>())
-}
-
-const fn _iowr<T>(type_: u8, nr: u8) -> c_ulong {
-    _ioc(_IOC_READ_WRITE, type_, nr, std::mem::size_of::<T>())
-}
-
-const HYMO_IOC_ADD_RULE: c_ulong    = _iow::<HymoIoctlArg>(HYMO_IOC_MAGIC, 1);
-#[allow(dead_code)]
-const HYMO_IOC_DEL_RULE: c_ulong    = _iow::<HymoIoctlArg>(HYMO_IOC_MAGIC, 2);
-const HYMO_IOC_HIDE_RULE: c_ulong   = _iow::<HymoIoctlArg>(HYMO_IOC_MAGIC, 3);
-const HYMO_IOC_CLEAR_ALL: c_ulong   = _io(HYMO_IOC_MAGIC, 5);
-const HYMO_IOC_GET_VERSION: c_ulong = _ior::<c_int>(HYMO_IOC_MAGIC, 6);
-#[allow(dead_code)]
-const HYMO_IOC_LIST_RULES: c_ulong  = _iowr::<HymoIoctlListArg>(HYMO_IOC_MAGIC, 7);
-const HYMO_IOC_SET_DEBUG: c_ulong   = _iow::<c_int>(HYMO_IOC_MAGIC, 8);
-
-#[repr(C)]
-struct HymoIoctlArg {
-    src: *const c_char,
-    target: *const c_char,
-    r#type: c_int,
-}
-
-#[repr(C)]
-#[allow(dead_code)]
-struct HymoIoctlListArg {
-    buf: *mut c_char,
-    size: usize,
-}
+pub const DEV_PATH: &str = "/dev/hymo_ctl";
+const HYMO_BATCH_MAX: usize = 128;
+const LIST_BUF_LEN: usize = 128 * 1024;
+/// Largest buffer a rule listing may grow to; a kernel that needs more is
+/// an error rather than a silently shortened listing.
+const MAX_LIST_BUF_LEN: usize = 32 * 1024 * 1024;
+const BATCH_UNKNOWN: u8 = 0;
+const BATCH_SUPPORTED: u8 = 1;
+const BATCH_UNSUPPORTED: u8 = 2;
+const ABI_UNKNOWN: u8 = u8::MAX;
+/// Kind bit of hide rules; adds use the bit of their file type.
+const HIDE_KIND_BIT: u32 = 1 << 16;
 
 #[repr(i32)]
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum HymoFileType {
     Unknown = 0,
     Fifo = 1,
@@ -110,9 +70,34 @@ impl From<std::fs::FileType> for HymoFileType {
     }
 }
 
-impl From<i32> for HymoFileType {
-    fn from(val: i32) -> Self {
-        match val {
+impl HymoFileType {
+    /// The `type` field of a rule ioctl for this kind, as the kernel's
+    /// `DT_*` values number them.
+    pub fn kernel_value(self) -> c_int {
+        match self {
+            HymoFileType::Unknown => 0,
+            HymoFileType::Fifo => 1,
+            HymoFileType::Chr => 2,
+            HymoFileType::Dir => 4,
+            HymoFileType::Blk => 6,
+            HymoFileType::Reg => 8,
+            HymoFileType::Lnk => 10,
+            HymoFileType::Sock => 12,
+            HymoFileType::Wht => 14,
+        }
+    }
+
+    fn kind_bit(self) -> u32 {
+        1 << self.kernel_value()
+    }
+}
+
+impl TryFrom<i32> for HymoFileType {
+    type Error = anyhow::Error;
+
+    fn try_from(val: i32) -> Result<Self> {
+        Ok(match val {
+            0 => HymoFileType::Unknown,
             1 => HymoFileType::Fifo,
             2 => HymoFileType::Chr,
             4 => HymoFileType::Dir,
@@ -121,291 +106,1850 @@ impl From<i32> for HymoFileType {
             10 => HymoFileType::Lnk,
             12 => HymoFileType::Sock,
             14 => HymoFileType::Wht,
-            _ => HymoFileType::Unknown,
+            _ => bail!("{} is not a HymoFS file type", val),
+        })
+    }
+}
+
+impl super::MountBackend for HymoFs {
+    fn name(&self) -> &'static str {
+        "hymofs"
+    }
+
+    fn is_usable(&self) -> bool {
+        self.is_available()
+    }
+
+    fn inject(&self, source: &Path, target: &Path) -> Result<()> {
+        let ops = if source.is_dir() {
+            collect_directory_ops(target, source)
+        } else {
+            if protect::protected().shadows(target) {
+                bail!("{} is protected", target.display());
+            }
+            let file_type = std::fs::symlink_metadata(source)
+                .with_context(|| format!("Failed to stat {}", source.display()))?
+                .file_type()
+                .into();
+            let mut ops = vec![RuleOp::Add { src: target.to_string_lossy().into_owned(), target: source.to_string_lossy().into_owned(), file_type }];
+            symlinks::rewrite(&mut ops, symlinks::policy());
+            ops
+        };
+        let mut transaction = Transaction::begin(self);
+        transaction.apply_all(&ops).with_context(|| format!("Failed to inject {}", target.display()))?;
+        transaction.commit();
+        Ok(())
+    }
+
+    /// Deletes the rules at or below `target`, except the ones adopted from
+    /// or namespaced by other consumers, and drops them from the ledger.
+    /// Fails naming the rules it could not delete, once the rest went.
+    fn remove(&self, target: &Path) -> Result<usize> {
+        let mut ledger = RuleLedger::load();
+        let rules = self.list_ops()?;
+        let mut removed = 0;
+        let mut failed = Vec::new();
+        let doomed: Vec<&RuleOp> = rules.iter()
+            .filter(|op| Path::new(op.path()).starts_with(target) && !ledger.is_foreign(op.path()))
+            .collect();
+        for op in doomed {
+            match self.delete_rule(op.path()) {
+                Ok(()) => {
+                    ledger.owned.remove(op.path());
+                    removed += 1;
+                }
+                Err(e) => failed.push(format!("{}: {}", op.path(), e)),
+            }
+        }
+        if removed > 0 {
+            ledger.save()?;
+        }
+        if !failed.is_empty() {
+            bail!("Failed to delete {} of {} rules under {}: {}", failed.len(), removed + failed.len(), target.display(), failed.join("; "));
         }
+        Ok(removed)
+    }
+
+    fn list(&self) -> Result<Vec<PathBuf>> {
+        Ok(self.list_ops()?.iter().map(|op| PathBuf::from(op.path())).collect())
+    }
+
+    /// Resets the kernel table all at once and puts the rules of other
+    /// consumers back, as a full apply does.
+    fn clear(&self) -> Result<usize> {
+        let before = self.list_ops()?.len();
+        HymoFs::clear(self)?;
+        let foreign = RuleLedger::load().foreign_rules();
+        let restored = foreign.iter().filter(|op| self.apply_op(op).is_ok()).count();
+        Ok(before.saturating_sub(restored))
+    }
+
+    fn status(&self) -> super::BackendStatus {
+        let detail = match HymoFs::status(self) {
+            HymoFsStatus::Available => match self.abi() {
+                Ok(abi) if !abi.is_current() => format!("protocol {} at {} through the {} shim", self.get_version().unwrap_or_default(), self.device().display(), abi.describe()),
+                _ => format!("protocol {} at {}", HYMO_PROTOCOL_VERSION, self.device().display()),
+            },
+            HymoFsStatus::NotPresent => format!("no kernel module behind {}", self.device().display()),
+            status => status.describe(),
+        };
+        super::BackendStatus { name: "hymofs", usable: self.is_available(), detail }
     }
 }
 
-#[derive(Debug, PartialEq)]
+/// Outcome of [`HymoFs::add_rules_batch`].
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BatchOutcome {
+    /// Indices of the rules the kernel rejected.
+    pub failed: Vec<usize>,
+    pub ioctls: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum HymoFsStatus {
     Available,
     NotPresent,
-    ProtocolMismatch,
+    /// No module answers and the running kernel predates every HymoFS
+    /// build, so none can be loaded.
+    KernelTooOld { release: String },
+    /// The module speaks a protocol older than any this binary drives.
+    ModuleTooOld { protocol: i32 },
+    /// The module speaks a protocol newer than this binary knows.
+    ModuleTooNew { protocol: i32 },
 }
 
-pub struct HymoController {
-    file: File,
+impl HymoFsStatus {
+    /// `version` is the protocol the module reported, `release` the
+    /// running kernel's `uname -r`.
+    fn check(version: Option<i32>, release: Option<&str>) -> Self {
+        match version {
+            Some(protocol) if hymo_abi::for_protocol(protocol).is_some() => HymoFsStatus::Available,
+            Some(protocol) if protocol < hymo_abi::oldest_protocol() => HymoFsStatus::ModuleTooOld { protocol },
+            Some(protocol) => HymoFsStatus::ModuleTooNew { protocol },
+            None => match release {
+                Some(release) if kernel_version(release).is_some_and(|version| version < HYMOFS_MIN_KERNEL) => {
+                    HymoFsStatus::KernelTooOld { release: release.to_string() }
+                }
+                _ => HymoFsStatus::NotPresent,
+            },
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            HymoFsStatus::Available => "available",
+            HymoFsStatus::NotPresent => "not present",
+            HymoFsStatus::KernelTooOld { .. } => "kernel too old",
+            HymoFsStatus::ModuleTooOld { .. } => "module too old",
+            HymoFsStatus::ModuleTooNew { .. } => "module too new",
+        }
+    }
+
+    /// What is wrong, worded for the user.
+    pub fn describe(&self) -> String {
+        match self {
+            HymoFsStatus::Available => "available".to_string(),
+            HymoFsStatus::NotPresent => "kernel module not loaded".to_string(),
+            HymoFsStatus::KernelTooOld { release } => format!(
+                "kernel {} is older than {}.{}, the oldest HymoFS supports", release, HYMOFS_MIN_KERNEL.0, HYMOFS_MIN_KERNEL.1,
+            ),
+            HymoFsStatus::ModuleTooOld { protocol } => format!(
+                "module protocol {} is older than {}, the oldest this build drives; update the kernel module", protocol, hymo_abi::oldest_protocol(),
+            ),
+            HymoFsStatus::ModuleTooNew { protocol } => format!(
+                "module protocol {} is newer than {}; update meta-hybrid", protocol, HYMO_PROTOCOL_VERSION,
+            ),
+        }
+    }
+}
+
+/// `(major, minor)` of a kernel release such as `5.10.198-android13-4`.
+fn kernel_version(release: &str) -> Option<(u32, u32)> {
+    let mut parts = release.split(|c: char| !c.is_ascii_digit());
+    Some((parts.next()?.parse().ok()?, parts.next()?.parse().ok()?))
+}
+
+/// The running kernel's release, as `uname -r` prints it.
+fn kernel_release() -> Option<String> {
+    let mut uts: libc::utsname = unsafe { std::mem::zeroed() };
+    if unsafe { libc::uname(&mut uts) } != 0 {
+        return None;
+    }
+    let release = unsafe { CStr::from_ptr(uts.release.as_ptr()) };
+    Some(release.to_string_lossy().into_owned())
+}
+
+#[derive(Clone)]
+pub struct HymoFs {
+    inner: Arc<HymoHandle>,
+    /// Relabels module files before rules redirect to them.
+    labeler: Option<Arc<Labeler>>,
+    priorities: Option<Arc<RulePriorities>>,
+}
+
+/// Kernel priorities of the rules that redirect into module directories: a
+/// rule takes the priority of the deepest directory its target lies in, 0
+/// when there is none.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RulePriorities {
+    dirs: Vec<(PathBuf, i32)>,
+}
+
+impl RulePriorities {
+    pub fn insert<P: Into<PathBuf>>(&mut self, dir: P, priority: i32) {
+        self.dirs.push((dir.into(), priority));
+    }
+
+    pub fn of(&self, target: &str) -> i32 {
+        let target = Path::new(target);
+        self.dirs.iter()
+            .filter(|(dir, _)| target.starts_with(dir))
+            .max_by_key(|(dir, _)| dir.components().count())
+            .map_or(0, |(_, priority)| *priority)
+    }
+}
+
+struct HymoHandle {
+    device: PathBuf,
+    file: RwLock<File>,
+    batch: AtomicU8,
+    /// Index in [`hymo_abi::GENERATIONS`] of the generation the kernel
+    /// reported, learned with the first version query.
+    abi: AtomicU8,
+    /// Learned with the first capabilities query.
+    caps: Mutex<Option<KernelCaps>>,
+    /// Rule kinds the kernel accepted, and rejected as unsupported, so far.
+    supported_kinds: AtomicU32,
+    unsupported_kinds: AtomicU32,
+}
+
+fn open_device(device: &Path) -> HymoResult<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(device)
+        .map_err(|e| HymoError::open(device.to_path_buf(), e))
+}
+
+fn is_stale_fd(err: &std::io::Error) -> bool {
+    matches!(err.raw_os_error(), Some(libc::EBADF) | Some(libc::ENODEV) | Some(libc::ENXIO))
 }
 
-impl HymoController {
-    pub fn new() -> Result<Self> {
-        let file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .open(DEV_PATH)
-            .with_context(|| format!("Failed to open {}", DEV_PATH))?;
-        Ok(Self { file })
+impl HymoFs {
+    pub fn open_at<P: AsRef<Path>>(device: P) -> HymoResult<Self> {
+        let device = device.as_ref();
+        let file = open_device(device)?;
+        Ok(Self {
+            inner: Arc::new(HymoHandle {
+                device: device.to_path_buf(),
+                file: RwLock::new(file),
+                batch: AtomicU8::new(BATCH_UNKNOWN),
+                abi: AtomicU8::new(ABI_UNKNOWN),
+                caps: Mutex::new(None),
+                supported_kinds: AtomicU32::new(0),
+                unsupported_kinds: AtomicU32::new(0),
+            }),
+            labeler: None,
+            priorities: None,
+        })
+    }
+
+    /// Labels the sources of added rules as `labels` asks from now on.
+    pub fn with_labels(mut self, labels: &SelinuxLabels) -> Self {
+        self.labeler = Labeler::new(labels).map(Arc::new);
+        self
+    }
+
+    /// Adds rules with `priorities` from now on, where the kernel keeps
+    /// several per path; elsewhere the rule added last wins as before.
+    pub fn with_priorities(mut self, priorities: RulePriorities) -> Self {
+        self.priorities = Some(Arc::new(priorities));
+        self
+    }
+
+    fn priority_of(&self, target: &str) -> i32 {
+        self.priorities.as_ref().map_or(0, |priorities| priorities.of(target))
     }
 
-    pub fn check_status() -> HymoFsStatus {
-        if !Path::new(DEV_PATH).exists() {
-            return HymoFsStatus::NotPresent;
+    /// Whether adds carry priorities the kernel honours, which the batch
+    /// request has no room for.
+    fn uses_priorities(&self) -> bool {
+        self.priorities.is_some() && self.caps().is_ok_and(|caps| caps.supports_priority)
+    }
+
+    /// Gives the sources of the adds in `ops` the SELinux context their
+    /// paths expect; returns how many were relabeled.
+    pub fn relabel(&self, ops: &[RuleOp]) -> usize {
+        self.labeler.as_ref().map_or(0, |labeler| labeler.relabel(ops))
+    }
+
+    pub fn device(&self) -> &Path {
+        &self.inner.device
+    }
+
+    pub fn status(&self) -> HymoFsStatus {
+        let version = self.get_version().ok();
+        let release = if version.is_none() { kernel_release() } else { None };
+        let status = HymoFsStatus::check(version, release.as_deref());
+        if status != HymoFsStatus::Available {
+            debug!("HymoFS[{}]: {}", self.device().display(), status.describe());
         }
-        
-        match Self::new().and_then(|ctl| ctl.get_version()) {
-            Ok(ver) if ver == HYMO_PROTOCOL_VERSION => HymoFsStatus::Available,
-            Ok(ver) => {
-                debug!("HymoFS protocol mismatch: kernel={}, user={}", ver, HYMO_PROTOCOL_VERSION);
-                HymoFsStatus::ProtocolMismatch
+        status
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.status() == HymoFsStatus::Available
+    }
+
+    pub fn reopen(&self) -> HymoResult<()> {
+        let file = open_device(&self.inner.device)?;
+        *self.inner.file.write().unwrap_or_else(PoisonError::into_inner) = file;
+        self.inner.abi.store(ABI_UNKNOWN, Ordering::Relaxed);
+        *self.inner.caps.lock().unwrap_or_else(PoisonError::into_inner) = None;
+        self.inner.batch.store(BATCH_UNKNOWN, Ordering::Relaxed);
+        debug!("HymoFS[{}]: control device reopened", self.device().display());
+        Ok(())
+    }
+
+    fn ioctl<T>(&self, request: c_ulong, arg: *mut T) -> std::io::Result<c_int> {
+        let call = || {
+            let file = self.inner.file.read().unwrap_or_else(PoisonError::into_inner);
+            let ret = unsafe { libc::ioctl(file.as_raw_fd(), request as libc::Ioctl, arg) };
+            if ret < 0 {
+                Err(std::io::Error::last_os_error())
+            } else {
+                Ok(ret)
+            }
+        };
+        match call() {
+            Err(e) if is_stale_fd(&e) => {
+                warn!("HymoFS[{}]: stale control fd ({}), reopening", self.device().display(), e);
+                if self.reopen().is_err() {
+                    return Err(e);
+                }
+                call()
             }
-            Err(_) => HymoFsStatus::NotPresent,
+            res => res,
         }
     }
 
-    pub fn get_version(&self) -> Result<i32> {
+    pub fn get_version(&self) -> HymoResult<i32> {
         let mut ver: c_int = 0;
-        let ret = unsafe {
-            libc::ioctl(self.file.as_raw_fd(), HYMO_IOC_GET_VERSION as c_int, &mut ver)
-        };
-        if ret < 0 {
-            bail!("Failed to get version: {}", std::io::Error::last_os_error());
+        if let Err(e) = self.ioctl(HYMO_IOC_GET_VERSION, &mut ver) {
+            return Err(HymoError::rejected("get_version", &e));
+        }
+        if let Some(index) = hymo_abi::generation_of(ver) {
+            if self.inner.abi.swap(index as u8, Ordering::Relaxed) != index as u8 && index > 0 {
+                debug!("HymoFS[{}]: kernel speaks protocol {}, using the {} ABI", self.device().display(), ver, self.describe_abi(index));
+            }
         }
         Ok(ver as i32)
     }
 
-    pub fn clear(&self) -> Result<()> {
-        debug!("HymoFS: Clearing all rules");
-        let ret = unsafe {
-            libc::ioctl(self.file.as_raw_fd(), HYMO_IOC_CLEAR_ALL as c_int)
+    fn describe_abi(&self, index: usize) -> String {
+        hymo_abi::GENERATIONS.get(index).map(KernelAbi::describe).unwrap_or_default()
+    }
+
+    /// What the loaded kernel module supports: the capabilities it reports,
+    /// or the ones its generation implies when it predates the query.
+    pub fn caps(&self) -> HymoResult<KernelCaps> {
+        if let Some(caps) = *self.inner.caps.lock().unwrap_or_else(PoisonError::into_inner) {
+            return Ok(caps);
+        }
+        let abi = self.abi()?;
+        let protocol = self.get_version()?;
+        let mut bits: u64 = 0;
+        let caps = match self.ioctl(HYMO_IOC_GET_CAPS, &mut bits) {
+            Ok(_) => KernelCaps::from_bits(protocol, abi, bits),
+            Err(e) if matches!(e.raw_os_error(), Some(libc::ENOTTY) | Some(libc::EINVAL)) => KernelCaps::implied(protocol, abi),
+            Err(e) => return Err(HymoError::rejected("capabilities query", &e)),
         };
-        if ret < 0 {
-            bail!("HymoFS clear failed: {}", std::io::Error::last_os_error());
+        debug!("HymoFS[{}]: capabilities {}", self.device().display(), caps.describe());
+        *self.inner.caps.lock().unwrap_or_else(PoisonError::into_inner) = Some(caps);
+        Ok(caps)
+    }
+
+    /// The ioctl generation of the loaded kernel module, which rule requests
+    /// are encoded for.
+    pub fn abi(&self) -> HymoResult<&'static KernelAbi> {
+        if let Some(abi) = hymo_abi::GENERATIONS.get(self.inner.abi.load(Ordering::Relaxed) as usize) {
+            return Ok(abi);
+        }
+        let version = self.get_version()?;
+        hymo_abi::for_protocol(version).ok_or_else(|| HymoError::Unavailable(HymoFsStatus::check(Some(version), None)))
+    }
+
+    fn rule_ioctl(&self, request: c_ulong, arg: &mut RuleArg) -> std::io::Result<c_int> {
+        match arg {
+            RuleArg::Typed(arg) => self.ioctl(request, arg),
+            RuleArg::Legacy(arg) => self.ioctl(request, arg),
+        }
+    }
+
+    pub fn clear(&self) -> HymoResult<()> {
+        debug!("HymoFS[{}]: Clearing all rules", self.device().display());
+        if let Err(e) = self.ioctl(HYMO_IOC_CLEAR_ALL, std::ptr::null_mut::<c_int>()) {
+            return Err(HymoError::rejected("clear", &e));
         }
         Ok(())
     }
 
-    pub fn set_debug(&self, enable: bool) -> Result<()> {
-        let val: c_int = if enable { 1 } else { 0 };
-        let ret = unsafe {
-            libc::ioctl(self.file.as_raw_fd(), HYMO_IOC_SET_DEBUG as c_int, &val)
+    pub fn set_debug(&self, enable: bool) -> HymoResult<()> {
+        let Some(request) = self.abi()?.set_debug().filter(|_| self.caps().is_ok_and(|c| c.supports_debug)) else {
+            return Err(HymoError::Unsupported(format!("protocol {} has no debug switch", self.get_version()?)));
         };
-        if ret < 0 {
-            bail!("HymoFS set_debug failed: {}", std::io::Error::last_os_error());
+        let mut val: c_int = if enable { 1 } else { 0 };
+        if let Err(e) = self.ioctl(request, &mut val) {
+            return Err(HymoError::rejected("set_debug", &e));
         }
         Ok(())
     }
 
-    pub fn add_rule(&self, src: &str, target: &str, type_val: HymoFileType) -> Result<()> {
-        debug!("HymoFS: ADD_RULE src='{}' target='{}' type={:?}", src, target, type_val);
-        let c_src = CString::new(src)?;
-        let c_target = CString::new(target)?;
-        
-        let arg = HymoIoctlArg {
-            src: c_src.as_ptr(),
-            target: c_target.as_ptr(),
-            r#type: type_val as c_int,
+    /// Redirects `src` to `target`. Of several rules for one path the kernel
+    /// serves the one of the highest `priority`, if it supports priorities;
+    /// otherwise it ignores them and the one added last wins.
+    pub fn add_rule(&self, src: &str, target: &str, type_val: HymoFileType, priority: i32) -> HymoResult<()> {
+        debug!("HymoFS[{}]: ADD_RULE src='{}' target='{}' type={:?} priority={}", self.device().display(), src, target, type_val, priority);
+        let abi = self.abi()?;
+        let c_src = c_path(src)?;
+        let c_target = c_path(target)?;
+        let prioritized = abi.add_rule_prio()
+            .filter(|_| priority != 0 && self.caps().is_ok_and(|caps| caps.supports_priority));
+        let result = match prioritized {
+            Some(request) => {
+                let mut arg = HymoIoctlPrioArg { src: c_src.as_ptr(), target: c_target.as_ptr(), r#type: type_val.kernel_value(), priority };
+                self.ioctl(request, &mut arg)
+            }
+            None => {
+                let mut arg = abi.rule_arg(c_src.as_ptr(), c_target.as_ptr(), type_val.kernel_value());
+                self.rule_ioctl(abi.add_rule(), &mut arg)
+            }
         };
+        self.note_kind(type_val.kind_bit(), &format!("{:?} redirect", type_val), &result);
+        if let Err(e) = result {
+            return Err(HymoError::rejected("add_rule", &e));
+        }
+        Ok(())
+    }
 
-        let ret = unsafe {
-            libc::ioctl(self.file.as_raw_fd(), HYMO_IOC_ADD_RULE as c_int, &arg)
-        };
+    pub fn delete_rule(&self, src: &str) -> HymoResult<()> {
+        debug!("HymoFS[{}]: DEL_RULE src='{}'", self.device().display(), src);
+        let abi = self.abi()?;
+        let c_src = c_path(src)?;
+        let mut arg = abi.rule_arg(c_src.as_ptr(), std::ptr::null(), 0);
+
+        if let Err(e) = self.rule_ioctl(abi.del_rule(), &mut arg) {
+            return Err(HymoError::rejected("delete_rule", &e));
+        }
+        Ok(())
+    }
+
+    pub fn hide_path(&self, path: &str) -> HymoResult<()> {
+        debug!("HymoFS[{}]: HIDE_RULE path='{}'", self.device().display(), path);
+        let abi = self.abi()?;
+        let c_hidden = c_path(path)?;
+        let mut arg = abi.rule_arg(c_hidden.as_ptr(), std::ptr::null(), 0);
 
-        if ret < 0 {
-            bail!("HymoFS add_rule failed: {}", std::io::Error::last_os_error());
+        let result = self.rule_ioctl(abi.hide_rule(), &mut arg);
+        self.note_kind(HIDE_KIND_BIT, "hide", &result);
+        if let Err(e) = result {
+            return Err(HymoError::rejected("hide_path", &e));
         }
         Ok(())
     }
 
-    #[allow(dead_code)]
-    pub fn delete_rule(&self, src: &str) -> Result<()> {
-        debug!("HymoFS: DEL_RULE src='{}'", src);
-        let c_src = CString::new(src)?;
-        
-        let arg = HymoIoctlArg {
+    /// Applies `op` for the processes of `uid` alone, on kernels reporting
+    /// [`KernelCaps::supports_uid_scope`]. A delete removes the rule for
+    /// everyone, scoped or not.
+    pub fn apply_scoped(&self, op: &RuleOp, uid: u32) -> HymoResult<()> {
+        debug!("HymoFS[{}]: SCOPED {} '{}' uid={}", self.device().display(), op.verb(), op.path(), uid);
+        let abi = self.abi()?;
+        let unsupported = || HymoError::Unsupported(format!("at {} cannot scope rules to a uid", self.device().display()));
+        if !self.caps()?.supports_uid_scope {
+            return Err(unsupported());
+        }
+        let (request, target, file_type, name) = match op {
+            RuleOp::Add { target, file_type, .. } => (abi.add_rule_scoped(), Some(c_path(target)?), file_type.kernel_value(), "add_rule_scoped"),
+            RuleOp::Hide { .. } => (abi.hide_rule_scoped(), None, 0, "hide_rule_scoped"),
+            RuleOp::Delete { src } => return self.delete_rule(src),
+        };
+        let request = request.ok_or_else(unsupported)?;
+        let c_src = c_path(op.path())?;
+        let mut arg = HymoIoctlScopedArg {
             src: c_src.as_ptr(),
-            target: std::ptr::null(),
-            r#type: 0,
+            target: target.as_ref().map_or(std::ptr::null(), |t| t.as_ptr()),
+            r#type: file_type,
+            uid,
         };
+        if let Err(e) = self.ioctl(request, &mut arg) {
+            return Err(HymoError::rejected(name, &e));
+        }
+        Ok(())
+    }
 
-        let ret = unsafe {
-            libc::ioctl(self.file.as_raw_fd(), HYMO_IOC_DEL_RULE as c_int, &arg)
-        };
+    /// Records whether the kernel takes rules of kind `bit`; the first
+    /// rejection as unsupported is logged once, later rules of that kind are
+    /// downgraded without asking the kernel again.
+    fn note_kind(&self, bit: u32, label: &str, result: &std::io::Result<c_int>) {
+        match result {
+            Ok(_) => {
+                self.inner.supported_kinds.fetch_or(bit, Ordering::Relaxed);
+            }
+            Err(e) => {
+                let ever_accepted = self.inner.supported_kinds.load(Ordering::Relaxed) & bit != 0;
+                if marks_unsupported(e.raw_os_error(), ever_accepted)
+                    && self.inner.unsupported_kinds.fetch_or(bit, Ordering::Relaxed) & bit == 0
+                {
+                    warn!("HymoFS[{}]: kernel does not support {} rules ({}), downgrading them", self.device().display(), label, e);
+                }
+            }
+        }
+    }
 
-        if ret < 0 {
-            bail!("HymoFS delete_rule failed: {}", std::io::Error::last_os_error());
+    fn kind_unsupported(&self, op: &RuleOp) -> bool {
+        op.kind_bit().is_some_and(|bit| self.inner.unsupported_kinds.load(Ordering::Relaxed) & bit != 0)
+    }
+
+    /// The kernel's whole rule listing. A listing that fills the buffer is
+    /// fetched again with a larger one, of the size the kernel reports back
+    /// when it does.
+    fn read_listing(&self) -> HymoResult<String> {
+        if !self.caps()?.supports_list {
+            return Err(HymoError::Unsupported(format!("at {} cannot list its rules", self.device().display())));
+        }
+        let mut capacity = LIST_BUF_LEN;
+        loop {
+            let mut buffer = zeroed_buffer(capacity)?;
+            let mut arg = HymoIoctlListArg {
+                buf: buffer.as_mut_ptr() as *mut c_char,
+                size: capacity,
+            };
+
+            if let Err(e) = self.ioctl(HYMO_IOC_LIST_RULES, &mut arg) {
+                return Err(HymoError::rejected("list_rules", &e));
+            }
+
+            let listing = CStr::from_bytes_until_nul(&buffer).ok();
+            let filled = listing.map_or(capacity, |c_str| c_str.to_bytes().len());
+            match next_list_capacity(capacity, arg.size, filled)? {
+                Some(next) => {
+                    debug!("HymoFS rule listing outgrew {} bytes, retrying with {}", capacity, next);
+                    capacity = next;
+                }
+                None => return Ok(listing.map(|c_str| c_str.to_string_lossy().into_owned()).unwrap_or_default()),
+            }
         }
-        Ok(())
     }
 
-    pub fn hide_path(&self, path: &str) -> Result<()> {
-        debug!("HymoFS: HIDE_RULE path='{}'", path);
-        let c_path = CString::new(path)?;
-        
-        let arg = HymoIoctlArg {
-            src: c_path.as_ptr(),
-            target: std::ptr::null(),
-            r#type: 0,
-        };
+    /// Active rules as the kernel lists them.
+    pub fn list_rules(&self) -> HymoResult<Vec<Rule>> {
+        Ok(parse_rules(&self.read_listing()?))
+    }
 
-        let ret = unsafe {
-            libc::ioctl(self.file.as_raw_fd(), HYMO_IOC_HIDE_RULE as c_int, &arg)
-        };
+    /// Active rules in the form they are applied in.
+    pub fn list_ops(&self) -> HymoResult<Vec<RuleOp>> {
+        Ok(self.list_rules()?.iter().filter_map(Rule::op).collect())
+    }
 
-        if ret < 0 {
-            bail!("HymoFS hide_path failed: {}", std::io::Error::last_os_error());
+    /// Checks that injecting `module_dir` over `target_base` took effect:
+    /// each added path must be its module file, by inode or else size and
+    /// content, and each hidden one gone. Returns the rules that are not.
+    pub fn verify(&self, target_base: &Path, module_dir: &Path) -> HymoResult<Vec<Unresolved>> {
+        let ops = collect_directory_ops(target_base, module_dir);
+        let live = if self.caps()?.supports_list { Some(self.list_ops()?) } else { None };
+        let registered: Option<HashSet<&str>> = live.as_ref().map(|live| live.iter().map(RuleOp::path).collect());
+        let mounts = std::fs::read_to_string("/proc/self/mountinfo").map(|info| mount_points(&info)).unwrap_or_default();
+        Ok(ops.iter()
+            .filter_map(|op| {
+                let visible = mntns::visible(op)?;
+                diagnose(op, visible, registered.as_ref(), target_base, &mounts)
+            })
+            .collect())
+    }
+
+    /// Redirects a canary file through the kernel and reads it back; returns
+    /// what was seen when the kernel honoured the rule.
+    pub fn self_test(&self, scratch_dir: &Path) -> Result<String> {
+        let source = scratch_dir.join(CANARY_SOURCE_NAME);
+        let token = format!("meta-hybrid canary {}", std::process::id());
+        std::fs::write(&source, &token)
+            .with_context(|| format!("Failed to write canary source {}", source.display()))?;
+        let source_str = source.to_string_lossy();
+        let result = self.add_rule(CANARY_TARGET, &source_str, HymoFileType::Reg, 0)
+            .map_err(anyhow::Error::from)
+            .and_then(|_| canary_outcome(std::fs::read_to_string(CANARY_TARGET), &token));
+        if let Err(e) = self.delete_rule(CANARY_TARGET) {
+            warn!("Failed to remove canary rule: {}", e);
         }
-        Ok(())
+        let _ = std::fs::remove_file(&source);
+        result
     }
 
-    #[allow(dead_code)]
-    pub fn list_active_rules(&self) -> Result<String> {
-        let capacity = 128 * 1024;
-        let mut buffer = vec![0u8; capacity];
-        let mut arg = HymoIoctlListArg {
-            buf: buffer.as_mut_ptr() as *mut c_char,
-            size: capacity,
+    /// Applies `ops` outside a boot apply, journaling each one so startup
+    /// can settle them after a crash. Returns how many the kernel rejected.
+    pub fn apply_journaled(&self, ops: &[RuleOp]) -> usize {
+        let mut journal = RuleJournal::begin(Path::new(RULE_JOURNAL_DIR), None)
+            .map_err(|e| warn!("Failed to open rule journal: {}", e))
+            .ok();
+        let failed = ops.iter().filter(|op| journal::apply(self, &mut journal, op).is_err()).count();
+        if let Some(Err(e)) = journal.map(RuleJournal::finish) {
+            warn!("Failed to finish rule journal: {}", e);
+        }
+        failed
+    }
+
+    /// Applies `op`, or its downgraded equivalent once its kind turned out
+    /// to be unsupported by the loaded kernel module.
+    pub fn apply_op(&self, op: &RuleOp) -> HymoResult<()> {
+        if !self.kind_unsupported(op) {
+            let result = self.apply_direct(op);
+            if result.is_ok() || !self.kind_unsupported(op) {
+                return result;
+            }
+        }
+        let downgraded = downgrade(op, Path::new(WHITEOUT_NODE))?;
+        debug!("HymoFS[{}]: downgraded {} {} to {:?}", self.device().display(), op.verb(), op.path(), downgraded);
+        self.apply_direct(&downgraded)
+    }
+
+    fn apply_direct(&self, op: &RuleOp) -> HymoResult<()> {
+        match op {
+            RuleOp::Add { src, target, file_type } => self.add_rule(src, target, *file_type, self.priority_of(target)),
+            RuleOp::Delete { src } => self.delete_rule(src),
+            RuleOp::Hide { path } => self.hide_path(path),
+        }
+    }
+
+    fn batch_supported(&self) -> bool {
+        self.inner.batch.load(Ordering::Relaxed) != BATCH_UNSUPPORTED
+            && self.caps().is_ok_and(|caps| caps.supports_batch)
+            && self.abi().ok().and_then(KernelAbi::add_rules_batch).is_some()
+    }
+
+    fn submit_add_batch(&self, ops: &[RuleOp]) -> std::io::Result<()> {
+        let Some(request) = self.abi().ok().and_then(KernelAbi::add_rules_batch) else {
+            return Err(std::io::Error::from_raw_os_error(libc::ENOTTY));
+        };
+        let to_cstring = |s: &str| {
+            CString::new(s).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
+        };
+        let mut strings = Vec::with_capacity(ops.len());
+        for op in ops {
+            if let RuleOp::Add { src, target, file_type } = op {
+                strings.push((to_cstring(src)?, to_cstring(target)?, *file_type));
+            }
+        }
+        let rules = strings.iter()
+            .map(|(src, target, file_type)| HymoIoctlArg {
+                src: src.as_ptr(),
+                target: target.as_ptr(),
+                r#type: file_type.kernel_value(),
+            })
+            .collect::<Vec<_>>();
+        let mut arg = HymoIoctlBatchArg {
+            rules: rules.as_ptr(),
+            count: rules.len() as c_int,
         };
 
-        let ret = unsafe {
-            libc::ioctl(self.file.as_raw_fd(), HYMO_IOC_LIST_RULES as c_int, &mut arg)
+        match self.ioctl(request, &mut arg) {
+            Ok(_) => {
+                self.inner.batch.store(BATCH_SUPPORTED, Ordering::Relaxed);
+                Ok(())
+            }
+            Err(e) => {
+                let unsupported = matches!(e.raw_os_error(), Some(libc::ENOTTY) | Some(libc::EOPNOTSUPP) | Some(libc::EINVAL));
+                if unsupported && self.inner.batch.load(Ordering::Relaxed) == BATCH_UNKNOWN {
+                    debug!("HymoFS[{}]: batch ioctl unsupported ({}), using per-rule calls", self.device().display(), e);
+                    self.inner.batch.store(BATCH_UNSUPPORTED, Ordering::Relaxed);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn apply_each<F: FnMut(usize, bool)>(&self, ops: &[RuleOp], offset: usize, record: &mut F) {
+        for (i, op) in ops.iter().enumerate() {
+            match self.apply_op(op) {
+                Ok(_) => record(offset + i, true),
+                Err(e) if self.kind_unsupported(op) => {
+                    debug!("Failed to {} {}: {}", op.verb(), op.path(), e);
+                    record(offset + i, false);
+                }
+                Err(e) => {
+                    warn!("Failed to {} {}: {}", op.verb(), op.path(), e);
+                    record(offset + i, false);
+                }
+            }
+        }
+    }
+
+    /// Applies `ops` in order, adds in batches where the kernel takes them,
+    /// and returns the number of ioctls issued.
+    fn apply_ops<F: FnMut(usize, bool)>(&self, ops: &[RuleOp], mut record: F) -> usize {
+        let mut ioctls = 0;
+        let mut offset = 0;
+        for run in ops.chunk_by(|a, b| a.same_kind(b)) {
+            if !matches!(run.first(), Some(RuleOp::Add { .. })) {
+                self.apply_each(run, offset, &mut record);
+                ioctls += run.len();
+                offset += run.len();
+                continue;
+            }
+            for chunk in run.chunks(HYMO_BATCH_MAX) {
+                if chunk.len() > 1 && self.batch_supported() && !self.uses_priorities() && !chunk.iter().any(|op| self.kind_unsupported(op)) {
+                    ioctls += 1;
+                    match self.submit_add_batch(chunk) {
+                        Ok(()) => {
+                            (0..chunk.len()).for_each(|i| record(offset + i, true));
+                            offset += chunk.len();
+                            continue;
+                        }
+                        Err(e) if self.batch_supported() => {
+                            debug!("HymoFS[{}]: batch of {} rules failed ({}), retrying per rule", self.device().display(), chunk.len(), e);
+                        }
+                        Err(_) => {}
+                    }
+                }
+                self.apply_each(chunk, offset, &mut record);
+                ioctls += chunk.len();
+                offset += chunk.len();
+            }
+        }
+        ioctls
+    }
+
+    /// Registers `rules` as (src, target, type) in as few ioctls as the
+    /// kernel allows: chunks of up to 128 through the batch ioctl, falling
+    /// back to one call per rule when the module does not support it.
+    pub fn add_rules_batch(&self, rules: &[(&str, &str, HymoFileType)]) -> BatchOutcome {
+        let ops: Vec<RuleOp> = rules.iter()
+            .map(|(src, target, file_type)| RuleOp::Add { src: src.to_string(), target: target.to_string(), file_type: *file_type })
+            .collect();
+        let mut failed = Vec::new();
+        let ioctls = self.apply_ops(&ops, |i, ok| if !ok { failed.push(i) });
+        BatchOutcome { failed, ioctls }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum RuleOp {
+    Add { src: String, target: String, file_type: HymoFileType },
+    Delete { src: String },
+    Hide { path: String },
+}
+
+impl RuleOp {
+    pub fn path(&self) -> &str {
+        match self {
+            RuleOp::Add { src, .. } | RuleOp::Delete { src } => src,
+            RuleOp::Hide { path } => path,
+        }
+    }
+
+    pub fn verb(&self) -> &'static str {
+        match self {
+            RuleOp::Add { .. } => "add rule for",
+            RuleOp::Delete { .. } => "delete rule for",
+            RuleOp::Hide { .. } => "hide path",
+        }
+    }
+
+    fn kind_bit(&self) -> Option<u32> {
+        match self {
+            RuleOp::Add { file_type, .. } => Some(file_type.kind_bit()),
+            RuleOp::Hide { .. } => Some(HIDE_KIND_BIT),
+            RuleOp::Delete { .. } => None,
+        }
+    }
+
+    fn same_kind(&self, other: &RuleOp) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+
+    fn into_delete(self) -> RuleOp {
+        match self {
+            RuleOp::Add { src, .. } | RuleOp::Delete { src } => RuleOp::Delete { src },
+            RuleOp::Hide { path } => RuleOp::Delete { src: path },
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FlushStats {
+    pub applied: usize,
+    pub failed: usize,
+}
+
+impl std::ops::AddAssign for FlushStats {
+    fn add_assign(&mut self, other: Self) {
+        self.applied += other.applied;
+        self.failed += other.failed;
+    }
+}
+
+/// Whether a failed rule ioctl means the kernel lacks that rule kind: it
+/// says so with EOPNOTSUPP, or rejects a kind it never accepted with EINVAL.
+fn marks_unsupported(errno: Option<i32>, ever_accepted: bool) -> bool {
+    match errno {
+        Some(libc::EOPNOTSUPP) => true,
+        Some(libc::EINVAL) => !ever_accepted,
+        _ => false,
+    }
+}
+
+/// The equivalent of `op` in a kind more kernels take: a symlink becomes a
+/// redirect to the file it resolves to, and a hide a whiteout redirect to a
+/// 0:0 character device at `whiteout`.
+fn downgrade(op: &RuleOp, whiteout: &Path) -> HymoResult<RuleOp> {
+    match op {
+        RuleOp::Add { src, target, file_type: HymoFileType::Lnk } => {
+            let resolved = std::fs::canonicalize(target).map_err(|_| HymoError::DanglingSymlink(target.clone()))?;
+            if !resolved.is_file() {
+                return Err(HymoError::DanglingSymlink(target.clone()));
+            }
+            Ok(RuleOp::Add { src: src.clone(), target: resolved.to_string_lossy().into_owned(), file_type: HymoFileType::Reg })
+        }
+        RuleOp::Hide { path } => {
+            ensure_whiteout(whiteout)?;
+            Ok(RuleOp::Add { src: path.clone(), target: whiteout.to_string_lossy().into_owned(), file_type: HymoFileType::Wht })
+        }
+        _ => Err(HymoError::Unsupported(format!("cannot {} {} and this rule kind has no fallback", op.verb(), op.path()))),
+    }
+}
+
+fn ensure_whiteout(path: &Path) -> HymoResult<()> {
+    if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_char_device()) {
+        return Ok(());
+    }
+    let failed = |source| HymoError::Whiteout { path: path.to_path_buf(), source };
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(failed)?;
+    }
+    let c_node = c_path(&path.to_string_lossy())?;
+    if unsafe { libc::mknod(c_node.as_ptr(), libc::S_IFCHR | 0o600, 0) } != 0 {
+        return Err(failed(std::io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+fn c_path(path: &str) -> HymoResult<CString> {
+    CString::new(path).map_err(|_| HymoError::InvalidPath(path.to_string()))
+}
+
+/// Allocates without aborting the process when memory is short, so a
+/// listing during an early-boot OOM fails like any other ioctl error.
+fn zeroed_buffer(len: usize) -> HymoResult<Vec<u8>> {
+    let mut buffer = Vec::new();
+    if buffer.try_reserve_exact(len).is_err() {
+        return Err(HymoError::OutOfMemory(len));
+    }
+    buffer.resize(len, 0);
+    Ok(buffer)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleKind {
+    Add,
+    Hide,
+}
+
+/// One line of the kernel's rule listing.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rule {
+    pub kind: RuleKind,
+    pub src: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_type: Option<HymoFileType>,
+    /// The `prio=<n>` field of kernels with rule priorities.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>,
+    /// Fields after the ones this build knows, e.g. flags a newer kernel
+    /// appends to its lines.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub flags: Vec<String>,
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split_whitespace();
+        let (kind, src) = (fields.next()?, fields.next()?.to_string());
+        let (kind, target, file_type) = match kind {
+            "add" => {
+                let target = fields.next()?.to_string();
+                let file_type = fields.next()
+                    .and_then(|t| t.parse::<i32>().ok())
+                    .and_then(|t| HymoFileType::try_from(t).ok())
+                    .unwrap_or(HymoFileType::Unknown);
+                (RuleKind::Add, Some(target), Some(file_type))
+            }
+            "hide" => (RuleKind::Hide, None, None),
+            _ => return None,
         };
+        let mut priority = None;
+        let flags = fields
+            .filter(|field| match field.strip_prefix("prio=").and_then(|p| p.parse().ok()) {
+                Some(p) => {
+                    priority = Some(p);
+                    false
+                }
+                None => true,
+            })
+            .map(str::to_string)
+            .collect();
+        Some(Self { kind, src, target, file_type, priority, flags })
+    }
 
-        if ret < 0 {
-            bail!("HymoFS list_rules failed: {}", std::io::Error::last_os_error());
+    /// The op that puts this rule back.
+    pub fn op(&self) -> Option<RuleOp> {
+        match self.kind {
+            RuleKind::Add => Some(RuleOp::Add {
+                src: self.src.clone(),
+                target: self.target.clone()?,
+                file_type: self.file_type.unwrap_or(HymoFileType::Unknown),
+            }),
+            RuleKind::Hide => Some(RuleOp::Hide { path: self.src.clone() }),
         }
+    }
+}
 
-        let c_str = unsafe { CStr::from_ptr(buffer.as_ptr() as *const c_char) };
-        Ok(c_str.to_string_lossy().into_owned())
+/// Parses the kernel's rule listing: one `add <src> <target> <type>` or
+/// `hide <path>` entry per line, optionally followed by flags. Unknown
+/// lines are skipped.
+pub fn parse_rules(listing: &str) -> Vec<Rule> {
+    listing.lines().filter_map(Rule::parse).collect()
+}
+
+/// Buffer size to list the rules again with, or `None` when `filled` bytes
+/// and their terminator fit in `capacity`. `reported` is the size field as
+/// the kernel left it, which it raises to what it needs when it supports
+/// the size query; otherwise the buffer doubles.
+fn next_list_capacity(capacity: usize, reported: usize, filled: usize) -> HymoResult<Option<usize>> {
+    if reported <= capacity && filled + 1 < capacity {
+        return Ok(None);
     }
+    let wanted = if reported > capacity { reported.saturating_add(1) } else { capacity.saturating_mul(2) };
+    if wanted > MAX_LIST_BUF_LEN {
+        return Err(HymoError::Truncated { limit: MAX_LIST_BUF_LEN });
+    }
+    Ok(Some(wanted))
 }
 
-pub struct HymoFs;
+struct DirStream(*mut libc::DIR);
 
-impl HymoFs {
-    pub fn is_available() -> bool {
-        HymoController::check_status() == HymoFsStatus::Available
+impl DirStream {
+    fn open_at(parent: c_int, name: &CStr) -> std::io::Result<Self> {
+        let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC | libc::O_NOFOLLOW;
+        let fd = unsafe { libc::openat(parent, name.as_ptr(), flags) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let dir = unsafe { libc::fdopendir(fd) };
+        if dir.is_null() {
+            let err = std::io::Error::last_os_error();
+            unsafe { libc::close(fd) };
+            return Err(err);
+        }
+        Ok(Self(dir))
     }
 
-    pub fn check_status() -> HymoFsStatus {
-        HymoController::check_status()
+    fn fd(&self) -> c_int {
+        unsafe { libc::dirfd(self.0) }
     }
 
-    pub fn get_version() -> Option<i32> {
-        HymoController::new().and_then(|ctl| ctl.get_version()).ok()
+    fn next_entry(&mut self) -> Option<&libc::dirent> {
+        let entry = unsafe { libc::readdir(self.0) };
+        unsafe { entry.as_ref() }
     }
+}
 
-    pub fn clear() -> Result<()> {
-        HymoController::new()?.clear()
+impl Drop for DirStream {
+    fn drop(&mut self) {
+        unsafe { libc::closedir(self.0) };
     }
+}
+
+fn stat_at(dir: c_int, name: &CStr) -> Option<libc::stat> {
+    let mut st = std::mem::MaybeUninit::<libc::stat>::uninit();
+    let ret = unsafe { libc::fstatat(dir, name.as_ptr(), st.as_mut_ptr(), libc::AT_SYMLINK_NOFOLLOW) };
+    (ret == 0).then(|| unsafe { st.assume_init() })
+}
 
-    pub fn set_debug(enable: bool) -> Result<()> {
-        HymoController::new()?.set_debug(enable)
+fn exists_at(dir: c_int, name: &str) -> bool {
+    CString::new(name).is_ok_and(|name| unsafe { libc::faccessat(dir, name.as_ptr(), libc::F_OK, 0) } == 0)
+}
+
+fn d_type_from_mode(mode: libc::mode_t) -> u8 {
+    match mode & libc::S_IFMT {
+        libc::S_IFDIR => libc::DT_DIR,
+        libc::S_IFREG => libc::DT_REG,
+        libc::S_IFLNK => libc::DT_LNK,
+        libc::S_IFCHR => libc::DT_CHR,
+        _ => libc::DT_UNKNOWN,
     }
+}
 
-    #[allow(dead_code)]
-    pub fn add_rule(src: &str, target: &str, type_val: i32) -> Result<()> {
-        HymoController::new()?.add_rule(src, target, HymoFileType::from(type_val))
+fn push_component(buf: &mut Vec<u8>, name: &[u8]) {
+    if buf.last() != Some(&b'/') {
+        buf.push(b'/');
     }
+    buf.extend_from_slice(name);
+}
 
-    #[allow(dead_code)]
-    pub fn delete_rule(src: &str) -> Result<()> {
-        HymoController::new()?.delete_rule(src)
+fn lossy(buf: &[u8]) -> String {
+    String::from_utf8_lossy(buf).into_owned()
+}
+
+/// Walks `module_dir` depth-first, each directory reusing one byte buffer
+/// per side, so the only per-entry allocations are the strings of the
+/// emitted rules. Subdirectories are walked in parallel; the rules still
+/// come out in the order of a sequential walk. Rules that would shadow a
+/// protected path are dropped.
+pub fn collect_directory_ops(target_base: &Path, module_dir: &Path) -> Vec<RuleOp> {
+    let mut ops = collect_directory_ops_with(arch::filter(), target_base, module_dir);
+    for path in protect::protected().retain(&mut ops) {
+        warn!("HymoFS: {} is protected, not shadowing it for {}", path, module_dir.display());
     }
+    ops
+}
+
+fn collect_directory_ops_with(filter: &arch::ArchFilter, target_base: &Path, module_dir: &Path) -> Vec<RuleOp> {
+    walk_directory(filter, target_base, module_dir).ops
+}
+
+/// What a walk found below one directory, in sequential walk order.
+#[derive(Default)]
+struct Walked {
+    ops: Vec<RuleOp>,
+    /// Every directory entered, as an [`PlannedOp::InjectDir`].
+    dirs: Vec<PlannedOp>,
+}
 
-    pub fn hide_path(path: &str) -> Result<()> {
-        HymoController::new()?.hide_path(path)
+/// A subdirectory met during a walk, opened and walked on its own task.
+struct Subdir {
+    name: CString,
+    source: Vec<u8>,
+    target: Vec<u8>,
+    is_lib: bool,
+    opaque: bool,
+}
+
+/// An entry's share of its directory's result: its rules, or the place a
+/// subdirectory's walk goes.
+enum Piece {
+    Op(RuleOp),
+    Subdir,
+}
+
+/// The walk behind [`collect_directory_ops`], also reporting the
+/// directories it enters below the root. Its symlink rules are rewritten by
+/// the configured [`symlinks::SymlinkPolicy`].
+fn walk_directory(filter: &arch::ArchFilter, target_base: &Path, module_dir: &Path) -> Walked {
+    let Ok(root) = CString::new(module_dir.as_os_str().as_bytes()) else {
+        return Walked::default();
+    };
+    let mut walked = match DirStream::open_at(libc::AT_FDCWD, &root) {
+        Ok(dir) => walk_dir(filter, dir, module_dir.as_os_str().as_bytes().to_vec(), target_base.as_os_str().as_bytes().to_vec(), false, false),
+        Err(e) => {
+            if module_dir.exists() {
+                warn!("HymoFS walk error: {}: {}", module_dir.display(), e);
+            }
+            return Walked::default();
+        }
+    };
+    let rewritten = symlinks::rewrite(&mut walked.ops, symlinks::policy());
+    if rewritten > 0 {
+        debug!("HymoFS: {} symlinks of {} redirected to their files", rewritten, module_dir.display());
     }
+    walked
+}
+
+fn walk_dir(filter: &arch::ArchFilter, mut dir: DirStream, mut source: Vec<u8>, mut target: Vec<u8>, is_lib: bool, opaque: bool) -> Walked {
+    let parent_fd = dir.fd();
+    let (source_len, target_len) = (source.len(), target.len());
+    let mut visible = Vec::new();
+    let mut pieces = Vec::new();
+    let mut subdirs = Vec::new();
+    let mut dirs = Vec::new();
+    while let Some(entry) = dir.next_entry() {
+        let name = unsafe { CStr::from_ptr(entry.d_name.as_ptr()) };
+        let bytes = name.to_bytes();
+        if bytes == b"." || bytes == b".." || modules::is_marker(bytes) {
+            continue;
+        }
+        if let Some(hidden) = modules::whiteout_target(bytes) {
+            target.truncate(target_len);
+            push_component(&mut target, hidden);
+            pieces.push(Piece::Op(RuleOp::Hide { path: lossy(&target) }));
+            if opaque {
+                visible.push(hidden.to_vec());
+            }
+            continue;
+        }
+        let mut d_type = entry.d_type;
+        if d_type == libc::DT_UNKNOWN {
+            d_type = stat_at(parent_fd, name).map_or(libc::DT_UNKNOWN, |st| d_type_from_mode(st.st_mode));
+        }
+
+        let mut mapped = bytes;
+        if is_lib {
+            let Ok(text) = std::str::from_utf8(bytes) else { continue };
+            match filter.resolve(text, |isa| exists_at(parent_fd, isa)) {
+                arch::ArchDecision::Skip => continue,
+                arch::ArchDecision::Rename(isa) => mapped = isa.as_bytes(),
+                arch::ArchDecision::Keep => {}
+            }
+        }
+        if opaque {
+            visible.push(mapped.to_vec());
+        }
+        source.truncate(source_len);
+        target.truncate(target_len);
+        push_component(&mut source, bytes);
+        push_component(&mut target, mapped);
 
-    #[allow(dead_code)]
-    pub fn list_active_rules() -> Result<String> {
-        HymoController::new()?.list_active_rules()
+        match d_type {
+            libc::DT_DIR => {
+                let opaque = modules::is_opaque_dir(Path::new(OsStr::from_bytes(&source)));
+                dirs.push(PlannedOp::InjectDir { path: lossy(&target), source: lossy(&source), opaque });
+                subdirs.push(Subdir { name: name.to_owned(), source: source.clone(), target: target.clone(), is_lib: bytes == b"lib", opaque });
+                pieces.push(Piece::Subdir);
+            }
+            libc::DT_REG | libc::DT_LNK => pieces.push(Piece::Op(RuleOp::Add {
+                src: lossy(&target),
+                target: lossy(&source),
+                file_type: if d_type == libc::DT_REG { HymoFileType::Reg } else { HymoFileType::Lnk },
+            })),
+            libc::DT_CHR if stat_at(parent_fd, name).is_some_and(|st| st.st_rdev == 0) => {
+                pieces.push(Piece::Op(RuleOp::Hide { path: lossy(&target) }));
+            }
+            _ => {}
+        }
     }
 
-    pub fn inject_directory(target_base: &Path, module_dir: &Path) -> Result<()> {
-        if !module_dir.exists() || !module_dir.is_dir() {
-            return Ok(());
+    let mut walked: Vec<Walked> = subdirs.into_par_iter()
+        .map(|sub| match DirStream::open_at(parent_fd, &sub.name) {
+            Ok(dir) => walk_dir(filter, dir, sub.source, sub.target, sub.is_lib, sub.opaque),
+            Err(e) => {
+                warn!("HymoFS walk error: {}: {}", String::from_utf8_lossy(&sub.source), e);
+                Walked::default()
+            }
+        })
+        .collect();
+    let mut result = Walked { ops: Vec::with_capacity(pieces.len()), dirs };
+    let mut subtrees = walked.iter_mut();
+    for piece in pieces {
+        match piece {
+            Piece::Op(op) => result.ops.push(op),
+            Piece::Subdir => {
+                if let Some(subtree) = subtrees.next() {
+                    result.ops.append(&mut subtree.ops);
+                    result.dirs.append(&mut subtree.dirs);
+                }
+            }
         }
+    }
+    if opaque {
+        target.truncate(target_len);
+        hide_replaced(&mut result.ops, &mut target, &visible);
+    }
+    result
+}
 
-        let ctl = HymoController::new()?;
+/// How many rules [`collect_directory_ops`] registers for `module_dir`,
+/// counted in one sequential walk that builds none of them. Protected paths
+/// are counted too, so the count is an upper bound.
+pub fn count_directory_ops(target_base: &Path, module_dir: &Path) -> usize {
+    count_directory_ops_with(arch::filter(), target_base, module_dir)
+}
 
-        for entry in WalkDir::new(module_dir).min_depth(1) {
-            let entry = match entry {
-                Ok(e) => e,
-                Err(e) => {
-                    warn!("HymoFS walk error: {}", e);
-                    continue;
+fn count_directory_ops_with(filter: &arch::ArchFilter, target_base: &Path, module_dir: &Path) -> usize {
+    let Ok(root) = CString::new(module_dir.as_os_str().as_bytes()) else { return 0 };
+    match DirStream::open_at(libc::AT_FDCWD, &root) {
+        Ok(dir) => count_dir(filter, dir, &mut module_dir.as_os_str().as_bytes().to_vec(), &mut target_base.as_os_str().as_bytes().to_vec(), false, false),
+        Err(_) => 0,
+    }
+}
+
+fn count_dir(filter: &arch::ArchFilter, mut dir: DirStream, source: &mut Vec<u8>, target: &mut Vec<u8>, is_lib: bool, opaque: bool) -> usize {
+    let parent_fd = dir.fd();
+    let (source_len, target_len) = (source.len(), target.len());
+    let mut visible = Vec::new();
+    let mut count = 0;
+    while let Some(entry) = dir.next_entry() {
+        let name = unsafe { CStr::from_ptr(entry.d_name.as_ptr()) };
+        let bytes = name.to_bytes();
+        if bytes == b"." || bytes == b".." || modules::is_marker(bytes) {
+            continue;
+        }
+        if let Some(hidden) = modules::whiteout_target(bytes) {
+            count += 1;
+            if opaque {
+                visible.push(hidden.to_vec());
+            }
+            continue;
+        }
+        let mut d_type = entry.d_type;
+        if d_type == libc::DT_UNKNOWN {
+            d_type = stat_at(parent_fd, name).map_or(libc::DT_UNKNOWN, |st| d_type_from_mode(st.st_mode));
+        }
+        let mut mapped = bytes;
+        if is_lib {
+            let Ok(text) = std::str::from_utf8(bytes) else { continue };
+            match filter.resolve(text, |isa| exists_at(parent_fd, isa)) {
+                arch::ArchDecision::Skip => continue,
+                arch::ArchDecision::Rename(isa) => mapped = isa.as_bytes(),
+                arch::ArchDecision::Keep => {}
+            }
+        }
+        if opaque {
+            visible.push(mapped.to_vec());
+        }
+        match d_type {
+            libc::DT_DIR => {
+                source.truncate(source_len);
+                target.truncate(target_len);
+                push_component(source, bytes);
+                push_component(target, mapped);
+                let sub_opaque = modules::is_opaque_dir(Path::new(OsStr::from_bytes(source)));
+                if let Ok(sub) = DirStream::open_at(parent_fd, name) {
+                    count += count_dir(filter, sub, source, target, bytes == b"lib", sub_opaque);
                 }
-            };
+            }
+            libc::DT_REG | libc::DT_LNK => count += 1,
+            libc::DT_CHR if stat_at(parent_fd, name).is_some_and(|st| st.st_rdev == 0) => count += 1,
+            _ => {}
+        }
+    }
+    source.truncate(source_len);
+    target.truncate(target_len);
+    if opaque {
+        if let Ok(entries) = std::fs::read_dir(OsStr::from_bytes(target)) {
+            count += entries.filter_map(|entry| entry.ok()).filter(|entry| !visible.contains(&entry.file_name().into_vec())).count();
+        }
+    }
+    count
+}
 
-            let current_path = entry.path();
-            let relative_path = match current_path.strip_prefix(module_dir) {
-                Ok(p) => p,
-                Err(_) => continue,
-            };
-            let target_path = target_base.join(relative_path);
-            let file_type = entry.file_type();
-
-            if file_type.is_file() || file_type.is_symlink() {
-                if let Err(e) = ctl.add_rule(
-                    &target_path.to_string_lossy(),
-                    &current_path.to_string_lossy(),
-                    HymoFileType::from(file_type)
-                ) {
-                    warn!("Failed to add rule for {}: {}", target_path.display(), e);
+/// The self-test verdict on reading `seen` back through the canary rule.
+fn canary_outcome(seen: std::io::Result<String>, token: &str) -> Result<String> {
+    let seen = seen.with_context(|| format!("Canary {} is not visible through the VFS", CANARY_TARGET))?;
+    if seen != token {
+        bail!("Canary {} resolved to unexpected content", CANARY_TARGET);
+    }
+    Ok("canary rule resolved through the VFS".to_string())
+}
+
+/// One step of injecting a module directory.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum PlannedOp {
+    /// A module directory merged into the target one, or replacing it when
+    /// `opaque`.
+    InjectDir { path: String, source: String, opaque: bool },
+    Add { path: String, source: String, file_type: HymoFileType },
+    Hide { path: String },
+}
+
+impl PlannedOp {
+    pub fn path(&self) -> &str {
+        match self {
+            PlannedOp::InjectDir { path, .. } | PlannedOp::Add { path, .. } | PlannedOp::Hide { path } => path,
+        }
+    }
+}
+
+/// A planned rule whose path already carries a different live rule.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Conflict {
+    pub planned: RuleOp,
+    pub live: RuleOp,
+    /// Who the live rule belongs to, as far as the ledger knows.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+}
+
+/// What injecting a directory would do, worked out without the kernel.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct InjectPlan {
+    /// In path order, so each directory comes before its contents.
+    pub steps: Vec<PlannedOp>,
+    /// The rules that would be registered.
+    pub rules: Vec<RuleOp>,
+    pub conflicts: Vec<Conflict>,
+    /// Paths left alone because they are protected.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub refused: Vec<String>,
+}
+
+fn owner_of(ledger: &RuleLedger, path: &str) -> Option<String> {
+    ledger.owned.owner(path).map(str::to_string)
+        .or_else(|| ledger.namespace_of(path).map(|namespace| format!("namespace {}", namespace)))
+        .or_else(|| ledger.is_external(path).then(|| EXTERNAL_GROUP.to_string()))
+}
+
+/// Previews injecting `module_dir` over `target_base`: the directories it
+/// merges, the rules it adds and hides, and where those collide with the
+/// `live` rule table.
+pub fn inject_directory_plan(target_base: &Path, module_dir: &Path, live: &[RuleOp], ledger: &RuleLedger) -> InjectPlan {
+    inject_directory_plan_with(arch::filter(), protect::protected(), target_base, module_dir, live, ledger)
+}
+
+fn inject_directory_plan_with(filter: &arch::ArchFilter, protected: &ProtectedPaths, target_base: &Path, module_dir: &Path, live: &[RuleOp], ledger: &RuleLedger) -> InjectPlan {
+    let mut steps = vec![PlannedOp::InjectDir {
+        path: target_base.to_string_lossy().into_owned(),
+        source: module_dir.to_string_lossy().into_owned(),
+        opaque: false,
+    }];
+    let Walked { ops: mut rules, dirs } = walk_directory(filter, target_base, module_dir);
+    let refused = protected.retain(&mut rules);
+    steps.extend(dirs);
+    steps.extend(rules.iter().filter_map(|rule| match rule {
+        RuleOp::Add { src, target, file_type } => Some(PlannedOp::Add { path: src.clone(), source: target.clone(), file_type: *file_type }),
+        RuleOp::Hide { path } => Some(PlannedOp::Hide { path: path.clone() }),
+        RuleOp::Delete { .. } => None,
+    }));
+    steps.sort_by(|a, b| a.path().cmp(b.path()));
+    let live: HashMap<&str, &RuleOp> = live.iter().map(|op| (op.path(), op)).collect();
+    let conflicts = rules.iter()
+        .filter_map(|planned| {
+            let existing = *live.get(planned.path())?;
+            (existing != planned).then(|| Conflict { planned: planned.clone(), live: existing.clone(), owner: owner_of(ledger, planned.path()) })
+        })
+        .collect();
+    InjectPlan { steps, rules, conflicts, refused }
+}
+
+/// Why a rule shows no effect at its path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Miss {
+    /// The kernel table lacks it, though it was applied.
+    NotRegistered,
+    /// A mount below the injection target covers the path.
+    Shadowed,
+    /// The added path does not exist.
+    Missing,
+    /// The added path is some other file.
+    Differs,
+    /// The hidden path still exists.
+    StillVisible,
+}
+
+/// A rule of an injection whose redirect is not effective.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Unresolved {
+    pub op: RuleOp,
+    pub miss: Miss,
+    /// The mount point covering the path, for [`Miss::Shadowed`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mount: Option<String>,
+}
+
+fn mount_points(mountinfo: &str) -> Vec<PathBuf> {
+    mountinfo.lines()
+        .filter_map(|line| line.split_whitespace().nth(4))
+        .map(|point| PathBuf::from(point.replace("\\040", " ")))
+        .collect()
+}
+
+/// Sorts out why `op` is not `visible`, if it is not; `registered` holds the
+/// paths of the live rules where the kernel can list them.
+fn diagnose(op: &RuleOp, visible: bool, registered: Option<&HashSet<&str>>, target_base: &Path, mounts: &[PathBuf]) -> Option<Unresolved> {
+    if visible {
+        return None;
+    }
+    let path = Path::new(op.path());
+    let unresolved = |miss, mount: Option<&PathBuf>| Some(Unresolved { op: op.clone(), miss, mount: mount.map(|point| point.to_string_lossy().into_owned()) });
+    if registered.is_some_and(|registered| !registered.contains(op.path())) {
+        return unresolved(Miss::NotRegistered, None);
+    }
+    let shadow = mounts.iter()
+        .filter(|point| point.starts_with(target_base) && point.as_path() != target_base && path.starts_with(point))
+        .max_by_key(|point| point.components().count());
+    if shadow.is_some() {
+        return unresolved(Miss::Shadowed, shadow);
+    }
+    match op {
+        RuleOp::Hide { .. } => unresolved(Miss::StillVisible, None),
+        _ if std::fs::symlink_metadata(path).is_err() => unresolved(Miss::Missing, None),
+        _ => unresolved(Miss::Differs, None),
+    }
+}
+
+/// Hides every entry of the directory at `target` that is not in `visible`,
+/// so an opaque module directory replaces it instead of merging into it.
+fn hide_replaced(ops: &mut Vec<RuleOp>, target: &mut Vec<u8>, visible: &[Vec<u8>]) {
+    let len = target.len();
+    let Ok(entries) = std::fs::read_dir(OsStr::from_bytes(target)) else { return };
+    let mut hidden: Vec<Vec<u8>> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().into_vec())
+        .filter(|name| !visible.contains(name))
+        .collect();
+    hidden.sort();
+    for name in hidden {
+        target.truncate(len);
+        push_component(target, &name);
+        ops.push(RuleOp::Hide { path: lossy(target) });
+    }
+    target.truncate(len);
+}
+
+const SHARED_FLUSH_THRESHOLD: usize = 256;
+
+struct Pending {
+    tag: Arc<str>,
+    op: RuleOp,
+}
+
+#[derive(Clone)]
+pub struct SharedHymoFs {
+    handle: HymoFs,
+    pending: Arc<Mutex<Vec<Pending>>>,
+    flush_lock: Arc<Mutex<()>>,
+    stats: Arc<Mutex<HashMap<String, FlushStats>>>,
+    flush_threshold: usize,
+}
+
+impl SharedHymoFs {
+    pub fn new(handle: HymoFs) -> Self {
+        Self {
+            handle,
+            pending: Arc::new(Mutex::new(Vec::new())),
+            flush_lock: Arc::new(Mutex::new(())),
+            stats: Arc::new(Mutex::new(HashMap::new())),
+            flush_threshold: SHARED_FLUSH_THRESHOLD,
+        }
+    }
+
+    /// Flushes once this many operations are queued instead of the default.
+    pub fn with_flush_threshold(mut self, threshold: usize) -> Self {
+        self.flush_threshold = threshold.max(1);
+        self
+    }
+
+    /// Queues the removal of `rules`, e.g. a module's directory rules.
+    pub fn delete_rules(&self, tag: &str, rules: Vec<RuleOp>) {
+        self.enqueue(tag, rules.into_iter().map(RuleOp::into_delete).collect());
+    }
+
+    pub fn enqueue(&self, tag: &str, ops: Vec<RuleOp>) {
+        let tag: Arc<str> = Arc::from(tag);
+        let full = {
+            let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+            for op in ops {
+                push_coalesced(&mut pending, &tag, op);
+            }
+            pending.len() >= self.flush_threshold
+        };
+        if full {
+            self.flush();
+        }
+    }
+
+    pub fn flush(&self) {
+        let _order = self.flush_lock.lock().unwrap_or_else(PoisonError::into_inner);
+        let queued = std::mem::take(&mut *self.pending.lock().unwrap_or_else(PoisonError::into_inner));
+        if queued.is_empty() {
+            return;
+        }
+        let (tags, ops): (Vec<_>, Vec<_>) = queued.into_iter().map(|p| (p.tag, p.op)).unzip();
+        let mut flushed: HashMap<String, FlushStats> = HashMap::new();
+        let ioctls = self.handle.apply_ops(&ops, |i, ok| {
+            let Some(tag) = tags.get(i) else { return };
+            let entry = flushed.entry(tag.to_string()).or_default();
+            if ok {
+                entry.applied += 1;
+            } else {
+                entry.failed += 1;
+            }
+        });
+        debug!("HymoFS[{}]: flushed {} queued ops ({} ioctls)", self.handle.device().display(), ops.len(), ioctls);
+        let mut stats = self.stats.lock().unwrap_or_else(PoisonError::into_inner);
+        for (tag, flushed) in flushed {
+            *stats.entry(tag).or_default() += flushed;
+        }
+    }
+
+    pub fn take_stats(&self) -> HashMap<String, FlushStats> {
+        std::mem::take(&mut *self.stats.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+fn push_coalesced(pending: &mut Vec<Pending>, tag: &Arc<str>, op: RuleOp) {
+    if let Some(last) = pending.last() {
+        if last.op == op {
+            return;
+        }
+        if let RuleOp::Add { src: queued, .. } = &last.op {
+            match &op {
+                RuleOp::Delete { src } if src == queued => {
+                    pending.pop();
+                    return;
                 }
-            } else if file_type.is_char_device() {
-                if let Ok(metadata) = entry.metadata() {
-                    if metadata.rdev() == 0 {
-                        if let Err(e) = ctl.hide_path(&target_path.to_string_lossy()) {
-                            warn!("Failed to hide path {}: {}", target_path.display(), e);
-                        }
-                    }
+                RuleOp::Add { src, .. } if src == queued => {
+                    pending.pop();
                 }
+                _ => {}
             }
         }
-        
-        Ok(())
+    }
+    pending.push(Pending { tag: tag.clone(), op });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add(src: &str, target: &str) -> RuleOp {
+        RuleOp::Add { src: src.into(), target: target.into(), file_type: HymoFileType::Reg }
     }
 
-    #[allow(dead_code)]
-    pub fn delete_directory_rules(target_base: &Path, module_dir: &Path) -> Result<()> {
-        if !module_dir.exists() || !module_dir.is_dir() {
-            return Ok(());
+    fn del(src: &str) -> RuleOp {
+        RuleOp::Delete { src: src.into() }
+    }
+
+    fn coalesce(ops: Vec<RuleOp>) -> Vec<RuleOp> {
+        let tag: Arc<str> = Arc::from("test");
+        let mut pending = Vec::new();
+        for op in ops {
+            push_coalesced(&mut pending, &tag, op);
         }
+        pending.into_iter().map(|p| p.op).collect()
+    }
 
-        let ctl = HymoController::new()?;
+    #[test]
+    fn drops_adjacent_duplicates() {
+        assert_eq!(coalesce(vec![add("/a", "/m/a"), add("/a", "/m/a")]), vec![add("/a", "/m/a")]);
+        assert_eq!(coalesce(vec![del("/a"), del("/a")]), vec![del("/a")]);
+    }
 
-        for entry in WalkDir::new(module_dir).min_depth(1) {
-            let entry = match entry {
-                Ok(e) => e,
-                Err(e) => {
-                    warn!("HymoFS walk error: {}", e);
-                    continue;
-                }
-            };
+    #[test]
+    fn delete_cancels_queued_add() {
+        assert!(coalesce(vec![add("/a", "/m/a"), del("/a")]).is_empty());
+        assert_eq!(coalesce(vec![add("/a", "/m/a"), del("/b")]), vec![add("/a", "/m/a"), del("/b")]);
+    }
 
-            let current_path = entry.path();
-            let relative_path = match current_path.strip_prefix(module_dir) {
-                Ok(p) => p,
-                Err(_) => continue,
-            };
-            let target_path = target_base.join(relative_path);
-            let file_type = entry.file_type();
+    #[test]
+    fn delete_add_delete_keeps_single_delete() {
+        assert_eq!(coalesce(vec![del("/a"), add("/a", "/m/a"), del("/a")]), vec![del("/a")]);
+    }
+
+    #[test]
+    fn later_add_supersedes_queued_add() {
+        assert_eq!(coalesce(vec![add("/a", "/m1/a"), add("/a", "/m2/a")]), vec![add("/a", "/m2/a")]);
+    }
+
+    fn scratch_tree(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("hymofs-walk-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        for dir in ["bin", "app/Foo/lib/arm64-v8a", "app/Foo/lib/x86_64", "app/Bar/lib/arm64", "app/Bar/lib/arm64-v8a"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in ["bin/tool", "app/Foo/lib/arm64-v8a/libfoo.so", "app/Foo/lib/x86_64/libfoo.so",
+            "app/Bar/lib/arm64/libbar.so", "app/Bar/lib/arm64-v8a/libbar.so"] {
+            std::fs::write(root.join(file), b"x").unwrap();
+        }
+        std::os::unix::fs::symlink("tool", root.join("bin/alias")).unwrap();
+        root
+    }
+
+    fn sorted(mut ops: Vec<RuleOp>) -> Vec<RuleOp> {
+        ops.sort_by(|a, b| a.path().cmp(b.path()));
+        ops
+    }
+
+    #[test]
+    fn canary_passes_only_on_its_own_content() {
+        assert_eq!(canary_outcome(Ok("token".into()), "token").unwrap(), "canary rule resolved through the VFS");
+        let stale = canary_outcome(Ok("stock".into()), "token").unwrap_err();
+        assert!(format!("{:#}", stale).contains("unexpected content"), "{:#}", stale);
+        let missing = canary_outcome(Err(std::io::ErrorKind::NotFound.into()), "token").unwrap_err();
+        assert!(format!("{:#}", missing).contains("not visible through the VFS"), "{:#}", missing);
+    }
+
+    #[test]
+    fn counts_the_rules_a_walk_builds() {
+        let root = scratch_tree("count");
+        std::fs::write(root.join("bin/.wh.old"), b"").unwrap();
+        let filter = arch::ArchFilter::default();
+        let ops = collect_directory_ops_with(&filter, Path::new("/system"), &root);
+        assert_eq!(count_directory_ops_with(&filter, Path::new("/system"), &root), ops.len());
+        assert_eq!(count_directory_ops_with(&filter, Path::new("/system"), &root.join("missing")), 0);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn walks_module_tree_into_rules() {
+        let root = scratch_tree("plain");
+        let ops = sorted(collect_directory_ops_with(&arch::ArchFilter::default(), Path::new("/system"), &root));
+        let src = |rel: &str| root.join(rel).to_string_lossy().into_owned();
+        assert_eq!(ops.len(), 6);
+        assert_eq!(ops[3], add("/system/app/Foo/lib/x86_64/libfoo.so", &src("app/Foo/lib/x86_64/libfoo.so")));
+        assert_eq!(ops[4], RuleOp::Add {
+            src: "/system/bin/alias".into(),
+            target: src("bin/alias"),
+            file_type: HymoFileType::Lnk,
+        });
+        std::fs::remove_dir_all(&root).unwrap();
+    }
 
-            if file_type.is_file() || file_type.is_symlink() {
-                if let Err(e) = ctl.delete_rule(&target_path.to_string_lossy()) {
-                    warn!("Failed to delete rule for {}: {}", target_path.display(), e);
+    #[test]
+    fn walk_applies_arch_filter_like_map_relative() {
+        let root = scratch_tree("arch");
+        let filter = arch::ArchFilter::from_abilist("arm64-v8a,armeabi-v7a");
+        let ops = sorted(collect_directory_ops_with(&filter, Path::new("/system/"), &root));
+        let paths: Vec<&str> = ops.iter().map(RuleOp::path).collect();
+        assert_eq!(paths, vec![
+            "/system/app/Bar/lib/arm64/libbar.so",
+            "/system/app/Foo/lib/arm64/libfoo.so",
+            "/system/bin/alias",
+            "/system/bin/tool",
+        ]);
+        assert_eq!(ops[1], add("/system/app/Foo/lib/arm64/libfoo.so", &root.join("app/Foo/lib/arm64-v8a/libfoo.so").to_string_lossy()));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn parallel_walk_keeps_sequential_order() {
+        let root = std::env::temp_dir().join(format!("hymofs-walk-order-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        for dir in 0..12 {
+            for sub in 0..3 {
+                let path = root.join(format!("d{}/s{}", dir, sub));
+                std::fs::create_dir_all(&path).unwrap();
+                for file in 0..8 {
+                    std::fs::write(path.join(format!("f{}", file)), "").unwrap();
                 }
-            } else if file_type.is_char_device() {
-                if let Ok(metadata) = entry.metadata() {
-                    if metadata.rdev() == 0 {
-                        if let Err(e) = ctl.delete_rule(&target_path.to_string_lossy()) {
-                            warn!("Failed to delete hidden rule for {}: {}", target_path.display(), e);
-                        }
-                    }
+            }
+            std::fs::write(root.join(format!("d{}/top", dir)), "").unwrap();
+        }
+        fn sequential(dir: &Path, target: &Path, ops: &mut Vec<RuleOp>) {
+            for entry in std::fs::read_dir(dir).unwrap().map(Result::unwrap) {
+                let (source, target) = (entry.path(), target.join(entry.file_name()));
+                if entry.file_type().unwrap().is_dir() {
+                    sequential(&source, &target, ops);
+                } else {
+                    ops.push(RuleOp::Add { src: target.to_string_lossy().into_owned(), target: source.to_string_lossy().into_owned(), file_type: HymoFileType::Reg });
                 }
             }
         }
-        Ok(())
+        let mut expected = Vec::new();
+        sequential(&root, Path::new("/system"), &mut expected);
+        let walked = walk_directory(&arch::ArchFilter::default(), Path::new("/system"), &root);
+        assert_eq!(walked.ops.len(), 12 * 25);
+        assert_eq!(walked.ops, expected);
+        assert_eq!(walked.dirs.len(), 12 * 4);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn walk_honours_whiteouts_and_opaque_dirs() {
+        let scratch = std::env::temp_dir().join(format!("hymofs-walk-whiteout-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&scratch);
+        let (module, system) = (scratch.join("module"), scratch.join("system"));
+        for dir in ["etc/replaced", "etc/opaque", "etc/merged", "lib/arm64"] {
+            std::fs::create_dir_all(system.join(dir)).unwrap();
+        }
+        for file in ["etc/replaced/old.conf", "etc/replaced/keep.conf", "etc/opaque/old.xml", "etc/merged/stays", "etc/gone", "lib/arm64/libold.so"] {
+            std::fs::write(system.join(file), b"x").unwrap();
+        }
+        for dir in ["etc/replaced", "etc/opaque", "etc/merged", "lib/arm64-v8a"] {
+            std::fs::create_dir_all(module.join(dir)).unwrap();
+        }
+        for file in ["etc/replaced/.replace", "etc/replaced/keep.conf", "etc/opaque/.wh..wh..opq", "etc/merged/.wh.stays",
+            "etc/.wh.gone", "lib/arm64-v8a/libnew.so", "lib/arm64-v8a/.replace"] {
+            std::fs::write(module.join(file), b"x").unwrap();
+        }
+
+        let filter = arch::ArchFilter::from_abilist("arm64-v8a");
+        let ops = sorted(collect_directory_ops_with(&filter, &system, &module));
+        let at = |rel: &str| system.join(rel).to_string_lossy().into_owned();
+        let hidden: Vec<String> = ops.iter().filter(|op| matches!(op, RuleOp::Hide { .. })).map(|op| op.path().to_string()).collect();
+        assert_eq!(hidden, [at("etc/gone"), at("etc/merged/stays"), at("etc/opaque/old.xml"), at("etc/replaced/old.conf"), at("lib/arm64/libold.so")]);
+        let added: Vec<&str> = ops.iter().filter(|op| matches!(op, RuleOp::Add { .. })).map(RuleOp::path).collect();
+        assert_eq!(added, [at("etc/replaced/keep.conf"), at("lib/arm64/libnew.so")]);
+        std::fs::remove_dir_all(&scratch).unwrap();
+    }
+
+    #[test]
+    fn plans_injection_and_flags_live_conflicts() {
+        let root = scratch_tree("plan");
+        let src = |rel: &str| root.join(rel).to_string_lossy().into_owned();
+        let live = vec![
+            add("/system/bin/tool", "/data/other/tool"),
+            RuleOp::Add { src: "/system/bin/alias".into(), target: src("bin/alias"), file_type: HymoFileType::Lnk },
+        ];
+        let mut ledger = RuleLedger::default();
+        ledger.record_owned([("other", &live[0])]);
+        let protected = ProtectedPaths::new(&[PathBuf::from("/system/bin/alias")]);
+        let plan = inject_directory_plan_with(&arch::ArchFilter::default(), &protected, Path::new("/system"), &root, &live, &ledger);
+
+        assert_eq!(plan.rules.len(), 5);
+        assert_eq!(plan.refused, ["/system/bin/alias"]);
+        assert!(plan.steps.iter().all(|step| step.path() != "/system/bin/alias"));
+        assert_eq!(plan.steps.first(), Some(&PlannedOp::InjectDir { path: "/system".into(), source: root.to_string_lossy().into_owned(), opaque: false }));
+        let dirs: Vec<&str> = plan.steps.iter().filter(|s| matches!(s, PlannedOp::InjectDir { .. })).map(PlannedOp::path).collect();
+        assert_eq!(dirs.len(), 11);
+        assert!(dirs.contains(&"/system/app/Foo/lib/x86_64"));
+        assert_eq!(plan.conflicts, vec![Conflict {
+            planned: add("/system/bin/tool", &src("bin/tool")),
+            live: live[0].clone(),
+            owner: Some("other".into()),
+        }]);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn missing_module_dir_yields_no_rules() {
+        assert!(collect_directory_ops_with(&arch::ArchFilter::default(), Path::new("/system"), Path::new("/nonexistent/hymofs")).is_empty());
+    }
+
+    #[test]
+    fn parses_rule_listing() {
+        let listing = "add /system/bin/foo /data/adb/modules/a/system/bin/foo 8\nhide /system/app/Bar\n\nbogus line\nadd /system/x\n";
+        let ops: Vec<RuleOp> = parse_rules(listing).iter().filter_map(Rule::op).collect();
+        assert_eq!(ops, vec![
+            add("/system/bin/foo", "/data/adb/modules/a/system/bin/foo"),
+            RuleOp::Hide { path: "/system/app/Bar".into() },
+        ]);
+    }
+
+    #[test]
+    fn sorts_failures_into_matchable_errors() {
+        let missing = std::env::temp_dir().join(format!("hymofs-missing-{}", std::process::id()));
+        assert!(matches!(HymoFs::open_at(&missing), Err(HymoError::DeviceNotFound(path)) if path == missing));
+        assert!(matches!(c_path("/system/a\0b"), Err(HymoError::InvalidPath(_))));
+        assert!(matches!(next_list_capacity(LIST_BUF_LEN, MAX_LIST_BUF_LEN, 0), Err(HymoError::Truncated { limit: MAX_LIST_BUF_LEN })));
+        let rejected = HymoError::rejected("add_rule", &std::io::Error::from_raw_os_error(libc::EINVAL));
+        assert_eq!(rejected.errno(), Some(libc::EINVAL));
+        assert!(rejected.to_string().starts_with("HymoFS add_rule failed: Invalid argument"));
+    }
+
+    #[test]
+    fn tells_apart_why_hymofs_is_unusable() {
+        assert_eq!(HymoFsStatus::check(Some(HYMO_PROTOCOL_VERSION), None), HymoFsStatus::Available);
+        assert_eq!(HymoFsStatus::check(Some(0), None), HymoFsStatus::ModuleTooOld { protocol: 0 });
+        assert_eq!(HymoFsStatus::check(Some(HYMO_PROTOCOL_VERSION + 1), None), HymoFsStatus::ModuleTooNew { protocol: HYMO_PROTOCOL_VERSION + 1 });
+        assert_eq!(HymoFsStatus::check(None, Some("4.14.186-perf+")), HymoFsStatus::KernelTooOld { release: "4.14.186-perf+".into() });
+        assert_eq!(HymoFsStatus::check(None, Some("5.10.198-android13-4")), HymoFsStatus::NotPresent);
+        assert_eq!(HymoFsStatus::check(None, Some("garbage")), HymoFsStatus::NotPresent);
+        assert_eq!(kernel_version("6.1.57-android14-11-g1ad9e7c5"), Some((6, 1)));
+        assert!(HymoFsStatus::ModuleTooNew { protocol: 9 }.describe().contains("update meta-hybrid"));
+    }
+
+    #[test]
+    fn maps_file_types_to_kernel_values() {
+        for file_type in [HymoFileType::Unknown, HymoFileType::Fifo, HymoFileType::Dir, HymoFileType::Reg, HymoFileType::Lnk, HymoFileType::Wht] {
+            assert_eq!(HymoFileType::try_from(file_type.kernel_value()).unwrap(), file_type);
+        }
+        assert_eq!(HymoFileType::Reg.kernel_value(), libc::DT_REG as c_int);
+        assert!(HymoFileType::try_from(3).is_err());
+        assert!(HymoFileType::try_from(-1).is_err());
+        let rules = parse_rules("add /system/bin/foo /data/a/foo 3\n");
+        assert_eq!(rules[0].file_type, Some(HymoFileType::Unknown));
+    }
+
+    #[test]
+    fn parses_rules_with_trailing_flags() {
+        let rules = parse_rules("add /system/bin/foo /data/a/foo 8 ro prio=7 0x4\nhide /system/app/Bar\n");
+        assert_eq!(rules[0], Rule {
+            kind: RuleKind::Add,
+            src: "/system/bin/foo".into(),
+            target: Some("/data/a/foo".into()),
+            file_type: Some(HymoFileType::Reg),
+            priority: Some(7),
+            flags: vec!["ro".into(), "0x4".into()],
+        });
+        assert_eq!(rules[1].priority, None);
+        assert_eq!(rules[1].op(), Some(RuleOp::Hide { path: "/system/app/Bar".into() }));
+
+        let json = serde_json::to_string(&rules).unwrap();
+        assert!(!json.contains("\"target\":null"));
+        assert_eq!(serde_json::from_str::<Vec<Rule>>(&json).unwrap(), rules);
+    }
+
+    #[test]
+    fn takes_the_priority_of_the_deepest_module_dir() {
+        let mut priorities = RulePriorities::default();
+        priorities.insert("/data/adb/modules/a", 1);
+        priorities.insert("/data/adb/modules/a/system/lib", 5);
+        priorities.insert("/data/adb/modules/b", 2);
+        assert_eq!(priorities.of("/data/adb/modules/a/system/bin/sh"), 1);
+        assert_eq!(priorities.of("/data/adb/modules/a/system/lib/libx.so"), 5);
+        assert_eq!(priorities.of("/data/adb/modules/bb/system/bin/sh"), 0);
+    }
+
+    #[test]
+    fn full_list_buffer_is_retried_larger() {
+        assert_eq!(next_list_capacity(LIST_BUF_LEN, LIST_BUF_LEN, 100).unwrap(), None);
+        assert_eq!(next_list_capacity(LIST_BUF_LEN, LIST_BUF_LEN, LIST_BUF_LEN - 1).unwrap(), Some(2 * LIST_BUF_LEN));
+        assert_eq!(next_list_capacity(LIST_BUF_LEN, 300_000, LIST_BUF_LEN - 1).unwrap(), Some(300_001));
+        assert!(next_list_capacity(MAX_LIST_BUF_LEN, MAX_LIST_BUF_LEN, MAX_LIST_BUF_LEN).is_err());
+        assert!(next_list_capacity(LIST_BUF_LEN, MAX_LIST_BUF_LEN, 0).is_err());
+    }
+
+    #[test]
+    fn list_buffer_allocation_failure_is_an_error() {
+        assert!(zeroed_buffer(usize::MAX).is_err());
+        assert_eq!(zeroed_buffer(LIST_BUF_LEN).unwrap().len(), LIST_BUF_LEN);
+    }
+
+    #[test]
+    fn non_adjacent_ops_are_kept() {
+        let ops = vec![add("/a", "/m/a"), add("/b", "/m/b"), del("/a")];
+        assert_eq!(coalesce(ops.clone()), ops);
+    }
+
+    #[test]
+    fn classifies_unsupported_rule_kinds() {
+        assert!(marks_unsupported(Some(libc::EOPNOTSUPP), true));
+        assert!(marks_unsupported(Some(libc::EINVAL), false));
+        assert!(!marks_unsupported(Some(libc::EINVAL), true));
+        assert!(!marks_unsupported(Some(libc::ENOENT), false));
+        assert_ne!(add("/a", "/m/a").kind_bit(), RuleOp::Hide { path: "/a".into() }.kind_bit());
+        assert_eq!(del("/a").kind_bit(), None);
+    }
+
+    #[test]
+    fn downgrades_symlinks_to_their_resolved_file() {
+        let dir = std::env::temp_dir().join(format!("hymofs-downgrade-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("real.so"), "elf").unwrap();
+        std::os::unix::fs::symlink("real.so", dir.join("link.so")).unwrap();
+        std::os::unix::fs::symlink("gone.so", dir.join("dangling.so")).unwrap();
+        let link = |name: &str| RuleOp::Add { src: "/system/lib/x.so".into(), target: dir.join(name).to_string_lossy().into_owned(), file_type: HymoFileType::Lnk };
+        let real = std::fs::canonicalize(dir.join("real.so")).unwrap();
+        assert_eq!(downgrade(&link("link.so"), &dir.join("wh")).unwrap(),
+            RuleOp::Add { src: "/system/lib/x.so".into(), target: real.to_string_lossy().into_owned(), file_type: HymoFileType::Reg });
+        assert!(downgrade(&link("dangling.so"), &dir.join("wh")).is_err());
+        assert!(downgrade(&add("/a", "/m/a"), &dir.join("wh")).is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn explains_why_rules_do_not_resolve() {
+        let mounts = mount_points("\
+22 1 259:3 / / ro - ext4 /dev/block/dm-0 ro
+40 22 0:35 / /system rw - ext4 /dev/block/dm-1 rw
+41 40 0:36 / /system/etc rw - tmpfs tmpfs rw
+42 40 0:37 / /system/etc/my\\040dir rw - tmpfs tmpfs rw
+");
+        assert_eq!(mounts.last(), Some(&PathBuf::from("/system/etc/my dir")));
+        let system = Path::new("/system");
+        let hide = RuleOp::Hide { path: "/system/app/Bloat".into() };
+        let registered: HashSet<&str> = ["/system/etc/my dir/a.conf", "/system/app/Bloat", "/system/bin/nope"].into();
+        let miss = |op: &RuleOp, registered| diagnose(op, false, registered, system, &mounts).map(|u| (u.miss, u.mount));
+
+        assert_eq!(diagnose(&hide, true, None, system, &mounts), None);
+        assert_eq!(miss(&add("/system/bin/gone", "/m/gone"), Some(&registered)), Some((Miss::NotRegistered, None)));
+        assert_eq!(miss(&add("/system/etc/my dir/a.conf", "/m/a"), Some(&registered)), Some((Miss::Shadowed, Some("/system/etc/my dir".into()))));
+        assert_eq!(miss(&hide, Some(&registered)), Some((Miss::StillVisible, None)));
+        assert_eq!(miss(&add("/system/bin/nope", "/m/nope"), None), Some((Miss::Missing, None)));
     }
 }
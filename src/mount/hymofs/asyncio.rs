@@ -0,0 +1,120 @@
+use std::path::PathBuf;
+use anyhow::{Context, Result};
+use tokio::task;
+use crate::{core::rules::RuleLedger, mount::{MountBackend, hymo_abi::KernelCaps}};
+use super::{HymoError, HymoFs, HymoFsStatus, InjectPlan, Rule, RuleOp, Unresolved};
+
+/// [`HymoFs`] for async callers: every ioctl and directory walk runs on
+/// tokio's blocking pool, so none of them stalls a runtime thread.
+#[derive(Clone)]
+pub struct AsyncHymoFs {
+    handle: HymoFs,
+}
+
+impl From<HymoFs> for AsyncHymoFs {
+    fn from(handle: HymoFs) -> Self {
+        Self { handle }
+    }
+}
+
+impl AsyncHymoFs {
+    pub async fn open_at<P: Into<PathBuf>>(device: P) -> Result<Self> {
+        let device = device.into();
+        let handle = task::spawn_blocking(move || HymoFs::open_at(device)).await.context("HymoFS open task failed")??;
+        Ok(Self { handle })
+    }
+
+    /// The blocking handle, for calls that need no offloading.
+    pub fn blocking(&self) -> &HymoFs {
+        &self.handle
+    }
+
+    async fn run<T, E, F>(&self, call: F) -> Result<T>
+    where
+        T: Send + 'static,
+        E: Into<anyhow::Error> + Send + 'static,
+        F: FnOnce(&HymoFs) -> std::result::Result<T, E> + Send + 'static,
+    {
+        let handle = self.handle.clone();
+        task::spawn_blocking(move || call(&handle)).await.context("HymoFS task failed")?.map_err(Into::into)
+    }
+
+    pub async fn status(&self) -> Result<HymoFsStatus> {
+        self.run(|h| Ok::<_, HymoError>(h.status())).await
+    }
+
+    pub async fn caps(&self) -> Result<KernelCaps> {
+        self.run(HymoFs::caps).await
+    }
+
+    /// Merges the file or tree at `source` over `target`.
+    pub async fn inject(&self, source: PathBuf, target: PathBuf) -> Result<()> {
+        self.run(move |h| h.inject(&source, &target)).await
+    }
+
+    /// Deletes the rules at or below `target` this tool owns; returns how
+    /// many.
+    pub async fn remove(&self, target: PathBuf) -> Result<usize> {
+        self.run(move |h| MountBackend::remove(h, &target)).await
+    }
+
+    /// Clears every rule but the ones other consumers own.
+    pub async fn clear(&self) -> Result<usize> {
+        self.run(MountBackend::clear).await
+    }
+
+    pub async fn list_rules(&self) -> Result<Vec<Rule>> {
+        self.run(HymoFs::list_rules).await
+    }
+
+    pub async fn list_ops(&self) -> Result<Vec<RuleOp>> {
+        self.run(HymoFs::list_ops).await
+    }
+
+    /// Applies `ops` journaled, as [`HymoFs::apply_journaled`] does;
+    /// returns how many the kernel rejected.
+    pub async fn apply(&self, ops: Vec<RuleOp>) -> Result<usize> {
+        self.run(move |h| Ok::<_, HymoError>(h.apply_journaled(&ops))).await
+    }
+
+    /// Applies `op` for the processes of `uid` alone, as
+    /// [`HymoFs::apply_scoped`] does.
+    pub async fn apply_scoped(&self, op: RuleOp, uid: u32) -> Result<()> {
+        self.run(move |h| h.apply_scoped(&op, uid)).await
+    }
+
+    /// The rules of injecting `source` over `target` that do not resolve,
+    /// as [`HymoFs::verify`] finds them.
+    pub async fn verify(&self, source: PathBuf, target: PathBuf) -> Result<Vec<Unresolved>> {
+        self.run(move |h| h.verify(&target, &source)).await
+    }
+
+    /// What injecting `source` over `target` would change, against the live
+    /// rules.
+    pub async fn plan(&self, source: PathBuf, target: PathBuf) -> Result<InjectPlan> {
+        self.run(move |h| {
+            let live = h.list_ops()?;
+            Ok::<_, HymoError>(super::inject_directory_plan(&target, &source, &live, &RuleLedger::load()))
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offloads_calls_and_surfaces_their_errors() {
+        let device = std::env::temp_dir().join(format!("asyncio-{}", std::process::id()));
+        std::fs::write(&device, "").unwrap();
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        runtime.block_on(async {
+            let hymofs = AsyncHymoFs::open_at(&device).await.unwrap();
+            assert!(!matches!(hymofs.status().await.unwrap(), HymoFsStatus::Available));
+            assert!(hymofs.list_rules().await.is_err());
+            assert!(AsyncHymoFs::open_at(device.join("missing")).await.is_err());
+        });
+        let _ = std::fs::remove_file(&device);
+    }
+}
@@ -0,0 +1,58 @@
+use std::io;
+use std::path::PathBuf;
+use super::HymoFsStatus;
+
+pub type HymoResult<T> = std::result::Result<T, HymoError>;
+
+/// Why a call on the HymoFS control device failed.
+#[derive(Debug, thiserror::Error)]
+pub enum HymoError {
+    #[error("HymoFS control device {} does not exist", .0.display())]
+    DeviceNotFound(PathBuf),
+    #[error("No permission to open {}", .0.display())]
+    PermissionDenied(PathBuf),
+    #[error("Failed to open {}: {source}", path.display())]
+    Open { path: PathBuf, source: io::Error },
+    /// The module answered `request` with `errno`.
+    #[error("HymoFS {request} failed: {}", io::Error::from_raw_os_error(*errno))]
+    KernelRejected { request: &'static str, errno: i32 },
+    #[error("HymoFS is unusable: {}", .0.describe())]
+    Unavailable(HymoFsStatus),
+    /// The loaded module lacks what the call needs.
+    #[error("HymoFS {0}")]
+    Unsupported(String),
+    #[error("{0:?} contains a NUL byte")]
+    InvalidPath(String),
+    /// The rule listing outgrew the largest buffer it may be read into.
+    #[error("HymoFS rule listing needs more than {limit} bytes")]
+    Truncated { limit: usize },
+    #[error("HymoFS: cannot allocate a {0} byte list buffer")]
+    OutOfMemory(usize),
+    /// A symlink rule could not fall back to a redirect to its file.
+    #[error("symlink {0} does not resolve to a regular file")]
+    DanglingSymlink(String),
+    #[error("Failed to create whiteout {}: {source}", path.display())]
+    Whiteout { path: PathBuf, source: io::Error },
+}
+
+impl HymoError {
+    pub(super) fn open(path: PathBuf, source: io::Error) -> Self {
+        match source.kind() {
+            io::ErrorKind::NotFound => HymoError::DeviceNotFound(path),
+            io::ErrorKind::PermissionDenied => HymoError::PermissionDenied(path),
+            _ => HymoError::Open { path, source },
+        }
+    }
+
+    pub(super) fn rejected(request: &'static str, source: &io::Error) -> Self {
+        HymoError::KernelRejected { request, errno: source.raw_os_error().unwrap_or(libc::EIO) }
+    }
+
+    /// The errno the module answered with, when it did.
+    pub fn errno(&self) -> Option<i32> {
+        match self {
+            HymoError::KernelRejected { errno, .. } => Some(*errno),
+            _ => None,
+        }
+    }
+}
@@ -15,8 +15,9 @@ use rustix::{
     },
 };
 use crate::{
-    defs::{DISABLE_FILE_NAME, REMOVE_FILE_NAME, SKIP_MOUNT_FILE_NAME},
-    mount::node::{Node, NodeFileType},
+    conf::config::Config,
+    defs::{DISABLE_FILE_NAME, MAGIC_INJECTIONS_FILE, REMOVE_FILE_NAME, RUN_DIR, SKIP_MOUNT_FILE_NAME},
+    mount::{node::{Node, NodeFileType}, propagation::{self, PropagationConfig}},
     utils::{ensure_dir_exists, lgetfilecon, lsetfilecon},
 };
 #[cfg(any(target_os = "linux", target_os = "android"))]
@@ -196,20 +197,22 @@ where
     }
     Ok(())
 }
-struct MagicMount {
+struct MagicMount<'a> {
     node: Node,
     path: PathBuf,
     work_dir_path: PathBuf,
     has_tmpfs: bool,
+    propagation: &'a PropagationConfig,
     #[cfg(any(target_os = "linux", target_os = "android"))]
     umount: bool,
 }
-impl MagicMount {
+impl<'a> MagicMount<'a> {
     fn new<P>(
         node: &Node,
         path: P,
         work_dir_path: P,
         has_tmpfs: bool,
+        propagation: &'a PropagationConfig,
         #[cfg(any(target_os = "linux", target_os = "android"))] umount: bool,
     ) -> Self
     where
@@ -220,6 +223,7 @@ impl MagicMount {
             path: path.as_ref().join(node.name.clone()),
             work_dir_path: work_dir_path.as_ref().join(node.name.clone()),
             has_tmpfs,
+            propagation,
             #[cfg(any(target_os = "linux", target_os = "android"))]
             umount,
         }
@@ -358,6 +362,7 @@ impl MagicMount {
                             &self.path,
                             &self.work_dir_path,
                             has_tmpfs,
+                            self.propagation,
                             #[cfg(any(target_os = "linux", target_os = "android"))]
                             self.umount,
                         )
@@ -396,6 +401,7 @@ impl MagicMount {
                 &self.path,
                 &self.work_dir_path,
                 has_tmpfs,
+                self.propagation,
                 #[cfg(any(target_os = "linux", target_os = "android"))]
                 self.umount,
             )
@@ -430,8 +436,9 @@ impl MagicMount {
                         self.path.display()
                     )
                 })?;
-            if let Err(e) = mount_change(&self.path, MountPropagationFlags::PRIVATE) {
-                log::warn!("make dir {} private: {e:#?}", self.path.display());
+            let wanted = self.propagation.for_mount(&self.path, self.propagation.magic);
+            if let Err(e) = propagation::apply(&self.path, wanted) {
+                log::warn!("set propagation of dir {}: {e:#}", self.path.display());
             }
             #[cfg(any(target_os = "linux", target_os = "android"))]
             if self.umount {
@@ -460,12 +467,93 @@ impl MagicMount {
         }
     }
 }
+/// Mirrors module trees onto their partitions with a tmpfs skeleton and
+/// recursive bind mounts, for kernels with neither HymoFS nor overlayfs.
+pub struct Backend {
+    mount_source: String,
+    partitions: Vec<String>,
+    propagation: PropagationConfig,
+    disable_umount: bool,
+}
+
+impl Backend {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            mount_source: config.mountsource.clone(),
+            partitions: config.partitions.clone(),
+            propagation: config.mount_propagation.clone(),
+            disable_umount: config.disable_umount,
+        }
+    }
+
+    /// Mount points earlier injections created that are still mounted.
+    fn injected(&self) -> Result<Vec<PathBuf>> {
+        let recorded: Vec<PathBuf> = fs::read_to_string(MAGIC_INJECTIONS_FILE)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+        let mounted: HashSet<PathBuf> = super::mounts()?.into_iter().map(|m| m.mount_point).collect();
+        Ok(recorded.into_iter().filter(|p| mounted.contains(p)).collect())
+    }
+
+    fn record(&self, points: &[PathBuf]) -> Result<()> {
+        crate::utils::atomic_write(MAGIC_INJECTIONS_FILE, serde_json::to_string(points)?)
+    }
+}
+
+impl super::MountBackend for Backend {
+    fn name(&self) -> &'static str {
+        "magic"
+    }
+
+    fn is_usable(&self) -> bool {
+        super::kernel_supports("tmpfs")
+    }
+
+    /// Lays `source` out as a one-off module below a scratch directory and
+    /// magic-mounts it. The mounts this creates are recorded, since tmpfs
+    /// skeletons and file binds look like any other mount afterwards.
+    fn inject(&self, source: &Path, target: &Path) -> Result<()> {
+        let relative = target.strip_prefix("/")
+            .with_context(|| format!("{} is not an absolute path", target.display()))?;
+        let mut partitions = self.partitions.clone();
+        partitions.extend(super::partition_of(target));
+        let scratch = Path::new(RUN_DIR).join(format!("inject-{}", std::process::id()));
+        let module = scratch.join("module");
+        let leaf = module.join(relative);
+        create_dir_all(&leaf)?;
+        mount_bind(source, &leaf).with_context(|| format!("bind {} for injection", source.display()))?;
+        let before: HashSet<i32> = super::mounts()?.iter().map(|m| m.mnt_id).collect();
+        let result = mount_partitions(&scratch, &[module], &self.mount_source, &partitions, HashMap::new(), &self.propagation, self.disable_umount);
+        let _ = unmount(&leaf, UnmountFlags::DETACH);
+        let _ = fs::remove_dir_all(&scratch);
+        result?;
+        let mut points = self.injected()?;
+        points.extend(super::mounts()?.into_iter().filter(|m| !before.contains(&m.mnt_id)).map(|m| m.mount_point));
+        points.sort();
+        points.dedup();
+        self.record(&points)
+    }
+
+    fn remove(&self, target: &Path) -> Result<usize> {
+        let (gone, kept): (Vec<PathBuf>, Vec<PathBuf>) = self.injected()?.into_iter().partition(|p| p.starts_with(target));
+        let removed = super::unmount_below(gone, target);
+        self.record(&kept)?;
+        Ok(removed)
+    }
+
+    fn list(&self) -> Result<Vec<PathBuf>> {
+        self.injected()
+    }
+}
+
 pub fn mount_partitions(
     tmp_path: &Path,
     module_paths: &[PathBuf],
     mount_source: &str,
     extra_partitions: &[String],
     exclusions: HashMap<PathBuf, HashSet<String>>,
+    propagation: &PropagationConfig,
     #[cfg(any(target_os = "linux", target_os = "android"))] disable_umount: bool,
     #[cfg(not(any(target_os = "linux", target_os = "android")))] _disable_umount: bool,
 ) -> Result<()> {
@@ -485,6 +573,7 @@ pub fn mount_partitions(
                 Path::new("/"),
                 tmp_dir.as_path(),
                 false,
+                propagation,
                 #[cfg(any(target_os = "linux", target_os = "android"))]
                 !disable_umount,
             )
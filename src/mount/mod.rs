@@ -1,5 +1,214 @@
 pub mod magic;
 pub mod overlay;
 pub mod node;
+pub mod propagation;
 #[cfg(any(target_os = "linux", target_os = "android"))]
-pub mod hymofs;
\ No newline at end of file
+#[cfg_attr(not(test), deny(clippy::unwrap_used, clippy::expect_used, clippy::panic, clippy::indexing_slicing))]
+pub mod hymo_abi;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[cfg_attr(not(test), deny(clippy::unwrap_used, clippy::expect_used, clippy::panic, clippy::indexing_slicing))]
+pub mod hymofs;
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use anyhow::{Context, Result};
+use procfs::process::{MountInfo, Process};
+use rustix::mount::{UnmountFlags, unmount};
+use serde::{Deserialize, Serialize};
+use crate::{conf::config::Config, core::health::HealthStore};
+
+const FILESYSTEMS_FILE: &str = "/proc/filesystems";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    #[value(name = "hymofs")]
+    HymoFs,
+    Overlay,
+    Magic,
+}
+
+pub fn default_backend_order() -> Vec<BackendKind> {
+    vec![BackendKind::HymoFs, BackendKind::Overlay, BackendKind::Magic]
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BackendStatus {
+    pub name: &'static str,
+    pub usable: bool,
+    pub detail: String,
+}
+
+/// A way of putting module files in place. Plans fall back from HymoFS to
+/// OverlayFS to Magic Mount, skipping backends the kernel cannot serve.
+pub trait MountBackend {
+    fn name(&self) -> &'static str;
+    fn is_usable(&self) -> bool;
+    /// Merges the tree at `source` over the directory `target`.
+    fn inject(&self, source: &Path, target: &Path) -> Result<()>;
+    /// Takes back what this backend put at or below `target`; returns how
+    /// many mounts or rules went.
+    fn remove(&self, target: &Path) -> Result<usize>;
+    /// The paths this backend currently serves.
+    fn list(&self) -> Result<Vec<PathBuf>>;
+    /// Takes back everything this backend put in place.
+    fn clear(&self) -> Result<usize> {
+        self.remove(Path::new("/"))
+    }
+    fn status(&self) -> BackendStatus {
+        BackendStatus { name: self.name(), usable: self.is_usable(), detail: String::new() }
+    }
+}
+
+/// Every backend of this build, set up from the config.
+pub struct Backends {
+    pub hymofs: Option<hymofs::HymoFs>,
+    pub overlay: overlay::Backend,
+    pub magic: magic::Backend,
+}
+
+impl Backends {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            hymofs: hymofs::HymoFs::open_at(&config.hymofs_device).ok().map(|h| h.with_labels(&config.selinux_labels)),
+            overlay: overlay::Backend::new(config),
+            magic: magic::Backend::new(config),
+        }
+    }
+
+    pub fn get(&self, kind: BackendKind) -> Option<&dyn MountBackend> {
+        match kind {
+            BackendKind::HymoFs => self.hymofs.as_ref().map(|h| h as &dyn MountBackend),
+            BackendKind::Overlay => Some(&self.overlay),
+            BackendKind::Magic => Some(&self.magic),
+        }
+    }
+
+    pub fn all(&self) -> Vec<&dyn MountBackend> {
+        default_backend_order().into_iter().filter_map(|kind| self.get(kind)).collect()
+    }
+}
+
+/// Backends to try for `partition`: its `partition_backends` entry, the
+/// pinned backend, then `backend_order`, which without a pin is ranked by
+/// how the backends did on this device.
+fn candidates(config: &Config, health: &HealthStore, partition: &str) -> Vec<BackendKind> {
+    let order = if config.pinned_backend.is_some() || config.disable_backend_learning {
+        config.backend_order.clone()
+    } else {
+        health.rank(&config.backend_order)
+    };
+    let mut kinds: Vec<BackendKind> = Vec::new();
+    for kind in config.partition_backends.get(partition).iter().copied().chain(&config.pinned_backend).chain(&order) {
+        if !kinds.contains(kind) {
+            kinds.push(*kind);
+        }
+    }
+    kinds
+}
+
+fn choose(candidates: &[BackendKind], usable: impl Fn(BackendKind) -> bool) -> Option<BackendKind> {
+    candidates.iter().copied().find(|kind| usable(*kind))
+}
+
+/// The preferred backend for `partition` the kernel can serve.
+pub fn select_backend<'a>(backends: &'a Backends, config: &Config, health: &HealthStore, partition: &str) -> Option<&'a dyn MountBackend> {
+    let kind = choose(&candidates(config, health, partition), |kind| backends.get(kind).is_some_and(|b| b.is_usable()))?;
+    backends.get(kind)
+}
+
+/// The backend `kind` names, or the one [`select_backend`] prefers for the
+/// partition of `target`.
+pub fn pick_backend<'a>(backends: &'a Backends, config: &Config, kind: Option<BackendKind>, target: &Path) -> Result<&'a dyn MountBackend> {
+    let backend = match kind {
+        Some(kind) => backends.get(kind).filter(|b| b.is_usable()),
+        None => partition_of(target).and_then(|partition| select_backend(backends, config, &HealthStore::load(), &partition)),
+    };
+    backend.with_context(|| format!("No usable backend for {}", target.display()))
+}
+
+/// The partition `target` lies on, e.g. `vendor` for `/vendor/etc`.
+pub fn partition_of(target: &Path) -> Option<String> {
+    target.components()
+        .find_map(|c| match c {
+            Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+            _ => None,
+        })
+}
+
+/// Whether a `/proc/filesystems` listing has `fstype`, e.g. `overlay`.
+fn lists_filesystem(listing: &str, fstype: &str) -> bool {
+    listing.lines().any(|line| line.split_whitespace().last() == Some(fstype))
+}
+
+fn kernel_supports(fstype: &str) -> bool {
+    fs::read_to_string(FILESYSTEMS_FILE).is_ok_and(|listing| lists_filesystem(&listing, fstype))
+}
+
+fn mounts() -> Result<Vec<MountInfo>> {
+    Ok(Process::myself()?.mountinfo()?.0)
+}
+
+/// Detaches the mount points at or below `target`, deepest first; returns
+/// how many went.
+fn unmount_below(mut points: Vec<PathBuf>, target: &Path) -> usize {
+    points.retain(|p| p.starts_with(target));
+    points.sort_by_key(|p| std::cmp::Reverse(p.components().count()));
+    points.iter()
+        .filter(|p| match unmount(p.as_path(), UnmountFlags::DETACH) {
+            Ok(()) => true,
+            Err(e) => {
+                log::warn!("!! Failed to unmount {}: {}", p.display(), e);
+                false
+            }
+        })
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::core::health;
+
+    #[test]
+    fn reads_filesystem_listing() {
+        let listing = "nodev\tsysfs\nnodev\ttmpfs\n\text4\nnodev\toverlay\n";
+        assert!(lists_filesystem(listing, "overlay"));
+        assert!(lists_filesystem(listing, "ext4"));
+        assert!(!lists_filesystem(listing, "erofs"));
+        assert!(!lists_filesystem(listing, "nodev"));
+    }
+
+    #[test]
+    fn partition_preference_comes_first() {
+        let mut config = Config::default();
+        let health = HealthStore::default();
+        config.partition_backends.insert("vendor".into(), BackendKind::Magic);
+        assert_eq!(candidates(&config, &health, "system"), default_backend_order());
+        assert_eq!(candidates(&config, &health, "vendor"), vec![BackendKind::Magic, BackendKind::HymoFs, BackendKind::Overlay]);
+
+        let no_hymofs = |kind| kind != BackendKind::HymoFs;
+        assert_eq!(choose(&candidates(&config, &health, "system"), no_hymofs), Some(BackendKind::Overlay));
+        assert_eq!(choose(&candidates(&config, &health, "vendor"), no_hymofs), Some(BackendKind::Magic));
+        assert_eq!(choose(&[], no_hymofs), None);
+        assert_eq!(partition_of(Path::new("/vendor/etc")).as_deref(), Some("vendor"));
+    }
+
+    #[test]
+    fn pinned_backend_comes_before_the_learned_order() {
+        let mut health = HealthStore::default();
+        for _ in 0..health::MIN_BOOTS {
+            health.record(&[
+                health::BackendRun { backend: BackendKind::HymoFs, attempts: 2, failures: 2, elapsed: Duration::ZERO },
+                health::BackendRun { backend: BackendKind::Overlay, attempts: 2, failures: 0, elapsed: Duration::ZERO },
+            ]);
+        }
+        let mut config = Config::default();
+        assert_eq!(candidates(&config, &health, "system"), vec![BackendKind::Overlay, BackendKind::HymoFs, BackendKind::Magic]);
+        config.pinned_backend = Some(BackendKind::HymoFs);
+        assert_eq!(candidates(&config, &health, "system"), default_backend_order());
+        config.partition_backends.insert("vendor".into(), BackendKind::Magic);
+        assert_eq!(candidates(&config, &health, "vendor"), vec![BackendKind::Magic, BackendKind::HymoFs, BackendKind::Overlay]);
+    }
+}
@@ -1,5 +1,5 @@
 use std::{collections::HashMap, fs::FileType, path::PathBuf, fmt};
-use crate::core::modules::ModuleFile;
+use crate::core::{arch, modules::{self, ModuleFile}};
 #[derive(PartialEq, Eq, Hash, Clone, Debug, Copy)]
 pub enum NodeFileType {
     RegularFile,
@@ -93,10 +93,14 @@ impl Node {
         {
             let path = entry.path();
             let relative_path = path.strip_prefix(root)?;
-            let module_file = ModuleFile::new(root, relative_path)?;
+            let Some(mapped_path) = arch::filter().map_relative(relative_path, |p| root.join(p).exists()) else {
+                continue;
+            };
+            let mut module_file = ModuleFile::new(root, relative_path)?;
             if module_file.is_replace_file {
                 continue;
             }
+            module_file.relative_path = modules::visible_path(&mapped_path);
             self.add_module_file(module_file);
         }
         Ok(())
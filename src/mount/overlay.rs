@@ -7,11 +7,52 @@ use std::time::{SystemTime, UNIX_EPOCH};
 use std::ffi::CString;
 use procfs::process::Process;
 use rustix::{fd::AsFd, fs::CWD, mount::*};
-use crate::defs::{KSU_OVERLAY_SOURCE, RUN_DIR};
+use crate::{conf::config::Config, defs::{KSU_OVERLAY_SOURCE, RUN_DIR}};
 #[cfg(any(target_os = "linux", target_os = "android"))]
 use crate::try_umount::send_unmountable;
 
 const PAGE_LIMIT: usize = 4000;
+
+/// Stacks module partitions as overlayfs lower layers.
+pub struct Backend {
+    disable_umount: bool,
+}
+
+impl Backend {
+    pub fn new(config: &Config) -> Self {
+        Self { disable_umount: config.disable_umount }
+    }
+}
+
+impl super::MountBackend for Backend {
+    fn name(&self) -> &'static str {
+        "overlay"
+    }
+
+    fn is_usable(&self) -> bool {
+        super::kernel_supports("overlay")
+    }
+
+    fn inject(&self, source: &Path, target: &Path) -> Result<()> {
+        mount_overlay(&target.to_string_lossy(), &[source.to_string_lossy().into_owned()], None, None, self.disable_umount)
+    }
+
+    fn remove(&self, target: &Path) -> Result<usize> {
+        Ok(super::unmount_below(self.list()?, target))
+    }
+
+    /// Overlays mounted with this crate's source name, child mounts included.
+    fn list(&self) -> Result<Vec<PathBuf>> {
+        let mut points: Vec<PathBuf> = super::mounts()?.into_iter()
+            .filter(|m| m.fs_type == "overlay" && m.mount_source.as_deref() == Some(KSU_OVERLAY_SOURCE))
+            .map(|m| m.mount_point)
+            .collect();
+        points.sort();
+        points.dedup();
+        Ok(points)
+    }
+}
+
 pub fn mount_overlayfs(
     lower_dirs: &[String],
     lowest: &str,
@@ -0,0 +1,134 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use anyhow::{Context, Result, bail};
+use rustix::mount::{MountPropagationFlags, mount_change};
+use serde::{Deserialize, Serialize};
+
+const MOUNTINFO_FILE: &str = "/proc/self/mountinfo";
+
+/// Propagation type of a mount, as in `mount --make-private` and friends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Propagation {
+    /// Keep whatever the mount inherited from its parent.
+    #[default]
+    Unchanged,
+    /// Neither receives nor sends mount events.
+    Private,
+    /// Receives mount events from its peers but sends none back.
+    Slave,
+    /// Exchanges mount events with its peer group.
+    Shared,
+}
+
+/// Propagation of the mounts the backends create. `mounts` overrides the
+/// backend default for single mount points, e.g. `"/vendor" = "slave"`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PropagationConfig {
+    pub overlay: Propagation,
+    pub magic: Propagation,
+    pub mounts: BTreeMap<String, Propagation>,
+}
+
+impl Default for PropagationConfig {
+    /// Overlays keep the propagation of the partition they cover; the tmpfs
+    /// directories of Magic Mount stay private as they always were.
+    fn default() -> Self {
+        Self {
+            overlay: Propagation::Unchanged,
+            magic: Propagation::Private,
+            mounts: BTreeMap::new(),
+        }
+    }
+}
+
+impl PropagationConfig {
+    /// The propagation wanted for `mount_point`, falling back to `backend`.
+    pub fn for_mount(&self, mount_point: &Path, backend: Propagation) -> Propagation {
+        let key = mount_point.to_string_lossy();
+        let key = match key.trim_end_matches('/') {
+            "" => "/",
+            trimmed => trimmed,
+        };
+        self.mounts.get(key).copied().unwrap_or(backend)
+    }
+}
+
+/// Propagation of the topmost mount exactly at `mount_point`, read from the
+/// optional fields of its mountinfo entry.
+pub fn observed(mountinfo: &str, mount_point: &Path) -> Option<Propagation> {
+    let mut found = None;
+    for line in mountinfo.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let Some(point) = fields.get(4) else { continue };
+        if Path::new(&point.replace("\\040", " ")) != mount_point {
+            continue;
+        }
+        let optional = fields.iter().skip(6).take_while(|f| **f != "-");
+        let mut propagation = Propagation::Private;
+        for field in optional {
+            if field.starts_with("shared:") {
+                propagation = Propagation::Shared;
+                break;
+            }
+            if field.starts_with("master:") {
+                propagation = Propagation::Slave;
+            }
+        }
+        found = Some(propagation);
+    }
+    found
+}
+
+/// Changes the propagation of the mount at `mount_point` and checks in
+/// mountinfo that the kernel took it.
+pub fn apply(mount_point: &Path, propagation: Propagation) -> Result<()> {
+    let flags = match propagation {
+        Propagation::Unchanged => return Ok(()),
+        Propagation::Private => MountPropagationFlags::PRIVATE,
+        Propagation::Slave => MountPropagationFlags::DOWNSTREAM,
+        Propagation::Shared => MountPropagationFlags::SHARED,
+    };
+    mount_change(mount_point, flags)
+        .with_context(|| format!("Failed to make {} {:?}", mount_point.display(), propagation))?;
+    let mountinfo = fs::read_to_string(MOUNTINFO_FILE).context("Failed to read mountinfo")?;
+    match observed(&mountinfo, mount_point) {
+        Some(seen) if seen == propagation => Ok(()),
+        // A slave of a peer group that is itself gone reads as private.
+        Some(Propagation::Private) if propagation == Propagation::Slave => Ok(()),
+        Some(seen) => bail!("{} is {:?} after making it {:?}", mount_point.display(), seen, propagation),
+        None => bail!("{} is not in mountinfo", mount_point.display()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOUNTINFO: &str = "\
+21 1 253:0 / / ro,relatime shared:1 - ext4 /dev/root ro
+30 21 0:20 / /vendor ro,relatime shared:5 - ext4 /dev/vendor ro
+41 30 0:33 / /vendor ro,relatime master:5 - overlay KSU ro,lowerdir=/a:/vendor
+42 21 0:34 / /my\\040product rw,relatime - tmpfs tmpfs rw
+43 21 0:35 / /odm rw shared:9 master:5 - overlay KSU ro";
+
+    #[test]
+    fn reads_the_topmost_mount() {
+        assert_eq!(observed(MOUNTINFO, Path::new("/")), Some(Propagation::Shared));
+        assert_eq!(observed(MOUNTINFO, Path::new("/vendor")), Some(Propagation::Slave));
+        assert_eq!(observed(MOUNTINFO, Path::new("/my product")), Some(Propagation::Private));
+        assert_eq!(observed(MOUNTINFO, Path::new("/odm")), Some(Propagation::Shared));
+        assert_eq!(observed(MOUNTINFO, Path::new("/vendor/lib")), None);
+    }
+
+    #[test]
+    fn overrides_win_over_the_backend_default() {
+        let mut config = PropagationConfig::default();
+        config.mounts.insert("/vendor".into(), Propagation::Slave);
+        assert_eq!(config.for_mount(Path::new("/vendor/"), config.overlay), Propagation::Slave);
+        assert_eq!(config.for_mount(Path::new("/system"), config.overlay), Propagation::Unchanged);
+        assert_eq!(config.for_mount(Path::new("/system/app"), config.magic), Propagation::Private);
+    }
+}
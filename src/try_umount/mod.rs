@@ -3,6 +3,7 @@ mod kernel;
 use std::{
     fs::{self, read_dir},
     path::Path,
+    sync::OnceLock,
 };
 use anyhow::Result;
 use crate::defs::{DISABLE_FILE_NAME, REMOVE_FILE_NAME, SKIP_MOUNT_FILE_NAME};
@@ -10,6 +11,19 @@ use crate::defs::{DISABLE_FILE_NAME, REMOVE_FILE_NAME, SKIP_MOUNT_FILE_NAME};
 #[cfg(any(target_os = "linux", target_os = "android"))]
 use crate::mount::hymofs::HymoFs;
 
+#[cfg(any(target_os = "linux", target_os = "android"))]
+static HYMOFS: OnceLock<Option<HymoFs>> = OnceLock::new();
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn register_hymofs(handle: Option<HymoFs>) {
+    let _ = HYMOFS.set(handle);
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn hymofs() -> Option<&'static HymoFs> {
+    HYMOFS.get().and_then(Option::as_ref)
+}
+
 pub fn send_unmountable<P>(target: P) -> Result<()>
 where
     P: AsRef<Path>,
@@ -47,8 +61,8 @@ where
     }
 
     #[cfg(any(target_os = "linux", target_os = "android"))]
-    if HymoFs::is_available() {
-        if let Err(e) = HymoFs::hide_path(&target.as_ref().to_string_lossy()) {
+    if let Some(handle) = hymofs().filter(|h| h.is_available()) {
+        if let Err(e) = handle.hide_path(&target.as_ref().to_string_lossy()) {
             log::warn!("HymoFS hide failed for {}: {}", target.as_ref().display(), e);
         } else {
              log::info!("HymoFS hide {} successful!", target.as_ref().display());
@@ -31,6 +31,69 @@ const SELINUX_XATTR: &str = "security.selinux";
 const XATTR_TEST_FILE: &str = ".xattr_test";
 const DEFAULT_CONTEXT: &str = "u:object_r:system_file:s0";
 
+const BOOT_ID_FILE: &str = "/proc/sys/kernel/random/boot_id";
+
+static SESSION_ID: OnceLock<String> = OnceLock::new();
+
+pub fn format_session_id(boot_id: &str, started_at: u64, pid: u32) -> String {
+    let boot: String = boot_id.chars().filter(char::is_ascii_hexdigit).take(8).collect();
+    let boot = if boot.is_empty() { "00000000".to_string() } else { boot };
+    format!("{}-{:x}-{:x}", boot, started_at, pid)
+}
+
+/// Identifies this run; the boot_id prefix groups every run of the same boot.
+pub fn session_id() -> &'static str {
+    SESSION_ID.get_or_init(|| {
+        let boot_id = fs::read_to_string(BOOT_ID_FILE).unwrap_or_default();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        format_session_id(&boot_id, now, std::process::id())
+    })
+}
+
+/// Session ids start with the boot_id prefix, so equal prefixes mean both
+/// runs happened during the same boot.
+pub fn same_boot(session_a: &str, session_b: &str) -> bool {
+    match (session_a.split_once('-'), session_b.split_once('-')) {
+        (Some((a, _)), Some((b, _))) => a == b,
+        _ => false,
+    }
+}
+
+/// Replaces `path` so readers see either the old or the new contents, never
+/// a partial write: the data goes to a sibling file that is synced and then
+/// renamed over the target.
+pub fn atomic_write<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> Result<()> {
+    let path = path.as_ref();
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(".tmp.{}", std::process::id()));
+    let tmp = PathBuf::from(tmp);
+    let written = fs::File::create(&tmp)
+        .and_then(|mut file| file.write_all(contents.as_ref()).and_then(|_| file.sync_all()))
+        .and_then(|_| fs::rename(&tmp, path));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&tmp);
+        return Err(e).with_context(|| format!("Failed to write {}", path.display()));
+    }
+    Ok(())
+}
+
+/// `YYYY-MM-DD HH:MM:SS` in UTC, using the days-to-civil conversion from
+/// Howard Hinnant's date algorithms.
+pub fn format_utc(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, rem / 3600, rem % 3600 / 60, rem % 60)
+}
+
 struct SimpleFormatter;
 impl<S, N> FormatEvent<S, N> for SimpleFormatter
 where
@@ -44,7 +107,7 @@ where
         event: &Event<'_>,
     ) -> std_fmt::Result {
         let level = *event.metadata().level();
-        write!(writer, "[{}] ", level)?;
+        write!(writer, "[{}] [{}] ", level, session_id())?;
         ctx.field_format().format_fields(writer.by_ref(), event)?;
         writeln!(writer)
     }
@@ -83,7 +146,7 @@ pub fn init_logging(verbose: bool, log_path: &Path) -> Result<WorkerGuard> {
             },
         };
         let location = info.location().map(|l| format!("{}:{}", l.file(), l.line())).unwrap_or_default();
-        let error_msg = format!("\n[ERROR] PANIC: Thread crashed at {}: {}\n", location, msg);
+        let error_msg = format!("\n[ERROR] [{}] PANIC: Thread crashed at {}: {}\n", session_id(), location, msg);
         if let Ok(mut file) = std::fs::OpenOptions::new().create(true).append(true).open(&log_path_buf) {
             let _ = writeln!(file, "{}", error_msg);
         }
@@ -156,6 +219,15 @@ pub fn random_kworker_name() -> String {
     format!("kworker/u{}:{}", x, y)
 }
 
+pub fn get_prop(name: &str) -> Option<String> {
+    let output = Command::new("getprop").arg(name).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if value.is_empty() { None } else { Some(value) }
+}
+
 pub fn is_xattr_supported(path: &Path) -> bool {
     let test_file = path.join(XATTR_TEST_FILE);
     if let Err(e) = write(&test_file, b"test") {
@@ -174,10 +246,8 @@ pub fn is_mounted<P: AsRef<Path>>(path: P) -> bool {
     if let Ok(content) = fs::read_to_string("/proc/mounts") {
         for line in content.lines() {
             let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() > 1 {
-                if parts[1] == search {
-                    return true;
-                }
+            if parts.len() > 1 && parts[1] == search {
+                return true;
             }
         }
     }
@@ -185,8 +255,12 @@ pub fn is_mounted<P: AsRef<Path>>(path: P) -> bool {
 }
 
 pub fn mount_tmpfs(target: &Path, source: &str) -> Result<()> {
+    mount_tmpfs_with(target, source, "mode=0755")
+}
+
+pub fn mount_tmpfs_with(target: &Path, source: &str, options: &str) -> Result<()> {
     ensure_dir_exists(target)?;
-    let data = CString::new("mode=0755")?;
+    let data = CString::new(options)?;
     mount(source, target, "tmpfs", MountFlags::empty(), data.as_c_str())
         .context("Failed to mount tmpfs")?;
     Ok(())
@@ -222,6 +296,19 @@ pub fn repair_image(image_path: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Copies a file, sharing its blocks through a reflink when source and
+/// destination live on a filesystem that supports it.
+pub fn clone_or_copy(src: &Path, dst: &Path) -> Result<()> {
+    let from = fs::File::open(src)?;
+    let to = fs::File::create(dst)?;
+    if rustix::fs::ioctl_ficlone(&to, &from).is_ok() {
+        return Ok(());
+    }
+    drop(to);
+    fs::copy(src, dst)?;
+    Ok(())
+}
+
 fn native_cp_r(src: &Path, dst: &Path) -> Result<()> {
     if !dst.exists() {
         create_dir_all(dst)?;
@@ -242,7 +329,7 @@ fn native_cp_r(src: &Path, dst: &Path) -> Result<()> {
             symlink(&link_target, &dst_path)?;
             let _ = lsetfilecon(&dst_path, DEFAULT_CONTEXT);
         } else {
-            fs::copy(&src_path, &dst_path)?;
+            clone_or_copy(&src_path, &dst_path)?;
             let src_meta = src_path.metadata()?;
             fs::set_permissions(&dst_path, src_meta.permissions())?;
             lsetfilecon(&dst_path, DEFAULT_CONTEXT)?;
@@ -351,3 +438,42 @@ pub fn ksu_nuke_sysfs(_target: &str) -> Result<()> {
 pub fn send_unmountable<P>(_target: P) -> Result<()> {
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_session_ids() {
+        assert_eq!(format_session_id("3f2a9c1e-77b0-4c1d-9b1e-0123456789ab\n", 0x6523_1a00, 4242), "3f2a9c1e-65231a00-1092");
+        assert_eq!(format_session_id("", 16, 1), "00000000-10-1");
+    }
+
+    #[test]
+    fn compares_boots_by_session_prefix() {
+        assert!(same_boot("3f2a9c1e-1-2", "3f2a9c1e-9-9"));
+        assert!(!same_boot("3f2a9c1e-1-2", "00000000-1-2"));
+        assert!(!same_boot("", ""));
+    }
+
+    #[test]
+    fn atomic_write_replaces_contents() {
+        let path = std::env::temp_dir().join(format!("atomic-{}.json", std::process::id()));
+        atomic_write(&path, "old").unwrap();
+        atomic_write(&path, "new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        let leftovers = fs::read_dir(std::env::temp_dir()).unwrap().flatten()
+            .filter(|e| e.file_name().to_string_lossy().starts_with(&format!("atomic-{}.json.tmp", std::process::id())))
+            .count();
+        assert_eq!(leftovers, 0);
+        fs::remove_file(&path).unwrap();
+        assert!(atomic_write(std::env::temp_dir().join("no-such-dir/x"), "x").is_err());
+    }
+
+    #[test]
+    fn formats_utc_timestamps() {
+        assert_eq!(format_utc(0), "1970-01-01 00:00:00");
+        assert_eq!(format_utc(951_782_400), "2000-02-29 00:00:00");
+        assert_eq!(format_utc(1_700_000_000), "2023-11-14 22:13:20");
+    }
+}
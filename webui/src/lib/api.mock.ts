@@ -114,8 +114,20 @@ export const MockAPI = {
   async getConflicts(): Promise<ConflictEntry[]> {
     await delay(500);
     return [
-        { partition: "system", relative_path: "etc/hosts", contending_modules: ["magisk_module_1", "overlay_module_2"] },
-        { partition: "vendor", relative_path: "etc/audio_policy_configuration.xml", contending_modules: ["sound_mod", "dolby"] }
+        {
+          partition: "system", relative_path: "etc/hosts", contending_modules: ["magisk_module_1", "overlay_module_2"], winner: "magisk_module_1",
+          provenance: [
+            { module_id: "magisk_module_1", backend: "overlay", source: "/data/adb/meta-hybrid/mnt/magisk_module_1/system/etc/hosts" },
+            { module_id: "overlay_module_2", backend: "overlay", source: "/data/adb/meta-hybrid/mnt/overlay_module_2/system/etc/hosts" }
+          ]
+        },
+        {
+          partition: "vendor", relative_path: "etc/audio_policy_configuration.xml", contending_modules: ["sound_mod", "dolby"], winner: "sound_mod",
+          provenance: [
+            { module_id: "sound_mod", backend: "hymofs", source: "/data/adb/meta-hybrid/mnt/sound_mod/vendor/etc/audio_policy_configuration.xml" },
+            { module_id: "dolby", backend: "overlay", source: "/data/adb/meta-hybrid/mnt/dolby/vendor/etc/audio_policy_configuration.xml" }
+          ]
+        }
     ];
   },
   async getDiagnostics(): Promise<DiagnosticIssue[]> {
@@ -76,10 +76,18 @@ export interface ModeStats {
   hymofs: number;
 }
 
+export interface ConflictCandidate {
+  module_id: string;
+  backend: 'hymofs' | 'overlay';
+  source: string;
+}
+
 export interface ConflictEntry {
   partition: string;
   relative_path: string;
   contending_modules: string[];
+  winner: string;
+  provenance: ConflictCandidate[];
 }
 
 export interface DiagnosticIssue {
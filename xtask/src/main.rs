@@ -58,14 +58,16 @@ enum Commands {
         release: bool,
         #[arg(long)]
         skip_webui: bool,
+        #[arg(long)]
+        selftest: bool,
     },
 }
 fn main() -> Result<()> {
     let cli = Cli::parse();
     let root = project_root();
     match cli.command {
-        Commands::Build { release, skip_webui } => {
-            build_full(&root, release, skip_webui)?;
+        Commands::Build { release, skip_webui, selftest } => {
+            build_full(&root, release, skip_webui, selftest)?;
         }
     }
     Ok(())
@@ -77,7 +79,7 @@ fn project_root() -> PathBuf {
         .unwrap()
         .to_path_buf()
 }
-fn build_full(root: &Path, release: bool, skip_webui: bool) -> Result<()> {
+fn build_full(root: &Path, release: bool, skip_webui: bool, selftest: bool) -> Result<()> {
     let output_dir = root.join("output");
     let stage_dir = output_dir.join("staging");
     if output_dir.exists() { fs::remove_dir_all(&output_dir)?; }
@@ -101,6 +103,11 @@ fn build_full(root: &Path, release: bool, skip_webui: bool) -> Result<()> {
         fs::create_dir_all(&stage_bin_dir)?;
         if src_bin.exists() {
              fs::copy(&src_bin, stage_bin_dir.join(bin_name))?;
+             if selftest {
+                 let kit_dir = output_dir.join("hymo-selftest").join(arch.android_abi());
+                 fs::create_dir_all(&kit_dir)?;
+                 fs::copy(&src_bin, kit_dir.join("hymo-selftest"))?;
+             }
         } else {
              println!("Warning: Binary not found at {}", src_bin.display());
         }